@@ -0,0 +1,771 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, ensure};
+use sha1::{Digest, Sha1};
+
+use crate::config::Config;
+use crate::git_objects::{GitObject, TreeFileModes};
+use crate::pack::{packs, PackIndex};
+use crate::refs::shallow_commits;
+use crate::revision::CommitWalker;
+use crate::utils::{be_u32, from_hex, object_exists, to_hex_string, write_to_file};
+
+const SIGNATURE: &[u8] = b"BITM";
+
+const VERSION: u16 = 1;
+
+/// Every bitmap covers all the objects reachable from its commit.
+const OPTION_FULL_DAG: u16 = 0x1;
+
+/// A name hash of each object follows the bitmaps.
+const OPTION_HASH_CACHE: u16 = 0x4;
+
+/// How many entries back a bitmap may name the one it is XOR-ed with.
+const MAX_XOR_OFFSET: usize = 160;
+
+/// Runs of clean words and literal words an EWAH marker word can count.
+const RUN_MAX: usize = u32::MAX as usize;
+const LITERALS_MAX: usize = (1 << 31) - 1;
+
+/// With fewer commits than this, every commit gets a bitmap.
+const SELECT_ALL_BELOW: usize = 100;
+
+/// Commit selection: every one of the newest `MUST_REGION`, then one in at most
+/// `MIN_COMMITS` up to `MIN_REGION`, and one in up to `MAX_COMMITS` after that.
+const MUST_REGION: usize = 100;
+const MIN_REGION: usize = 20000;
+const MIN_COMMITS: usize = 100;
+const MAX_COMMITS: usize = 5000;
+
+/// Object types in the order of their bitmaps in the file.
+const TYPES: [&str; 4] = ["commit", "tree", "blob", "tag"];
+
+/// A set of objects of a pack, bit `n` standing for the `n`th object in pack order.
+#[derive(Debug, Clone, Default)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    fn set(&mut self, position: usize) {
+        if self.words.len() <= position / 64 {
+            self.words.resize(position / 64 + 1, 0);
+        }
+
+        self.words[position / 64] |= 1 << (position % 64);
+    }
+
+    fn get(&self, position: usize) -> bool {
+        self.words
+            .get(position / 64)
+            .is_some_and(|word| word & (1 << (position % 64)) != 0)
+    }
+
+    fn or(&mut self, other: &Bitmap) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    fn and(&mut self, other: &Bitmap) {
+        self.words.truncate(other.words.len());
+
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= other;
+        }
+    }
+
+    fn and_not(&mut self, other: &Bitmap) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+    }
+
+    fn xor(&mut self, other: &Bitmap) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word ^= other;
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Positions of the objects in the set, in pack order.
+    fn positions(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index * 64 + bit)
+        })
+    }
+
+    /// Reads an EWAH-compressed bitmap, returning it and how many bytes it took.
+    fn read_ewah(data: &[u8]) -> anyhow::Result<(Self, usize)> {
+        ensure!(data.len() >= 8, "bitmap is truncated");
+
+        let count = be_u32(&data[4..]) as usize;
+
+        let end = 8 + count * 8 + 4;
+
+        ensure!(data.len() >= end, "bitmap is truncated");
+
+        let buffer: Vec<u64> = data[8..8 + count * 8]
+            .chunks(8)
+            .map(|word| u64::from_be_bytes(word.try_into().unwrap_or_default()))
+            .collect();
+
+        let mut words = Vec::new();
+
+        let mut index = 0;
+
+        // Each marker word counts a run of all-zero or all-one words and then the
+        // literal words that follow it
+        while index < buffer.len() {
+            let marker = buffer[index];
+
+            let clean = match marker & 1 {
+                0 => 0,
+
+                _ => u64::MAX,
+            };
+
+            let run = ((marker >> 1) & u32::MAX as u64) as usize;
+
+            let literals = (marker >> 33) as usize;
+
+            ensure!(
+                index + 1 + literals <= buffer.len(),
+                "bitmap has more literal words than it holds"
+            );
+
+            words.resize(words.len() + run, clean);
+
+            words.extend(&buffer[index + 1..index + 1 + literals]);
+
+            index += 1 + literals;
+        }
+
+        Ok((Bitmap { words }, end))
+    }
+
+    fn write_ewah(&self, output: &mut Vec<u8>) {
+        let words = match self.words.iter().rposition(|&word| word != 0) {
+            Some(last) => &self.words[..=last],
+
+            None => &[][..],
+        };
+
+        let size = words.last().map_or(0, |last| {
+            (words.len() - 1) * 64 + 64 - last.leading_zeros() as usize
+        });
+
+        let mut buffer: Vec<u64> = Vec::new();
+
+        let mut marker;
+
+        let mut index = 0;
+
+        loop {
+            let clean = match words.get(index) {
+                Some(&u64::MAX) => u64::MAX,
+
+                _ => 0,
+            };
+
+            let run_start = index;
+
+            while index < words.len() && words[index] == clean && index - run_start < RUN_MAX {
+                index += 1;
+            }
+
+            let run = index - run_start;
+
+            let literal_start = index;
+
+            while index < words.len()
+                && words[index] != 0
+                && words[index] != u64::MAX
+                && index - literal_start < LITERALS_MAX
+            {
+                index += 1;
+            }
+
+            marker = buffer.len();
+
+            buffer.push((clean & 1) | (run as u64) << 1 | ((index - literal_start) as u64) << 33);
+
+            buffer.extend(&words[literal_start..index]);
+
+            if index >= words.len() {
+                break;
+            }
+        }
+
+        output.extend((size as u32).to_be_bytes());
+
+        output.extend((buffer.len() as u32).to_be_bytes());
+
+        for word in buffer {
+            output.extend(word.to_be_bytes());
+        }
+
+        output.extend((marker as u32).to_be_bytes());
+    }
+}
+
+/// The `.bitmap` of a pack: which objects of the pack each of a selection of its commits
+/// reaches, and which objects are of each type.
+pub struct PackBitmap {
+    pack: Arc<PackIndex>,
+
+    /// Position in pack order of each object, by position in the index.
+    positions: Vec<usize>,
+
+    /// Position in the index of each object, in pack order.
+    order: Vec<usize>,
+
+    /// Commits, trees, blobs and tags.
+    types: [Bitmap; 4],
+
+    /// The objects each selected commit reaches, by commit id.
+    commits: HashMap<Vec<u8>, Bitmap>,
+
+    /// Objects outside the pack met while walking, at the positions after its objects.
+    extended: Vec<Vec<u8>>,
+
+    /// Position of each of `extended`.
+    extended_positions: HashMap<Vec<u8>, usize>,
+}
+
+impl PackBitmap {
+    /// Loads the bitmap of the first pack that has one, unless `pack.useBitmaps` turns
+    /// them off or history is shallow, which bitmaps do not describe. A bitmap that cannot
+    /// be read is only warned about, leaving callers to walk the objects instead.
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        if Config::load()?.get("pack.useBitmaps") == Some("false") || !shallow_commits().is_empty()
+        {
+            return Ok(None);
+        }
+
+        for pack in packs()? {
+            let path = bitmap_path(&pack);
+
+            if Path::new(&path).exists() {
+                return match Self::read(pack, &path) {
+                    Ok(bitmap) => Ok(Some(bitmap)),
+
+                    Err(error) => {
+                        eprintln!("warning: ignoring {path}: {error}");
+
+                        Ok(None)
+                    }
+                };
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn read(pack: Arc<PackIndex>, path: &str) -> anyhow::Result<Self> {
+        let data = fs::read(path)?;
+
+        ensure!(data.len() >= 32 + 20, "corrupted bitmap index (too small)");
+
+        ensure!(
+            &data[..4] == SIGNATURE,
+            "corrupted bitmap index file (wrong header)"
+        );
+
+        let version = u16::from_be_bytes([data[4], data[5]]);
+
+        ensure!(
+            version == VERSION,
+            "unsupported version '{version}' for bitmap index file"
+        );
+
+        let options = u16::from_be_bytes([data[6], data[7]]);
+
+        ensure!(
+            options & OPTION_FULL_DAG != 0,
+            "bitmaps without the full closure are not supported"
+        );
+
+        ensure!(
+            options & !(OPTION_FULL_DAG | OPTION_HASH_CACHE) == 0,
+            "unsupported options {options:#x} in bitmap index file"
+        );
+
+        ensure!(
+            data[12..32] == pack_checksum(&pack)?,
+            "checksum doesn't match in MIDX and bitmap"
+        );
+
+        let count = be_u32(&data[8..]) as usize;
+
+        let (order, positions) = pack_order(&pack);
+
+        let mut offset = 32;
+
+        let mut types: [Bitmap; 4] = Default::default();
+
+        for bitmap in &mut types {
+            let (read, used) = Bitmap::read_ewah(&data[offset..])?;
+
+            *bitmap = read;
+
+            offset += used;
+        }
+
+        // Each entry may be stored XOR-ed with one before it
+        let mut entries: Vec<(Vec<u8>, Bitmap)> = Vec::with_capacity(count);
+
+        for number in 0..count {
+            ensure!(
+                data.len() >= offset + 6,
+                "corrupted bitmap index (truncated)"
+            );
+
+            let index = be_u32(&data[offset..]) as usize;
+
+            let xor_offset = data[offset + 4] as usize;
+
+            let Some(id) = pack.hashes.get(index) else {
+                bail!("corrupted bitmap pack index");
+            };
+
+            ensure!(
+                xor_offset <= MAX_XOR_OFFSET && xor_offset <= number,
+                "corrupted bitmap pack index"
+            );
+
+            let (mut bitmap, used) = Bitmap::read_ewah(&data[offset + 6..])?;
+
+            if xor_offset > 0 {
+                bitmap.xor(&entries[number - xor_offset].1);
+            }
+
+            entries.push((id.to_vec(), bitmap));
+
+            offset += 6 + used;
+        }
+
+        let hash_cache = match options & OPTION_HASH_CACHE {
+            0 => 0,
+
+            _ => pack.hashes.len() * 4,
+        };
+
+        ensure!(
+            data.len() == offset + hash_cache + 20,
+            "corrupted bitmap index file (wrong size)"
+        );
+
+        Ok(PackBitmap {
+            pack,
+            positions,
+            order,
+            types,
+            commits: entries.into_iter().collect(),
+            extended: Vec::new(),
+            extended_positions: HashMap::new(),
+        })
+    }
+
+    fn position(&self, id: &[u8]) -> Option<usize> {
+        match self
+            .pack
+            .hashes
+            .binary_search_by(|candidate| candidate.as_slice().cmp(id))
+        {
+            Ok(index) => Some(self.positions[index]),
+
+            Err(_) => self.extended_positions.get(id).copied(),
+        }
+    }
+
+    /// Position of an object outside the pack, giving it the next free one.
+    fn extend(&mut self, id: Vec<u8>, kind: &str) -> usize {
+        let position = self.positions.len() + self.extended.len();
+
+        self.extended_positions.insert(id.clone(), position);
+
+        self.extended.push(id);
+
+        if let Some(index) = TYPES.iter().position(|name| *name == kind) {
+            self.types[index].set(position);
+        }
+
+        position
+    }
+
+    /// The objects reachable from `tips`, those outside the pack taking extended positions.
+    pub fn reachable(&mut self, tips: &[String]) -> anyhow::Result<Bitmap> {
+        let mut reachable = Bitmap::default();
+
+        let mut stack = tips.to_vec();
+
+        while let Some(hash) = stack.pop() {
+            let id = from_hex(&hash)?;
+
+            let (position, kind, content) = match self.position(&id) {
+                Some(position) if reachable.get(position) => continue,
+
+                Some(position) if position < self.positions.len() => {
+                    if let Some(bitmap) = self.commits.get(&id) {
+                        reachable.or(bitmap);
+
+                        continue;
+                    }
+
+                    let offset = self.pack.offsets[self.order[position]];
+
+                    let (kind, content) = self.pack.read_object(offset)?;
+
+                    (position, kind, content)
+                }
+
+                Some(position) => {
+                    let (kind, content) = GitObject::read_raw(&hash)?;
+
+                    (position, kind, content)
+                }
+
+                None => {
+                    let (kind, content) = GitObject::read_raw(&hash)?;
+
+                    (self.extend(id, &kind), kind, content)
+                }
+            };
+
+            reachable.set(position);
+
+            stack.extend(links(&kind, &content)?.into_iter().map(|(hash, _)| hash));
+        }
+
+        Ok(reachable)
+    }
+
+    /// The objects reachable from `include` but not from `exclude`. Objects to exclude
+    /// that the repository lacks are skipped.
+    pub fn between(&mut self, exclude: &[String], include: &[String]) -> anyhow::Result<Bitmap> {
+        let mut objects = self.reachable(include)?;
+
+        let exclude: Vec<String> = exclude
+            .iter()
+            .filter(|hash| object_exists(hash))
+            .cloned()
+            .collect();
+
+        objects.and_not(&self.reachable(&exclude)?);
+
+        Ok(objects)
+    }
+
+    /// Keeps only the commits of a set.
+    pub fn commits_of(&self, bitmap: &mut Bitmap) {
+        bitmap.and(&self.types[0]);
+    }
+
+    /// Hashes of the objects of a set: commits, trees, blobs and then tags, each in pack
+    /// order followed by those outside the pack.
+    pub fn hashes(&self, bitmap: &Bitmap) -> Vec<String> {
+        let mut hashes = Vec::new();
+
+        for kind in &self.types {
+            let mut objects = bitmap.clone();
+
+            objects.and(kind);
+
+            hashes.extend(
+                objects
+                    .positions()
+                    .map(|position| match self.order.get(position) {
+                        Some(&index) => to_hex_string(&self.pack.hashes[index]),
+
+                        None => to_hex_string(&self.extended[position - self.order.len()]),
+                    }),
+            );
+        }
+
+        hashes
+    }
+}
+
+/// The objects a pack of `include` needs for a repository with `exclude`, found through
+/// the reachability bitmaps. `None` when there are none to use.
+pub fn objects_between(
+    exclude: &[String],
+    include: &[String],
+) -> anyhow::Result<Option<Vec<String>>> {
+    let Some(mut bitmap) = PackBitmap::load()? else {
+        return Ok(None);
+    };
+
+    let objects = bitmap.between(exclude, include)?;
+
+    Ok(Some(bitmap.hashes(&objects)))
+}
+
+/// Writes the `.bitmap` of a pack holding everything its commits reach, as `repack -a`
+/// makes: reachability bitmaps for a selection of the commits, the objects of each type
+/// and a hash of the path of each object for picking delta bases.
+pub fn write(index_path: &str) -> anyhow::Result<()> {
+    let pack = Arc::new(PackIndex::read(index_path)?);
+
+    let (order, positions) = pack_order(&pack);
+
+    let mut types: [Bitmap; 4] = Default::default();
+
+    let mut commits = Vec::new();
+
+    let mut walker = CommitWalker::new();
+
+    for &index in &order {
+        let kind = pack.object_type(pack.offsets[index])?;
+
+        let Some(number) = TYPES.iter().position(|name| *name == kind) else {
+            bail!(
+                "object {} has unknown type {kind}",
+                to_hex_string(&pack.hashes[index])
+            );
+        };
+
+        types[number].set(positions[index]);
+
+        if number == 0 {
+            let hash = to_hex_string(&pack.hashes[index]);
+
+            commits.push((walker.date(&hash)?, index));
+        }
+    }
+
+    // Newest first, the order entries are written in
+    commits.sort_by_key(|&(date, _)| Reverse(date));
+
+    let selected = select_commits(&commits, |index| {
+        let hash = to_hex_string(&pack.hashes[index]);
+
+        Ok(walker.parents(&hash)?.len() > 1)
+    })?;
+
+    // Each bitmap is built upon those of the older selected commits it reaches
+    let mut bitmaps: HashMap<usize, Bitmap> = HashMap::new();
+
+    let mut paths: HashMap<usize, String> = HashMap::new();
+
+    for &index in selected.iter().rev() {
+        let mut reachable = Bitmap::default();
+
+        let mut stack = vec![(pack.hashes[index].to_vec(), String::new())];
+
+        while let Some((id, path)) = stack.pop() {
+            let Ok(found) = pack
+                .hashes
+                .binary_search_by(|candidate| candidate.as_slice().cmp(&id))
+            else {
+                bail!(
+                    "Failed to write bitmap index. Packfile doesn't have full closure (object {} is missing)",
+                    to_hex_string(&id)
+                );
+            };
+
+            if reachable.get(positions[found]) {
+                continue;
+            }
+
+            if let Some(bitmap) = bitmaps.get(&found) {
+                reachable.or(bitmap);
+
+                continue;
+            }
+
+            reachable.set(positions[found]);
+
+            if !path.is_empty() {
+                paths.entry(found).or_insert_with(|| path.clone());
+            }
+
+            let (kind, content) = pack.read_object(pack.offsets[found])?;
+
+            for (hash, name) in links(&kind, &content)? {
+                let child_path = match (name.is_empty(), path.is_empty()) {
+                    (true, _) => String::new(),
+
+                    (false, true) => name,
+
+                    (false, false) => format!("{path}/{name}"),
+                };
+
+                stack.push((from_hex(&hash)?, child_path));
+            }
+        }
+
+        bitmaps.insert(index, reachable);
+    }
+
+    let mut data = SIGNATURE.to_vec();
+
+    data.extend(VERSION.to_be_bytes());
+
+    data.extend((OPTION_FULL_DAG | OPTION_HASH_CACHE).to_be_bytes());
+
+    data.extend((selected.len() as u32).to_be_bytes());
+
+    data.extend(pack_checksum(&pack)?);
+
+    for bitmap in &types {
+        bitmap.write_ewah(&mut data);
+    }
+
+    for index in &selected {
+        data.extend((*index as u32).to_be_bytes());
+
+        // Neither XOR-ed with another entry nor flagged
+        data.extend([0, 0]);
+
+        bitmaps[index].write_ewah(&mut data);
+    }
+
+    for index in 0..pack.hashes.len() {
+        data.extend(
+            paths
+                .get(&index)
+                .map_or(0, |path| name_hash(path))
+                .to_be_bytes(),
+        );
+    }
+
+    let checksum = Sha1::digest(&data);
+
+    data.extend(checksum);
+
+    write_to_file(&bitmap_path(&pack), &data)
+}
+
+/// Picks the commits to give bitmaps from `commits`, `(date, index)` newest first:
+/// all the newest ones, then fewer and fewer further back, preferring merges within each
+/// stretch.
+fn select_commits(
+    commits: &[(i64, usize)],
+    mut is_merge: impl FnMut(usize) -> anyhow::Result<bool>,
+) -> anyhow::Result<Vec<usize>> {
+    if commits.len() < SELECT_ALL_BELOW {
+        return Ok(commits.iter().map(|&(_, index)| index).collect());
+    }
+
+    let mut selected = Vec::new();
+
+    let mut start = 0;
+
+    loop {
+        let next = match start {
+            _ if start <= MUST_REGION => 0,
+
+            _ if start <= MIN_REGION => (start - MUST_REGION).min(MIN_COMMITS),
+
+            _ => (start - MIN_REGION).clamp(MIN_COMMITS, MAX_COMMITS),
+        };
+
+        if start + next >= commits.len() {
+            break;
+        }
+
+        let mut chosen = commits[start + next].1;
+
+        if next > 0 {
+            for &(_, index) in &commits[start..=start + next] {
+                if is_merge(index)? {
+                    chosen = index;
+                }
+            }
+        }
+
+        selected.push(chosen);
+
+        start += next + 1;
+    }
+
+    Ok(selected)
+}
+
+/// Objects an object links to, with the names trees give them.
+fn links(kind: &str, content: &[u8]) -> anyhow::Result<Vec<(String, String)>> {
+    Ok(match kind {
+        "commit" | "tag" => String::from_utf8_lossy(content)
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| {
+                let (key, value) = line.split_once(' ')?;
+
+                matches!(key, "tree" | "parent" | "object")
+                    .then(|| (value.to_string(), String::new()))
+            })
+            .collect(),
+
+        "tree" => GitObject::parse_tree_entries(content)?
+            .into_iter()
+            .filter(|(mode, _, _)| *mode != TreeFileModes::Gitlink)
+            .map(|(_, name, hash)| (hash, name))
+            .collect(),
+
+        _ => Vec::new(),
+    })
+}
+
+/// Index positions of a pack's objects in pack order, and the inverse.
+fn pack_order(pack: &PackIndex) -> (Vec<usize>, Vec<usize>) {
+    let mut order: Vec<usize> = (0..pack.hashes.len()).collect();
+
+    order.sort_by_key(|&index| pack.offsets[index]);
+
+    let mut positions = vec![0; order.len()];
+
+    for (position, &index) in order.iter().enumerate() {
+        positions[index] = position;
+    }
+
+    (order, positions)
+}
+
+/// The checksum ending a pack, which its bitmap repeats.
+fn pack_checksum(pack: &PackIndex) -> anyhow::Result<[u8; 20]> {
+    let mut file = File::open(&pack.pack_path)?;
+
+    file.seek(SeekFrom::End(-20))?;
+
+    let mut checksum = [0; 20];
+
+    file.read_exact(&mut checksum)?;
+
+    Ok(checksum)
+}
+
+fn bitmap_path(pack: &PackIndex) -> String {
+    format!("{}.bitmap", pack.pack_path.trim_end_matches(".pack"))
+}
+
+/// Git's hash of a path for grouping similar objects, weighing its last characters most.
+fn name_hash(path: &str) -> u32 {
+    path.bytes()
+        .filter(|byte| !matches!(byte, b' ' | b'\t' | b'\n' | b'\r'))
+        .fold(0u32, |hash, byte| {
+            (hash >> 2).wrapping_add((byte as u32) << 24)
+        })
+}
@@ -0,0 +1,335 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::{bail, ensure};
+
+use crate::git_objects::GitObject;
+use crate::pack::{index_pack, write_pack};
+use crate::pkt_line::PacketWriter;
+use crate::refs::{expand_ref, head_target, list_refs, resolve_revision, HEAD};
+use crate::revision::CommitWalker;
+use crate::transport::{Transport, UPLOAD_PACK};
+use crate::utils::{object_exists, read_file, write_to_file};
+
+const V2_SIGNATURE: &str = "# v2 git bundle";
+
+const V3_SIGNATURE: &str = "# v3 git bundle";
+
+/// A bundle file: the refs it carries, the commits a repository must already have to
+/// take them, and a pack with everything in between.
+pub struct Bundle {
+    /// `<key>=<value>` capabilities of a version 3 bundle, such as `object-format=sha1`.
+    pub capabilities: Vec<String>,
+
+    /// `(hash, comment)` of each prerequisite commit, the comment being its subject.
+    pub prerequisites: Vec<(String, String)>,
+
+    /// `(hash, name)` of each ref.
+    pub refs: Vec<(String, String)>,
+
+    pack: Vec<u8>,
+}
+
+impl Bundle {
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let data = read_file(path)?;
+
+        let mut header = Vec::new();
+
+        let mut position = 0;
+
+        // The header ends with an empty line, the pack follows
+        loop {
+            let Some(end) = data[position..].iter().position(|&byte| byte == b'\n') else {
+                bail!("'{path}' does not look like a v2 or v3 bundle file");
+            };
+
+            let line = String::from_utf8_lossy(&data[position..position + end]).to_string();
+
+            position += end + 1;
+
+            if line.is_empty() {
+                break;
+            }
+
+            header.push(line);
+        }
+
+        let version = match header.first().map(String::as_str) {
+            Some(V2_SIGNATURE) => 2,
+
+            Some(V3_SIGNATURE) => 3,
+
+            _ => bail!("'{path}' does not look like a v2 or v3 bundle file"),
+        };
+
+        let mut bundle = Bundle {
+            capabilities: Vec::new(),
+            prerequisites: Vec::new(),
+            refs: Vec::new(),
+            pack: data[position..].to_vec(),
+        };
+
+        for line in &header[1..] {
+            if let Some(capability) = line.strip_prefix('@').filter(|_| version == 3) {
+                match capability.split_once('=') {
+                    Some(("object-format", "sha1") | ("filter", _)) => {}
+
+                    _ => bail!("unknown capability '{capability}'"),
+                }
+
+                bundle.capabilities.push(capability.to_string());
+            } else if let Some(prerequisite) = line.strip_prefix('-') {
+                let (hash, comment) = prerequisite.split_once(' ').unwrap_or((prerequisite, ""));
+
+                bundle
+                    .prerequisites
+                    .push((hash.to_string(), comment.to_string()));
+            } else if let Some((hash, name)) = line.split_once(' ') {
+                bundle.refs.push((hash.to_string(), name.to_string()));
+            } else {
+                bail!("unrecognized header: {line}");
+            }
+        }
+
+        Ok(bundle)
+    }
+
+    /// Whether `path` is a file starting with a bundle signature.
+    pub fn is_bundle(path: &str) -> bool {
+        let mut signature = Vec::new();
+
+        File::open(path)
+            .and_then(|file| file.take(16).read_to_end(&mut signature))
+            .is_ok()
+            && [V2_SIGNATURE, V3_SIGNATURE]
+                .iter()
+                .any(|expected| signature == format!("{expected}\n").as_bytes())
+    }
+
+    /// Whether this repository has every prerequisite commit, listing those it lacks
+    /// when not (without their comments, as git does).
+    pub fn has_prerequisites(&self) -> bool {
+        let missing: Vec<_> = self
+            .prerequisites
+            .iter()
+            .filter(|(hash, _)| !object_exists(hash))
+            .collect();
+
+        if missing.is_empty() {
+            return true;
+        }
+
+        eprintln!("error: Repository lacks these prerequisite commits:");
+
+        for (hash, _) in missing {
+            eprintln!("error: {hash} ");
+        }
+
+        false
+    }
+
+    /// Stores the pack in this repository, which must have the prerequisites. Deltas
+    /// against them get their bases appended, so the stored pack stands alone.
+    pub fn unbundle(&self) -> anyhow::Result<()> {
+        index_pack(&self.pack)?;
+
+        Ok(())
+    }
+
+    /// Branch `HEAD` is taken to point at: one at the same commit, preferably the branch
+    /// this repository's `HEAD` names.
+    fn head_branch(&self) -> anyhow::Result<Option<&str>> {
+        let Some((head, _)) = self.refs.iter().find(|(_, name)| name == HEAD) else {
+            return Ok(None);
+        };
+
+        let mut branches = self
+            .refs
+            .iter()
+            .filter(|(hash, name)| hash == head && name.starts_with("refs/heads/"))
+            .map(|(_, name)| name.as_str());
+
+        let preferred = head_target()?;
+
+        Ok(branches
+            .clone()
+            .find(|name| Some(*name) == preferred.as_deref())
+            .or_else(|| branches.next()))
+    }
+}
+
+/// Writes a bundle of the objects reachable from the positive `revisions` but not from
+/// the negative ones (`^A`, or `A` in `A..B`). The positive revisions that name refs
+/// become the bundle's refs, and there must be at least one. `--all`, `--branches` and
+/// `--tags` stand for the refs they cover.
+pub fn create(path: &str, version: u8, revisions: &[String]) -> anyhow::Result<()> {
+    let signature = match version {
+        2 => V2_SIGNATURE,
+
+        3 => V3_SIGNATURE,
+
+        _ => bail!("unsupported bundle version {version}"),
+    };
+
+    let mut refs: Vec<(String, String)> = Vec::new();
+
+    let mut includes = Vec::new();
+
+    let mut excludes = Vec::new();
+
+    let mut expanded = Vec::new();
+
+    for revision in revisions {
+        let prefix = match revision.as_str() {
+            "--all" => "refs/",
+
+            "--branches" => "refs/heads/",
+
+            "--tags" => "refs/tags/",
+
+            _ => {
+                expanded.push(revision.clone());
+
+                continue;
+            }
+        };
+
+        expanded.extend(list_refs(prefix)?.into_iter().map(|(name, _)| name));
+
+        if prefix == "refs/" {
+            expanded.push(HEAD.to_string());
+        }
+    }
+
+    for revision in &expanded {
+        let or_head = |name: &str| if name.is_empty() { HEAD } else { name }.to_string();
+
+        let (excluded, included) = match revision.split_once("..") {
+            Some((from, to)) => (Some(or_head(from)), Some(or_head(to))),
+
+            None => match revision.strip_prefix('^') {
+                Some(excluded) => (Some(excluded.to_string()), None),
+
+                None => (None, Some(revision.clone())),
+            },
+        };
+
+        if let Some(name) = excluded {
+            excludes.push(resolve_revision(&name)?);
+        }
+
+        if let Some(name) = included {
+            let hash = resolve_revision(&name)?;
+
+            if let Some(full_name) = expand_ref(&name)? {
+                if !refs.iter().any(|(_, existing)| *existing == full_name) {
+                    refs.push((hash.clone(), full_name));
+                }
+            }
+
+            includes.push(hash);
+        }
+    }
+
+    let mut walker = CommitWalker::new();
+
+    let mut excluded = HashSet::new();
+
+    for hash in &excludes {
+        excluded.extend(walker.ancestors(&GitObject::peel(hash)?)?);
+    }
+
+    // A ref the negative revisions leave out has nothing to bring
+    refs.retain(|(hash, _)| !excluded.contains(hash));
+
+    ensure!(!refs.is_empty(), "Refusing to create empty bundle.");
+
+    let mut content = format!("{signature}\n");
+
+    if version == 3 {
+        content.push_str("@object-format=sha1\n");
+    }
+
+    for hash in walker.boundary(&excludes, &includes)? {
+        let GitObject::Commit { message, .. } = GitObject::from_hash(&hash)? else {
+            bail!("{hash} is not a commit");
+        };
+
+        let subject = message.lines().next().unwrap_or_default();
+
+        content.push_str(&format!("-{hash} {subject}\n"));
+    }
+
+    for (hash, name) in &refs {
+        content.push_str(&format!("{hash} {name}\n"));
+    }
+
+    content.push('\n');
+
+    let mut data = content.into_bytes();
+
+    data.extend(write_pack(&walker.objects_between(&excludes, &includes)?)?);
+
+    write_to_file(path, &data)
+}
+
+/// A bundle file serving fetches as if it were a repository: it advertises its refs the
+/// way upload-pack does in protocol version 0, and answers any request with its pack.
+pub struct BundleTransport {
+    bundle: Bundle,
+}
+
+impl BundleTransport {
+    pub fn new(path: &str) -> anyhow::Result<Self> {
+        Ok(BundleTransport {
+            bundle: Bundle::read(path)?,
+        })
+    }
+}
+
+impl Transport for BundleTransport {
+    fn advertisement(&mut self, service: &str) -> anyhow::Result<Vec<u8>> {
+        ensure!(service == UPLOAD_PACK, "unsupported service '{service}'");
+
+        let capabilities = match self.bundle.head_branch()? {
+            Some(branch) => format!("\0symref={HEAD}:{branch}"),
+
+            None => String::new(),
+        };
+
+        let mut response = PacketWriter::new();
+
+        for (index, (hash, name)) in self.bundle.refs.iter().enumerate() {
+            match index {
+                0 => response.line(&format!("{hash} {name}{capabilities}")),
+
+                _ => response.line(&format!("{hash} {name}")),
+            };
+        }
+
+        response.flush();
+
+        Ok(response.into_bytes())
+    }
+
+    fn request(&mut self, service: &str, _body: &[u8]) -> anyhow::Result<Vec<u8>> {
+        ensure!(service == UPLOAD_PACK, "unsupported service '{service}'");
+
+        ensure!(
+            self.bundle.has_prerequisites(),
+            "remote transport reported error"
+        );
+
+        let mut response = PacketWriter::new();
+
+        response.line("NAK");
+
+        let mut response = response.into_bytes();
+
+        response.extend(&self.bundle.pack);
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,110 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use anyhow::ensure;
+use sha1::{Digest, Sha1};
+
+use crate::utils::be_u64;
+
+/// Start and end of each chunk of a chunked file (a commit-graph or a multi-pack-index),
+/// from the table of contents of `count` entries at `start`. The entry after the last
+/// one marks where the last chunk ends, which must be before the trailing checksum.
+pub fn read_chunks(
+    data: &[u8],
+    start: usize,
+    count: usize,
+) -> anyhow::Result<HashMap<[u8; 4], (usize, usize)>> {
+    let end = data.len().saturating_sub(20);
+
+    ensure!(
+        start + (count + 1) * 12 <= end,
+        "chunk table of {count} chunks does not fit in the file"
+    );
+
+    let mut chunks = HashMap::new();
+
+    for index in 0..count {
+        let entry = start + index * 12;
+
+        let offset = be_u64(&data[entry + 4..]) as usize;
+
+        let next = be_u64(&data[entry + 16..]) as usize;
+
+        ensure!(
+            offset <= next && next <= end,
+            "improper chunk offset(s) {offset:x} and {next:x}"
+        );
+
+        chunks.insert(data[entry..entry + 4].try_into()?, (offset, next));
+    }
+
+    Ok(chunks)
+}
+
+/// A chunked file: `header`, the table of contents, the chunks in order and a checksum
+/// of all that.
+pub fn write_chunks(header: &[u8], chunks: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut data = header.to_vec();
+
+    let mut offset = header.len() + (chunks.len() + 1) * 12;
+
+    for (id, content) in chunks {
+        data.extend(id);
+
+        data.extend((offset as u64).to_be_bytes());
+
+        offset += content.len();
+    }
+
+    data.extend([0; 4]);
+
+    data.extend((offset as u64).to_be_bytes());
+
+    for (_, content) in chunks {
+        data.extend(content);
+    }
+
+    let checksum = Sha1::digest(&data);
+
+    data.extend(checksum);
+
+    data
+}
+
+/// Position of `id` in a sorted table of object ids, using the table of 256 counts of
+/// ids up to each first byte that comes with it.
+pub fn find_id(fanout: &[u8], ids: &[u8], id: &[u8]) -> Option<usize> {
+    let first = *id.first()? as usize;
+
+    let count = |byte: usize| {
+        u32::from_be_bytes(
+            fanout[byte * 4..byte * 4 + 4]
+                .try_into()
+                .unwrap_or_default(),
+        ) as usize
+    };
+
+    let start = match first {
+        0 => 0,
+
+        _ => count(first - 1),
+    };
+
+    let end = count(first).min(ids.len() / 20);
+
+    let mut range = start.min(end)..end;
+
+    while !range.is_empty() {
+        let middle = (range.start + range.end) / 2;
+
+        match ids[middle * 20..middle * 20 + 20].cmp(id) {
+            Ordering::Less => range.start = middle + 1,
+
+            Ordering::Greater => range.end = middle,
+
+            Ordering::Equal => return Some(middle),
+        }
+    }
+
+    None
+}
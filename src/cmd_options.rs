@@ -1,8 +1,13 @@
 use clap::{Parser, Subcommand};
 use std::fmt::Display;
 
+use crate::diff::DEFAULT_RENAME_LIMIT;
+
+/// Options that take their value attached, the way git spells them (`-M50%`).
+const ATTACHED_VALUE_OPTIONS: [&str; 2] = ["-M", "-C"];
+
 #[derive(Parser, Debug, Clone)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, disable_help_subcommand = true)]
 pub struct CmdOptions {
     #[command(subcommand)]
     pub command: Commands,
@@ -47,13 +52,732 @@ pub enum Commands {
         message: String,
 
         #[arg(short = 'p', long = "parent")]
-        parent: Option<String>,
+        parents: Vec<String>,
 
         tree: String,
     },
 
     WriteTree,
 
+    DiffTree {
+        #[arg(short = 'r')]
+        recursive: bool,
+
+        #[arg(long = "name-only")]
+        name_only: bool,
+
+        #[arg(long = "name-status")]
+        name_status: bool,
+
+        #[arg(short = 'M', long = "find-renames", num_args = 0..=1, require_equals = true, default_missing_value = "50%")]
+        find_renames: Option<String>,
+
+        #[arg(short = 'C', long = "find-copies", num_args = 0..=1, require_equals = true, default_missing_value = "50%")]
+        find_copies: Option<String>,
+
+        #[arg(long = "find-copies-harder")]
+        find_copies_harder: bool,
+
+        #[arg(short = 'l', default_value_t = DEFAULT_RENAME_LIMIT)]
+        rename_limit: usize,
+
+        tree_ish: String,
+
+        other_tree_ish: Option<String>,
+    },
+
+    MergeTree {
+        #[arg(long = "write-tree")]
+        write_tree: bool,
+
+        #[arg(long = "name-only")]
+        name_only: bool,
+
+        #[arg(long = "no-messages")]
+        no_messages: bool,
+
+        branch1: String,
+
+        branch2: String,
+    },
+
+    Merge {
+        #[arg(short = 'm', long = "message")]
+        message: Option<String>,
+
+        #[arg(long = "no-ff")]
+        no_ff: bool,
+
+        #[arg(long = "ff-only")]
+        ff_only: bool,
+
+        /// Commit the merge once its conflicts are resolved
+        #[arg(long = "continue", conflicts_with_all = ["abort", "commits"])]
+        resume: bool,
+
+        /// Drop a conflicted merge, going back to `HEAD`
+        #[arg(long = "abort", conflicts_with = "commits")]
+        abort: bool,
+
+        #[arg(required_unless_present_any = ["resume", "abort"])]
+        commits: Vec<String>,
+    },
+
+    MergeBase {
+        #[arg(short = 'a', long = "all")]
+        all: bool,
+
+        #[arg(long = "octopus")]
+        octopus: bool,
+
+        #[arg(long = "is-ancestor", conflicts_with_all = ["octopus", "fork_point"])]
+        is_ancestor: bool,
+
+        #[arg(long = "fork-point", conflicts_with = "octopus")]
+        fork_point: bool,
+
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+
+    Switch {
+        #[arg(short = 'c', long = "create")]
+        create: Option<String>,
+
+        #[arg(short = 'd', long = "detach")]
+        detach: bool,
+
+        #[arg(short = 'f', long = "force", alias = "discard-changes")]
+        force: bool,
+
+        target: Option<String>,
+    },
+
+    Checkout {
+        #[arg(short = 'b')]
+        new_branch: Option<String>,
+
+        #[arg(long = "detach")]
+        detach: bool,
+
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+
+        target: Option<String>,
+
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+
+    Restore {
+        #[arg(short = 's', long = "source")]
+        source: Option<String>,
+
+        #[arg(short = 'S', long = "staged")]
+        staged: bool,
+
+        #[arg(short = 'W', long = "worktree")]
+        worktree: bool,
+
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    Branch {
+        #[arg(short = 'l', long = "list")]
+        list: bool,
+
+        #[arg(short = 'd', long = "delete")]
+        delete: bool,
+
+        #[arg(short = 'D')]
+        force_delete: bool,
+
+        #[arg(short = 'm', long = "move")]
+        rename: bool,
+
+        #[arg(short = 'c', long = "copy")]
+        copy: bool,
+
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+
+        #[arg(short = 'u', long = "set-upstream-to")]
+        set_upstream_to: Option<String>,
+
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        verbose: u8,
+
+        #[arg(short = 'a', long = "all")]
+        all: bool,
+
+        #[arg(short = 'r', long = "remotes")]
+        remotes: bool,
+
+        #[arg(long = "contains", num_args = 0..=1, default_missing_value = "HEAD")]
+        contains: Option<String>,
+
+        #[arg(long = "merged", num_args = 0..=1, default_missing_value = "HEAD")]
+        merged: Option<String>,
+
+        #[arg(long = "no-merged", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_merged: Option<String>,
+
+        args: Vec<String>,
+    },
+
+    CheckRefFormat {
+        #[arg(long = "branch")]
+        branch: bool,
+
+        #[arg(long = "allow-onelevel")]
+        allow_onelevel: bool,
+
+        refname: String,
+    },
+
+    Tag {
+        #[arg(short = 'a', long = "annotate")]
+        annotate: bool,
+
+        #[arg(short = 'm', long = "message")]
+        message: Option<String>,
+
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+
+        #[arg(short = 'd', long = "delete")]
+        delete: bool,
+
+        #[arg(short = 'l', long = "list")]
+        list: bool,
+
+        #[arg(short = 'n', num_args = 0..=1, default_missing_value = "1", require_equals = false)]
+        lines: Option<usize>,
+
+        #[arg(long = "sort")]
+        sort: Option<String>,
+
+        #[arg(long = "points-at", num_args = 0..=1, default_missing_value = "HEAD")]
+        points_at: Option<String>,
+
+        args: Vec<String>,
+    },
+
+    Reflog {
+        #[arg(long = "expire")]
+        expire: Option<String>,
+
+        #[arg(long = "expire-unreachable")]
+        expire_unreachable: Option<String>,
+
+        #[arg(long = "all")]
+        all: bool,
+
+        /// `show`, `expire` or `delete`, followed by refs (or `<ref>@{<n>}` entries to delete)
+        args: Vec<String>,
+    },
+
+    Reset {
+        #[arg(long = "soft", conflicts_with_all = ["mixed", "hard"])]
+        soft: bool,
+
+        #[arg(long = "mixed", conflicts_with = "hard")]
+        mixed: bool,
+
+        #[arg(long = "hard")]
+        hard: bool,
+
+        args: Vec<String>,
+
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+
+    Stash {
+        #[arg(short = 'm', long = "message")]
+        message: Option<String>,
+
+        #[arg(short = 'u', long = "include-untracked")]
+        include_untracked: bool,
+
+        #[arg(long = "index")]
+        index: bool,
+
+        /// `push` (the default), `list`, `apply`, `pop` or `drop`, and a stash entry
+        args: Vec<String>,
+    },
+
+    CherryPick {
+        /// Append a "(cherry picked from commit ...)" line to the message
+        #[arg(short = 'x')]
+        record_origin: bool,
+
+        #[arg(short = 'n', long = "no-commit")]
+        no_commit: bool,
+
+        #[arg(short = 'm', long = "mainline")]
+        mainline: Option<usize>,
+
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort"])]
+        resume: bool,
+
+        #[arg(long = "skip", conflicts_with = "abort")]
+        skip: bool,
+
+        #[arg(long = "abort")]
+        abort: bool,
+
+        commits: Vec<String>,
+    },
+
+    Revert {
+        #[arg(short = 'n', long = "no-commit")]
+        no_commit: bool,
+
+        #[arg(short = 'm', long = "mainline")]
+        mainline: Option<usize>,
+
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort"])]
+        resume: bool,
+
+        #[arg(long = "skip", conflicts_with = "abort")]
+        skip: bool,
+
+        #[arg(long = "abort")]
+        abort: bool,
+
+        commits: Vec<String>,
+    },
+
+    Rebase {
+        #[arg(short = 'i', long = "interactive")]
+        interactive: bool,
+
+        #[arg(long = "onto")]
+        onto: Option<String>,
+
+        #[arg(long = "autosquash")]
+        autosquash: bool,
+
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort"])]
+        resume: bool,
+
+        #[arg(long = "skip", conflicts_with = "abort")]
+        skip: bool,
+
+        #[arg(long = "abort")]
+        abort: bool,
+
+        /// `<upstream>`, then the branch to rebase (the current one by default)
+        args: Vec<String>,
+    },
+
+    Clone {
+        /// Copy objects of a local repository by hardlinking them (the default for paths)
+        #[arg(short = 'l', long = "local", conflicts_with = "no_local")]
+        local: bool,
+
+        /// Go through the transport even for a local repository
+        #[arg(long = "no-local")]
+        no_local: bool,
+
+        /// Borrow the source repository's objects through alternates instead of copying
+        #[arg(short = 's', long = "shared")]
+        shared: bool,
+
+        /// Fetch only this many commits of history
+        #[arg(long = "depth")]
+        depth: Option<usize>,
+
+        /// Fetch only history newer than this date
+        #[arg(long = "shallow-since")]
+        shallow_since: Option<String>,
+
+        /// Leave out history reachable from this remote ref
+        #[arg(long = "shallow-exclude")]
+        shallow_exclude: Vec<String>,
+
+        /// Fetch only the remote's default branch (implied by the shallow options)
+        #[arg(long = "single-branch", conflicts_with = "no_single_branch")]
+        single_branch: bool,
+
+        #[arg(long = "no-single-branch")]
+        no_single_branch: bool,
+
+        /// Leave out objects, such as `blob:none`, to fetch them when they are needed
+        #[arg(long = "filter")]
+        filter: Option<String>,
+
+        url: String,
+
+        directory: Option<String>,
+    },
+
+    Fetch {
+        /// Limit history to this many commits from the tips fetched
+        #[arg(long = "depth", conflicts_with_all = ["deepen", "unshallow"])]
+        depth: Option<usize>,
+
+        /// Extend shallow history by this many commits
+        #[arg(long = "deepen", conflicts_with = "unshallow")]
+        deepen: Option<usize>,
+
+        #[arg(long = "shallow-since")]
+        shallow_since: Option<String>,
+
+        #[arg(long = "shallow-exclude")]
+        shallow_exclude: Vec<String>,
+
+        /// Fetch all the history a shallow repository is missing
+        #[arg(long = "unshallow")]
+        unshallow: bool,
+
+        /// Leave out objects, making the remote a promisor remote
+        #[arg(long = "filter")]
+        filter: Option<String>,
+
+        /// Remote name or URL (`origin` by default)
+        remote: Option<String>,
+
+        refspecs: Vec<String>,
+    },
+
+    Remote {
+        /// Show URLs after the names
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+
+        /// `add`: fetch from the new remote right away
+        #[arg(short = 'f')]
+        fetch: bool,
+
+        /// `add`: track only these branches
+        #[arg(short = 't', long = "track")]
+        track: Vec<String>,
+
+        /// `add`: branch the remote's `HEAD` points at
+        #[arg(short = 'm', long = "master")]
+        master: Option<String>,
+
+        /// `add`: fetch every tag
+        #[arg(long = "tags", conflicts_with = "no_tags")]
+        tags: bool,
+
+        /// `add`: follow no tags
+        #[arg(long = "no-tags")]
+        no_tags: bool,
+
+        /// `set-url`: change the push URLs
+        #[arg(long = "push")]
+        push: bool,
+
+        /// `set-url`: add a URL instead of changing one
+        #[arg(long = "add", conflicts_with = "delete")]
+        add: bool,
+
+        /// `set-url`: remove the URLs matching the given one
+        #[arg(long = "delete")]
+        delete: bool,
+
+        /// `show`: do not ask the remote; `prune`: only report what would go
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+
+        /// `add`, `rename`, `remove`, `set-url`, `show` or `prune`, and its arguments
+        args: Vec<String>,
+    },
+
+    /// Move refs and the objects they need through a file
+    Bundle {
+        /// `create`, `verify`, `list-heads` or `unbundle`, its options (`--version=<n>`
+        /// for `create`, `-q` for `verify`), then the bundle file and the revisions (which
+        /// may include `--all`, `--branches` and `--tags`) or ref names
+        #[arg(allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    Push {
+        /// Update remote refs even when that drops commits from them
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+
+        /// Force only refs that are still where they were expected: `[<ref>[:<expect>]]`
+        #[arg(
+            long = "force-with-lease",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ""
+        )]
+        force_with_lease: Vec<String>,
+
+        /// Delete the named refs from the remote
+        #[arg(short = 'd', long = "delete")]
+        delete: bool,
+
+        /// Update either every ref on the remote or none of them
+        #[arg(long = "atomic")]
+        atomic: bool,
+
+        /// Strings handed to the remote's hooks
+        #[arg(short = 'o', long = "push-option")]
+        push_options: Vec<String>,
+
+        /// Make the pushed branches track the refs they were pushed to
+        #[arg(short = 'u', long = "set-upstream")]
+        set_upstream: bool,
+
+        /// Remote name or URL (the upstream's remote, or `origin`, by default)
+        remote: Option<String>,
+
+        refspecs: Vec<String>,
+    },
+
+    /// Pack the repository and remove what nothing refers to
+    Gc {
+        /// Only do something when `gc.auto` or `gc.autoPackLimit` says there is work
+        #[arg(long = "auto")]
+        auto: bool,
+
+        /// Prune loose objects older than this date (`gc.pruneExpire`, or two weeks ago)
+        #[arg(long = "prune", require_equals = true, conflicts_with = "no_prune")]
+        prune: Option<String>,
+
+        #[arg(long = "no-prune")]
+        no_prune: bool,
+
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+    },
+
+    /// Pack loose objects, or every reachable object into a single pack
+    Repack {
+        /// Pack every reachable object, not only loose ones
+        #[arg(short = 'a')]
+        all: bool,
+
+        /// Like `-a`, keeping unreachable objects of removed packs as loose objects
+        #[arg(short = 'A')]
+        all_keep_unreachable: bool,
+
+        /// Remove packs and loose objects made redundant
+        #[arg(short = 'd')]
+        delete: bool,
+
+        /// With `-a`, write a reachability bitmap for the new pack
+        #[arg(
+            short = 'b',
+            long = "write-bitmap-index",
+            conflicts_with = "no_write_bitmap_index"
+        )]
+        write_bitmap_index: bool,
+
+        #[arg(long = "no-write-bitmap-index")]
+        no_write_bitmap_index: bool,
+
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+    },
+
+    /// Remove unreachable loose objects
+    Prune {
+        /// Only report what would be removed
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+
+        /// Report removed objects
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+
+        /// Only remove objects older than this date
+        #[arg(long = "expire")]
+        expire: Option<String>,
+    },
+
+    /// Move loose refs into `packed-refs`
+    PackRefs {
+        /// Pack every ref, not only tags
+        #[arg(long = "all")]
+        all: bool,
+
+        /// Keep the loose files of the packed refs
+        #[arg(long = "no-prune")]
+        no_prune: bool,
+    },
+
+    /// Count loose objects and the disk space they take
+    CountObjects {
+        /// Also report packs, objects that could be pruned and garbage
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+
+        /// Show sizes in human-readable units
+        #[arg(short = 'H', long = "human-readable")]
+        human_readable: bool,
+    },
+
+    /// Report what takes up room in the repository: the largest blobs, the deepest
+    /// directories, the longest delta chains and the paths that grew the most
+    Stats {
+        /// How many entries each list shows
+        #[arg(short = 'n', long = "limit", default_value_t = 10)]
+        limit: usize,
+    },
+
+    /// Verify the objects of the repository and that everything reachable is there
+    Fsck {
+        /// Report warnings as errors
+        #[arg(long = "strict")]
+        strict: bool,
+
+        /// Write dangling objects to `.git/lost-found`
+        #[arg(long = "lost-found")]
+        lost_found: bool,
+
+        /// Only check connectivity, not the content of objects
+        #[arg(long = "connectivity-only")]
+        connectivity_only: bool,
+
+        /// Report every unreachable object, not only dangling ones
+        #[arg(long = "unreachable")]
+        unreachable: bool,
+
+        #[arg(long = "no-dangling")]
+        no_dangling: bool,
+    },
+
+    /// List commits reachable from some revisions but not from others (`^A`, `A..B`)
+    RevList {
+        /// Also list the tags, trees and blobs the commits bring
+        #[arg(long = "objects")]
+        objects: bool,
+
+        /// Print how many there are instead of listing them
+        #[arg(long = "count")]
+        count: bool,
+
+        /// Start from every ref and `HEAD`
+        #[arg(long = "all")]
+        all: bool,
+
+        /// Answer from the reachability bitmaps when they cover the revisions
+        #[arg(long = "use-bitmap-index")]
+        use_bitmap_index: bool,
+
+        revisions: Vec<String>,
+    },
+
+    /// Write or verify the commit-graph file that speeds up history walks
+    CommitGraph {
+        /// `write`: start from the commits the refs point at rather than those in packs
+        #[arg(long = "reachable", conflicts_with = "stdin_commits")]
+        reachable: bool,
+
+        /// `write`: start from the commits listed on standard input
+        #[arg(long = "stdin-commits")]
+        stdin_commits: bool,
+
+        /// `write`: keep the commits of the existing graph
+        #[arg(long = "append")]
+        append: bool,
+
+        /// `write`: add a layer to the chain of graph files, merging it with smaller ones
+        /// below unless `no-merge`, or make it the only one with `replace`
+        #[arg(
+            long = "split",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ""
+        )]
+        split: Option<String>,
+
+        /// `write`: merge a layer below when it holds at most this many times as many
+        /// commits as the new one
+        #[arg(long = "size-multiple", default_value_t = 2)]
+        size_multiple: usize,
+
+        /// `write`: merge layers below while the new one holds more commits than this
+        #[arg(long = "max-commits")]
+        max_commits: Option<usize>,
+
+        /// `write`: store changed-path Bloom filters
+        #[arg(long = "changed-paths", conflicts_with = "no_changed_paths")]
+        changed_paths: bool,
+
+        /// `write`: drop the changed-path Bloom filters of the existing graph
+        #[arg(long = "no-changed-paths")]
+        no_changed_paths: bool,
+
+        /// `verify`: only check the top layer of a chain
+        #[arg(long = "shallow")]
+        shallow: bool,
+
+        /// `write` or `verify`
+        command: String,
+    },
+
+    /// Write, verify or tidy up the multi-pack-index over the packs
+    MultiPackIndex {
+        /// `write`: read objects found in several packs from this one
+        #[arg(long = "preferred-pack")]
+        preferred_pack: Option<String>,
+
+        /// `repack`: gather packs whose objects in use come to less than this many
+        /// bytes, with an optional k, m or g suffix, 0 meaning every pack
+        #[arg(long = "batch-size", default_value = "0")]
+        batch_size: String,
+
+        /// `write`, `verify`, `expire` or `repack`
+        command: String,
+    },
+
+    /// Serve fetches from a repository over stdin and stdout
+    UploadPack {
+        /// Answer a single request without the advertisement, as behind an HTTP server
+        #[arg(long = "stateless-rpc")]
+        stateless_rpc: bool,
+
+        /// Only send the ref advertisement
+        #[arg(long = "advertise-refs", alias = "http-backend-info-refs")]
+        advertise_refs: bool,
+
+        directory: String,
+    },
+
+    /// Serve pushes to a repository over stdin and stdout
+    ReceivePack {
+        /// Answer a single request without the advertisement, as behind an HTTP server
+        #[arg(long = "stateless-rpc")]
+        stateless_rpc: bool,
+
+        /// Only send the ref advertisement
+        #[arg(long = "advertise-refs", alias = "http-backend-info-refs")]
+        advertise_refs: bool,
+
+        directory: String,
+    },
+
+    /// Serve the repositories under a directory over smart HTTP
+    Serve {
+        /// Address to listen on
+        #[arg(long = "listen", default_value = "127.0.0.1")]
+        listen: String,
+
+        /// Port to listen on, or 0 for any free one
+        #[arg(long = "port", default_value_t = 8080)]
+        port: u16,
+
+        /// Accept pushes to repositories that do not set `http.receivepack`
+        #[arg(long = "enable-receive-pack")]
+        enable_receive_pack: bool,
+
+        #[arg(default_value = ".")]
+        directory: String,
+    },
+
+    /// Answer a smart HTTP request as a CGI program
+    HttpBackend,
+
     Init,
 
     Help,
@@ -69,8 +793,59 @@ impl Display for Commands {
             Commands::CatFile { .. } => "cat-file",
             Commands::HashObject { .. } => "hash-object",
             Commands::CommitTree { .. } => "commit-tree",
+            Commands::DiffTree { .. } => "diff-tree",
+            Commands::MergeTree { .. } => "merge-tree",
+            Commands::Merge { .. } => "merge",
+            Commands::MergeBase { .. } => "merge-base",
+            Commands::Switch { .. } => "switch",
+            Commands::Checkout { .. } => "checkout",
+            Commands::Restore { .. } => "restore",
+            Commands::Branch { .. } => "branch",
+            Commands::CheckRefFormat { .. } => "check-ref-format",
+            Commands::Tag { .. } => "tag",
+            Commands::Reflog { .. } => "reflog",
+            Commands::Reset { .. } => "reset",
+            Commands::Stash { .. } => "stash",
+            Commands::CherryPick { .. } => "cherry-pick",
+            Commands::Revert { .. } => "revert",
+            Commands::Rebase { .. } => "rebase",
+            Commands::Clone { .. } => "clone",
+            Commands::Fetch { .. } => "fetch",
+            Commands::Remote { .. } => "remote",
+            Commands::Bundle { .. } => "bundle",
+            Commands::Push { .. } => "push",
+            Commands::Gc { .. } => "gc",
+            Commands::Repack { .. } => "repack",
+            Commands::Prune { .. } => "prune",
+            Commands::PackRefs { .. } => "pack-refs",
+            Commands::CountObjects { .. } => "count-objects",
+            Commands::Stats { .. } => "stats",
+            Commands::Fsck { .. } => "fsck",
+            Commands::RevList { .. } => "rev-list",
+            Commands::CommitGraph { .. } => "commit-graph",
+            Commands::MultiPackIndex { .. } => "multi-pack-index",
+            Commands::UploadPack { .. } => "upload-pack",
+            Commands::ReceivePack { .. } => "receive-pack",
+            Commands::Serve { .. } => "serve",
+            Commands::HttpBackend => "http-backend",
         };
 
         write!(f, "{command_name}")
     }
 }
+
+/// Rewrites git's attached option values (`-M50%`) into the `-M=50%` form clap expects,
+/// so a bare `-M` does not swallow the positional argument that follows it.
+pub fn normalize_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    args.map(|arg| {
+        match ATTACHED_VALUE_OPTIONS
+            .iter()
+            .find(|option| arg.len() > 2 && arg.starts_with(*option))
+        {
+            Some(option) if !arg[2..].starts_with('=') => format!("{option}={}", &arg[2..]),
+
+            _ => arg,
+        }
+    })
+    .collect()
+}
@@ -0,0 +1,1218 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, ensure};
+use sha1::{Digest, Sha1};
+
+use crate::chunk_file::{find_id, read_chunks, write_chunks};
+use crate::config::Config;
+use crate::diff::{diff_trees, RenameOptions};
+use crate::git_objects::GitObject;
+use crate::pack::packs;
+use crate::refs::{git_path, list_refs, shallow_commits};
+use crate::revision::{CommitInfo, CommitWalker};
+use crate::utils::{
+    be_u32, be_u64, create_directory, from_hex, object_exists, to_hex_string, write_to_file,
+};
+
+const SIGNATURE: &[u8] = b"CGPH";
+
+const CHUNK_OID_FANOUT: [u8; 4] = *b"OIDF";
+const CHUNK_OID_LOOKUP: [u8; 4] = *b"OIDL";
+const CHUNK_COMMIT_DATA: [u8; 4] = *b"CDAT";
+const CHUNK_GENERATION_DATA: [u8; 4] = *b"GDA2";
+const CHUNK_GENERATION_OVERFLOW: [u8; 4] = *b"GDO2";
+const CHUNK_EXTRA_EDGES: [u8; 4] = *b"EDGE";
+const CHUNK_BLOOM_INDEXES: [u8; 4] = *b"BIDX";
+const CHUNK_BLOOM_DATA: [u8; 4] = *b"BDAT";
+const CHUNK_BASE_GRAPHS: [u8; 4] = *b"BASE";
+
+/// Parent position of a commit with fewer parents.
+const PARENT_NONE: u32 = 0x7000_0000;
+
+/// Set on the second parent of an octopus merge, the rest being the index of its
+/// other parents in the extra edges, and on the last of those edges.
+const EDGE_MASK: u32 = 0x8000_0000;
+
+const LEVEL_MAX: u32 = 0x3fff_ffff;
+
+/// Corrected date offsets above this go to the overflow chunk, the high bit of the
+/// stored value then giving their index there.
+const OFFSET_MAX: u64 = 0x7fff_ffff;
+
+const OFFSET_OVERFLOW: u32 = 0x8000_0000;
+
+/// Generation of the commits a graph does not know, above every other.
+pub const GENERATION_INFINITY: u64 = u64::MAX;
+
+/// Settings of the changed-path Bloom filters, the only ones git writes.
+const BLOOM_HASH_VERSION: u32 = 1;
+const BLOOM_HASHES: u32 = 7;
+const BLOOM_BITS_PER_ENTRY: usize = 10;
+
+/// Commits changing more paths than this get a filter that matches everything.
+const BLOOM_MAX_CHANGES: usize = 512;
+
+const BLOOM_SEEDS: [u32; 2] = [0x293a_e76f, 0x7e64_6e2c];
+
+/// The loaded commit-graph, `None` until it is looked for.
+static GRAPH: Mutex<Option<Option<Arc<CommitGraph>>>> = Mutex::new(None);
+
+/// What the commit data chunk holds about a commit, parents as positions.
+struct Entry {
+    tree: String,
+
+    parents: Vec<u32>,
+
+    level: u32,
+
+    date: i64,
+
+    /// Present when the layer has generation data.
+    corrected: Option<u64>,
+}
+
+/// One commit-graph file: the whole graph, or a layer of a chain.
+#[derive(Debug)]
+struct Layer {
+    path: String,
+
+    data: Vec<u8>,
+
+    /// Start and end of each chunk by id.
+    chunks: HashMap<[u8; 4], (usize, usize)>,
+
+    count: usize,
+}
+
+impl Layer {
+    fn read(path: &str) -> anyhow::Result<Self> {
+        let data = fs::read(path)?;
+
+        ensure!(data.len() >= 8 + 12 + 20, "commit-graph file is too small");
+
+        ensure!(
+            &data[..4] == SIGNATURE,
+            "commit-graph signature {:X} does not match signature {:X}",
+            be_u32(&data[..4]),
+            be_u32(SIGNATURE)
+        );
+
+        ensure!(
+            data[4] == 1,
+            "commit-graph version {:X} does not match version 1",
+            data[4]
+        );
+
+        ensure!(
+            data[5] == 1,
+            "commit-graph hash version {:X} does not match version 1",
+            data[5]
+        );
+
+        let chunks = read_chunks(&data, 8, data[6] as usize)?;
+
+        let mut layer = Layer {
+            path: path.to_string(),
+            data,
+            chunks,
+            count: 0,
+        };
+
+        let Some(fanout) = layer.chunk(CHUNK_OID_FANOUT).filter(|c| c.len() == 1024) else {
+            bail!("commit-graph required OID fanout chunk missing or corrupted");
+        };
+
+        let count = be_u32(&fanout[1020..]) as usize;
+
+        ensure!(
+            layer
+                .chunk(CHUNK_OID_LOOKUP)
+                .is_some_and(|chunk| chunk.len() == count * 20),
+            "commit-graph required OID lookup chunk missing or corrupted"
+        );
+
+        ensure!(
+            layer
+                .chunk(CHUNK_COMMIT_DATA)
+                .is_some_and(|chunk| chunk.len() == count * 36),
+            "commit-graph required commit data chunk missing or corrupted"
+        );
+
+        layer.count = count;
+
+        Ok(layer)
+    }
+
+    fn chunk(&self, id: [u8; 4]) -> Option<&[u8]> {
+        self.chunks
+            .get(&id)
+            .map(|&(start, end)| &self.data[start..end])
+    }
+
+    /// The file's trailing checksum, which names the layers of a chain.
+    fn checksum(&self) -> String {
+        to_hex_string(&self.data[self.data.len() - 20..])
+    }
+
+    fn id(&self, index: usize) -> &[u8] {
+        let (start, _) = self.chunks[&CHUNK_OID_LOOKUP];
+
+        &self.data[start + index * 20..start + index * 20 + 20]
+    }
+
+    fn find(&self, id: &[u8]) -> Option<usize> {
+        find_id(
+            self.chunk(CHUNK_OID_FANOUT)?,
+            self.chunk(CHUNK_OID_LOOKUP)?,
+            id,
+        )
+    }
+
+    fn entry(&self, index: usize) -> anyhow::Result<Entry> {
+        let (start, _) = self.chunks[&CHUNK_COMMIT_DATA];
+
+        let record = &self.data[start + index * 36..start + index * 36 + 36];
+
+        let mut parents = Vec::new();
+
+        let (first, second) = (be_u32(&record[20..]), be_u32(&record[24..]));
+
+        if first != PARENT_NONE {
+            parents.push(first);
+        }
+
+        if second & EDGE_MASK != 0 {
+            let Some(edges) = self.chunk(CHUNK_EXTRA_EDGES) else {
+                bail!("commit-graph has no extra edges for an octopus merge");
+            };
+
+            let mut edge = (second & !EDGE_MASK) as usize;
+
+            loop {
+                ensure!(
+                    edge * 4 + 4 <= edges.len(),
+                    "commit-graph extra edge {edge} is out of range"
+                );
+
+                let parent = be_u32(&edges[edge * 4..]);
+
+                parents.push(parent & !EDGE_MASK);
+
+                if parent & EDGE_MASK != 0 {
+                    break;
+                }
+
+                edge += 1;
+            }
+        } else if second != PARENT_NONE {
+            parents.push(second);
+        }
+
+        let high = be_u32(&record[28..]);
+
+        let date = ((high as i64 & 3) << 32) | be_u32(&record[32..]) as i64;
+
+        let corrected = match self.chunk(CHUNK_GENERATION_DATA) {
+            Some(offsets) if offsets.len() == self.count * 4 => {
+                let offset = be_u32(&offsets[index * 4..]);
+
+                let offset = match offset & OFFSET_OVERFLOW {
+                    0 => offset as u64,
+
+                    _ => {
+                        let overflow = (offset & !OFFSET_OVERFLOW) as usize * 8;
+
+                        match self.chunk(CHUNK_GENERATION_OVERFLOW) {
+                            Some(overflows) if overflow + 8 <= overflows.len() => {
+                                be_u64(&overflows[overflow..])
+                            }
+
+                            _ => bail!("commit-graph overflow generation data is too small"),
+                        }
+                    }
+                };
+
+                Some(date as u64 + offset)
+            }
+
+            _ => None,
+        };
+
+        Ok(Entry {
+            tree: to_hex_string(&record[..20]),
+            parents,
+            level: high >> 2,
+            date,
+            corrected,
+        })
+    }
+
+    /// The changed-path Bloom filter of the commit at `index`, if the layer has them.
+    fn bloom_filter(&self, index: usize) -> Option<&[u8]> {
+        let (indexes, data) = (
+            self.chunk(CHUNK_BLOOM_INDEXES)?,
+            self.chunk(CHUNK_BLOOM_DATA)?,
+        );
+
+        if indexes.len() != self.count * 4
+            || data.len() < 12
+            || (be_u32(data), be_u32(&data[4..]), be_u32(&data[8..]))
+                != (
+                    BLOOM_HASH_VERSION,
+                    BLOOM_HASHES,
+                    BLOOM_BITS_PER_ENTRY as u32,
+                )
+        {
+            return None;
+        }
+
+        let start = match index {
+            0 => 0,
+
+            _ => be_u32(&indexes[(index - 1) * 4..]) as usize,
+        };
+
+        let end = be_u32(&indexes[index * 4..]) as usize;
+
+        data.get(12 + start..12 + end)
+    }
+}
+
+/// The commit-graph of the repository: a single file, or a chain of layers each
+/// adding commits to those below.
+#[derive(Debug)]
+pub struct CommitGraph {
+    /// The base layer first. Positions count on from one layer to the next.
+    layers: Vec<Layer>,
+}
+
+impl CommitGraph {
+    /// Reads `objects/info/commit-graph`, or else the chain of layers in
+    /// `objects/info/commit-graphs`.
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        let single = single_path();
+
+        if Path::new(&single).exists() {
+            return Ok(Some(CommitGraph {
+                layers: vec![Layer::read(&single)?],
+            }));
+        }
+
+        let Ok(chain) = fs::read_to_string(chain_path()) else {
+            return Ok(None);
+        };
+
+        let mut layers: Vec<Layer> = Vec::new();
+
+        for checksum in chain.lines() {
+            let layer = Layer::read(&layer_path(checksum))?;
+
+            let bases: Vec<String> = layers.iter().map(Layer::checksum).collect();
+
+            let listed: Vec<String> = layer
+                .chunk(CHUNK_BASE_GRAPHS)
+                .unwrap_or_default()
+                .chunks(20)
+                .map(to_hex_string)
+                .collect();
+
+            ensure!(
+                layer.data[7] as usize == bases.len() && listed == bases,
+                "commit-graph chain does not match at {checksum}"
+            );
+
+            layers.push(layer);
+        }
+
+        Ok((!layers.is_empty()).then_some(CommitGraph { layers }))
+    }
+
+    /// Position of a commit in the bottom `layers` layers.
+    fn position(&self, hash: &str, layers: usize) -> Option<u32> {
+        let id = from_hex(hash).ok()?;
+
+        let mut base = 0;
+
+        for layer in &self.layers[..layers] {
+            if let Some(index) = layer.find(&id) {
+                return Some((base + index) as u32);
+            }
+
+            base += layer.count;
+        }
+
+        None
+    }
+
+    /// Layer and index within it of a position.
+    fn locate(&self, position: u32) -> anyhow::Result<(&Layer, usize)> {
+        let mut index = position as usize;
+
+        for layer in &self.layers {
+            if index < layer.count {
+                return Ok((layer, index));
+            }
+
+            index -= layer.count;
+        }
+
+        bail!("commit-graph position {position} is out of range")
+    }
+
+    /// Number of commits in the bottom `layers` layers.
+    fn count(&self, layers: usize) -> usize {
+        self.layers[..layers].iter().map(|layer| layer.count).sum()
+    }
+
+    fn hash_at(&self, position: u32) -> anyhow::Result<String> {
+        let (layer, index) = self.locate(position)?;
+
+        Ok(to_hex_string(layer.id(index)))
+    }
+
+    /// Whether the bottom `layers` layers all have corrected commit dates: one layer
+    /// without them leaves the whole chain to topological levels.
+    fn has_generation_data(&self, layers: usize) -> bool {
+        self.layers[..layers]
+            .iter()
+            .all(|layer| layer.chunks.contains_key(&CHUNK_GENERATION_DATA))
+    }
+
+    /// A commit as the graph records it, its generation being the corrected commit date
+    /// when every layer has them and the topological level otherwise.
+    fn read_commit(&self, position: u32) -> anyhow::Result<CommitInfo> {
+        let (layer, index) = self.locate(position)?;
+
+        let entry = layer.entry(index)?;
+
+        let generation = match (self.has_generation_data(self.layers.len()), entry.corrected) {
+            (true, Some(corrected)) => corrected,
+
+            _ => entry.level as u64,
+        };
+
+        Ok(CommitInfo {
+            tree: entry.tree,
+            parents: entry
+                .parents
+                .iter()
+                .map(|&parent| self.hash_at(parent))
+                .collect::<anyhow::Result<_>>()?,
+            date: entry.date,
+            generation,
+        })
+    }
+
+    /// The commit as the graph records it, if it does and its object is still there: a
+    /// graph outliving pruned commits must not bring them back.
+    pub fn commit(&self, hash: &str) -> Option<CommitInfo> {
+        let position = self.position(hash, self.layers.len())?;
+
+        self.read_commit(position)
+            .ok()
+            .filter(|_| object_exists(hash))
+    }
+
+    fn bloom_filter(&self, hash: &str) -> Option<&[u8]> {
+        let (layer, index) = self.locate(self.position(hash, self.layers.len())?).ok()?;
+
+        layer.bloom_filter(index)
+    }
+}
+
+/// The repository's commit-graph, loaded once. `None` when there is none, it cannot be
+/// read or `core.commitGraph` turns it off, commits then being parsed from their objects.
+pub fn commit_graph() -> Option<Arc<CommitGraph>> {
+    let mut cache = GRAPH.lock().ok()?;
+
+    if let Some(graph) = cache.as_ref() {
+        return graph.clone();
+    }
+
+    let enabled = Config::load().map_or(true, |config| {
+        config.get("core.commitGraph") != Some("false")
+    });
+
+    let graph = match enabled {
+        true => CommitGraph::load().ok().flatten().map(Arc::new),
+
+        false => None,
+    };
+
+    *cache = Some(graph.clone());
+
+    graph
+}
+
+/// Forgets the loaded commit-graph so that a rewritten one is noticed.
+pub fn reload_commit_graph() {
+    if let Ok(mut cache) = GRAPH.lock() {
+        *cache = None;
+    }
+}
+
+fn single_path() -> String {
+    git_path("objects/info/commit-graph")
+}
+
+fn layers_directory() -> String {
+    git_path("objects/info/commit-graphs")
+}
+
+fn chain_path() -> String {
+    format!("{}/commit-graph-chain", layers_directory())
+}
+
+fn layer_path(checksum: &str) -> String {
+    format!("{}/graph-{checksum}.graph", layers_directory())
+}
+
+/// Where `commit-graph write` starts looking for commits, their ancestors being added.
+pub enum Source {
+    /// Every commit in a pack.
+    Packs,
+
+    /// The commits the refs point at.
+    Refs,
+
+    Commits(Vec<String>),
+}
+
+/// How `--split` puts the new commits on top of the existing chain.
+#[derive(PartialEq)]
+pub enum Split {
+    /// A new layer, merged with the layers below that are not much bigger than it.
+    Merge,
+
+    /// A new layer, the existing ones left alone.
+    NoMerge,
+
+    /// A single layer with all the commits, in place of the chain.
+    Replace,
+}
+
+pub struct WriteOptions {
+    pub source: Source,
+
+    /// Keep the commits of the existing graph.
+    pub append: bool,
+
+    /// Write a layer of a chain rather than a single file.
+    pub split: Option<Split>,
+
+    /// A layer below is merged into the new one when it holds at most this many times
+    /// as many commits, or when the new one would hold more than `max_commits`.
+    pub size_multiple: usize,
+
+    pub max_commits: Option<usize>,
+
+    /// Whether to store changed-path Bloom filters. By default they are written when
+    /// the existing graph has them.
+    pub changed_paths: Option<bool>,
+}
+
+/// Writes a commit-graph of the commits `options` start from and their ancestors, as
+/// git does: nothing is written for a shallow repository, whose parents are rewritten.
+pub fn write(options: &WriteOptions) -> anyhow::Result<()> {
+    if !shallow_commits().is_empty() {
+        return Ok(());
+    }
+
+    let existing = CommitGraph::load().ok().flatten();
+
+    let existing_layers = existing.as_ref().map_or(0, |graph| graph.layers.len());
+
+    let mut walker = CommitWalker::new();
+
+    let mut starts = Vec::new();
+
+    match &options.source {
+        Source::Packs => {
+            for pack in packs()? {
+                for (id, &offset) in pack.hashes.iter().zip(&pack.offsets) {
+                    if pack.object_type(offset)? == "commit" {
+                        starts.push(to_hex_string(id));
+                    }
+                }
+            }
+        }
+
+        Source::Refs => {
+            for (_, hash) in list_refs("refs/")? {
+                if let Ok(commit) = GitObject::peel(&hash) {
+                    if walker.commit(&commit).is_ok() {
+                        starts.push(commit);
+                    }
+                }
+            }
+        }
+
+        Source::Commits(hashes) => {
+            for hash in hashes {
+                let commit = GitObject::peel(hash).unwrap_or_default();
+
+                ensure!(
+                    walker.commit(&commit).is_ok(),
+                    "invalid commit object id: {hash}"
+                );
+
+                starts.push(commit);
+            }
+        }
+    }
+
+    if let (true, Some(graph)) = (options.append, &existing) {
+        for position in 0..graph.count(existing_layers) {
+            starts.push(graph.hash_at(position as u32)?);
+        }
+    }
+
+    let mut commits = BTreeSet::new();
+
+    while let Some(hash) = starts.pop() {
+        if !commits.contains(&hash) {
+            starts.extend(walker.parents(&hash)?);
+
+            commits.insert(hash);
+        }
+    }
+
+    // Layers of the existing chain the new one goes on top of
+    let mut kept = 0;
+
+    if let (Some(split), Some(graph)) = (&options.split, &existing) {
+        if *split != Split::Replace {
+            commits.retain(|hash| graph.position(hash, existing_layers).is_none());
+
+            kept = existing_layers;
+        }
+
+        if *split == Split::Merge {
+            let mut count = commits.len();
+
+            while kept > 0 {
+                let below = graph.layers[kept - 1].count;
+
+                if below > options.size_multiple * count
+                    && options.max_commits.map_or(true, |max| count <= max)
+                {
+                    break;
+                }
+
+                count += below;
+
+                kept -= 1;
+            }
+
+            for position in graph.count(kept)..graph.count(existing_layers) {
+                commits.insert(graph.hash_at(position as u32)?);
+            }
+        }
+    }
+
+    if commits.is_empty() {
+        return Ok(());
+    }
+
+    let commits: Vec<String> = commits.into_iter().collect();
+
+    let base = existing.as_ref().map_or(0, |graph| graph.count(kept));
+
+    let mut positions = HashMap::new();
+
+    let mut infos = HashMap::new();
+
+    for (index, hash) in commits.iter().enumerate() {
+        positions.insert(hash.clone(), (base + index) as u32);
+
+        infos.insert(hash.clone(), walker.commit(hash)?.clone());
+    }
+
+    let position_of = |hash: &str| -> anyhow::Result<u32> {
+        match positions.get(hash) {
+            Some(&position) => Ok(position),
+
+            None => existing
+                .as_ref()
+                .and_then(|graph| graph.position(hash, kept))
+                .ok_or_else(|| anyhow::anyhow!("commit-graph is missing parent {hash}")),
+        }
+    };
+
+    let corrected_dates = Config::load()?.get("commitGraph.generationVersion") != Some("1")
+        && existing
+            .as_ref()
+            .map_or(true, |graph| graph.has_generation_data(kept));
+
+    let generations = generations(&commits, &infos, existing.as_ref(), &position_of)?;
+
+    let changed_paths = options.changed_paths.unwrap_or_else(|| {
+        existing.as_ref().is_some_and(|graph| {
+            graph.layers[existing_layers - 1]
+                .chunks
+                .contains_key(&CHUNK_BLOOM_DATA)
+        })
+    });
+
+    let mut chunks = Vec::new();
+
+    let mut fanout = vec![0u32; 256];
+
+    let mut lookup = Vec::new();
+
+    for hash in &commits {
+        let id = from_hex(hash)?;
+
+        for count in &mut fanout[id[0] as usize..] {
+            *count += 1;
+        }
+
+        lookup.extend(id);
+    }
+
+    chunks.push((
+        CHUNK_OID_FANOUT,
+        fanout
+            .iter()
+            .flat_map(|count| count.to_be_bytes())
+            .collect(),
+    ));
+
+    chunks.push((CHUNK_OID_LOOKUP, lookup));
+
+    let mut commit_data = Vec::new();
+
+    let mut edges: Vec<u32> = Vec::new();
+
+    let mut offsets = Vec::new();
+
+    let mut overflows = Vec::new();
+
+    for hash in &commits {
+        let info = &infos[hash];
+
+        let parents: Vec<u32> = info
+            .parents
+            .iter()
+            .map(|parent| position_of(parent))
+            .collect::<anyhow::Result<_>>()?;
+
+        let (first, second) = match parents.as_slice() {
+            [] => (PARENT_NONE, PARENT_NONE),
+
+            [first] => (*first, PARENT_NONE),
+
+            [first, second] => (*first, *second),
+
+            [first, rest @ ..] => {
+                let start = edges.len() as u32;
+
+                edges.extend(rest);
+
+                if let Some(last) = edges.last_mut() {
+                    *last |= EDGE_MASK;
+                }
+
+                (*first, start | EDGE_MASK)
+            }
+        };
+
+        let (level, corrected) = generations[hash];
+
+        let date = info.date.max(0) as u64;
+
+        commit_data.extend(from_hex(&info.tree)?);
+
+        commit_data.extend(first.to_be_bytes());
+
+        commit_data.extend(second.to_be_bytes());
+
+        commit_data.extend(((level << 2) | (date >> 32) as u32 & 3).to_be_bytes());
+
+        commit_data.extend((date as u32).to_be_bytes());
+
+        let offset = corrected - date;
+
+        if offset > OFFSET_MAX {
+            offsets.extend((OFFSET_OVERFLOW | (overflows.len() / 8) as u32).to_be_bytes());
+
+            overflows.extend(offset.to_be_bytes());
+        } else {
+            offsets.extend((offset as u32).to_be_bytes());
+        }
+    }
+
+    chunks.push((CHUNK_COMMIT_DATA, commit_data));
+
+    if corrected_dates {
+        chunks.push((CHUNK_GENERATION_DATA, offsets));
+
+        if !overflows.is_empty() {
+            chunks.push((CHUNK_GENERATION_OVERFLOW, overflows));
+        }
+    }
+
+    if !edges.is_empty() {
+        chunks.push((
+            CHUNK_EXTRA_EDGES,
+            edges.iter().flat_map(|edge| edge.to_be_bytes()).collect(),
+        ));
+    }
+
+    if changed_paths {
+        let mut indexes = Vec::new();
+
+        let mut data: Vec<u8> = [
+            BLOOM_HASH_VERSION,
+            BLOOM_HASHES,
+            BLOOM_BITS_PER_ENTRY as u32,
+        ]
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect();
+
+        for hash in &commits {
+            // Filters of commits already in a graph are taken from there
+            match existing.as_ref().and_then(|graph| graph.bloom_filter(hash)) {
+                Some(filter) => data.extend(filter),
+
+                None => {
+                    let info = &infos[hash];
+
+                    let parent_tree = match info.parents.first() {
+                        Some(parent) => Some(walker.commit(parent)?.tree.clone()),
+
+                        None => None,
+                    };
+
+                    data.extend(changed_path_filter(parent_tree.as_deref(), &info.tree)?);
+                }
+            }
+
+            indexes.extend(((data.len() - 12) as u32).to_be_bytes());
+        }
+
+        chunks.push((CHUNK_BLOOM_INDEXES, indexes));
+
+        chunks.push((CHUNK_BLOOM_DATA, data));
+    }
+
+    let bases: Vec<&Layer> = existing
+        .as_ref()
+        .map(|graph| graph.layers[..kept].iter().collect())
+        .unwrap_or_default();
+
+    if !bases.is_empty() {
+        chunks.push((
+            CHUNK_BASE_GRAPHS,
+            bases
+                .iter()
+                .map(|layer| from_hex(&layer.checksum()))
+                .collect::<Result<Vec<_>, _>>()?
+                .concat(),
+        ));
+    }
+
+    let data = write_chunks(
+        &[SIGNATURE, &[1, 1, chunks.len() as u8, bases.len() as u8]].concat(),
+        &chunks,
+    );
+
+    let checksum = to_hex_string(&data[data.len() - 20..]);
+
+    match options.split {
+        Some(_) => {
+            create_directory(&layers_directory())?;
+
+            write_to_file(&layer_path(&checksum), &data)?;
+
+            let mut chain: Vec<String> = bases.iter().map(|layer| layer.checksum()).collect();
+
+            chain.push(checksum);
+
+            // A single file below the new layer becomes the base of the chain
+            for layer in &bases {
+                if layer.path != layer_path(&layer.checksum()) {
+                    fs::rename(&layer.path, layer_path(&layer.checksum()))?;
+                }
+            }
+
+            write_to_file(&chain_path(), format!("{}\n", chain.join("\n")).as_bytes())?;
+
+            if Path::new(&single_path()).exists() {
+                fs::remove_file(single_path())?;
+            }
+
+            remove_layers(&chain)?;
+        }
+
+        None => {
+            write_to_file(&single_path(), &data)?;
+
+            if Path::new(&chain_path()).exists() {
+                fs::remove_file(chain_path())?;
+            }
+
+            remove_layers(&[])?;
+        }
+    }
+
+    reload_commit_graph();
+
+    Ok(())
+}
+
+/// Topological level and corrected commit date of each new commit, those of parents
+/// in the layers kept below coming from the graph.
+fn generations(
+    commits: &[String],
+    infos: &HashMap<String, CommitInfo>,
+    existing: Option<&CommitGraph>,
+    position_of: &impl Fn(&str) -> anyhow::Result<u32>,
+) -> anyhow::Result<HashMap<String, (u32, u64)>> {
+    let mut generations: HashMap<String, (u32, u64)> = HashMap::new();
+
+    for hash in commits {
+        let mut stack = vec![hash.clone()];
+
+        while let Some(current) = stack.last().cloned() {
+            if generations.contains_key(&current) {
+                stack.pop();
+
+                continue;
+            }
+
+            let info = &infos[&current];
+
+            let pending: Vec<String> = info
+                .parents
+                .iter()
+                .filter(|parent| infos.contains_key(*parent) && !generations.contains_key(*parent))
+                .cloned()
+                .collect();
+
+            if !pending.is_empty() {
+                stack.extend(pending);
+
+                continue;
+            }
+
+            let (mut level, mut corrected) = (0, 0);
+
+            for parent in &info.parents {
+                let (parent_level, parent_corrected) = match generations.get(parent) {
+                    Some(&generation) => generation,
+
+                    None => {
+                        let Some(graph) = existing else {
+                            bail!("commit-graph is missing parent {parent}");
+                        };
+
+                        let (layer, index) = graph.locate(position_of(parent)?)?;
+
+                        let entry = layer.entry(index)?;
+
+                        (entry.level, entry.corrected.unwrap_or(0))
+                    }
+                };
+
+                level = level.max(parent_level);
+
+                corrected = corrected.max(parent_corrected);
+            }
+
+            let date = info.date.max(0) as u64;
+
+            generations.insert(
+                current,
+                ((level + 1).min(LEVEL_MAX), date.max(corrected + 1)),
+            );
+
+            stack.pop();
+        }
+    }
+
+    Ok(generations)
+}
+
+/// Removes the layer files that are not part of `chain`.
+fn remove_layers(chain: &[String]) -> anyhow::Result<()> {
+    let Ok(entries) = fs::read_dir(layers_directory()) else {
+        return Ok(());
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if let Some(checksum) = name
+            .strip_prefix("graph-")
+            .and_then(|rest| rest.strip_suffix(".graph"))
+        {
+            if !chain.iter().any(|kept| kept == checksum) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The Bloom filter of the paths a commit changes from its first parent, leading
+/// directories included: one byte matching everything past `BLOOM_MAX_CHANGES`, one
+/// byte matching nothing when there are no changes.
+fn changed_path_filter(parent_tree: Option<&str>, tree: &str) -> anyhow::Result<Vec<u8>> {
+    let changes = diff_trees(parent_tree, Some(tree), true, &RenameOptions::default())?;
+
+    if changes.len() > BLOOM_MAX_CHANGES {
+        return Ok(vec![0xff]);
+    }
+
+    let mut paths = HashSet::new();
+
+    for change in &changes {
+        let mut path = change.new_path.as_str();
+
+        loop {
+            paths.insert(path);
+
+            match path.rsplit_once('/') {
+                Some((parent, _)) => path = parent,
+
+                None => break,
+            }
+        }
+    }
+
+    let mut filter = vec![0u8; (paths.len() * BLOOM_BITS_PER_ENTRY).div_ceil(8).max(1)];
+
+    let bits = filter.len() as u64 * 8;
+
+    for path in paths {
+        let [first, second] = BLOOM_SEEDS.map(|seed| murmur3(seed, path.as_bytes()));
+
+        for index in 0..BLOOM_HASHES {
+            let bit = first.wrapping_add(index.wrapping_mul(second)) as u64 % bits;
+
+            filter[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+
+    Ok(filter)
+}
+
+/// 32-bit murmur3 the way git's first Bloom filter version computes it: each byte is
+/// taken as a signed char and sign-extended before it is combined with the others.
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let word = |bytes: &[u8]| {
+        bytes.iter().enumerate().fold(0u32, |word, (index, &byte)| {
+            word | (byte as i8 as i32 as u32) << (index * 8)
+        })
+    };
+
+    let mut hash = seed;
+
+    let blocks = data.chunks_exact(4);
+
+    let tail = blocks.remainder();
+
+    for block in blocks {
+        let k = word(block)
+            .wrapping_mul(C1)
+            .rotate_left(15)
+            .wrapping_mul(C2);
+
+        hash = (hash ^ k)
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+
+    if !tail.is_empty() {
+        let k = tail.iter().enumerate().fold(0u32, |k, (index, &byte)| {
+            k ^ (byte as i8 as i32 as u32) << (index * 8)
+        });
+
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    hash ^= data.len() as u32;
+
+    hash ^= hash >> 16;
+
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+
+    hash ^= hash >> 13;
+
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+
+    hash ^ (hash >> 16)
+}
+
+/// Checks the commit-graph against the objects, printing each problem the way git
+/// does; with `shallow` only the top layer of a chain. Returns whether all is well.
+pub fn verify(shallow: bool) -> anyhow::Result<bool> {
+    let graph = match CommitGraph::load() {
+        Ok(Some(graph)) => graph,
+
+        Ok(None) => return Ok(true),
+
+        Err(error) => {
+            eprintln!("error: {error}");
+
+            return Ok(false);
+        }
+    };
+
+    let mut ok = true;
+
+    let mut report = |problem: String| {
+        eprintln!("{problem}");
+
+        ok = false;
+    };
+
+    let bottom = match shallow {
+        true => graph.layers.len() - 1,
+
+        false => 0,
+    };
+
+    for number in (bottom..graph.layers.len()).rev() {
+        let layer = &graph.layers[number];
+
+        let (content, trailer) = layer.data.split_at(layer.data.len() - 20);
+
+        if Sha1::digest(content).as_slice() != trailer {
+            report("the commit-graph file has incorrect checksum and is likely corrupt".into());
+        }
+
+        let mut broken = false;
+
+        for index in 1..layer.count {
+            if layer.id(index - 1) >= layer.id(index) {
+                report(format!(
+                    "commit-graph has incorrect OID order: {} then {}",
+                    to_hex_string(layer.id(index - 1)),
+                    to_hex_string(layer.id(index))
+                ));
+
+                broken = true;
+            }
+        }
+
+        let fanout = layer.chunk(CHUNK_OID_FANOUT).unwrap_or_default();
+
+        for first in 0..256 {
+            let expected = (0..layer.count)
+                .filter(|&index| layer.id(index)[0] as usize <= first)
+                .count();
+
+            let found = be_u32(&fanout[first * 4..]) as usize;
+
+            if found != expected {
+                report(format!(
+                    "commit-graph has incorrect fanout value: fanout[{first}] = {found} != {expected}"
+                ));
+
+                broken = true;
+            }
+        }
+
+        if broken {
+            return Ok(false);
+        }
+
+        let base = graph.count(number);
+
+        for index in 0..layer.count {
+            let hash = to_hex_string(layer.id(index));
+
+            let Ok(recorded) = graph.read_commit((base + index) as u32) else {
+                report(format!("failed to parse commit {hash} from commit-graph"));
+
+                continue;
+            };
+
+            let Ok(GitObject::Commit {
+                tree,
+                parents,
+                committer,
+                ..
+            }) = GitObject::from_hash(&hash)
+            else {
+                report(format!(
+                    "failed to parse commit {hash} from object database for commit-graph"
+                ));
+
+                continue;
+            };
+
+            if recorded.tree != tree {
+                report(format!(
+                    "root tree OID for commit {hash} in commit-graph is {} != {tree}",
+                    recorded.tree
+                ));
+            }
+
+            let mut highest = 0;
+
+            for (position, parent) in recorded.parents.iter().enumerate() {
+                match parents.get(position) {
+                    None => {
+                        report(format!(
+                            "commit-graph parent list for commit {hash} is too long"
+                        ));
+
+                        break;
+                    }
+
+                    Some(expected) if expected != parent => report(format!(
+                        "commit-graph parent for {hash} is {parent} != {expected}"
+                    )),
+
+                    Some(_) => {}
+                }
+
+                if let Some(Ok(parent)) = graph
+                    .position(parent, graph.layers.len())
+                    .map(|position| graph.read_commit(position))
+                {
+                    highest = highest.max(parent.generation);
+                }
+            }
+
+            if parents.len() > recorded.parents.len() {
+                report(format!(
+                    "commit-graph parent list for commit {hash} terminates early"
+                ));
+            }
+
+            if !graph.has_generation_data(graph.layers.len()) && highest == LEVEL_MAX as u64 {
+                highest -= 1;
+            }
+
+            if recorded.generation < highest + 1 {
+                report(format!(
+                    "commit-graph generation for commit {hash} is {} < {}",
+                    recorded.generation,
+                    highest + 1
+                ));
+            }
+
+            if recorded.date != committer.timestamp {
+                report(format!(
+                    "commit date for commit {hash} in commit-graph is {} != {}",
+                    recorded.date, committer.timestamp
+                ));
+            }
+        }
+    }
+
+    Ok(ok)
+}
@@ -0,0 +1,439 @@
+use std::env;
+
+use crate::refs::git_path;
+use crate::utils::{read_file, write_to_file};
+
+/// The repository's own config file.
+pub fn local_config() -> String {
+    git_path("config")
+}
+
+/// Flattened view over the global and repository config files, later files
+/// overriding earlier ones. Keys are stored as `section.subsection.key`, with the
+/// section and key lowercased the way git compares them.
+#[derive(Debug, Default)]
+pub struct Config {
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = Config::default();
+
+        if let Some(home) = env::var_os("HOME") {
+            let global = std::path::Path::new(&home).join(".gitconfig");
+
+            if let Some(path) = global.to_str() {
+                config.read(path)?;
+            }
+        }
+
+        config.read(&local_config())?;
+
+        Ok(config)
+    }
+
+    fn read(&mut self, path: &str) -> anyhow::Result<()> {
+        let content = match read_file(path) {
+            Ok(content) => String::from_utf8_lossy(&content).to_string(),
+
+            Err(_) => return Ok(()),
+        };
+
+        self.entries.extend(parse_config(&content));
+
+        Ok(())
+    }
+
+    /// Last value set for the key.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Names of the `[<section> "<name>"]` sections that hold any keys, in file order.
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let prefix = format!("{}.", section.to_lowercase());
+
+        let mut names: Vec<&str> = Vec::new();
+
+        for (key, _) in &self.entries {
+            let Some(name) = key
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.rsplit_once('.'))
+                .map(|(name, _)| name)
+            else {
+                continue;
+            };
+
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        names
+    }
+
+    /// Every value set for a multi-valued key, in file order.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+
+        self.entries
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+}
+
+/// Sets `key` in the repository config file, replacing an existing value or adding it
+/// at the end of its section (which is created if needed).
+pub fn set_local(key: &str, value: &str) -> anyhow::Result<()> {
+    write_local_key(key, value, true)
+}
+
+/// Adds another value for a multi-valued key such as `remote.<name>.fetch`.
+pub fn add_local(key: &str, value: &str) -> anyhow::Result<()> {
+    write_local_key(key, value, false)
+}
+
+/// Removes every value of `key` from the repository config file.
+pub fn unset_local(key: &str) -> anyhow::Result<()> {
+    replace_local(key, |_| true, None)
+}
+
+/// Replaces, where they stand, the values of `key` that `matches` accepts with `value`,
+/// or removes them when `value` is `None`.
+pub fn replace_local(
+    key: &str,
+    matches: impl Fn(&str) -> bool,
+    value: Option<&str>,
+) -> anyhow::Result<()> {
+    let entry = value.map(|value| format_entry(key, value));
+
+    let key = normalize_key(key);
+
+    let Some((section, name)) = key.rsplit_once('.') else {
+        anyhow::bail!("key does not contain a section: {key}");
+    };
+
+    let mut lines = Vec::new();
+
+    let mut current = String::new();
+
+    // Headers of the section, with whether something was removed from under them
+    // and whether anything is left
+    let mut headers: Vec<(usize, bool, bool)> = Vec::new();
+
+    for line in read_local_lines() {
+        if let Some(header) = parse_section_header(line.trim()) {
+            if header == section {
+                headers.push((lines.len(), false, false));
+            }
+
+            current = header;
+        } else if let Some((_, removed, left)) = headers.last_mut().filter(|_| current == section) {
+            if line_key(&line).as_deref() == Some(name) && matches(&line_value(&line)) {
+                let Some(entry) = &entry else {
+                    *removed = true;
+
+                    continue;
+                };
+
+                lines.push(entry.clone());
+
+                *left = true;
+
+                continue;
+            }
+
+            *left |= !line.trim().is_empty();
+        }
+
+        lines.push(line);
+    }
+
+    // A section emptied by the removal goes as well
+    for (index, ..) in headers
+        .into_iter()
+        .rev()
+        .filter(|(_, removed, left)| *removed && !left)
+    {
+        lines.remove(index);
+    }
+
+    write_local_lines(&lines)
+}
+
+fn write_local_key(key: &str, value: &str, replace: bool) -> anyhow::Result<()> {
+    let entry = format_entry(key, value);
+
+    let key = normalize_key(key);
+
+    let Some((section, name)) = key.rsplit_once('.') else {
+        anyhow::bail!("key does not contain a section: {key}");
+    };
+
+    let mut lines = read_local_lines();
+
+    let mut current = String::new();
+
+    let mut section_end = None;
+
+    let mut existing = None;
+
+    for (index, line) in lines.iter().enumerate() {
+        if let Some(header) = parse_section_header(line.trim()) {
+            current = header;
+        } else if current != section {
+            continue;
+        } else if replace && line_key(line).as_deref() == Some(name) {
+            existing = Some(index);
+        }
+
+        if current == section {
+            section_end = Some(index + 1);
+        }
+    }
+
+    match (existing, section_end) {
+        (Some(index), _) => lines[index] = entry,
+
+        (None, Some(end)) => lines.insert(end, entry),
+
+        (None, None) => {
+            lines.push(format_section_header(section));
+            lines.push(entry);
+        }
+    }
+
+    write_local_lines(&lines)
+}
+
+/// Renames a section such as `branch.old` (with every key in it), or removes it when
+/// `new` is `None`.
+pub fn rename_section(old: &str, new: Option<&str>) -> anyhow::Result<()> {
+    let old = normalize_section(old);
+
+    let mut lines = Vec::new();
+
+    let mut in_old = false;
+
+    for line in read_local_lines() {
+        if let Some(header) = parse_section_header(line.trim()) {
+            in_old = header == old;
+
+            if in_old {
+                if let Some(new) = new {
+                    lines.push(format_section_header(&normalize_section(new)));
+                }
+
+                continue;
+            }
+        }
+
+        if !(in_old && new.is_none()) {
+            lines.push(line);
+        }
+    }
+
+    write_local_lines(&lines)
+}
+
+/// Copies every key of a section into another one.
+pub fn copy_section(old: &str, new: &str) -> anyhow::Result<()> {
+    let prefix = format!("{}.", normalize_section(old));
+
+    let content = read_local_lines().join("\n");
+
+    for (key, value) in parse_config(&content) {
+        if let Some(name) = key.strip_prefix(&prefix) {
+            set_local(&format!("{new}.{name}"), &value)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_local_lines() -> Vec<String> {
+    match read_file(&local_config()) {
+        Ok(content) => String::from_utf8_lossy(&content)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+
+        Err(_) => Vec::new(),
+    }
+}
+
+fn write_local_lines(lines: &[String]) -> anyhow::Result<()> {
+    let mut content = lines.join("\n");
+
+    content.push('\n');
+
+    write_to_file(&local_config(), content.as_bytes())
+}
+
+/// Lowercases the section name of `section.subsection`, keeping the subsection's case.
+fn normalize_section(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => format!("{}.{subsection}", name.to_lowercase()),
+
+        None => section.to_lowercase(),
+    }
+}
+
+/// `[section "subsection"]` for `section.subsection`.
+fn format_section_header(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => format!("[{name} \"{subsection}\"]"),
+
+        None => format!("[{section}]"),
+    }
+}
+
+fn parse_section_header(line: &str) -> Option<String> {
+    if !line.starts_with('[') {
+        return None;
+    }
+
+    let header = line
+        .trim_start_matches('[')
+        .split(']')
+        .next()
+        .unwrap_or_default();
+
+    Some(match header.split_once(' ') {
+        Some((name, subsection)) => format!(
+            "{}.{}",
+            name.to_lowercase(),
+            subsection.trim().trim_matches('"')
+        ),
+
+        None => header.to_lowercase(),
+    })
+}
+
+fn line_key(line: &str) -> Option<String> {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+        return None;
+    }
+
+    let name = line.split_once('=').map_or(line, |(name, _)| name);
+
+    Some(name.trim().to_lowercase())
+}
+
+/// The value set on a `name = value` line, or `true` for a bare name.
+fn line_value(line: &str) -> String {
+    line.split_once('=')
+        .map_or("true".to_string(), |(_, value)| parse_value(value.trim()))
+}
+
+/// The `name = value` line for `key`, with the variable name spelt as given.
+fn format_entry(key: &str, value: &str) -> String {
+    let name = key.rsplit_once('.').map_or(key, |(_, name)| name);
+
+    format!("\t{name} = {}", format_value(value))
+}
+
+/// Quotes values that would otherwise lose whitespace or be cut at a comment.
+fn format_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+
+    if escaped != escaped.trim() || escaped.contains(['#', ';']) {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
+
+/// Lowercases the section and variable name, leaving any subsection untouched.
+pub fn normalize_key(key: &str) -> String {
+    let section_end = key.find('.').unwrap_or(key.len());
+    let name_start = key.rfind('.').map(|i| i + 1).unwrap_or(0);
+
+    if name_start <= section_end {
+        return key.to_lowercase();
+    }
+
+    format!(
+        "{}{}{}",
+        key[..section_end].to_lowercase(),
+        &key[section_end..name_start],
+        key[name_start..].to_lowercase()
+    )
+}
+
+pub fn parse_config(content: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+
+    let mut section = String::new();
+
+    for raw_line in content.lines() {
+        let line = raw_line.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(header) = parse_section_header(line) {
+            section = header;
+
+            continue;
+        }
+
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name.trim(), parse_value(value.trim())),
+
+            // A bare key is a boolean set to true
+            None => (line, "true".to_string()),
+        };
+
+        entries.push((format!("{section}.{}", name.to_lowercase()), value));
+    }
+
+    entries
+}
+
+fn parse_value(value: &str) -> String {
+    let mut result = String::new();
+
+    let mut quoted = false;
+
+    let mut chars = value.chars();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => quoted = !quoted,
+
+            '\\' => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(other) => result.push(other),
+                None => {}
+            },
+
+            '#' | ';' if !quoted => break,
+
+            _ => result.push(ch),
+        }
+    }
+
+    if quoted {
+        result
+    } else {
+        result.trim_end().to_string()
+    }
+}
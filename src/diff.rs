@@ -0,0 +1,926 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::bail;
+
+use crate::git_objects::{GitObject, TreeFileModes};
+
+/// Similarity scores are fixed point numbers where `MAX_SCORE` means identical content.
+pub const MAX_SCORE: u64 = 60000;
+
+pub const DEFAULT_RENAME_SCORE: u64 = 30000;
+
+pub const DEFAULT_RENAME_LIMIT: usize = 1000;
+
+pub const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub mode: TreeFileModes,
+
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStatus {
+    Added,
+
+    Deleted,
+
+    Modified,
+
+    TypeChanged,
+
+    Renamed,
+
+    Copied,
+}
+
+impl std::fmt::Display for DiffStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            DiffStatus::Added => "A",
+            DiffStatus::Deleted => "D",
+            DiffStatus::Modified => "M",
+            DiffStatus::TypeChanged => "T",
+            DiffStatus::Renamed => "R",
+            DiffStatus::Copied => "C",
+        };
+
+        write!(f, "{value}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffEntry {
+    pub status: DiffStatus,
+
+    pub old_path: String,
+
+    pub new_path: String,
+
+    pub old: Option<FileEntry>,
+
+    pub new: Option<FileEntry>,
+
+    /// Similarity for renames and copies, in the `0..=MAX_SCORE` range.
+    pub score: u64,
+}
+
+impl DiffEntry {
+    fn added(path: &str, entry: FileEntry) -> Self {
+        Self {
+            status: DiffStatus::Added,
+            old_path: path.to_string(),
+            new_path: path.to_string(),
+            old: None,
+            new: Some(entry),
+            score: 0,
+        }
+    }
+
+    fn deleted(path: &str, entry: FileEntry) -> Self {
+        Self {
+            status: DiffStatus::Deleted,
+            old_path: path.to_string(),
+            new_path: path.to_string(),
+            old: Some(entry),
+            new: None,
+            score: 0,
+        }
+    }
+
+    fn modified(path: &str, old: FileEntry, new: FileEntry) -> Self {
+        let status = if is_symlink(old.mode) != is_symlink(new.mode) {
+            DiffStatus::TypeChanged
+        } else {
+            DiffStatus::Modified
+        };
+
+        Self {
+            status,
+            old_path: path.to_string(),
+            new_path: path.to_string(),
+            old: Some(old),
+            new: Some(new),
+            score: 0,
+        }
+    }
+
+    /// Status letter, followed by the similarity percentage for renames and copies.
+    pub fn status_string(&self) -> String {
+        match self.status {
+            DiffStatus::Renamed | DiffStatus::Copied => {
+                format!("{}{:03}", self.status, similarity_percent(self.score))
+            }
+
+            _ => self.status.to_string(),
+        }
+    }
+
+    pub fn paths_string(&self) -> String {
+        match self.status {
+            DiffStatus::Renamed | DiffStatus::Copied => {
+                format!("{}\t{}", self.old_path, self.new_path)
+            }
+
+            _ => self.new_path.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (old_mode, old_hash) = raw_side(&self.old);
+        let (new_mode, new_hash) = raw_side(&self.new);
+
+        write!(
+            f,
+            ":{old_mode} {new_mode} {old_hash} {new_hash} {}\t{}",
+            self.status_string(),
+            self.paths_string()
+        )
+    }
+}
+
+fn raw_side(entry: &Option<FileEntry>) -> (String, &str) {
+    match entry {
+        Some(entry) => (
+            format!("{:0>6}", entry.mode.to_string()),
+            entry.hash.as_str(),
+        ),
+
+        None => ("000000".to_string(), NULL_HASH),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectMode {
+    Off,
+
+    Renames,
+
+    Copies,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenameOptions {
+    pub detect: DetectMode,
+
+    pub minimum_score: u64,
+
+    /// Also consider unmodified files of the old tree as copy sources.
+    pub find_copies_harder: bool,
+
+    /// Inexact detection is skipped when `sources * destinations` exceeds the square of this.
+    pub rename_limit: usize,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            detect: DetectMode::Off,
+            minimum_score: DEFAULT_RENAME_SCORE,
+            find_copies_harder: false,
+            rename_limit: DEFAULT_RENAME_LIMIT,
+        }
+    }
+}
+
+pub fn similarity_percent(score: u64) -> u64 {
+    score * 100 / MAX_SCORE
+}
+
+/// Parses a similarity argument the way git does: `50%` is a percentage, while bare
+/// digits are read as a decimal fraction, so `5` and `50` both mean 50%. A score of
+/// zero leaves the default in place.
+pub fn parse_rename_score(value: &str) -> anyhow::Result<u64> {
+    let mut num: u64 = 0;
+    let mut scale: u64 = 1;
+    let mut dot = false;
+
+    let mut chars = value.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if !dot && ch == '.' {
+            scale = 1;
+            dot = true;
+        } else if ch == '%' {
+            scale = if dot { scale * 100 } else { 100 };
+
+            if chars.peek().is_some() {
+                bail!("Invalid similarity score: {value}");
+            }
+        } else if let Some(digit) = ch.to_digit(10) {
+            if scale < 100000 {
+                scale *= 10;
+                num = num * 10 + digit as u64;
+            }
+        } else {
+            bail!("Invalid similarity score: {value}");
+        }
+    }
+
+    if num >= scale {
+        Ok(MAX_SCORE)
+    } else if num == 0 {
+        Ok(DEFAULT_RENAME_SCORE)
+    } else {
+        Ok(MAX_SCORE * num / scale)
+    }
+}
+
+/// Peels a commit down to its tree, returning the hash of a tree object.
+pub fn resolve_tree(hash: &str) -> anyhow::Result<String> {
+    let (obj_type, content) = GitObject::read_raw(hash)?;
+
+    match obj_type.as_str() {
+        "tree" => Ok(hash.to_string()),
+
+        "commit" => {
+            let content_str = String::from_utf8_lossy(&content);
+
+            match content_str
+                .lines()
+                .next()
+                .and_then(|l| l.strip_prefix("tree "))
+            {
+                Some(tree) => Ok(tree.to_string()),
+
+                None => bail!("commit {hash} has no tree"),
+            }
+        }
+
+        "tag" => resolve_tree(&GitObject::peel(hash)?),
+
+        _ => bail!("{hash} is a {obj_type}, not a tree-ish"),
+    }
+}
+
+pub fn read_tree_entries(hash: &str) -> anyhow::Result<Vec<(TreeFileModes, String, String)>> {
+    let (obj_type, content) = GitObject::read_raw(hash)?;
+
+    if obj_type != "tree" {
+        bail!("{hash} is not a tree object");
+    }
+
+    GitObject::parse_tree_entries(&content)
+}
+
+/// Lists every file of a tree, keyed by its full path.
+pub fn flatten_tree(hash: &str) -> anyhow::Result<BTreeMap<String, FileEntry>> {
+    let mut files = BTreeMap::new();
+
+    flatten_tree_into(hash, "", &mut files)?;
+
+    Ok(files)
+}
+
+fn flatten_tree_into(
+    hash: &str,
+    prefix: &str,
+    files: &mut BTreeMap<String, FileEntry>,
+) -> anyhow::Result<()> {
+    for (mode, name, entry_hash) in read_tree_entries(hash)? {
+        let path = join_path(prefix, &name);
+
+        if mode == TreeFileModes::Directory {
+            flatten_tree_into(&entry_hash, &path, files)?;
+        } else {
+            files.insert(
+                path,
+                FileEntry {
+                    mode,
+                    hash: entry_hash,
+                },
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}/{name}")
+    }
+}
+
+/// Whether an entry can be paired up as a rename or copy: not a directory, nor a
+/// submodule, whose commit is not here to compare.
+fn can_rename(mode: TreeFileModes) -> bool {
+    !matches!(mode, TreeFileModes::Directory | TreeFileModes::Gitlink)
+}
+
+fn is_symlink(mode: TreeFileModes) -> bool {
+    mode == TreeFileModes::SymbolicLink
+}
+
+/// Compares two trees. Identical subtrees are skipped without being read, and
+/// subtrees are only descended into when `recursive` is set.
+pub fn diff_trees(
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+    recursive: bool,
+    options: &RenameOptions,
+) -> anyhow::Result<Vec<DiffEntry>> {
+    let mut entries = Vec::new();
+
+    diff_trees_into(old_tree, new_tree, "", recursive, &mut entries)?;
+
+    if options.detect != DetectMode::Off {
+        let unmodified = match (options.find_copies_harder, old_tree) {
+            (true, Some(old_tree)) => flatten_tree(old_tree)?,
+
+            _ => BTreeMap::new(),
+        };
+
+        entries = detect_renames(entries, &unmodified, options)?;
+    }
+
+    Ok(entries)
+}
+
+fn diff_trees_into(
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+    prefix: &str,
+    recursive: bool,
+    entries: &mut Vec<DiffEntry>,
+) -> anyhow::Result<()> {
+    let mut old_entries = BTreeMap::new();
+    let mut new_entries = BTreeMap::new();
+
+    if let Some(hash) = old_tree {
+        for (mode, name, hash) in read_tree_entries(hash)? {
+            old_entries.insert(name, FileEntry { mode, hash });
+        }
+    }
+
+    if let Some(hash) = new_tree {
+        for (mode, name, hash) in read_tree_entries(hash)? {
+            new_entries.insert(name, FileEntry { mode, hash });
+        }
+    }
+
+    let mut names: Vec<&String> = old_entries.keys().chain(new_entries.keys()).collect();
+
+    names.sort();
+    names.dedup();
+
+    for name in names {
+        let path = join_path(prefix, name);
+
+        let old = old_entries.get(name);
+        let new = new_entries.get(name);
+
+        let old_is_dir = old.is_some_and(|e| e.mode == TreeFileModes::Directory);
+        let new_is_dir = new.is_some_and(|e| e.mode == TreeFileModes::Directory);
+
+        if let (Some(old), Some(new)) = (old, new) {
+            if old.hash == new.hash && old.mode == new.mode {
+                continue;
+            }
+        }
+
+        if recursive && (old_is_dir || new_is_dir) {
+            // A path that switches between file and directory is a deletion plus additions
+            let old_subtree = old.filter(|_| old_is_dir).map(|e| e.hash.as_str());
+            let new_subtree = new.filter(|_| new_is_dir).map(|e| e.hash.as_str());
+
+            if let Some(old) = old.filter(|_| !old_is_dir) {
+                entries.push(DiffEntry::deleted(&path, old.clone()));
+            }
+
+            diff_trees_into(old_subtree, new_subtree, &path, recursive, entries)?;
+
+            if let Some(new) = new.filter(|_| !new_is_dir) {
+                entries.push(DiffEntry::added(&path, new.clone()));
+            }
+
+            continue;
+        }
+
+        match (old, new) {
+            (Some(old), Some(new)) if old_is_dir == new_is_dir => {
+                entries.push(DiffEntry::modified(&path, old.clone(), new.clone()))
+            }
+
+            (Some(old), Some(new)) => {
+                entries.push(DiffEntry::deleted(&path, old.clone()));
+                entries.push(DiffEntry::added(&path, new.clone()));
+            }
+
+            (Some(old), None) => entries.push(DiffEntry::deleted(&path, old.clone())),
+
+            (None, Some(new)) => entries.push(DiffEntry::added(&path, new.clone())),
+
+            (None, None) => {}
+        }
+    }
+
+    Ok(())
+}
+
+struct RenameSource {
+    path: String,
+
+    entry: FileEntry,
+
+    /// Deleted sources turn into renames; everything else can only be copied from.
+    deleted: bool,
+}
+
+/// Pairs added paths with deleted (and, for copies, modified or unmodified) paths: first
+/// by identical content, then by similarity of their contents.
+pub fn detect_renames(
+    entries: Vec<DiffEntry>,
+    unmodified: &BTreeMap<String, FileEntry>,
+    options: &RenameOptions,
+) -> anyhow::Result<Vec<DiffEntry>> {
+    let copies = options.detect == DetectMode::Copies || options.find_copies_harder;
+
+    let mut sources: Vec<RenameSource> = Vec::new();
+
+    let mut destinations: Vec<usize> = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        match (entry.status, &entry.old, &entry.new) {
+            (DiffStatus::Deleted, Some(old), _) if can_rename(old.mode) => {
+                sources.push(RenameSource {
+                    path: entry.old_path.clone(),
+                    entry: old.clone(),
+                    deleted: true,
+                })
+            }
+
+            (DiffStatus::Added, _, Some(new)) if can_rename(new.mode) => destinations.push(index),
+
+            (DiffStatus::Modified | DiffStatus::TypeChanged, Some(old), _)
+                if copies && can_rename(old.mode) =>
+            {
+                sources.push(RenameSource {
+                    path: entry.old_path.clone(),
+                    entry: old.clone(),
+                    deleted: false,
+                })
+            }
+
+            _ => {}
+        }
+    }
+
+    if copies && options.find_copies_harder {
+        for (path, entry) in unmodified {
+            if can_rename(entry.mode) && !sources.iter().any(|s| &s.path == path) {
+                sources.push(RenameSource {
+                    path: path.clone(),
+                    entry: entry.clone(),
+                    deleted: false,
+                });
+            }
+        }
+    }
+
+    if sources.is_empty() || destinations.is_empty() {
+        return Ok(entries);
+    }
+
+    // destination index -> (source index, score)
+    let mut pairs: HashMap<usize, (usize, u64)> = HashMap::new();
+
+    let mut rename_used = vec![false; sources.len()];
+
+    // Exact matches first, preferring a deleted file with the same base name
+    for &dst in &destinations {
+        let new = entries[dst]
+            .new
+            .as_ref()
+            .expect("destinations have a new side");
+
+        let candidates: Vec<usize> = (0..sources.len())
+            .filter(|&src| {
+                sources[src].entry.hash == new.hash
+                    && is_symlink(sources[src].entry.mode) == is_symlink(new.mode)
+                    && (copies || !rename_used[src])
+            })
+            .collect();
+
+        let best = candidates.iter().copied().max_by_key(|&src| {
+            let source = &sources[src];
+
+            (
+                source.deleted && !rename_used[src],
+                basename(&source.path) == basename(&entries[dst].new_path),
+                std::cmp::Reverse(src),
+            )
+        });
+
+        if let Some(src) = best {
+            if sources[src].deleted {
+                rename_used[src] = true;
+            }
+
+            pairs.insert(dst, (src, MAX_SCORE));
+        }
+    }
+
+    let remaining: Vec<usize> = destinations
+        .iter()
+        .copied()
+        .filter(|dst| !pairs.contains_key(dst))
+        .collect();
+
+    let remaining_sources: Vec<usize> = (0..sources.len())
+        .filter(|&src| copies || !rename_used[src])
+        .collect();
+
+    let limit = options.rename_limit;
+
+    if !remaining.is_empty() && !remaining_sources.is_empty() {
+        if limit > 0 && remaining.len() * remaining_sources.len() > limit * limit {
+            eprintln!("warning: exhaustive rename detection was skipped due to too many files.");
+
+            eprintln!(
+                "warning: you may want to set your diff.renameLimit variable to at least {} and retry the command.",
+                remaining.len().max(remaining_sources.len())
+            );
+        } else {
+            let mut source_spans = HashMap::new();
+
+            for &src in &remaining_sources {
+                let content = read_blob(&sources[src].entry.hash)?;
+
+                source_spans.insert(src, (content.len() as u64, hash_spans(&content)));
+            }
+
+            let mut candidates: Vec<(u64, bool, usize, usize)> = Vec::new();
+
+            for &dst in &remaining {
+                let new = entries[dst]
+                    .new
+                    .as_ref()
+                    .expect("destinations have a new side");
+
+                let content = read_blob(&new.hash)?;
+
+                let dst_size = content.len() as u64;
+
+                let dst_spans = hash_spans(&content);
+
+                for &src in &remaining_sources {
+                    if is_symlink(sources[src].entry.mode) != is_symlink(new.mode) {
+                        continue;
+                    }
+
+                    let (src_size, spans) = &source_spans[&src];
+
+                    let score = estimate_similarity(
+                        *src_size,
+                        spans,
+                        dst_size,
+                        &dst_spans,
+                        options.minimum_score,
+                    );
+
+                    if score >= options.minimum_score {
+                        let same_name =
+                            basename(&sources[src].path) == basename(&entries[dst].new_path);
+
+                        candidates.push((score, same_name, dst, src));
+                    }
+                }
+            }
+
+            candidates.sort_by(|a, b| {
+                b.0.cmp(&a.0)
+                    .then(b.1.cmp(&a.1))
+                    .then(a.2.cmp(&b.2))
+                    .then(a.3.cmp(&b.3))
+            });
+
+            // Renames are settled first so a deleted file goes to its best match
+            for (score, _, dst, src) in &candidates {
+                if pairs.contains_key(dst) || !sources[*src].deleted || rename_used[*src] {
+                    continue;
+                }
+
+                rename_used[*src] = true;
+
+                pairs.insert(*dst, (*src, *score));
+            }
+
+            if copies {
+                for (score, _, dst, src) in &candidates {
+                    if pairs.contains_key(dst) {
+                        continue;
+                    }
+
+                    pairs.insert(*dst, (*src, *score));
+                }
+            }
+        }
+    }
+
+    // The last destination using a deleted source (in path order) is the rename,
+    // any earlier ones are copies of it
+    let mut last_user: HashMap<usize, usize> = HashMap::new();
+
+    for &dst in &destinations {
+        if let Some((src, _)) = pairs.get(&dst) {
+            if sources[*src].deleted {
+                last_user.insert(*src, dst);
+            }
+        }
+    }
+
+    let consumed: Vec<&str> = last_user
+        .keys()
+        .map(|&src| sources[src].path.as_str())
+        .collect();
+
+    let mut result = Vec::new();
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        if entry.status == DiffStatus::Deleted && consumed.contains(&entry.old_path.as_str()) {
+            continue;
+        }
+
+        match pairs.get(&index) {
+            Some(&(src, score)) => {
+                let source = &sources[src];
+
+                let status = if last_user.get(&src) == Some(&index) {
+                    DiffStatus::Renamed
+                } else {
+                    DiffStatus::Copied
+                };
+
+                result.push(DiffEntry {
+                    status,
+                    old_path: source.path.clone(),
+                    new_path: entry.new_path,
+                    old: Some(source.entry.clone()),
+                    new: entry.new,
+                    score,
+                });
+            }
+
+            None => result.push(entry),
+        }
+    }
+
+    Ok(result)
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Content of a blob, refusing any other type of object.
+pub fn read_blob(hash: &str) -> anyhow::Result<Vec<u8>> {
+    let (obj_type, content) = GitObject::read_raw(hash)?;
+
+    if obj_type != "blob" {
+        bail!("{hash} is not a blob");
+    }
+
+    Ok(content)
+}
+
+/// Counts the bytes of every span in the content, a span ending at a newline or
+/// after 64 bytes, keyed by the hash of the span.
+fn hash_spans(content: &[u8]) -> HashMap<u64, u64> {
+    let mut spans = HashMap::new();
+
+    let mut hash: u64 = 0;
+    let mut length: u64 = 0;
+
+    for &byte in content {
+        hash = hash.wrapping_mul(0x100000001b3) ^ byte as u64;
+        length += 1;
+
+        if byte == b'\n' || length >= 64 {
+            *spans.entry(hash).or_insert(0) += length;
+
+            hash = 0;
+            length = 0;
+        }
+    }
+
+    if length > 0 {
+        *spans.entry(hash).or_insert(0) += length;
+    }
+
+    spans
+}
+
+fn estimate_similarity(
+    src_size: u64,
+    src_spans: &HashMap<u64, u64>,
+    dst_size: u64,
+    dst_spans: &HashMap<u64, u64>,
+    minimum_score: u64,
+) -> u64 {
+    let max_size = src_size.max(dst_size);
+    let base_size = src_size.min(dst_size);
+
+    // Not worth comparing when the size difference alone rules out the threshold
+    if base_size * (MAX_SCORE - minimum_score) < (max_size - base_size) * MAX_SCORE {
+        return 0;
+    }
+
+    if dst_size == 0 {
+        return 0;
+    }
+
+    let copied: u64 = src_spans
+        .iter()
+        .map(|(hash, count)| (*count).min(*dst_spans.get(hash).unwrap_or(&0)))
+        .sum();
+
+    copied * MAX_SCORE / max_size
+}
+
+/// A run of lines that differ: `old_len` lines at `old_start` were replaced by the
+/// `new_len` lines at `new_start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+
+    pub old_len: usize,
+
+    pub new_start: usize,
+
+    pub new_len: usize,
+}
+
+/// Splits content into lines, each keeping its trailing newline.
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Line diff using Myers' algorithm, returning the changed regions in order.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]]) -> Vec<Hunk> {
+    // Compare integer ids instead of line contents
+    let mut ids: HashMap<&[u8], usize> = HashMap::new();
+
+    let old_ids: Vec<usize> = old
+        .iter()
+        .map(|line| {
+            let next = ids.len();
+            *ids.entry(line).or_insert(next)
+        })
+        .collect();
+
+    let new_ids: Vec<usize> = new
+        .iter()
+        .map(|line| {
+            let next = ids.len();
+            *ids.entry(line).or_insert(next)
+        })
+        .collect();
+
+    let prefix = old_ids
+        .iter()
+        .zip(&new_ids)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let suffix = old_ids[prefix..]
+        .iter()
+        .rev()
+        .zip(new_ids[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let a = &old_ids[prefix..old_ids.len() - suffix];
+    let b = &new_ids[prefix..new_ids.len() - suffix];
+
+    let mut matches: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+
+    matches.extend(
+        myers_matches(a, b)
+            .into_iter()
+            .map(|(x, y)| (x + prefix, y + prefix)),
+    );
+
+    matches.extend((0..suffix).map(|i| (old.len() - suffix + i, new.len() - suffix + i)));
+
+    let mut hunks = Vec::new();
+
+    let (mut old_pos, mut new_pos) = (0, 0);
+
+    for (x, y) in matches
+        .into_iter()
+        .chain(std::iter::once((old.len(), new.len())))
+    {
+        if x > old_pos || y > new_pos {
+            hunks.push(Hunk {
+                old_start: old_pos,
+                old_len: x - old_pos,
+                new_start: new_pos,
+                new_len: y - new_pos,
+            });
+        }
+
+        old_pos = x + 1;
+        new_pos = y + 1;
+    }
+
+    hunks
+}
+
+/// Pairs of matching line indexes along a shortest edit script.
+fn myers_matches(a: &[usize], b: &[usize]) -> Vec<(usize, usize)> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+
+    if n == 0 || m == 0 {
+        return Vec::new();
+    }
+
+    let max = n + m;
+    let offset = max;
+
+    let mut v = vec![0isize; (2 * max + 2) as usize];
+
+    // trace[d] holds the furthest x for diagonals -d..=d after step d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max {
+        let mut k = -d;
+
+        while k <= d {
+            let index = (k + offset) as usize;
+
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+
+            let mut y = x - k;
+
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[index] = x;
+
+            if x >= n && y >= m {
+                trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+
+                break 'search;
+            }
+
+            k += 2;
+        }
+
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+
+    let mut matches = Vec::new();
+
+    let (mut x, mut y) = (n, m);
+
+    for d in (1..trace.len() as isize).rev() {
+        let previous = &trace[(d - 1) as usize];
+
+        let k = x - y;
+
+        let at = |k: isize| previous[(k + d - 1) as usize];
+
+        let previous_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+
+        let previous_x = at(previous_k);
+        let previous_y = previous_x - previous_k;
+
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+
+            matches.push((x as usize, y as usize));
+        }
+
+        x = previous_x;
+        y = previous_y;
+    }
+
+    while x > 0 && y > 0 {
+        x -= 1;
+        y -= 1;
+
+        matches.push((x as usize, y as usize));
+    }
+
+    matches.reverse();
+
+    matches
+}
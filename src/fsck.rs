@@ -0,0 +1,1027 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use sha1::{Digest, Sha1};
+
+use crate::diff::NULL_HASH;
+use crate::gc::loose_objects;
+use crate::git_objects::{GitObject, TreeFileModes};
+use crate::index::Index;
+use crate::pack::{index_paths, PackIndex};
+use crate::reflog::{list_reflogs, read_reflog};
+use crate::refs::{
+    check_ref_format, git_path, list_refs, read_ref, resolve_ref, shallow_commits, HEAD,
+};
+use crate::utils::{
+    decompress, generate_object_id, object_directories, object_exists, to_hex_string, write_to_file,
+};
+
+/// Exit status bits, as git sets them: broken objects, missing reachable objects,
+/// corrupt packs and bad refs.
+const ERROR_OBJECT: i32 = 0o1;
+const ERROR_REACHABLE: i32 = 0o2;
+const ERROR_PACK: i32 = 0o4;
+const ERROR_REFS: i32 = 0o10;
+
+/// What `fsck` checks and reports.
+pub struct FsckOptions {
+    /// Report warnings as errors, and group-writable files as bad modes.
+    pub strict: bool,
+
+    /// Only check that everything reachable is there, not what objects contain.
+    pub connectivity_only: bool,
+
+    /// Report every unreachable object, not only the dangling ones.
+    pub unreachable: bool,
+
+    pub dangling: bool,
+
+    /// Write dangling objects to `lost-found/`, ignoring the reflogs.
+    pub lost_found: bool,
+}
+
+/// How bad a problem in an object is. `Warning`s become errors with `--strict`,
+/// `Info`s stay warnings.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+/// A problem found in an object: its severity, git's message id and the message.
+type Problem = (Severity, &'static str, String);
+
+/// An object that could be read, and the objects it refers to as `(type, hash)`.
+struct Object {
+    kind: String,
+
+    links: Vec<(String, String)>,
+}
+
+/// A tree entry as stored, before any validation of its mode or name.
+struct TreeEntry<'a> {
+    mode_text: &'a [u8],
+
+    mode: u32,
+
+    name: &'a [u8],
+
+    hash: String,
+}
+
+impl TreeEntry<'_> {
+    fn is_directory(&self) -> bool {
+        TreeFileModes::from_bits(self.mode).is_ok_and(|mode| mode == TreeFileModes::Directory)
+    }
+}
+
+struct Fsck<'a> {
+    options: &'a FsckOptions,
+
+    objects: BTreeMap<String, Object>,
+
+    /// Objects a promisor remote has promised to provide, which may be missing.
+    promised: HashSet<String>,
+
+    shallow: HashSet<String>,
+
+    errors: i32,
+}
+
+/// Checks the objects of the repository and its alternates, then their connectivity
+/// from the refs, `HEAD`, the reflogs and the index. Problems are reported the way git
+/// does; the returned exit status has git's error bits set.
+pub fn fsck(options: &FsckOptions) -> anyhow::Result<i32> {
+    let mut fsck = Fsck {
+        options,
+        objects: BTreeMap::new(),
+        promised: HashSet::new(),
+        shallow: shallow_commits(),
+        errors: 0,
+    };
+
+    fsck.check_loose_objects()?;
+
+    fsck.check_packs()?;
+
+    fsck.check_connectivity()?;
+
+    Ok(fsck.errors)
+}
+
+impl Fsck<'_> {
+    fn check_loose_objects(&mut self) -> anyhow::Result<()> {
+        for directory in object_directories() {
+            if !Path::new(&directory).is_dir() {
+                continue;
+            }
+
+            for (hash, path) in loose_objects(&directory)? {
+                let path = path.display();
+
+                let Some(data) = fs::read(path.to_string())
+                    .ok()
+                    .and_then(|compressed| decompress(&compressed).ok())
+                else {
+                    self.error(
+                        ERROR_OBJECT,
+                        &format!("{hash}: object corrupt or missing: {path}"),
+                    );
+
+                    continue;
+                };
+
+                if !self.options.connectivity_only {
+                    let actual = generate_object_id(&data)?;
+
+                    if actual != hash {
+                        self.error(
+                            ERROR_OBJECT,
+                            &format!("{actual}: hash-path mismatch, found at: {path}"),
+                        );
+
+                        continue;
+                    }
+                }
+
+                let Some((kind, content)) = split_header(&data) else {
+                    self.error(
+                        ERROR_OBJECT,
+                        &format!("{hash}: object corrupt or missing: {path}"),
+                    );
+
+                    continue;
+                };
+
+                self.add(&hash, kind, content, false);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks every pack, reporting an index that cannot be read rather than stopping
+    /// at it.
+    fn check_packs(&mut self) -> anyhow::Result<()> {
+        let paths: Vec<String> = object_directories()
+            .iter()
+            .flat_map(|directory| index_paths(&format!("{directory}/pack")))
+            .collect();
+
+        for path in paths {
+            let pack = match PackIndex::read(&path) {
+                Ok(pack) => pack,
+
+                Err(error) => {
+                    self.error(ERROR_PACK, &format!("{error}"));
+
+                    continue;
+                }
+            };
+
+            let name = &pack.pack_path;
+
+            if !self.options.connectivity_only {
+                if let Some(problem) = checksum_problem(&pack)? {
+                    self.error(ERROR_PACK, &format!("{name} {problem}"));
+                }
+            }
+
+            let promisor = Path::new(&name.replace(".pack", ".promisor")).exists();
+
+            for (id, &offset) in pack.hashes.iter().zip(&pack.offsets) {
+                let hash = to_hex_string(id);
+
+                let Ok((kind, content)) = pack.read_object(offset) else {
+                    self.error(
+                        ERROR_PACK,
+                        &format!("cannot unpack {hash} from {name} at offset {offset}"),
+                    );
+
+                    continue;
+                };
+
+                if !self.options.connectivity_only {
+                    let header = format!("{kind} {}\0", content.len());
+
+                    if generate_object_id(&[header.as_bytes(), &content].concat())? != hash {
+                        self.error(ERROR_PACK, &format!("packed {hash} from {name} is corrupt"));
+
+                        continue;
+                    }
+                }
+
+                if !self.objects.contains_key(&hash) {
+                    self.add(&hash, &kind, &content, promisor);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks an object unless only connectivity matters, and records what it refers
+    /// to. What objects of a promisor pack refer to may be missing.
+    fn add(&mut self, hash: &str, kind: &str, content: &[u8], promisor: bool) {
+        if !self.options.connectivity_only {
+            let problems = match kind {
+                "commit" => commit_problems(content),
+
+                "tag" => tag_problems(content),
+
+                "tree" => tree_problems(content, self.options.strict),
+
+                _ => Vec::new(),
+            };
+
+            for (severity, id, message) in problems {
+                self.report(kind, hash, severity, id, &message);
+            }
+        }
+
+        let mut links = links(kind, content);
+
+        // Parents of a shallow commit are not meant to be here
+        if self.shallow.contains(hash) {
+            links.retain(|(kind, _)| kind != "commit");
+        }
+
+        if promisor {
+            self.promised
+                .extend(links.iter().map(|(_, hash)| hash.clone()));
+        }
+
+        self.objects.insert(
+            hash.to_string(),
+            Object {
+                kind: kind.to_string(),
+                links,
+            },
+        );
+    }
+
+    fn check_connectivity(&mut self) -> anyhow::Result<()> {
+        let mut roots = self.check_refs()?;
+
+        if !self.options.lost_found {
+            roots.extend(self.check_reflogs()?);
+        }
+
+        let mut reachable = HashSet::new();
+
+        let mut missing = BTreeMap::new();
+
+        if Index::exists() {
+            for entry in Index::read()?.entries {
+                if entry.mode == TreeFileModes::Gitlink {
+                    continue;
+                }
+
+                if !self.objects.contains_key(&entry.hash) && !self.promised.contains(&entry.hash) {
+                    missing.insert(entry.hash.clone(), "blob".to_string());
+                }
+
+                reachable.insert(entry.hash);
+            }
+        }
+
+        let mut queue: VecDeque<String> = roots
+            .into_iter()
+            .filter(|hash| reachable.insert(hash.clone()))
+            .collect();
+
+        while let Some(hash) = queue.pop_front() {
+            let Some(object) = self.objects.get(&hash) else {
+                continue;
+            };
+
+            for (kind, link) in &object.links {
+                if !reachable.insert(link.clone()) {
+                    continue;
+                }
+
+                if self.objects.contains_key(link) {
+                    queue.push_back(link.clone());
+                } else if !self.promised.contains(link) {
+                    // An object that is there but could not be read is only missing
+                    if !object_exists(link) {
+                        println!(
+                            "broken link from {:>7} {hash}\n              to {kind:>7} {link}",
+                            object.kind
+                        );
+                    }
+
+                    missing.insert(link.clone(), kind.clone());
+                }
+            }
+        }
+
+        let used: HashSet<&String> = self
+            .objects
+            .values()
+            .flat_map(|object| object.links.iter().map(|(_, hash)| hash))
+            .collect();
+
+        let mut report = BTreeMap::new();
+
+        for (hash, kind) in &missing {
+            report.insert(hash, format!("missing {kind} {hash}"));
+        }
+
+        for (hash, object) in &self.objects {
+            let kind = &object.kind;
+
+            if reachable.contains(hash) {
+                continue;
+            }
+
+            if self.options.unreachable {
+                report.insert(hash, format!("unreachable {kind} {hash}"));
+
+                continue;
+            }
+
+            if used.contains(hash) {
+                continue;
+            }
+
+            if self.options.dangling {
+                report.insert(hash, format!("dangling {kind} {hash}"));
+            }
+
+            if self.options.lost_found {
+                write_lost_found(hash, kind)?;
+            }
+        }
+
+        for line in report.values() {
+            println!("{line}");
+        }
+
+        if !missing.is_empty() {
+            self.errors |= ERROR_REACHABLE;
+        }
+
+        Ok(())
+    }
+
+    /// Checks `HEAD` and the refs, returning the objects they point at.
+    fn check_refs(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut refs = list_refs("refs/")?;
+
+        if let Some(head) = read_ref(HEAD)? {
+            match head.strip_prefix("ref: ") {
+                Some(target) if !target.starts_with("refs/heads/") => self.error(
+                    ERROR_REFS,
+                    &format!("HEAD points to something strange ({target})"),
+                ),
+
+                Some(target) if resolve_ref(HEAD)?.is_none() => eprintln!(
+                    "notice: HEAD points to an unborn branch ({})",
+                    &target["refs/heads/".len()..]
+                ),
+
+                Some(_) => {}
+
+                None if head == NULL_HASH => {
+                    self.error(ERROR_REFS, "HEAD: detached HEAD points at nothing")
+                }
+
+                None => refs.push((HEAD.to_string(), head)),
+            }
+        }
+
+        if refs.is_empty() {
+            eprintln!("notice: No default references");
+        }
+
+        let mut roots = Vec::new();
+
+        for (name, hash) in refs {
+            let Some(object) = self.objects.get(&hash) else {
+                if !self.promised.contains(&hash) {
+                    self.error(
+                        ERROR_REACHABLE,
+                        &format!("{name}: invalid sha1 pointer {hash}"),
+                    );
+                }
+
+                continue;
+            };
+
+            if object.kind != "commit" && (name == HEAD || name.starts_with("refs/heads/")) {
+                self.error(ERROR_REFS, &format!("{name}: not a commit"));
+            }
+
+            roots.push(hash);
+        }
+
+        Ok(roots)
+    }
+
+    /// Checks the old and new objects of every reflog entry, returning those present.
+    fn check_reflogs(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut roots = Vec::new();
+
+        for name in list_reflogs()? {
+            for entry in read_reflog(&name)? {
+                for hash in [entry.old, entry.new] {
+                    if hash == NULL_HASH {
+                        continue;
+                    }
+
+                    if self.objects.contains_key(&hash) {
+                        roots.push(hash);
+                    } else if !self.promised.contains(&hash) {
+                        self.error(
+                            ERROR_REACHABLE,
+                            &format!("{name}: invalid reflog entry {hash}"),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(roots)
+    }
+
+    /// Reports a problem in an object, as an error or a warning.
+    fn report(&mut self, kind: &str, hash: &str, severity: Severity, id: &str, message: &str) {
+        match severity {
+            Severity::Error => {}
+
+            Severity::Warning if self.options.strict => {}
+
+            Severity::Warning | Severity::Info => {
+                eprintln!("warning in {kind} {hash}: {id}: {message}");
+
+                return;
+            }
+        }
+
+        eprintln!("error in {kind} {hash}: {id}: {message}");
+
+        self.errors |= ERROR_OBJECT;
+    }
+
+    fn error(&mut self, bit: i32, message: &str) {
+        eprintln!("error: {message}");
+
+        self.errors |= bit;
+    }
+}
+
+/// Splits a loose object's `<type> <size>\0<content>`, checking the type and size.
+fn split_header(data: &[u8]) -> Option<(&str, &[u8])> {
+    let end = data.iter().position(|&byte| byte == 0)?;
+
+    let (kind, size) = std::str::from_utf8(&data[..end]).ok()?.split_once(' ')?;
+
+    let content = &data[end + 1..];
+
+    (["blob", "tree", "commit", "tag"].contains(&kind) && size.parse() == Ok(content.len()))
+        .then_some((kind, content))
+}
+
+/// What is wrong with a pack's trailing checksum, or its copy in the index.
+fn checksum_problem(pack: &PackIndex) -> anyhow::Result<Option<&'static str>> {
+    let data = fs::read(&pack.pack_path)?;
+
+    let index = fs::read(pack.pack_path.replace(".pack", ".idx"))?;
+
+    if data.len() < 32 || index.len() < 40 {
+        return Ok(Some("pack checksum mismatch"));
+    }
+
+    let (body, checksum) = data.split_at(data.len() - 20);
+
+    if Sha1::digest(body).as_slice() != checksum {
+        return Ok(Some("pack checksum mismatch"));
+    }
+
+    if &index[index.len() - 40..index.len() - 20] != checksum {
+        return Ok(Some("pack checksum does not match its index"));
+    }
+
+    Ok(None)
+}
+
+/// `(type, hash)` of the objects an object refers to. Submodule commits in trees are
+/// not followed.
+fn links(kind: &str, content: &[u8]) -> Vec<(String, String)> {
+    let link = |kind: &str, hash: &str| (kind.to_string(), hash.to_string());
+
+    if kind == "tree" {
+        return tree_entries(content)
+            .0
+            .into_iter()
+            .filter(|entry| {
+                TreeFileModes::from_bits(entry.mode).ok() != Some(TreeFileModes::Gitlink)
+            })
+            .map(|entry| match entry.is_directory() {
+                true => link("tree", &entry.hash),
+
+                false => link("blob", &entry.hash),
+            })
+            .collect();
+    }
+
+    let text = String::from_utf8_lossy(content);
+
+    let headers = text.split("\n\n").next().unwrap_or_default();
+
+    let mut links = Vec::new();
+
+    let mut tagged = None;
+
+    for line in headers.lines() {
+        let Some((key, value)) = line.split_once(' ') else {
+            continue;
+        };
+
+        match (kind, key) {
+            ("commit", "tree") => links.push(link("tree", value)),
+
+            ("commit", "parent") => links.push(link("commit", value)),
+
+            ("tag", "object") => tagged = Some(value),
+
+            ("tag", "type") => {
+                if let Some(object) = tagged {
+                    links.push(link(value, object));
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    links.retain(|(_, hash)| hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()));
+
+    links
+}
+
+/// The entries of a tree up to the first malformed one, and what is wrong with that one.
+fn tree_entries(content: &[u8]) -> (Vec<TreeEntry<'_>>, Option<&'static str>) {
+    let mut entries = Vec::new();
+
+    let mut rest = content;
+
+    while !rest.is_empty() {
+        // Whatever the name, a NUL must come just before the last hash
+        if rest.len() < 23 || rest[rest.len() - 21] != 0 {
+            return (entries, Some("too-short tree object"));
+        }
+
+        let space = rest
+            .iter()
+            .position(|byte| !(b'0'..=b'7').contains(byte))
+            .filter(|&end| end > 0 && rest[end] == b' ');
+
+        let Some(space) = space else {
+            return (entries, Some("malformed mode in tree entry"));
+        };
+
+        let nul = space
+            + 1
+            + rest[space + 1..]
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(0);
+
+        if nul == space + 1 {
+            return (entries, Some("empty filename in tree entry"));
+        }
+
+        entries.push(TreeEntry {
+            mode_text: &rest[..space],
+            mode: rest[..space]
+                .iter()
+                .fold(0, |mode, byte| (mode << 3) | (byte - b'0') as u32),
+            name: &rest[space + 1..nul],
+            hash: to_hex_string(&rest[nul + 1..nul + 21]),
+        });
+
+        rest = &rest[nul + 21..];
+    }
+
+    (entries, None)
+}
+
+fn tree_problems(content: &[u8], strict: bool) -> Vec<Problem> {
+    let (entries, malformed) = tree_entries(content);
+
+    let mut problems = Vec::new();
+
+    if let Some(reason) = malformed {
+        eprintln!("error: {reason}");
+
+        problems.push(error("badTree", "cannot be parsed as a tree"));
+    }
+
+    let mut names = HashSet::new();
+
+    let (mut null_hash, mut full_path, mut dot, mut dotdot, mut dotgit) =
+        (false, false, false, false, false);
+
+    let (mut zero_padded, mut bad_mode, mut duplicates, mut unsorted) =
+        (false, false, false, false);
+
+    for (index, entry) in entries.iter().enumerate() {
+        let name = entry.name;
+
+        null_hash |= entry.hash == NULL_HASH;
+        full_path |= name.contains(&b'/');
+        dot |= name == b".";
+        dotdot |= name == b"..";
+        dotgit |= name.eq_ignore_ascii_case(b".git");
+        zero_padded |= entry.mode_text[0] == b'0';
+        duplicates |= !names.insert(name);
+
+        bad_mode |= match entry.mode {
+            0o100664 => strict,
+
+            mode => TreeFileModes::from_bits(mode).map_or(true, |kind| kind.bits() != mode),
+        };
+
+        if let Some(previous) = index.checked_sub(1).map(|previous| &entries[previous]) {
+            // Directories sort as if their names ended with a slash
+            let key = |entry: &TreeEntry| {
+                [entry.name, if entry.is_directory() { b"/" } else { b"" }].concat()
+            };
+
+            unsorted |= previous.name != name && key(previous) > key(entry);
+        }
+    }
+
+    let found = [
+        (
+            null_hash,
+            Severity::Warning,
+            "nullSha1",
+            "contains entries pointing to null sha1",
+        ),
+        (
+            full_path,
+            Severity::Warning,
+            "fullPathname",
+            "contains full pathnames",
+        ),
+        (dot, Severity::Warning, "hasDot", "contains '.'"),
+        (dotdot, Severity::Warning, "hasDotdot", "contains '..'"),
+        (dotgit, Severity::Warning, "hasDotgit", "contains '.git'"),
+        (
+            zero_padded,
+            Severity::Warning,
+            "zeroPaddedFilemode",
+            "contains zero-padded file modes",
+        ),
+        (
+            bad_mode,
+            Severity::Info,
+            "badFilemode",
+            "contains bad file modes",
+        ),
+        (
+            duplicates,
+            Severity::Error,
+            "duplicateEntries",
+            "contains duplicate file entries",
+        ),
+        (
+            unsorted,
+            Severity::Error,
+            "treeNotSorted",
+            "not properly sorted",
+        ),
+    ];
+
+    for (flag, severity, id, message) in found {
+        if flag {
+            problems.push((severity, id, message.to_string()));
+        }
+    }
+
+    problems
+}
+
+fn commit_problems(content: &[u8]) -> Vec<Problem> {
+    if let Some(problem) = header_problem(content) {
+        return vec![problem];
+    }
+
+    let Some(rest) = content.strip_prefix(b"tree ") else {
+        return vec![error(
+            "missingTree",
+            "invalid format - expected 'tree' line",
+        )];
+    };
+
+    let Some(mut rest) = after_hash_line(rest) else {
+        return vec![error(
+            "badTreeSha1",
+            "invalid 'tree' line format - bad sha1",
+        )];
+    };
+
+    while let Some(parent) = rest.strip_prefix(b"parent ") {
+        let Some(next) = after_hash_line(parent) else {
+            return vec![error(
+                "badParentSha1",
+                "invalid 'parent' line format - bad sha1",
+            )];
+        };
+
+        rest = next;
+    }
+
+    let mut authors = 0;
+
+    while let Some(author) = rest.strip_prefix(b"author ") {
+        authors += 1;
+
+        match check_ident(author) {
+            Ok(next) => rest = next,
+
+            Err(problem) => return vec![problem],
+        }
+    }
+
+    match authors {
+        0 => {
+            return vec![error(
+                "missingAuthor",
+                "invalid format - expected 'author' line",
+            )]
+        }
+
+        1 => {}
+
+        _ => {
+            return vec![error(
+                "multipleAuthors",
+                "invalid format - multiple 'author' lines",
+            )]
+        }
+    }
+
+    let Some(committer) = rest.strip_prefix(b"committer ") else {
+        return vec![error(
+            "missingCommitter",
+            "invalid format - expected 'committer' line",
+        )];
+    };
+
+    if let Err(problem) = check_ident(committer) {
+        return vec![problem];
+    }
+
+    match content.contains(&0) {
+        true => vec![(
+            Severity::Warning,
+            "nulInCommit",
+            "NUL byte in the commit object body".to_string(),
+        )],
+
+        false => Vec::new(),
+    }
+}
+
+fn tag_problems(content: &[u8]) -> Vec<Problem> {
+    if let Some(problem) = header_problem(content) {
+        return vec![problem];
+    }
+
+    let Some(rest) = content.strip_prefix(b"object ") else {
+        return vec![error(
+            "missingObject",
+            "invalid format - expected 'object' line",
+        )];
+    };
+
+    let Some(rest) = after_hash_line(rest) else {
+        return vec![error(
+            "badObjectSha1",
+            "invalid 'object' line format - bad sha1",
+        )];
+    };
+
+    let Some(rest) = rest.strip_prefix(b"type ") else {
+        return vec![error(
+            "missingTypeEntry",
+            "invalid format - expected 'type' line",
+        )];
+    };
+
+    let Some((kind, rest)) = split_line(rest) else {
+        return vec![error(
+            "missingType",
+            "invalid format - unexpected end after 'type' line",
+        )];
+    };
+
+    if ![&b"blob"[..], b"tree", b"commit", b"tag"].contains(&kind) {
+        return vec![error("badType", "invalid 'type' value")];
+    }
+
+    let Some(rest) = rest.strip_prefix(b"tag ") else {
+        return vec![error(
+            "missingTagEntry",
+            "invalid format - expected 'tag' line",
+        )];
+    };
+
+    let Some((name, rest)) = split_line(rest) else {
+        return vec![error(
+            "missingTag",
+            "invalid format - unexpected end after 'type' line",
+        )];
+    };
+
+    let mut problems = Vec::new();
+
+    let name = String::from_utf8_lossy(name);
+
+    if !check_ref_format(&format!("refs/tags/{name}"), false) {
+        problems.push((
+            Severity::Info,
+            "badTagName",
+            format!("invalid 'tag' name: {name}"),
+        ));
+    }
+
+    match rest.strip_prefix(b"tagger ") {
+        Some(tagger) => {
+            if let Err(problem) = check_ident(tagger) {
+                problems.push(problem);
+            }
+        }
+
+        None => problems.push((
+            Severity::Info,
+            "missingTaggerEntry",
+            "invalid format - expected 'tagger' line".to_string(),
+        )),
+    }
+
+    problems
+}
+
+/// A NUL in the header, or a header without the blank line (or final newline) that
+/// ends it.
+fn header_problem(content: &[u8]) -> Option<Problem> {
+    for (offset, &byte) in content.iter().enumerate() {
+        match byte {
+            0 => {
+                return Some(error(
+                    "nulInHeader",
+                    &format!("unterminated header: NUL at offset {offset}"),
+                ))
+            }
+
+            b'\n' if content.get(offset + 1) == Some(&b'\n') => return None,
+
+            _ => {}
+        }
+    }
+
+    match content.last() {
+        Some(b'\n') => None,
+
+        _ => Some(error("unterminatedHeader", "unterminated header")),
+    }
+}
+
+/// Checks `Name <email> <timestamp> <timezone>\n` as git does, returning what follows.
+fn check_ident(value: &[u8]) -> Result<&[u8], Problem> {
+    let end = value
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(value.len(), |end| end + 1);
+
+    let (line, rest) = value.split_at(end);
+
+    let bad = |id, message: &str| {
+        Err(error(
+            id,
+            &format!("invalid author/committer line - {message}"),
+        ))
+    };
+
+    let delimiter = |from: usize| {
+        from + line[from..]
+            .iter()
+            .position(|byte| b"<>\n".contains(byte))
+            .unwrap_or(line.len() - from)
+    };
+
+    if line.first() == Some(&b'<') {
+        return bad("missingNameBeforeEmail", "missing space before email");
+    }
+
+    let mut position = delimiter(0);
+
+    match line.get(position) {
+        Some(b'>') => return bad("badName", "bad name"),
+
+        Some(b'<') => {}
+
+        _ => return bad("missingEmail", "missing email"),
+    }
+
+    if line[position - 1] != b' ' {
+        return bad("missingSpaceBeforeEmail", "missing space before email");
+    }
+
+    position = delimiter(position + 1);
+
+    if line.get(position) != Some(&b'>') {
+        return bad("badEmail", "bad email");
+    }
+
+    position += 1;
+
+    if line.get(position) != Some(&b' ') {
+        return bad("missingSpaceBeforeDate", "missing space before date");
+    }
+
+    position += 1;
+
+    let digits = line[position..]
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+
+    if line.get(position) == Some(&b'0') && line.get(position + 1) != Some(&b' ') {
+        return bad("zeroPaddedDate", "zero-padded date");
+    }
+
+    let date = String::from_utf8_lossy(&line[position..position + digits]);
+
+    if digits > 0 && date.parse::<i64>().is_err() {
+        return bad("badDateOverflow", "date causes integer overflow");
+    }
+
+    if digits == 0 || line.get(position + digits) != Some(&b' ') {
+        return bad("badDate", "bad date");
+    }
+
+    position += digits + 1;
+
+    let timezone = &line[position.min(line.len())..];
+
+    let valid_timezone = timezone.len() == 6
+        && matches!(timezone[0], b'+' | b'-')
+        && timezone[1..5].iter().all(u8::is_ascii_digit)
+        && timezone[5] == b'\n';
+
+    match valid_timezone {
+        true => Ok(rest),
+
+        false => bad("badTimezone", "bad time zone"),
+    }
+}
+
+/// What follows a `<hash>\n` line, `None` when it isn't one.
+fn after_hash_line(value: &[u8]) -> Option<&[u8]> {
+    let line = value.get(..41)?;
+
+    (line[..40].iter().all(u8::is_ascii_hexdigit) && line[40] == b'\n').then_some(&value[41..])
+}
+
+/// A line without its newline, and what follows it.
+fn split_line(value: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = value.iter().position(|&byte| byte == b'\n')?;
+
+    Some((&value[..end], &value[end + 1..]))
+}
+
+fn error(id: &'static str, message: &str) -> Problem {
+    (Severity::Error, id, message.to_string())
+}
+
+/// Saves a dangling object in `lost-found/commit/` or `lost-found/other/`: its content
+/// for a blob, its hash for anything else.
+fn write_lost_found(hash: &str, kind: &str) -> anyhow::Result<()> {
+    let directory = match kind {
+        "commit" => "commit",
+
+        _ => "other",
+    };
+
+    let path = git_path(&format!("lost-found/{directory}/{hash}"));
+
+    fs::create_dir_all(git_path(&format!("lost-found/{directory}")))?;
+
+    match kind {
+        "blob" => {
+            let (_, content) = GitObject::read_raw(hash)?;
+
+            write_to_file(&path, &content)
+        }
+
+        _ => write_to_file(&path, format!("{hash}\n").as_bytes()),
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::bitmap;
+use crate::config::Config;
+use crate::diff::NULL_HASH;
+use crate::git_objects::GitObject;
+use crate::index::Index;
+use crate::midx::{midx_path, MultiPackIndex};
+use crate::pack::{
+    index_pack, pack_dir, packed_object_exists, reload_packs, write_pack, PackIndex,
+};
+use crate::reflog::{list_reflogs, read_reflog};
+use crate::refs::{git_path, list_refs, resolve_ref, HEAD};
+use crate::revision::CommitWalker;
+use crate::utils::{
+    compress, create_object_directory, object_exists, to_hex_string, write_to_file,
+};
+
+/// Loose objects in any fan-out directory beyond this many mean `gc --auto` has work.
+const DEFAULT_AUTO_THRESHOLD: i64 = 6700;
+
+/// More packs than this mean `gc --auto` consolidates them.
+const DEFAULT_AUTO_PACK_LIMIT: i64 = 50;
+
+/// Every object reachable from the refs, `HEAD`, the reflogs and the index, commits
+/// first as a pack wants them. Objects a partial clone left out are skipped.
+pub fn reachable_objects() -> anyhow::Result<Vec<String>> {
+    let mut roots: Vec<String> = list_refs("refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+
+    roots.extend(resolve_ref(HEAD)?);
+
+    for name in list_reflogs()? {
+        for entry in read_reflog(&name)? {
+            roots.extend([entry.old, entry.new]);
+        }
+    }
+
+    roots.extend(Index::read()?.entries.into_iter().map(|entry| entry.hash));
+
+    let mut seen = HashSet::new();
+
+    roots.retain(|hash| hash != NULL_HASH && object_exists(hash) && seen.insert(hash.clone()));
+
+    let mut objects = CommitWalker::new().objects_between(&[], &roots)?;
+
+    objects.retain(|hash| object_exists(hash));
+
+    Ok(objects)
+}
+
+/// `(hash, path)` of every loose object in an object directory.
+pub fn loose_objects(objects_directory: &str) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let mut objects = Vec::new();
+
+    for directory in fs::read_dir(objects_directory)?.flatten() {
+        let prefix = directory.file_name().to_string_lossy().to_string();
+
+        if prefix.len() != 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+
+        for file in fs::read_dir(directory.path())?.flatten() {
+            let rest = file.file_name().to_string_lossy().to_string();
+
+            if rest.len() == 38 && rest.chars().all(|c| c.is_ascii_hexdigit()) {
+                objects.push((format!("{prefix}{rest}"), file.path()));
+            }
+        }
+    }
+
+    objects.sort();
+
+    Ok(objects)
+}
+
+/// Packs in the repository's own pack directory, as their paths without extension,
+/// along with whether a `.keep` file protects them.
+fn local_packs() -> anyhow::Result<Vec<(String, bool)>> {
+    let mut local = Vec::new();
+
+    let Ok(entries) = fs::read_dir(pack_dir()) else {
+        return Ok(local);
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path().to_string_lossy().to_string();
+
+        if let Some(base) = path.strip_suffix(".pack") {
+            local.push((
+                base.to_string(),
+                Path::new(&format!("{base}.keep")).exists(),
+            ));
+        }
+    }
+
+    local.sort();
+
+    Ok(local)
+}
+
+/// Packs the reachable objects: with `all` every one of them into a single new pack,
+/// otherwise just those not in a pack yet. With `delete` what became redundant goes: loose
+/// objects now packed and, with `all`, the old packs. Unreachable objects of those packs
+/// are dropped, or kept as loose objects as old as their pack with `keep_unreachable`
+/// so that `prune` decides on them. With `all` and `write_bitmap` the new pack gets a
+/// reachability bitmap. Returns whether there was anything to pack.
+pub fn repack(
+    all: bool,
+    delete: bool,
+    keep_unreachable: bool,
+    write_bitmap: bool,
+) -> anyhow::Result<bool> {
+    let reachable = reachable_objects()?;
+
+    let objects: Vec<String> = match all {
+        true => reachable.clone(),
+
+        false => reachable
+            .iter()
+            .filter(|hash| !packed_object_exists(hash))
+            .cloned()
+            .collect(),
+    };
+
+    let packed = match objects.is_empty() {
+        true => None,
+
+        false => Some(format!(
+            "{}/pack-{}",
+            pack_dir(),
+            index_pack(&write_pack(&objects)?)?
+        )),
+    };
+
+    if let Some(packed) = &packed {
+        let path = format!("{packed}.bitmap");
+
+        if all && write_bitmap {
+            // Objects a partial clone lacks leave the pack without full closure
+            if let Err(error) = bitmap::write(&format!("{packed}.idx")) {
+                eprintln!("warning: {error}");
+            }
+        } else if Path::new(&path).exists() {
+            // The same objects packed again keep the name, but not the bitmap
+            fs::remove_file(path)?;
+        }
+    }
+
+    if delete && all {
+        let reachable: HashSet<&String> = reachable.iter().collect();
+
+        let midx = MultiPackIndex::read(&pack_dir()).ok();
+
+        for (base, kept) in local_packs()? {
+            if kept || Some(&base) == packed.as_ref() {
+                continue;
+            }
+
+            // A multi-pack-index naming a deleted pack is no longer valid
+            let name = format!("{}.idx", base.rsplit('/').next().unwrap_or_default());
+
+            if midx
+                .as_ref()
+                .is_some_and(|midx| midx.pack_names.binary_search(&name).is_ok())
+            {
+                let path = midx_path(&pack_dir());
+
+                if Path::new(&path).exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+
+            if keep_unreachable {
+                loosen_unreachable(&base, &reachable)?;
+            }
+
+            // What a promisor remote promised stays promised
+            if Path::new(&format!("{base}.promisor")).exists() {
+                if let Some(packed) = &packed {
+                    write_to_file(&format!("{packed}.promisor"), b"")?;
+                }
+            }
+
+            for extension in ["pack", "idx", "bitmap", "promisor"] {
+                let path = format!("{base}.{extension}");
+
+                if Path::new(&path).exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+
+        reload_packs();
+    }
+
+    if delete {
+        prune_packed()?;
+    }
+
+    Ok(packed.is_some())
+}
+
+/// `repack.writeBitmaps` (formerly `pack.writeBitmaps`): whether packing everything
+/// writes a reachability bitmap. Unset, it does in bare repositories, which serve clones.
+pub fn write_bitmaps_setting(config: &Config) -> Option<bool> {
+    config
+        .get("repack.writeBitmaps")
+        .or(config.get("pack.writeBitmaps"))
+        .map(|value| value == "true")
+}
+
+/// Writes the unreachable objects of a pack about to go as loose objects, dated with the
+/// pack's modification time.
+fn loosen_unreachable(base: &str, reachable: &HashSet<&String>) -> anyhow::Result<()> {
+    let index = PackIndex::read(&format!("{base}.idx"))?;
+
+    let modified = fs::metadata(&index.pack_path)?.modified()?;
+
+    for id in &index.hashes {
+        let hash = to_hex_string(id);
+
+        if reachable.contains(&hash) {
+            continue;
+        }
+
+        let path = create_object_directory(&hash)?;
+
+        if Path::new(&path).exists() {
+            continue;
+        }
+
+        let (kind, content) = GitObject::read_raw(&hash)?;
+
+        let data = [format!("{kind} {}\0", content.len()).as_bytes(), &content].concat();
+
+        write_to_file(&path, &compress(&data)?)?;
+
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(modified)?;
+    }
+
+    Ok(())
+}
+
+/// Removes loose objects that a pack holds as well.
+pub fn prune_packed() -> anyhow::Result<()> {
+    for (hash, path) in loose_objects(&git_path("objects"))? {
+        if packed_object_exists(&hash) {
+            fs::remove_file(&path)?;
+
+            remove_empty_directory(&path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Unreachable loose objects last modified before `expire` (seconds since the epoch),
+/// with their types.
+pub fn prunable_objects(expire: i64) -> anyhow::Result<Vec<(String, String, PathBuf)>> {
+    let reachable: HashSet<String> = reachable_objects()?.into_iter().collect();
+
+    let mut prunable = Vec::new();
+
+    for (hash, path) in loose_objects(&git_path("objects"))? {
+        if reachable.contains(&hash) {
+            continue;
+        }
+
+        let modified = fs::metadata(&path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |age| age.as_secs() as i64);
+
+        if modified <= expire {
+            let kind = GitObject::read_raw(&hash).map_or_else(|_| "unknown".to_string(), |o| o.0);
+
+            prunable.push((hash, kind, path));
+        }
+    }
+
+    Ok(prunable)
+}
+
+/// Removes the fan-out directory of a removed loose object once it is empty.
+pub fn remove_empty_directory(path: &Path) {
+    if let Some(directory) = path.parent() {
+        let _ = fs::remove_dir(directory);
+    }
+}
+
+/// What makes `gc --auto` run.
+pub enum AutoGc {
+    /// More loose objects than `gc.auto`, estimated from one fan-out directory.
+    LooseObjects,
+
+    /// More packs than `gc.autoPackLimit`, which are then consolidated.
+    Packs,
+}
+
+/// Whether `gc --auto` has work to do, and why.
+pub fn auto_gc_reason(config: &Config) -> anyhow::Result<Option<AutoGc>> {
+    let setting = |key: &str, default: i64| -> anyhow::Result<i64> {
+        match config.get(key) {
+            Some(value) => Ok(value.parse()?),
+
+            None => Ok(default),
+        }
+    };
+
+    let threshold = setting("gc.auto", DEFAULT_AUTO_THRESHOLD)?;
+
+    if threshold <= 0 {
+        return Ok(None);
+    }
+
+    let pack_limit = setting("gc.autoPackLimit", DEFAULT_AUTO_PACK_LIMIT)?;
+
+    let unkept = local_packs()?.iter().filter(|(_, kept)| !kept).count() as i64;
+
+    if pack_limit > 0 && unkept > pack_limit {
+        return Ok(Some(AutoGc::Packs));
+    }
+
+    let sample = fs::read_dir(git_path("objects/17"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| entry.file_name().len() == 38)
+                .count()
+        })
+        .unwrap_or(0) as i64;
+
+    Ok((sample > (threshold + 255) / 256).then_some(AutoGc::LooseObjects))
+}
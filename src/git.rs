@@ -1,7 +1,87 @@
-use anyhow::{bail, ensure};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashSet};
+use std::path::Path;
+use std::process::Command;
 
+use anyhow::{anyhow, bail, ensure};
+
+use crate::bitmap::PackBitmap;
+use crate::bundle::{self, Bundle};
+use crate::commit_graph::{self, Source, Split, WriteOptions};
+use crate::config::{
+    add_local, copy_section, rename_section, replace_local, set_local, unset_local, Config,
+};
+use crate::diff::{
+    diff_trees, flatten_tree, parse_rename_score, resolve_tree, DetectMode, FileEntry,
+    RenameOptions,
+};
+use crate::fsck::{self, FsckOptions};
+use crate::gc::{self, AutoGc};
+use crate::git_objects::Signature;
+use crate::index::{Index, IndexEntry};
+use crate::merge::{
+    merge_commits, merge_trees, write_tree_from_paths, ConflictStyle, MergeOptions,
+};
+use crate::midx;
+use crate::pack;
+use crate::promisor::{self, fetch_missing, missing_blobs, promisor_remote};
+use crate::reflog::{list_reflogs, read_reflog, write_reflog, ReflogEntry};
+use crate::refs::{
+    check_ref_format, delete_ref, detach_head, expand_ref, find_repository, git_path, head_target,
+    in_repository, is_bare, is_valid_branch_name, list_refs, move_reflog, pack_refs, read_ref,
+    ref_candidates, resolve_commit, resolve_ref, resolve_revision, set_git_dir, shallow_commits,
+    shorten_ref, update_ref, write_symbolic_ref, HEAD,
+};
+use crate::remote::{
+    display_url, find_remote, is_excluded, open_transport, push_updates, remote_config,
+    remote_names, PushOptions, PushUpdate, Refspec, RemoteConfig,
+};
+use crate::revision::{is_fast_forward, CommitWalker};
+use crate::sequencer::{
+    self, mainline_parent, pick_commit, rebase_dir, rebase_path, Action, RebaseState,
+    SequencerOptions, TodoItem,
+};
+use crate::stats;
+use crate::transport::{
+    Connection, FetchOptions, LocalTransport, PushConnection, RefUpdate, RemoteRef, INFINITE_DEPTH,
+    ZERO_HASH,
+};
 use crate::utils::*;
-use crate::{cmd_options::Commands, git_objects::GitObject};
+use crate::worktree::{
+    checkout_merge_result, checkout_tree, is_worktree_modified, list_untracked_files,
+    matches_pathspec, read_index_or_tree, remove_worktree_file, store_worktree_file,
+    write_worktree_file,
+};
+use crate::{
+    cmd_options::Commands, git_objects::GitObject, http_backend, receive_pack, upload_pack,
+};
+
+const STASH_REF: &str = "refs/stash";
+
+/// Advice git gives when push refuses to update a ref.
+const ADVICE_PULL_BEFORE_PUSH: &str = "\
+Updates were rejected because the tip of your current branch is behind
+its remote counterpart. Integrate the remote changes (e.g.
+'git pull ...') before pushing again.
+See the 'Note about fast-forwards' in 'git push --help' for details.";
+
+const ADVICE_CHECKOUT_PULL_PUSH: &str = "\
+Updates were rejected because a pushed branch tip is behind its remote
+counterpart. Check out this branch and integrate the remote changes
+(e.g. 'git pull ...') before pushing again.
+See the 'Note about fast-forwards' in 'git push --help' for details.";
+
+const ADVICE_FETCH_FIRST: &str = "\
+Updates were rejected because the remote contains work that you do
+not have locally. This is usually caused by another repository pushing
+to the same ref. You may want to first integrate the remote changes
+(e.g., 'git pull ...') before pushing again.
+See the 'Note about fast-forwards' in 'git push --help' for details.";
+
+const ADVICE_REF_ALREADY_EXISTS: &str =
+    "Updates were rejected because the tag already exists in the remote.";
+
+/// Most commits offered to a remote while negotiating a fetch.
+const MAX_HAVES: usize = 1024;
 
 pub struct Git {}
 
@@ -13,11 +93,7 @@ impl Git {
     pub fn execute(&self, command: &Commands) -> anyhow::Result<()> {
         match command {
             Commands::Init => {
-                create_directory(".git")?;
-                create_directory(".git/refs")?;
-                create_directory(".git/objects")?;
-
-                write_to_file(".git/HEAD", b"ref: refs/heads/main\n")?;
+                init_repository()?;
 
                 println!("Initialized git directory")
             }
@@ -37,9 +113,9 @@ impl Git {
                     bail!("Invalid Command");
                 };
 
-                let compressed_content = read_object(hash.as_str())?;
+                let hash = resolve_revision(hash)?;
 
-                let object = GitObject::from_file_content(hash.to_owned(), compressed_content)?;
+                let object = GitObject::from_hash(&hash)?;
 
                 if let Some(obj_type) = object_type {
                     ensure!(object.get_type() == obj_type.as_str(), "Invalid object");
@@ -73,9 +149,9 @@ impl Git {
             }
 
             Commands::LsTree { name_only, hash } => {
-                let compressed_content = read_object(hash)?;
+                let hash = resolve_tree(&resolve_revision(hash)?)?;
 
-                let object = GitObject::from_file_content(hash.clone(), compressed_content)?;
+                let object = GitObject::from_hash(&hash)?;
 
                 object.print_content(*name_only);
             }
@@ -90,39 +166,5584 @@ impl Git {
 
             Commands::CommitTree {
                 message,
-                parent,
+                parents,
                 tree,
             } => {
-                let tree_content = read_object(tree)?;
-
-                let tree_object = GitObject::from_file_content(tree.clone(), tree_content)?;
+                let tree_object = GitObject::from_hash(tree)?;
 
                 ensure!(tree_object.is_tree(), "hash must be a tree object");
 
-                let mut parents: Option<Vec<GitObject>> = None;
-
-                if let Some(parent) = parent {
-                    let parent_content = read_object(parent)?;
-
-                    let parent_object =
-                        GitObject::from_file_content(parent.clone(), parent_content)?;
+                for parent in parents {
+                    let parent_object = GitObject::from_hash(parent)?;
 
                     ensure!(parent_object.is_commit(), "parent is not a commit object");
-
-                    parents = Some(vec![parent_object])
                 }
 
-                let commit =
-                    GitObject::new_commit(message.as_str(), tree.as_str(), tree_object, parents);
+                let commit = GitObject::new_commit(
+                    &format!("{message}\n"),
+                    tree,
+                    parents.clone(),
+                    Signature::current("AUTHOR")?,
+                    Signature::current("COMMITTER")?,
+                )?;
 
                 commit.write_to_file()?;
 
                 print!("{}", commit.get_hash());
             }
 
-            _ => println!("Unsupported command: {}", command),
-        }
+            Commands::DiffTree {
+                recursive,
+                name_only,
+                name_status,
+                find_renames,
+                find_copies,
+                find_copies_harder,
+                rename_limit,
+                tree_ish,
+                other_tree_ish,
+            } => {
+                let mut options = RenameOptions {
+                    rename_limit: *rename_limit,
+                    find_copies_harder: *find_copies_harder,
+                    ..Default::default()
+                };
 
-        Ok(())
-    }
+                if let Some(score) = find_renames {
+                    options.detect = DetectMode::Renames;
+                    options.minimum_score = parse_rename_score(score)?;
+                }
+
+                if let Some(score) = find_copies {
+                    options.detect = DetectMode::Copies;
+                    options.minimum_score = parse_rename_score(score)?;
+                }
+
+                if *find_copies_harder {
+                    options.detect = DetectMode::Copies;
+                }
+
+                let (old_tree, new_tree) = match other_tree_ish {
+                    Some(other) => (
+                        Some(resolve_tree(&resolve_revision(tree_ish)?)?),
+                        resolve_tree(&resolve_revision(other)?)?,
+                    ),
+
+                    // A single commit is compared against its first parent
+                    None => {
+                        let tree_ish = &resolve_commit(tree_ish)?;
+
+                        let (obj_type, content) = GitObject::read_raw(tree_ish)?;
+
+                        ensure!(obj_type == "commit", "{tree_ish} is not a commit");
+
+                        let content_str = String::from_utf8_lossy(&content);
+
+                        let parent = content_str
+                            .lines()
+                            .take_while(|line| !line.is_empty())
+                            .find_map(|line| line.strip_prefix("parent "));
+
+                        println!("{tree_ish}");
+
+                        (
+                            parent.map(resolve_tree).transpose()?,
+                            resolve_tree(tree_ish)?,
+                        )
+                    }
+                };
+
+                let entries =
+                    diff_trees(old_tree.as_deref(), Some(&new_tree), *recursive, &options)?;
+
+                for entry in entries {
+                    if *name_only {
+                        println!("{}", entry.new_path);
+                    } else if *name_status {
+                        println!("{}\t{}", entry.status_string(), entry.paths_string());
+                    } else {
+                        println!("{entry}");
+                    }
+                }
+            }
+
+            Commands::MergeTree {
+                write_tree,
+                name_only,
+                no_messages,
+                branch1,
+                branch2,
+            } => {
+                ensure!(*write_tree, "only --write-tree mode is supported");
+
+                let ours = resolve_commit(branch1)?;
+                let theirs = resolve_commit(branch2)?;
+
+                let options = merge_options(branch1, branch2)?;
+
+                let result = merge_commits(&mut CommitWalker::new(), &ours, &theirs, &options)?;
+
+                println!("{}", result.tree);
+
+                if result.is_clean() {
+                    return Ok(());
+                }
+
+                let mut printed = Vec::new();
+
+                for conflict in &result.conflicts {
+                    if *name_only {
+                        if !printed.contains(&conflict.path) {
+                            println!("{}", conflict.path);
+
+                            printed.push(conflict.path.clone());
+                        }
+
+                        continue;
+                    }
+
+                    for (stage, entry) in &conflict.stages {
+                        println!(
+                            "{:0>6} {} {stage}\t{}",
+                            entry.mode.to_string(),
+                            entry.hash,
+                            conflict.path
+                        );
+                    }
+                }
+
+                if !*no_messages {
+                    println!();
+
+                    for message in &result.messages {
+                        println!("{message}");
+                    }
+                }
+
+                std::process::exit(1);
+            }
+
+            Commands::Merge {
+                message,
+                no_ff,
+                ff_only,
+                resume,
+                abort,
+                commits,
+            } => match (resume, abort) {
+                (true, _) => self.merge_continue()?,
+
+                (_, true) => self.merge_abort()?,
+
+                _ => self.merge(message.as_deref(), *no_ff, *ff_only, commits)?,
+            },
+
+            Commands::MergeBase {
+                all,
+                octopus,
+                is_ancestor,
+                fork_point,
+                commits,
+            } => {
+                let mut walker = CommitWalker::new();
+
+                if *is_ancestor {
+                    ensure!(
+                        commits.len() == 2,
+                        "--is-ancestor takes exactly two commits"
+                    );
+
+                    let ancestor = resolve_commit(&commits[0])?;
+                    let descendant = resolve_commit(&commits[1])?;
+
+                    if !walker.is_ancestor(&ancestor, &descendant)? {
+                        std::process::exit(1);
+                    }
+
+                    return Ok(());
+                }
+
+                let bases = if *fork_point {
+                    ensure!(
+                        commits.len() <= 2,
+                        "--fork-point takes a ref and an optional commit"
+                    );
+
+                    let name = expand_ref(&commits[0])?
+                        .ok_or_else(|| anyhow!("not a valid ref: {}", commits[0]))?;
+
+                    let commit = resolve_commit(commits.get(1).map_or(HEAD, |c| c.as_str()))?;
+
+                    let mut history: Vec<String> = read_reflog(&name)?
+                        .into_iter()
+                        .map(|entry| entry.new)
+                        .collect();
+
+                    history.extend(resolve_ref(&name)?);
+
+                    walker.fork_point(&commit, &history)?.into_iter().collect()
+                } else {
+                    let hashes = commits
+                        .iter()
+                        .map(|c| resolve_commit(c))
+                        .collect::<anyhow::Result<Vec<String>>>()?;
+
+                    if *octopus {
+                        walker.merge_bases_octopus(&hashes)?
+                    } else {
+                        ensure!(hashes.len() >= 2, "merge-base needs at least two commits");
+
+                        walker.merge_bases_many(&hashes[0], &hashes[1..])?
+                    }
+                };
+
+                if bases.is_empty() {
+                    std::process::exit(1);
+                }
+
+                for base in bases.iter().take(if *all { bases.len() } else { 1 }) {
+                    println!("{base}");
+                }
+            }
+
+            Commands::Switch {
+                create,
+                detach,
+                force,
+                target,
+            } => self.switch(
+                target.as_deref(),
+                create.as_deref(),
+                *detach,
+                *force,
+                "switch",
+            )?,
+
+            Commands::Checkout {
+                new_branch,
+                detach,
+                force,
+                target,
+                paths,
+            } => {
+                if paths.is_empty() {
+                    self.switch(
+                        target.as_deref(),
+                        new_branch.as_deref(),
+                        *detach,
+                        *force,
+                        "checkout",
+                    )?;
+                } else {
+                    // With a tree-ish both the index and the files are updated from it
+                    self.restore(target.as_deref(), target.is_some(), true, true, paths)?;
+                }
+            }
+
+            Commands::Restore {
+                source,
+                staged,
+                worktree,
+                paths,
+            } => self.restore(
+                source.as_deref(),
+                *staged,
+                *worktree || !*staged,
+                false,
+                paths,
+            )?,
+
+            Commands::Branch {
+                list,
+                delete,
+                force_delete,
+                rename,
+                copy,
+                force,
+                set_upstream_to,
+                verbose,
+                all,
+                remotes,
+                contains,
+                merged,
+                no_merged,
+                args,
+            } => {
+                let prefixes: &[&str] = match (*all, *remotes) {
+                    (true, _) => &["refs/heads/", "refs/remotes/"],
+
+                    (false, true) => &["refs/remotes/"],
+
+                    (false, false) => &["refs/heads/"],
+                };
+
+                if *delete || *force_delete {
+                    self.delete_branches(args, *force_delete || *force, prefixes[0])?;
+                } else if *rename || *copy {
+                    self.move_branch(args, *force, *copy)?;
+                } else if let Some(upstream) = set_upstream_to {
+                    self.set_upstream(args.first().map(String::as_str), upstream)?;
+                } else if *list
+                    || args.is_empty()
+                    || contains.is_some()
+                    || merged.is_some()
+                    || no_merged.is_some()
+                {
+                    let filters = [contains, merged, no_merged].map(|filter| filter.as_deref());
+
+                    self.list_branches(prefixes, args, *verbose, filters)?;
+                } else {
+                    ensure!(args.len() <= 2, "too many arguments");
+
+                    self.create_branch(&args[0], args.get(1).map(String::as_str), *force)?;
+                }
+            }
+
+            Commands::CheckRefFormat {
+                branch,
+                allow_onelevel,
+                refname,
+            } => {
+                if *branch {
+                    ensure!(
+                        is_valid_branch_name(refname),
+                        "'{refname}' is not a valid branch name"
+                    );
+
+                    println!("{refname}");
+                } else if !check_ref_format(refname, *allow_onelevel) {
+                    std::process::exit(1);
+                }
+            }
+
+            Commands::Tag {
+                annotate,
+                message,
+                force,
+                delete,
+                list,
+                lines,
+                sort,
+                points_at,
+                args,
+            } => {
+                if *delete {
+                    self.delete_tags(args)?;
+                } else if *list || args.is_empty() || lines.is_some() || points_at.is_some() {
+                    self.list_tags(args, *lines, sort.as_deref(), points_at.as_deref())?;
+                } else {
+                    ensure!(args.len() <= 2, "too many arguments");
+
+                    let message = match (annotate, message) {
+                        (_, Some(message)) => Some(message.as_str()),
+
+                        (true, None) => bail!("no tag message?"),
+
+                        (false, None) => None,
+                    };
+
+                    self.create_tag(&args[0], args.get(1).map(String::as_str), message, *force)?;
+                }
+            }
+
+            Commands::Reflog {
+                expire,
+                expire_unreachable,
+                all,
+                args,
+            } => match args.first().map(String::as_str) {
+                Some("expire") => self.expire_reflogs(
+                    &args[1..],
+                    *all,
+                    expire.as_deref(),
+                    expire_unreachable.as_deref(),
+                )?,
+
+                Some("delete") => self.delete_reflog_entries(&args[1..])?,
+
+                Some("show") => self.show_reflog(args.get(1).map_or(HEAD, String::as_str))?,
+
+                Some(name) => self.show_reflog(name)?,
+
+                None => self.show_reflog(HEAD)?,
+            },
+
+            Commands::Reset {
+                soft,
+                mixed: _,
+                hard,
+                args,
+                paths,
+            } => {
+                // Without `--`, a single argument naming no revision is a path
+                let (target, paths) = match (args.as_slice(), paths.is_empty()) {
+                    ([], _) => (None, paths.clone()),
+
+                    ([first, rest @ ..], true) if resolve_commit(first).is_err() => {
+                        ensure!(
+                            std::path::Path::new(first).exists() || rest.is_empty(),
+                            "ambiguous argument '{first}': unknown revision or path not in the working tree."
+                        );
+
+                        (None, args.clone())
+                    }
+
+                    ([first, rest @ ..], true) => (Some(first.as_str()), rest.to_vec()),
+
+                    ([first], false) => (Some(first.as_str()), paths.clone()),
+
+                    _ => bail!("too many revisions given"),
+                };
+
+                if paths.is_empty() {
+                    let mode = match (*soft, *hard) {
+                        (true, _) => "soft",
+
+                        (_, true) => "hard",
+
+                        _ => "mixed",
+                    };
+
+                    self.reset(target, mode)?;
+                } else {
+                    ensure!(!*soft, "Cannot do soft reset with paths.");
+                    ensure!(!*hard, "Cannot do hard reset with paths.");
+
+                    self.restore(target, true, false, false, &paths)?;
+
+                    print_unstaged_changes(&Index::read()?)?;
+                }
+            }
+
+            Commands::Stash {
+                message,
+                include_untracked,
+                index,
+                args,
+            } => match args.first().map(String::as_str) {
+                None | Some("push") => self.stash_push(message.as_deref(), *include_untracked)?,
+
+                Some("list") => {
+                    for (position, entry) in read_reflog(STASH_REF)?.iter().rev().enumerate() {
+                        println!("stash@{{{position}}}: {}", entry.message);
+                    }
+                }
+
+                Some("apply") => self.stash_apply(args.get(1), *index, false)?,
+
+                Some("pop") => self.stash_apply(args.get(1), *index, true)?,
+
+                Some("drop") => self.stash_drop(args.get(1))?,
+
+                Some(other) => bail!("unknown stash subcommand: {other}"),
+            },
+
+            Commands::CherryPick {
+                record_origin,
+                no_commit,
+                mainline,
+                resume,
+                skip,
+                abort,
+                commits,
+            } => {
+                let options = SequencerOptions {
+                    record_origin: *record_origin,
+                    mainline: *mainline,
+                    no_commit: *no_commit,
+                };
+
+                self.sequencer_command(Action::Pick, commits, options, *resume, *skip, *abort)?;
+            }
+
+            Commands::Revert {
+                no_commit,
+                mainline,
+                resume,
+                skip,
+                abort,
+                commits,
+            } => {
+                let options = SequencerOptions {
+                    record_origin: false,
+                    mainline: *mainline,
+                    no_commit: *no_commit,
+                };
+
+                self.sequencer_command(Action::Revert, commits, options, *resume, *skip, *abort)?;
+            }
+
+            Commands::Rebase {
+                interactive,
+                onto,
+                autosquash,
+                resume,
+                skip,
+                abort,
+                args,
+            } => {
+                if *resume || *skip || *abort {
+                    ensure!(sequencer::rebase_in_progress(), "No rebase in progress?");
+
+                    let state = RebaseState::read()?;
+
+                    match (*resume, *skip) {
+                        (true, _) => self.rebase_continue(&state)?,
+
+                        (_, true) => self.rebase_skip(&state)?,
+
+                        _ => self.rebase_abort(&state)?,
+                    }
+                } else {
+                    self.rebase(
+                        args.first().map(String::as_str),
+                        args.get(1).map(String::as_str),
+                        onto.as_deref(),
+                        *interactive,
+                        *autosquash,
+                    )?;
+                }
+            }
+
+            Commands::Clone {
+                local,
+                no_local,
+                shared,
+                depth,
+                shallow_since,
+                shallow_exclude,
+                single_branch,
+                no_single_branch,
+                filter,
+                url,
+                directory,
+            } => {
+                if *local && url.starts_with("file://") {
+                    eprintln!("warning: --local is ignored");
+                }
+
+                let options =
+                    fetch_options(*depth, shallow_since.as_deref(), shallow_exclude, filter)?;
+
+                // Shallow clones take just the default branch unless told otherwise
+                let single_branch = *single_branch || (options.deepens() && !no_single_branch);
+
+                self.clone(
+                    url,
+                    directory.as_deref(),
+                    !no_local,
+                    *shared,
+                    single_branch,
+                    options,
+                )?
+            }
+
+            Commands::Fetch {
+                depth,
+                deepen,
+                shallow_since,
+                shallow_exclude,
+                unshallow,
+                filter,
+                remote,
+                refspecs,
+            } => {
+                let mut options = fetch_options(
+                    depth.or(*deepen),
+                    shallow_since.as_deref(),
+                    shallow_exclude,
+                    filter,
+                )?;
+
+                options.deepen_relative = deepen.is_some();
+
+                if *unshallow {
+                    ensure!(
+                        !shallow_commits().is_empty(),
+                        "--unshallow on a complete repository does not make sense"
+                    );
+
+                    options.depth = Some(INFINITE_DEPTH);
+                }
+
+                self.fetch(remote.as_deref(), refspecs, options)?
+            }
+
+            Commands::Remote {
+                verbose,
+                fetch,
+                track,
+                master,
+                tags,
+                no_tags,
+                push,
+                add,
+                delete,
+                dry_run,
+                args,
+            } => match (args.first().map(String::as_str), &args[args.len().min(1)..]) {
+                // `show` without names lists them, like no command at all
+                (None, _) | (Some("show"), []) => {
+                    for name in remote_names()? {
+                        let Some(remote) = find_remote(&name)?.filter(|_| *verbose) else {
+                            println!("{name}");
+
+                            continue;
+                        };
+
+                        println!("{name}\t{} (fetch)", remote.url());
+
+                        for url in remote.push_targets() {
+                            println!("{name}\t{url} (push)");
+                        }
+                    }
+                }
+
+                (Some("add"), [name, url]) => {
+                    let tag_opt = match (tags, no_tags) {
+                        (true, _) => Some("--tags"),
+
+                        (_, true) => Some("--no-tags"),
+
+                        _ => None,
+                    };
+
+                    self.remote_add(name, url, track, master.as_deref(), tag_opt)?;
+
+                    if *fetch {
+                        println!("Updating {name}");
+
+                        self.fetch(Some(name), &[], FetchOptions::default())?;
+                    }
+                }
+
+                (Some("rename"), [old, new]) => self.remote_rename(old, new)?,
+
+                (Some("remove" | "rm"), [name]) => self.remote_remove(name)?,
+
+                (Some("set-url"), [name, url, old @ ..]) if old.len() <= 1 => {
+                    let key = if *push { "pushurl" } else { "url" };
+
+                    self.remote_set_url(name, key, url, old.first(), *add, *delete)?
+                }
+
+                (Some("show"), names) => {
+                    for name in names {
+                        self.remote_show(name, !dry_run)?;
+                    }
+                }
+
+                (Some("prune"), names) => {
+                    for name in names {
+                        self.remote_prune(name, *dry_run)?;
+                    }
+                }
+
+                (Some(command @ ("add" | "rename" | "remove" | "rm" | "set-url")), _) => {
+                    bail!("wrong number of arguments for 'git remote {command}'")
+                }
+
+                (Some(other), _) => bail!("unknown subcommand: {other}"),
+            },
+
+            Commands::Bundle { args } => {
+                let (command, rest) =
+                    (args.first().map(String::as_str), &args[args.len().min(1)..]);
+
+                // Options come between the subcommand and the file
+                let (options, rest) =
+                    rest.split_at(rest.iter().take_while(|arg| arg.starts_with('-')).count());
+
+                let mut version = 2;
+
+                let mut quiet = false;
+
+                for option in options {
+                    match (command, option.strip_prefix("--version=")) {
+                        (Some("create"), Some(value)) => version = value.parse()?,
+
+                        _ if option == "-q" || option == "--quiet" => quiet = true,
+
+                        _ => bail!("unknown option `{}'", option.trim_start_matches('-')),
+                    }
+                }
+
+                match (command, rest) {
+                    (Some("create"), [file, revisions @ ..]) => {
+                        bundle::create(file, version, revisions)?
+                    }
+
+                    (Some("verify"), [file]) => {
+                        let bundle = Bundle::read(file)?;
+
+                        if !bundle.has_prerequisites() {
+                            std::process::exit(1);
+                        }
+
+                        eprintln!("{file} is okay");
+
+                        if !quiet {
+                            print_bundle_summary(&bundle);
+                        }
+                    }
+
+                    (Some("list-heads"), [file, names @ ..]) => {
+                        print_bundle_heads(&Bundle::read(file)?, names);
+                    }
+
+                    (Some("unbundle"), [file, names @ ..]) => {
+                        let bundle = Bundle::read(file)?;
+
+                        if !bundle.has_prerequisites() {
+                            std::process::exit(1);
+                        }
+
+                        bundle.unbundle()?;
+
+                        print_bundle_heads(&bundle, names);
+                    }
+
+                    (Some(command @ ("create" | "verify" | "list-heads" | "unbundle")), _) => {
+                        bail!("wrong number of arguments for 'git bundle {command}'")
+                    }
+
+                    (Some(other), _) => bail!("Unknown subcommand: {other}"),
+
+                    (None, _) => bail!("need a subcommand"),
+                }
+            }
+
+            Commands::Push {
+                force,
+                force_with_lease,
+                delete,
+                atomic,
+                push_options,
+                set_upstream,
+                remote,
+                refspecs,
+            } => {
+                let refspecs = if *delete {
+                    ensure!(
+                        !refspecs.is_empty(),
+                        "--delete doesn't make sense without any refs"
+                    );
+
+                    ensure!(
+                        refspecs.iter().all(|name| !name.contains(':')),
+                        "--delete only accepts plain target ref names"
+                    );
+
+                    refspecs.iter().map(|name| format!(":{name}")).collect()
+                } else {
+                    refspecs.clone()
+                };
+
+                let options = PushOptions {
+                    force: *force,
+                    leases: force_with_lease.clone(),
+                    atomic: *atomic,
+                    push_options: push_options.clone(),
+                    set_upstream: *set_upstream,
+                };
+
+                self.push(remote.as_deref(), &refspecs, &options)?
+            }
+
+            Commands::Gc {
+                auto,
+                prune,
+                no_prune,
+                quiet,
+            } => {
+                let prune = match no_prune {
+                    true => None,
+
+                    false => Some(prune.as_deref()),
+                };
+
+                self.gc(*auto, prune, *quiet)?
+            }
+
+            Commands::Repack {
+                all,
+                all_keep_unreachable,
+                delete,
+                write_bitmap_index,
+                no_write_bitmap_index,
+                quiet,
+            } => {
+                let all = *all || *all_keep_unreachable;
+
+                let write_bitmap = match (write_bitmap_index, no_write_bitmap_index) {
+                    (true, _) => Some(true),
+
+                    (_, true) => Some(false),
+
+                    _ => gc::write_bitmaps_setting(&Config::load()?),
+                };
+
+                let write_bitmap = match write_bitmap {
+                    Some(write_bitmap) => {
+                        ensure!(
+                            all || !write_bitmap,
+                            "Incremental repacks are incompatible with bitmap indexes.  Use\n--no-write-bitmap-index or disable the pack.writeBitmaps configuration."
+                        );
+
+                        write_bitmap
+                    }
+
+                    None => all && is_bare()?,
+                };
+
+                if !gc::repack(all, *delete, *all_keep_unreachable, write_bitmap)? && !quiet {
+                    println!("Nothing new to pack.");
+                }
+            }
+
+            Commands::Prune {
+                dry_run,
+                verbose,
+                expire,
+            } => {
+                // Without a grace period every unreachable loose object goes
+                let expire = match expire {
+                    Some(expire) => parse_expiry(expire)?,
+
+                    None => i64::MAX,
+                };
+
+                self.prune(expire, *dry_run, *verbose)?
+            }
+
+            Commands::PackRefs { all, no_prune } => pack_refs(*all, !no_prune)?,
+
+            Commands::CountObjects {
+                verbose,
+                human_readable,
+            } => self.count_objects(*verbose, *human_readable)?,
+
+            Commands::Stats { limit } => self.stats(*limit)?,
+
+            Commands::Fsck {
+                strict,
+                lost_found,
+                connectivity_only,
+                unreachable,
+                no_dangling,
+            } => {
+                let errors = fsck::fsck(&FsckOptions {
+                    strict: *strict,
+                    connectivity_only: *connectivity_only,
+                    unreachable: *unreachable,
+                    dangling: !no_dangling,
+                    lost_found: *lost_found,
+                })?;
+
+                if errors != 0 {
+                    std::process::exit(errors);
+                }
+            }
+
+            Commands::RevList {
+                objects,
+                count,
+                all,
+                use_bitmap_index,
+                revisions,
+            } => self.rev_list(*objects, *count, *all, *use_bitmap_index, revisions)?,
+
+            Commands::CommitGraph {
+                reachable,
+                stdin_commits,
+                append,
+                split,
+                size_multiple,
+                max_commits,
+                changed_paths,
+                no_changed_paths,
+                shallow,
+                command,
+            } => match command.as_str() {
+                "write" => {
+                    let source = match (reachable, stdin_commits) {
+                        (true, _) => Source::Refs,
+
+                        (_, true) => Source::Commits(
+                            std::io::stdin()
+                                .lines()
+                                .map(|line| Ok(line?.trim().to_string()))
+                                .filter(|line| !line.as_ref().is_ok_and(String::is_empty))
+                                .collect::<anyhow::Result<_>>()?,
+                        ),
+
+                        _ => Source::Packs,
+                    };
+
+                    let split = match split.as_deref() {
+                        None => None,
+
+                        Some("") => Some(Split::Merge),
+
+                        Some("no-merge") => Some(Split::NoMerge),
+
+                        Some("replace") => Some(Split::Replace),
+
+                        Some(other) => bail!("unrecognized --split argument, {other}"),
+                    };
+
+                    let changed_paths = match (changed_paths, no_changed_paths) {
+                        (true, _) => Some(true),
+
+                        (_, true) => Some(false),
+
+                        _ => None,
+                    };
+
+                    commit_graph::write(&WriteOptions {
+                        source,
+                        append: *append,
+                        split,
+                        size_multiple: *size_multiple,
+                        max_commits: *max_commits,
+                        changed_paths,
+                    })?
+                }
+
+                "verify" => {
+                    if !commit_graph::verify(*shallow)? {
+                        std::process::exit(1);
+                    }
+                }
+
+                other => bail!("unrecognized subcommand: {other}"),
+            },
+
+            Commands::MultiPackIndex {
+                preferred_pack,
+                batch_size,
+                command,
+            } => match command.as_str() {
+                "write" => midx::write(preferred_pack.as_deref())?,
+
+                "verify" => {
+                    if !midx::verify()? {
+                        std::process::exit(1);
+                    }
+                }
+
+                "expire" => midx::expire()?,
+
+                "repack" => {
+                    let Some(batch_size) = parse_magnitude(batch_size) else {
+                        bail!("option `batch-size' expects a non-negative integer value with an optional k/m/g suffix");
+                    };
+
+                    midx::repack(batch_size)?
+                }
+
+                other => bail!("unrecognized subcommand: {other}"),
+            },
+
+            Commands::UploadPack {
+                stateless_rpc,
+                advertise_refs,
+                directory,
+            } => in_repository(&find_repository(directory)?, || {
+                let protocol = std::env::var("GIT_PROTOCOL").unwrap_or_default();
+
+                upload_pack::serve(
+                    &mut std::io::stdin().lock(),
+                    &mut std::io::stdout().lock(),
+                    upload_pack::protocol_version(&protocol),
+                    *stateless_rpc,
+                    *advertise_refs,
+                )
+            })?,
+
+            Commands::ReceivePack {
+                stateless_rpc,
+                advertise_refs,
+                directory,
+            } => in_repository(&find_repository(directory)?, || {
+                receive_pack::serve(
+                    &mut std::io::stdin().lock(),
+                    &mut std::io::stdout().lock(),
+                    *stateless_rpc,
+                    *advertise_refs,
+                )
+            })?,
+
+            Commands::Serve {
+                listen,
+                port,
+                enable_receive_pack,
+                directory,
+            } => http_backend::serve(
+                &format!("{listen}:{port}"),
+                &Path::new(directory).canonicalize()?,
+                *enable_receive_pack,
+            )?,
+
+            Commands::HttpBackend => http_backend::cgi()?,
+
+            _ => println!("Unsupported command: {}", command),
+        }
+
+        Ok(())
+    }
+
+    fn switch(
+        &self,
+        target: Option<&str>,
+        new_branch: Option<&str>,
+        detach: bool,
+        force: bool,
+        command: &str,
+    ) -> anyhow::Result<()> {
+        let old_head = resolve_ref(HEAD)?;
+
+        let old_tree = commit_tree(old_head.as_deref())?;
+
+        let was_detached = head_target()?.is_none();
+
+        let moving_from = match head_target()? {
+            Some(name) => shorten_ref(&name).to_string(),
+
+            None => old_head.clone().unwrap_or_default(),
+        };
+
+        if let Some(branch) = new_branch {
+            ensure!(
+                is_valid_branch_name(branch),
+                "'{branch}' is not a valid branch name"
+            );
+
+            let name = format!("refs/heads/{branch}");
+
+            ensure!(
+                read_ref(&name)?.is_none(),
+                "a branch named '{branch}' already exists"
+            );
+
+            let start = match target {
+                Some(target) => Some(resolve_commit(target)?),
+
+                None => old_head,
+            };
+
+            checkout_tree(
+                old_tree.as_deref(),
+                commit_tree(start.as_deref())?.as_deref(),
+                force,
+                "checkout",
+            )?;
+
+            if let Some(start) = start {
+                update_ref(
+                    &name,
+                    &start,
+                    &format!("branch: Created from {}", target.unwrap_or(HEAD)),
+                )?;
+            }
+
+            write_symbolic_ref(
+                HEAD,
+                &name,
+                &format!("checkout: moving from {moving_from} to {branch}"),
+            )?;
+
+            println!("Switched to a new branch '{branch}'");
+
+            return Ok(());
+        }
+
+        let Some(target) = target else {
+            bail!("missing branch or commit argument");
+        };
+
+        let branch_ref = format!("refs/heads/{target}");
+
+        let target_is_branch = !detach && read_ref(&branch_ref)?.is_some();
+
+        if !target_is_branch && command == "switch" && !detach {
+            bail!("a branch is expected, got '{target}'\nhint: If you want to detach HEAD at the commit, try again with the --detach option.");
+        }
+
+        if target_is_branch && head_target()?.as_deref() == Some(branch_ref.as_str()) {
+            println!("Already on '{target}'");
+
+            return Ok(());
+        }
+
+        let hash = resolve_commit(target)?;
+
+        checkout_tree(
+            old_tree.as_deref(),
+            commit_tree(Some(&hash))?.as_deref(),
+            force,
+            "checkout",
+        )?;
+
+        if let Some(old_head) = old_head.filter(|old| was_detached && *old != hash) {
+            println!(
+                "Previous HEAD position was {} {}",
+                &old_head[..7],
+                commit_subject(&old_head)?
+            );
+        }
+
+        let reason = format!("checkout: moving from {moving_from} to {target}");
+
+        if target_is_branch {
+            write_symbolic_ref(HEAD, &branch_ref, &reason)?;
+
+            println!("Switched to branch '{target}'");
+        } else {
+            detach_head(&hash, &reason)?;
+
+            println!("HEAD is now at {} {}", &hash[..7], commit_subject(&hash)?);
+        }
+
+        Ok(())
+    }
+
+    /// Restores paths in the working tree (from the index, or `source`) and/or in the
+    /// index (from `HEAD`, or `source`). In overlay mode, paths missing from the source
+    /// are left alone instead of being deleted.
+    fn restore(
+        &self,
+        source: Option<&str>,
+        staged: bool,
+        worktree: bool,
+        overlay: bool,
+        pathspecs: &[String],
+    ) -> anyhow::Result<()> {
+        let head_tree = commit_tree(resolve_ref(HEAD)?.as_deref())?;
+
+        let mut index = read_index_or_tree(head_tree.as_deref())?;
+
+        let source_files = match source {
+            Some(source) => Some(flatten_tree(&resolve_tree(&resolve_revision(source)?)?)?),
+
+            None if staged => match &head_tree {
+                Some(tree) => Some(flatten_tree(tree)?),
+
+                None => Some(BTreeMap::new()),
+            },
+
+            None => None,
+        };
+
+        let index_files: BTreeMap<String, FileEntry> = index
+            .entries
+            .iter()
+            .filter(|entry| entry.stage == 0)
+            .map(|entry| (entry.path.clone(), entry.file_entry()))
+            .collect();
+
+        let known: BTreeSet<&String> = index_files
+            .keys()
+            .chain(source_files.iter().flat_map(|files| files.keys()))
+            .collect();
+
+        for pathspec in pathspecs {
+            let spec = [pathspec.clone()];
+
+            ensure!(
+                known.iter().any(|path| matches_pathspec(path, &spec)),
+                "pathspec '{pathspec}' did not match any file(s) known to git"
+            );
+        }
+
+        let paths: Vec<String> = known
+            .into_iter()
+            .filter(|path| matches_pathspec(path, pathspecs))
+            .cloned()
+            .collect();
+
+        let files = source_files.as_ref().unwrap_or(&index_files);
+
+        for path in &paths {
+            let entry = files.get(path);
+
+            if overlay && entry.is_none() {
+                continue;
+            }
+
+            if staged {
+                match entry {
+                    Some(entry) => index.add(IndexEntry::new(path, entry)),
+
+                    None => index.remove(path),
+                }
+            }
+
+            if worktree {
+                match entry {
+                    Some(entry) => {
+                        write_worktree_file(path, entry)?;
+
+                        // The file now matches what is staged, so its stat data can be kept
+                        if index
+                            .get(path)
+                            .map(IndexEntry::file_entry)
+                            .is_some_and(|e| e.hash == entry.hash && e.mode == entry.mode)
+                        {
+                            index.add(IndexEntry::from_file(path, entry)?);
+                        }
+                    }
+
+                    None => remove_worktree_file(path)?,
+                }
+            }
+        }
+
+        index.write()
+    }
+
+    fn create_branch(&self, name: &str, start: Option<&str>, force: bool) -> anyhow::Result<()> {
+        ensure!(
+            is_valid_branch_name(name),
+            "'{name}' is not a valid branch name"
+        );
+
+        let full_name = format!("refs/heads/{name}");
+
+        if read_ref(&full_name)?.is_some() {
+            ensure!(force, "a branch named '{name}' already exists");
+
+            ensure!(
+                head_target()?.as_deref() != Some(full_name.as_str()),
+                "cannot force update the current branch"
+            );
+        }
+
+        let start = start.unwrap_or(HEAD);
+
+        let hash =
+            resolve_commit(start).map_err(|_| anyhow!("not a valid object name: '{start}'"))?;
+
+        let reason = match read_ref(&full_name)? {
+            Some(_) => format!("branch: Reset to {start}"),
+
+            None => format!("branch: Created from {start}"),
+        };
+
+        update_ref(&full_name, &hash, &reason)?;
+
+        // Branching off a remote-tracking branch tracks it, like branch.autoSetupMerge
+        if expand_ref(start)?.is_some_and(|name| name.starts_with("refs/remotes/")) {
+            self.set_upstream(Some(name), start)?;
+        }
+
+        Ok(())
+    }
+
+    fn list_branches(
+        &self,
+        prefixes: &[&str],
+        patterns: &[String],
+        verbose: u8,
+        [contains, merged, no_merged]: [Option<&str>; 3],
+    ) -> anyhow::Result<()> {
+        let mut walker = CommitWalker::new();
+
+        let contains = contains.map(resolve_commit).transpose()?;
+        let merged = merged.map(resolve_commit).transpose()?;
+        let no_merged = no_merged.map(resolve_commit).transpose()?;
+
+        let current = head_target()?;
+
+        // (display name, full ref name, hash)
+        let mut branches: Vec<(String, Option<String>, String)> = Vec::new();
+
+        if prefixes.contains(&"refs/heads/") && current.is_none() && patterns.is_empty() {
+            if let Some(hash) = resolve_ref(HEAD)? {
+                branches.push((format!("(HEAD detached at {})", &hash[..7]), None, hash));
+            }
+        }
+
+        for prefix in prefixes {
+            for (name, hash) in list_refs(prefix)? {
+                let display = match (*prefix, prefixes.len()) {
+                    ("refs/remotes/", 2) => name["refs/".len()..].to_string(),
+
+                    _ => name[prefix.len()..].to_string(),
+                };
+
+                if !patterns.is_empty() && !patterns.iter().any(|p| glob_match(p, &display)) {
+                    continue;
+                }
+
+                branches.push((display, Some(name), hash));
+            }
+        }
+
+        let mut shown = Vec::new();
+
+        for (display, name, hash) in branches {
+            let commit = GitObject::from_hash(&hash)?.is_commit();
+
+            if let Some(contains) = &contains {
+                if !commit || !walker.is_ancestor(contains, &hash)? {
+                    continue;
+                }
+            }
+
+            if let Some(merged) = &merged {
+                if !commit || !walker.is_ancestor(&hash, merged)? {
+                    continue;
+                }
+            }
+
+            if let Some(no_merged) = &no_merged {
+                if !commit || walker.is_ancestor(&hash, no_merged)? {
+                    continue;
+                }
+            }
+
+            shown.push((display, name, hash));
+        }
+
+        let width = shown
+            .iter()
+            .map(|(display, ..)| display.len())
+            .max()
+            .unwrap_or(0);
+
+        for (display, name, hash) in &shown {
+            let is_current = match name {
+                Some(name) => current.as_ref() == Some(name),
+
+                None => true,
+            };
+
+            let marker = if is_current { '*' } else { ' ' };
+
+            let symbolic_target = match name {
+                Some(name) => {
+                    read_ref(name)?.and_then(|v| v.strip_prefix("ref: ").map(str::to_string))
+                }
+
+                None => None,
+            };
+
+            if let Some(target) = symbolic_target {
+                println!("{marker} {display} -> {}", shorten_ref(&target));
+
+                continue;
+            }
+
+            if verbose == 0 {
+                println!("{marker} {display}");
+
+                continue;
+            }
+
+            let mut tracking = String::new();
+
+            // `-v` shows how far the branch is from its upstream, `-vv` names it too
+            if let Some(branch) = name.as_deref().and_then(|n| n.strip_prefix("refs/heads/")) {
+                if let Some(upstream) = branch_upstream(branch)? {
+                    let short = shorten_ref(&upstream);
+
+                    let counts = match resolve_ref(&upstream)? {
+                        Some(upstream_hash) => match walker.ahead_behind(hash, &upstream_hash)? {
+                            (0, 0) => String::new(),
+
+                            (ahead, 0) => format!("ahead {ahead}"),
+
+                            (0, behind) => format!("behind {behind}"),
+
+                            (ahead, behind) => format!("ahead {ahead}, behind {behind}"),
+                        },
+
+                        None => "gone".to_string(),
+                    };
+
+                    tracking = match (verbose > 1, counts.is_empty()) {
+                        (true, true) => format!("[{short}] "),
+
+                        (true, false) => format!("[{short}: {counts}] "),
+
+                        (false, true) => String::new(),
+
+                        (false, false) => format!("[{counts}] "),
+                    };
+                }
+            }
+
+            println!(
+                "{marker} {display:<width$} {} {tracking}{}",
+                &hash[..7],
+                commit_subject(hash)?
+            );
+        }
+
+        Ok(())
+    }
+
+    fn delete_branches(&self, names: &[String], force: bool, prefix: &str) -> anyhow::Result<()> {
+        ensure!(!names.is_empty(), "branch name required");
+
+        let remote = prefix == "refs/remotes/";
+
+        let current = head_target()?;
+
+        let mut walker = CommitWalker::new();
+
+        let mut failed = false;
+
+        for name in names {
+            let full_name = format!("{prefix}{name}");
+
+            let Some(hash) = resolve_ref(&full_name)? else {
+                let kind = if remote {
+                    "remote-tracking branch"
+                } else {
+                    "branch"
+                };
+
+                eprintln!("error: {kind} '{name}' not found.");
+
+                failed = true;
+
+                continue;
+            };
+
+            if current.as_deref() == Some(full_name.as_str()) {
+                let cwd = std::env::current_dir()?;
+
+                eprintln!(
+                    "error: Cannot delete branch '{name}' checked out at '{}'",
+                    cwd.display()
+                );
+
+                failed = true;
+
+                continue;
+            }
+
+            if !force && !remote {
+                // Merged means merged into its upstream if it has one, otherwise into HEAD
+                let target = match branch_upstream(name)? {
+                    Some(upstream) if resolve_ref(&upstream)?.is_some() => upstream,
+
+                    _ => HEAD.to_string(),
+                };
+
+                let merged = match resolve_ref(&target)? {
+                    Some(target) => walker.is_ancestor(&hash, &target)?,
+
+                    None => false,
+                };
+
+                if !merged {
+                    eprintln!("error: The branch '{name}' is not fully merged.\nIf you are sure you want to delete it, run 'git branch -D {name}'.");
+
+                    failed = true;
+
+                    continue;
+                }
+            }
+
+            delete_ref(&full_name)?;
+
+            if remote {
+                println!(
+                    "Deleted remote-tracking branch {name} (was {}).",
+                    &hash[..7]
+                );
+            } else {
+                rename_section(&format!("branch.{name}"), None)?;
+
+                println!("Deleted branch {name} (was {}).", &hash[..7]);
+            }
+        }
+
+        if failed {
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    /// `branch -m` / `branch -c`: renames or copies a branch along with its reflog and
+    /// config. With a single name, the current branch is the source.
+    fn move_branch(&self, args: &[String], force: bool, copy: bool) -> anyhow::Result<()> {
+        let current = head_target()?;
+
+        let current_branch = current
+            .as_deref()
+            .and_then(|name| name.strip_prefix("refs/heads/"));
+
+        let (old, new) = match args {
+            [new] => match current_branch {
+                Some(old) => (old.to_string(), new.clone()),
+
+                None => bail!("cannot rename the current branch while not on any"),
+            },
+
+            [old, new] => (old.clone(), new.clone()),
+
+            _ => bail!("branch name required"),
+        };
+
+        ensure!(
+            is_valid_branch_name(&new),
+            "'{new}' is not a valid branch name"
+        );
+
+        let old_ref = format!("refs/heads/{old}");
+        let new_ref = format!("refs/heads/{new}");
+
+        let is_current = current.as_deref() == Some(old_ref.as_str());
+
+        let hash = resolve_ref(&old_ref)?;
+
+        // The current branch may still be unborn, in which case only HEAD moves
+        ensure!(hash.is_some() || is_current, "no branch named '{old}'");
+
+        if old_ref != new_ref && read_ref(&new_ref)?.is_some() {
+            ensure!(force, "a branch named '{new}' already exists");
+        }
+
+        if old_ref == new_ref {
+            return Ok(());
+        }
+
+        if copy {
+            move_reflog(&old_ref, &new_ref, true)?;
+
+            if let Some(hash) = &hash {
+                update_ref(
+                    &new_ref,
+                    hash,
+                    &format!("Branch: copied {old_ref} to {new_ref}"),
+                )?;
+            }
+
+            copy_section(&format!("branch.{old}"), &format!("branch.{new}"))?;
+
+            return Ok(());
+        }
+
+        let reason = format!("Branch: renamed {old_ref} to {new_ref}");
+
+        move_reflog(&old_ref, &format!("{old_ref}.renaming"), false)?;
+
+        delete_ref(&old_ref)?;
+
+        move_reflog(&format!("{old_ref}.renaming"), &new_ref, false)?;
+
+        if let Some(hash) = &hash {
+            update_ref(&new_ref, hash, &reason)?;
+        }
+
+        rename_section(&format!("branch.{old}"), Some(&format!("branch.{new}")))?;
+
+        if is_current {
+            write_symbolic_ref(HEAD, &new_ref, &reason)?;
+        }
+
+        Ok(())
+    }
+
+    fn set_upstream(&self, branch: Option<&str>, upstream: &str) -> anyhow::Result<()> {
+        let branch = match branch {
+            Some(branch) => branch.to_string(),
+
+            None => match head_target()?.and_then(|n| n.strip_prefix("refs/heads/").map(str::to_string)) {
+                Some(branch) => branch,
+
+                None => bail!("could not set upstream of HEAD to {upstream} when it does not point to any branch."),
+            },
+        };
+
+        ensure!(
+            read_ref(&format!("refs/heads/{branch}"))?.is_some(),
+            "branch '{branch}' does not exist"
+        );
+
+        let (remote, merge) = if read_ref(&format!("refs/remotes/{upstream}"))?.is_some() {
+            let Some((remote, name)) = upstream.split_once('/') else {
+                bail!("the requested upstream branch '{upstream}' does not exist");
+            };
+
+            (remote.to_string(), format!("refs/heads/{name}"))
+        } else if read_ref(&format!("refs/heads/{upstream}"))?.is_some() {
+            (".".to_string(), format!("refs/heads/{upstream}"))
+        } else {
+            bail!("the requested upstream branch '{upstream}' does not exist");
+        };
+
+        set_local(&format!("branch.{branch}.remote"), &remote)?;
+        set_local(&format!("branch.{branch}.merge"), &merge)?;
+
+        println!("branch '{branch}' set up to track '{upstream}'.");
+
+        Ok(())
+    }
+
+    /// Creates a lightweight tag, or an annotated tag object when given a message.
+    fn create_tag(
+        &self,
+        name: &str,
+        target: Option<&str>,
+        message: Option<&str>,
+        force: bool,
+    ) -> anyhow::Result<()> {
+        let full_name = format!("refs/tags/{name}");
+
+        ensure!(
+            check_ref_format(&full_name, false),
+            "'{name}' is not a valid tag name."
+        );
+
+        let previous = resolve_ref(&full_name)?;
+
+        ensure!(previous.is_none() || force, "tag '{name}' already exists");
+
+        let target = resolve_revision(target.unwrap_or(HEAD))?;
+
+        let hash = match message {
+            Some(message) => {
+                let (object_type, _) = GitObject::read_raw(&target)?;
+
+                let tag = GitObject::new_tag(
+                    &target,
+                    &object_type,
+                    name,
+                    Signature::current("COMMITTER")?,
+                    &format!("{}\n", message.trim_end()),
+                )?;
+
+                tag.write_to_file()?;
+
+                tag.get_hash().clone()
+            }
+
+            None => target,
+        };
+
+        update_ref(&full_name, &hash, "tag: tagging")?;
+
+        if let Some(previous) = previous.filter(|previous| *previous != hash) {
+            println!("Updated tag '{name}' (was {})", &previous[..7]);
+        }
+
+        Ok(())
+    }
+
+    fn delete_tags(&self, names: &[String]) -> anyhow::Result<()> {
+        let mut failed = false;
+
+        for name in names {
+            let full_name = format!("refs/tags/{name}");
+
+            match resolve_ref(&full_name)? {
+                Some(hash) => {
+                    delete_ref(&full_name)?;
+
+                    println!("Deleted tag '{name}' (was {})", &hash[..7]);
+                }
+
+                None => {
+                    eprintln!("error: tag '{name}' not found.");
+
+                    failed = true;
+                }
+            }
+        }
+
+        if failed {
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    /// Lists tags matching the patterns, optionally with `lines` lines of their message.
+    fn list_tags(
+        &self,
+        patterns: &[String],
+        lines: Option<usize>,
+        sort: Option<&str>,
+        points_at: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let points_at = points_at.map(resolve_revision).transpose()?;
+
+        let mut tags = Vec::new();
+
+        for (name, hash) in list_refs("refs/tags/")? {
+            let short = name["refs/tags/".len()..].to_string();
+
+            if !patterns.is_empty() && !patterns.iter().any(|p| glob_match(p, &short)) {
+                continue;
+            }
+
+            // Like git, an annotated tag matches by what it tags, without peeling further
+            if let Some(points_at) = &points_at {
+                let tagged = match GitObject::from_hash(&hash)? {
+                    GitObject::Tag { object, .. } => Some(object),
+
+                    _ => None,
+                };
+
+                if hash != *points_at && tagged.as_ref() != Some(points_at) {
+                    continue;
+                }
+            }
+
+            tags.push((short, hash));
+        }
+
+        match sort {
+            None | Some("refname") => {}
+
+            Some("-refname") => tags.reverse(),
+
+            Some("version:refname" | "v:refname") => {
+                tags.sort_by(|(a, _), (b, _)| version_cmp(a, b));
+            }
+
+            Some("-version:refname" | "-v:refname") => {
+                tags.sort_by(|(a, _), (b, _)| version_cmp(b, a));
+            }
+
+            Some(other) => bail!("unsupported sort specification '{other}'"),
+        }
+
+        for (name, hash) in tags {
+            let Some(count) = lines else {
+                println!("{name}");
+
+                continue;
+            };
+
+            // Lightweight tags show the message of the commit they point at
+            let message = match GitObject::from_hash(&hash)? {
+                GitObject::Tag { message, .. } | GitObject::Commit { message, .. } => message,
+
+                _ => String::new(),
+            };
+
+            let mut message_lines = message.lines().take(count.max(1));
+
+            let first = message_lines.next().unwrap_or_default();
+
+            if count == 0 {
+                println!("{name}");
+
+                continue;
+            }
+
+            println!("{name:<15} {first}");
+
+            for line in message_lines {
+                println!("    {line}");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn show_reflog(&self, name: &str) -> anyhow::Result<()> {
+        let full_name = reflog_ref_name(name)?;
+
+        let entries = read_reflog(&full_name)?;
+
+        for (index, entry) in entries.iter().rev().enumerate() {
+            println!("{} {name}@{{{index}}}: {}", &entry.new[..7], entry.message);
+        }
+
+        Ok(())
+    }
+
+    /// Drops entries older than `expire`, and entries no longer reachable from the ref
+    /// that are older than `expire_unreachable` (by default 90 and 30 days, or
+    /// `gc.reflogExpire` and `gc.reflogExpireUnreachable`).
+    fn expire_reflogs(
+        &self,
+        names: &[String],
+        all: bool,
+        expire: Option<&str>,
+        expire_unreachable: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let config = Config::load()?;
+
+        let expire = parse_expiry(
+            expire
+                .or(config.get("gc.reflogExpire"))
+                .unwrap_or("90.days.ago"),
+        )?;
+
+        let expire_unreachable = parse_expiry(
+            expire_unreachable
+                .or(config.get("gc.reflogExpireUnreachable"))
+                .unwrap_or("30.days.ago"),
+        )?;
+
+        let names = if all {
+            list_reflogs()?
+        } else {
+            ensure!(!names.is_empty(), "no reflog specified to expire");
+
+            names
+                .iter()
+                .map(|name| reflog_ref_name(name))
+                .collect::<anyhow::Result<_>>()?
+        };
+
+        let mut walker = CommitWalker::new();
+
+        for name in names {
+            let entries = read_reflog(&name)?;
+
+            // Like git, what HEAD once pointed at counts as reachable from any ref
+            let tips = if expire_unreachable <= expire {
+                Vec::new()
+            } else if name == HEAD {
+                list_refs("refs/")?
+                    .into_iter()
+                    .map(|(_, hash)| hash)
+                    .collect()
+            } else {
+                resolve_ref(&name)?.into_iter().collect()
+            };
+
+            let mut reachable = HashSet::new();
+
+            let mut stack: Vec<String> = tips
+                .iter()
+                .filter_map(|tip| GitObject::peel(tip).ok())
+                .collect();
+
+            while let Some(commit) = stack.pop() {
+                if reachable.insert(commit.clone()) {
+                    stack.extend(walker.parents(&commit).unwrap_or_default());
+                }
+            }
+
+            let is_reachable = |hash: &String| hash == ZERO_HASH || reachable.contains(hash);
+
+            let kept: Vec<ReflogEntry> = entries
+                .into_iter()
+                .filter(|entry| {
+                    let time = entry.identity.timestamp;
+
+                    time >= expire
+                        && (time >= expire_unreachable
+                            || is_reachable(&entry.old) && is_reachable(&entry.new))
+                })
+                .collect();
+
+            write_reflog(&name, &kept)?;
+        }
+
+        Ok(())
+    }
+
+    /// Housekeeping: packs refs, expires reflogs, repacks everything reachable into one
+    /// pack and prunes unreachable loose objects older than `prune` (`None` for
+    /// `--no-prune`, `Some(None)` for the configured or default grace period). With
+    /// `auto` only when the thresholds say so, and then only the loose objects unless
+    /// there are too many packs.
+    fn gc(&self, auto: bool, prune: Option<Option<&str>>, quiet: bool) -> anyhow::Result<()> {
+        let config = Config::load()?;
+
+        let mut consolidate = true;
+
+        if auto {
+            let Some(reason) = gc::auto_gc_reason(&config)? else {
+                return Ok(());
+            };
+
+            consolidate = matches!(reason, AutoGc::Packs);
+
+            if !quiet {
+                eprintln!("Auto packing the repository for optimum performance.");
+                eprintln!("See \"git help gc\" for manual housekeeping.");
+            }
+        }
+
+        if config.get("gc.packRefs") != Some("false") {
+            pack_refs(true, true)?;
+        }
+
+        self.expire_reflogs(&[], true, None, None)?;
+
+        let expire = prune.map(|prune| {
+            prune
+                .or(config.get("gc.pruneExpire"))
+                .unwrap_or("2.weeks.ago")
+        });
+
+        // What is pruned right away needs no loose copies from the old packs
+        let write_bitmap = consolidate
+            && match gc::write_bitmaps_setting(&config) {
+                Some(write_bitmap) => write_bitmap,
+
+                None => is_bare()?,
+            };
+
+        gc::repack(consolidate, true, expire != Some("now"), write_bitmap)?;
+
+        if let Some(expire) = expire {
+            self.prune(parse_expiry(expire)?, false, false)?;
+        }
+
+        if config.get("gc.writeCommitGraph") == Some("false") {
+            return Ok(());
+        }
+
+        commit_graph::write(&WriteOptions {
+            source: Source::Refs,
+            append: false,
+            split: None,
+            size_multiple: 2,
+            max_commits: None,
+            changed_paths: None,
+        })
+    }
+
+    /// Removes unreachable loose objects last modified at `expire` or before, listing
+    /// them as `<hash> <type>` when only reporting or when `verbose`.
+    fn prune(&self, expire: i64, dry_run: bool, verbose: bool) -> anyhow::Result<()> {
+        for (hash, kind, path) in gc::prunable_objects(expire)? {
+            if dry_run || verbose {
+                println!("{hash} {kind}");
+            }
+
+            if !dry_run {
+                std::fs::remove_file(&path)?;
+
+                gc::remove_empty_directory(&path);
+            }
+        }
+
+        match dry_run {
+            true => Ok(()),
+
+            false => gc::prune_packed(),
+        }
+    }
+
+    /// Lists or counts what `revisions` reach, from the reachability bitmaps when asked to
+    /// and there are some: then objects come by type in pack order, without paths.
+    fn rev_list(
+        &self,
+        objects: bool,
+        count: bool,
+        all: bool,
+        use_bitmap_index: bool,
+        revisions: &[String],
+    ) -> anyhow::Result<()> {
+        let mut include = Vec::new();
+
+        let mut exclude = Vec::new();
+
+        let mut walker = CommitWalker::new();
+
+        for revision in revisions {
+            let or_head = |name: &str| match name {
+                "" => HEAD.to_string(),
+
+                _ => name.to_string(),
+            };
+
+            if let Some((from, to)) = revision.split_once("...") {
+                let (from, to) = (
+                    resolve_commit(&or_head(from))?,
+                    resolve_commit(&or_head(to))?,
+                );
+
+                exclude.extend(walker.merge_bases(&from, &to)?);
+
+                include.extend([from, to]);
+            } else if let Some((from, to)) = revision.split_once("..") {
+                exclude.push(resolve_revision(&or_head(from))?);
+                include.push(resolve_revision(&or_head(to))?);
+            } else if let Some(excluded) = revision.strip_prefix('^') {
+                exclude.push(resolve_revision(excluded)?);
+            } else {
+                include.push(resolve_revision(revision)?);
+            }
+        }
+
+        if all {
+            include.extend(list_refs("refs/")?.into_iter().map(|(_, hash)| hash));
+
+            include.extend(resolve_ref(HEAD)?);
+        }
+
+        let bitmap = match use_bitmap_index {
+            true => PackBitmap::load()?,
+
+            false => None,
+        };
+
+        let counted = match bitmap {
+            Some(mut bitmap) => {
+                let mut found = bitmap.between(&exclude, &include)?;
+
+                if !objects {
+                    bitmap.commits_of(&mut found);
+                }
+
+                Some(match count {
+                    true => vec![found.count().to_string()],
+
+                    false => bitmap.hashes(&found),
+                })
+            }
+
+            None => None,
+        };
+
+        let lines = match counted {
+            Some(lines) => lines,
+
+            None => {
+                let listed = walker.rev_list(&exclude, &include, objects)?;
+
+                match count {
+                    true => vec![listed.len().to_string()],
+
+                    false => listed
+                        .into_iter()
+                        .map(|(hash, name)| match name {
+                            Some(name) => format!("{hash} {name}"),
+
+                            None => hash,
+                        })
+                        .collect(),
+                }
+            }
+        };
+
+        for line in lines {
+            println!("{line}");
+        }
+
+        Ok(())
+    }
+
+    fn count_objects(&self, verbose: bool, human_readable: bool) -> anyhow::Result<()> {
+        let counts = stats::count_objects()?;
+
+        let size = |bytes: u64| match human_readable {
+            true => human_size(bytes),
+
+            false => (bytes / 1024).to_string(),
+        };
+
+        if !verbose {
+            let unit = if human_readable { "" } else { " kilobytes" };
+
+            println!("{} objects, {}{unit}", counts.count, size(counts.size));
+
+            return Ok(());
+        }
+
+        for (problem, path) in &counts.garbage {
+            eprintln!("warning: {problem}: {path}");
+        }
+
+        println!("count: {}", counts.count);
+        println!("size: {}", size(counts.size));
+        println!("in-pack: {}", counts.in_pack);
+        println!("packs: {}", counts.packs);
+        println!("size-pack: {}", size(counts.size_pack));
+        println!("prune-packable: {}", counts.prune_packable);
+        println!("garbage: {}", counts.garbage.len());
+        println!("size-garbage: {}", size(counts.size_garbage));
+
+        for alternate in &object_directories()[1..] {
+            let path = std::fs::canonicalize(alternate)
+                .map_or_else(|_| alternate.clone(), |path| path.display().to_string());
+
+            println!("alternate: {path}");
+        }
+
+        Ok(())
+    }
+
+    fn stats(&self, limit: usize) -> anyhow::Result<()> {
+        let stats = stats::stats(limit)?;
+
+        println!("Objects");
+
+        for (kind, count, size) in &stats.kinds {
+            println!(
+                "  {:<8}{count:>8}{:>12}",
+                format!("{kind}s"),
+                human_size(*size)
+            );
+        }
+
+        println!(
+            "  {} loose, {} packed in {} pack{}",
+            stats.loose,
+            stats.packed,
+            stats.packs,
+            if stats.packs == 1 { "" } else { "s" }
+        );
+
+        let path_or_blank = |path: &Option<String>| path.clone().unwrap_or_default();
+
+        if !stats.largest_blobs.is_empty() {
+            println!("\nLargest blobs");
+
+            for (size, hash, path) in &stats.largest_blobs {
+                println!(
+                    "  {:>10}  {hash}  {}",
+                    human_size(*size),
+                    path_or_blank(path)
+                );
+            }
+        }
+
+        if !stats.deepest_trees.is_empty() {
+            println!("\nDeepest trees");
+
+            for (depth, hash, path) in &stats.deepest_trees {
+                println!("  {depth:>10}  {hash}  {path}");
+            }
+        }
+
+        if !stats.delta_chains.is_empty() {
+            println!("\nLongest delta chains");
+
+            for (length, hash, kind, path) in &stats.delta_chains {
+                println!("  {length:>10}  {hash}  {kind:<6}  {}", path_or_blank(path));
+            }
+        }
+
+        if !stats.path_growth.is_empty() {
+            println!("\nPath growth");
+
+            for (size, versions, path) in &stats.path_growth {
+                println!(
+                    "  {:>10}  {versions:>4} version{}  {path}",
+                    human_size(*size),
+                    if *versions == 1 { " " } else { "s" }
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes single entries given as `<ref>@{<n>}`.
+    fn delete_reflog_entries(&self, selectors: &[String]) -> anyhow::Result<()> {
+        ensure!(!selectors.is_empty(), "no reflog specified to delete");
+
+        let mut by_ref: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+        for selector in selectors {
+            let Some((name, index)) = selector
+                .strip_suffix('}')
+                .and_then(|s| s.split_once("@{"))
+                .and_then(|(name, index)| Some((name, index.parse::<usize>().ok()?)))
+            else {
+                bail!("not a reflog: {selector}");
+            };
+
+            let name = if name.is_empty() { HEAD } else { name };
+
+            by_ref
+                .entry(reflog_ref_name(name)?)
+                .or_default()
+                .push(index);
+        }
+
+        for (name, indexes) in by_ref {
+            let mut entries = read_reflog(&name)?;
+
+            let count = entries.len();
+
+            // Entries are numbered from the newest one
+            let mut positions: Vec<usize> = indexes
+                .iter()
+                .filter_map(|index| count.checked_sub(index + 1))
+                .collect();
+
+            positions.sort_unstable();
+            positions.dedup();
+
+            for position in positions.into_iter().rev() {
+                entries.remove(position);
+            }
+
+            write_reflog(&name, &entries)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the current branch (or detached `HEAD`) to a commit. `mixed` also rebuilds
+    /// the index from its tree, `hard` the working tree as well.
+    fn reset(&self, target: Option<&str>, mode: &str) -> anyhow::Result<()> {
+        let old_head = resolve_ref(HEAD)?;
+
+        let hash = match target {
+            Some(target) => Some(resolve_commit(target)?),
+
+            None => old_head.clone(),
+        };
+
+        let tree = commit_tree(hash.as_deref())?;
+
+        match mode {
+            "soft" => {
+                ensure!(
+                    !Index::read()?.has_conflicts(),
+                    "Cannot do a soft reset in the middle of a merge."
+                );
+            }
+
+            "hard" => {
+                checkout_tree(
+                    commit_tree(old_head.as_deref())?.as_deref(),
+                    tree.as_deref(),
+                    true,
+                    "reset",
+                )?;
+            }
+
+            _ => {
+                let old_index = read_index_or_tree(commit_tree(old_head.as_deref())?.as_deref())?;
+
+                let mut index = Index::from_tree(tree.as_deref())?;
+
+                // Entries that didn't change keep their stat data
+                for entry in &mut index.entries {
+                    if let Some(old) = old_index.get(&entry.path) {
+                        if old.hash == entry.hash && old.mode == entry.mode {
+                            *entry = old.clone();
+                        }
+                    }
+                }
+
+                index.write()?;
+            }
+        }
+
+        if let Some(hash) = &hash {
+            if let Some(old_head) = &old_head {
+                write_to_file(&git_path("ORIG_HEAD"), format!("{old_head}\n").as_bytes())?;
+            }
+
+            update_ref(
+                HEAD,
+                hash,
+                &format!("reset: moving to {}", target.unwrap_or(HEAD)),
+            )?;
+        }
+
+        remove_merge_state();
+
+        match (mode, &hash) {
+            ("hard", Some(hash)) => {
+                println!("HEAD is now at {} {}", &hash[..7], commit_subject(hash)?);
+            }
+
+            ("mixed", _) => print_unstaged_changes(&Index::read()?)?,
+
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Saves the index and working tree as a stash commit whose parents are `HEAD`, a
+    /// commit of the index and, with `include_untracked`, a commit of untracked files,
+    /// then resets them to `HEAD`.
+    fn stash_push(&self, message: Option<&str>, include_untracked: bool) -> anyhow::Result<()> {
+        let Some(head) = resolve_ref(HEAD)? else {
+            bail!("You do not have the initial commit yet");
+        };
+
+        let head_tree = resolve_tree(&head)?;
+
+        let index = read_index_or_tree(Some(&head_tree))?;
+
+        ensure!(
+            !index.has_conflicts(),
+            "could not save index tree: the index has unmerged entries"
+        );
+
+        let staged: BTreeMap<String, FileEntry> = index
+            .entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.file_entry()))
+            .collect();
+
+        let mut worktree = staged.clone();
+
+        for entry in &index.entries {
+            if is_worktree_modified(entry)? {
+                match store_worktree_file(&entry.path)? {
+                    Some(file) => worktree.insert(entry.path.clone(), file),
+
+                    None => worktree.remove(&entry.path),
+                };
+            }
+        }
+
+        let index_tree = write_tree_from_paths(&staged)?;
+
+        let worktree_tree = write_tree_from_paths(&worktree)?;
+
+        let untracked = if include_untracked {
+            list_untracked_files(&index)?
+        } else {
+            Vec::new()
+        };
+
+        if index_tree == head_tree && worktree_tree == head_tree && untracked.is_empty() {
+            println!("No local changes to save");
+
+            return Ok(());
+        }
+
+        let branch = match head_target()? {
+            Some(name) => shorten_ref(&name).to_string(),
+
+            None => "(no branch)".to_string(),
+        };
+
+        let subject = format!("{branch}: {} {}", &head[..7], commit_subject(&head)?);
+
+        let new_commit = |message: String, tree: &str, parents: Vec<String>| {
+            let commit = GitObject::new_commit(
+                &message,
+                tree,
+                parents,
+                Signature::current("AUTHOR")?,
+                Signature::current("COMMITTER")?,
+            )?;
+
+            commit.write_to_file()?;
+
+            anyhow::Ok(commit.get_hash().clone())
+        };
+
+        let mut parents = vec![
+            head.clone(),
+            new_commit(
+                format!("index on {subject}\n"),
+                &index_tree,
+                vec![head.clone()],
+            )?,
+        ];
+
+        if !untracked.is_empty() {
+            let mut files = BTreeMap::new();
+
+            for path in &untracked {
+                if let Some(file) = store_worktree_file(path)? {
+                    files.insert(path.clone(), file);
+                }
+            }
+
+            let tree = write_tree_from_paths(&files)?;
+
+            parents.push(new_commit(
+                format!("untracked files on {subject}\n"),
+                &tree,
+                Vec::new(),
+            )?);
+        }
+
+        let message = match message {
+            Some(message) => format!("On {branch}: {message}"),
+
+            None => format!("WIP on {subject}"),
+        };
+
+        // Unlike the other two, git writes the stash commit's message without a newline
+        let stash = new_commit(message.clone(), &worktree_tree, parents)?;
+
+        update_ref(STASH_REF, &stash, &message)?;
+
+        checkout_tree(Some(&head_tree), Some(&head_tree), true, "reset")?;
+
+        for path in &untracked {
+            remove_worktree_file(path)?;
+        }
+
+        println!("Saved working directory and index state {message}");
+
+        Ok(())
+    }
+
+    /// Applies a stash by merging its changes (relative to the commit it was made on)
+    /// into the current index and working tree. Changes come back unstaged except for
+    /// new files, unless `restore_index` restores the stashed index as well.
+    fn stash_apply(
+        &self,
+        selector: Option<&String>,
+        restore_index: bool,
+        pop: bool,
+    ) -> anyhow::Result<()> {
+        let (name, _) = stash_entry(selector)?;
+
+        let stash =
+            resolve_revision(&name).map_err(|_| anyhow!("{name} is not a valid reference"))?;
+
+        let GitObject::Commit {
+            tree: stash_tree,
+            parents,
+            ..
+        } = GitObject::from_hash(&stash)?
+        else {
+            bail!("{name} is not a stash-like commit");
+        };
+
+        let [base, index_commit, rest @ ..] = parents.as_slice() else {
+            bail!("{name} is not a stash-like commit");
+        };
+
+        let head_tree = commit_tree(resolve_ref(HEAD)?.as_deref())?;
+
+        let index = read_index_or_tree(head_tree.as_deref())?;
+
+        ensure!(
+            !index.has_conflicts(),
+            "Cannot apply a stash in the middle of a merge"
+        );
+
+        let current: BTreeMap<String, FileEntry> = index
+            .entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.file_entry()))
+            .collect();
+
+        let current_tree = write_tree_from_paths(&current)?;
+
+        let untracked = match rest.first() {
+            Some(commit) => flatten_tree(&resolve_tree(commit)?)?,
+
+            None => BTreeMap::new(),
+        };
+
+        for path in untracked.keys() {
+            ensure!(
+                std::fs::symlink_metadata(path).is_err(),
+                "{path} already exists, no checkout\nerror: could not restore untracked files from stash"
+            );
+        }
+
+        let base_tree = resolve_tree(base)?;
+
+        let mut options = merge_options("Updated upstream", "Stashed changes")?;
+
+        options.base_label = "Stash base".to_string();
+
+        let staged_tree = if restore_index {
+            let staged = merge_trees(
+                Some(&base_tree),
+                &current_tree,
+                &resolve_tree(index_commit)?,
+                &options,
+            )?;
+
+            ensure!(
+                staged.is_clean(),
+                "Conflicts in index. Try without --index."
+            );
+
+            staged.tree
+        } else {
+            current_tree.clone()
+        };
+
+        let result = merge_trees(Some(&base_tree), &current_tree, &stash_tree, &options)?;
+
+        checkout_merge_result(Some(&current_tree), &result, "merge")?;
+
+        for (path, file) in &untracked {
+            write_worktree_file(path, file)?;
+        }
+
+        for message in &result.messages {
+            println!("{message}");
+        }
+
+        if !result.is_clean() {
+            if pop {
+                println!("The stash entry is kept in case you need it again.");
+            }
+
+            std::process::exit(1);
+        }
+
+        // Only files the stash added stay staged on top of the index being restored
+        let mut staged = flatten_tree(&staged_tree)?;
+
+        let base_files = flatten_tree(&base_tree)?;
+
+        for (path, file) in flatten_tree(&result.tree)? {
+            if !base_files.contains_key(&path) && !current.contains_key(&path) {
+                staged.entry(path).or_insert(file);
+            }
+        }
+
+        let old_index = Index::read()?;
+
+        let mut index = Index::from_tree(Some(&write_tree_from_paths(&staged)?))?;
+
+        for entry in &mut index.entries {
+            if let Some(old) = old_index.get(&entry.path) {
+                if old.hash == entry.hash && old.mode == entry.mode {
+                    *entry = old.clone();
+                }
+            }
+        }
+
+        index.write()?;
+
+        if pop {
+            self.stash_drop(selector)?;
+        }
+
+        Ok(())
+    }
+
+    fn stash_drop(&self, selector: Option<&String>) -> anyhow::Result<()> {
+        let (name, position) = stash_entry(selector)?;
+
+        let mut entries = read_reflog(STASH_REF)?;
+
+        let Some(index) = entries.len().checked_sub(position + 1) else {
+            bail!("{name} is not a valid reference");
+        };
+
+        let dropped = entries.remove(index);
+
+        match entries.last() {
+            None => delete_ref(STASH_REF)?,
+
+            Some(top) => {
+                // Moving the ref logs a new entry, so the trimmed log is written afterwards
+                update_ref(STASH_REF, &top.new, "drop")?;
+
+                write_reflog(STASH_REF, &entries)?;
+            }
+        }
+
+        println!("Dropped refs/{name} ({})", dropped.new);
+
+        Ok(())
+    }
+
+    fn sequencer_command(
+        &self,
+        action: Action,
+        revisions: &[String],
+        options: SequencerOptions,
+        resume: bool,
+        skip: bool,
+        abort: bool,
+    ) -> anyhow::Result<()> {
+        if !(resume || skip || abort) {
+            return self.start_sequence(action, revisions, options);
+        }
+
+        ensure!(
+            sequencer::in_progress(),
+            "no cherry-pick or revert in progress"
+        );
+
+        if abort {
+            let original = sequencer::original_head()?;
+
+            checkout_tree(
+                commit_tree(resolve_ref(HEAD)?.as_deref())?.as_deref(),
+                Some(&resolve_tree(&original)?),
+                true,
+                "reset",
+            )?;
+
+            update_ref(HEAD, &original, &format!("reset: moving to {original}"))?;
+
+            return sequencer::remove_state();
+        }
+
+        let todo = sequencer::read_todo(&sequencer::todo_path())?;
+
+        if let Some(item) = todo.first() {
+            if skip {
+                let head_tree = commit_tree(resolve_ref(HEAD)?.as_deref())?;
+
+                checkout_tree(head_tree.as_deref(), head_tree.as_deref(), true, "reset")?;
+
+                sequencer::remove_pick_files();
+            } else {
+                ensure!(
+                    !Index::read()?.has_conflicts(),
+                    "Committing is not possible because you have unmerged files.\nhint: Fix them up in the work tree, and then use 'git add/rm <file>'\nhint: as appropriate to mark resolution and make a commit."
+                );
+
+                // Without CHERRY_PICK_HEAD there is nothing to commit (`-n`, or the
+                // user already committed the resolution)
+                if std::path::Path::new(&item.action.head_file()).exists() {
+                    let message = sequencer::strip_comments(
+                        &std::fs::read_to_string(git_path("MERGE_MSG")).unwrap_or_default(),
+                    );
+
+                    let author = match (item.action, GitObject::from_hash(&item.commit)?) {
+                        (Action::Pick, GitObject::Commit { author, .. }) => Some(author),
+
+                        _ => None,
+                    };
+
+                    self.commit_picked(item.action, &item.commit, &message, author)?;
+                }
+            }
+
+            sequencer::write_todo(&sequencer::todo_path(), &todo[1..])?;
+        }
+
+        self.run_sequence(SequencerOptions::read()?)
+    }
+
+    /// Cherry-picks or reverts commits one by one, recording what is left to do in the
+    /// sequencer so that the sequence can be resumed after a conflict.
+    fn start_sequence(
+        &self,
+        action: Action,
+        revisions: &[String],
+        options: SequencerOptions,
+    ) -> anyhow::Result<()> {
+        let command = action.command();
+
+        ensure!(
+            !sequencer::in_progress(),
+            "a cherry-pick or revert is already in progress\nhint: try \"git {command} (--continue | --skip | --abort)\""
+        );
+
+        let Some(head) = resolve_ref(HEAD)? else {
+            bail!("can't {command} into empty head");
+        };
+
+        let commits = sequence_commits(action, revisions)?;
+
+        ensure!(!commits.is_empty(), "empty commit set passed");
+
+        // Checked up front so that a refused start leaves no sequencer behind
+        sequence_base(command, options.no_commit)?;
+
+        let mut walker = CommitWalker::new();
+
+        let todo = commits
+            .iter()
+            .map(|commit| {
+                mainline_parent(commit, &walker.parents(commit)?, options.mainline)?;
+
+                Ok(TodoItem {
+                    action,
+                    commit: commit.clone(),
+                    subject: commit_subject(commit)?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        sequencer::start(&head, &todo, &options)?;
+
+        self.run_sequence(options)
+    }
+
+    /// Applies the remaining todo items, stopping at the first conflict.
+    fn run_sequence(&self, options: SequencerOptions) -> anyhow::Result<()> {
+        let merge_options = merge_options(HEAD, "")?;
+
+        loop {
+            let todo = sequencer::read_todo(&sequencer::todo_path())?;
+
+            let Some(item) = todo.first() else {
+                return sequencer::remove_state();
+            };
+
+            let command = item.action.command();
+
+            let ours_tree = sequence_base(command, options.no_commit)?;
+
+            let picked = pick_commit(
+                item.action,
+                &item.commit,
+                &ours_tree,
+                &options,
+                &merge_options,
+            )?;
+
+            checkout_merge_result(Some(&ours_tree), &picked.result, "merge")?;
+
+            for message in &picked.result.messages {
+                println!("{message}");
+            }
+
+            if !picked.result.is_clean() {
+                let mut message = format!("{}\n# Conflicts:\n", picked.message);
+
+                for conflict in &picked.result.conflicts {
+                    message.push_str(&format!("#\t{}\n", conflict.path));
+                }
+
+                write_to_file(&git_path("MERGE_MSG"), message.as_bytes())?;
+
+                if !options.no_commit {
+                    write_to_file(
+                        &item.action.head_file(),
+                        format!("{}\n", item.commit).as_bytes(),
+                    )?;
+                }
+
+                let verb = match item.action {
+                    Action::Revert => "revert",
+
+                    _ => "apply",
+                };
+
+                eprintln!(
+                    "error: could not {verb} {}... {}",
+                    &item.commit[..7],
+                    item.subject
+                );
+                eprintln!("hint: After resolving the conflicts, mark them with");
+                eprintln!("hint: \"git add/rm <pathspec>\", then run");
+                eprintln!("hint: \"git {command} --continue\".");
+                eprintln!("hint: You can instead skip this commit with \"git {command} --skip\".");
+                eprintln!("hint: To abort and get back to the state before \"git {command}\",");
+                eprintln!("hint: run \"git {command} --abort\".");
+
+                std::process::exit(1);
+            }
+
+            if !options.no_commit {
+                self.commit_picked(item.action, &item.commit, &picked.message, picked.author)?;
+            }
+
+            sequencer::write_todo(&sequencer::todo_path(), &todo[1..])?;
+        }
+    }
+
+    /// Commits the index on top of `HEAD` for a picked or reverted commit. An empty
+    /// result stops the sequence, leaving the choice to skip it to the user.
+    fn commit_picked(
+        &self,
+        action: Action,
+        commit: &str,
+        message: &str,
+        author: Option<Signature>,
+    ) -> anyhow::Result<()> {
+        let command = action.command();
+
+        let Some(head) = resolve_ref(HEAD)? else {
+            bail!("can't {command} into empty head");
+        };
+
+        let tree = index_tree(&Index::read()?)?;
+
+        if tree == resolve_tree(&head)? {
+            write_to_file(&git_path("MERGE_MSG"), message.as_bytes())?;
+
+            write_to_file(&action.head_file(), format!("{commit}\n").as_bytes())?;
+
+            eprintln!("The previous {command} is now empty, possibly due to conflict resolution.");
+            eprintln!("If you wish to commit it anyway, use:\n\n    git commit --allow-empty\n");
+            eprintln!("Otherwise, please use 'git {command} --skip'");
+
+            std::process::exit(1);
+        }
+
+        let author = match author {
+            Some(author) => author,
+
+            None => Signature::current("AUTHOR")?,
+        };
+
+        let new_commit = GitObject::new_commit(
+            message,
+            &tree,
+            vec![head],
+            author,
+            Signature::current("COMMITTER")?,
+        )?;
+
+        new_commit.write_to_file()?;
+
+        let hash = new_commit.get_hash();
+
+        let subject = message.lines().next().unwrap_or_default();
+
+        update_ref(HEAD, hash, &format!("{command}: {subject}"))?;
+
+        sequencer::remove_pick_files();
+
+        let branch = match head_target()? {
+            Some(name) => shorten_ref(&name).to_string(),
+
+            None => "detached HEAD".to_string(),
+        };
+
+        println!("[{branch} {}] {subject}", &hash[..7]);
+
+        Ok(())
+    }
+
+    /// Replays the commits of a branch that `upstream` doesn't have on top of `onto`
+    /// (`upstream` itself by default), following a todo list the user may edit.
+    fn rebase(
+        &self,
+        upstream: Option<&str>,
+        branch: Option<&str>,
+        onto: Option<&str>,
+        interactive: bool,
+        autosquash: bool,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            !sequencer::rebase_in_progress(),
+            "It seems that there is already a rebase-merge directory, and\nI wonder if you are in the middle of another rebase.  If that is the\ncase, please try\n\tgit rebase (--continue | --abort | --skip)"
+        );
+
+        let (head_name, orig_head) = match branch {
+            Some(branch) => {
+                let name = format!("refs/heads/{branch}");
+
+                let Some(hash) = resolve_ref(&name)? else {
+                    bail!("no such branch: {branch}");
+                };
+
+                (Some(name), hash)
+            }
+
+            None => {
+                let Some(hash) = resolve_ref(HEAD)? else {
+                    bail!("You do not have the initial commit yet");
+                };
+
+                (head_target()?, hash)
+            }
+        };
+
+        let upstream_name = match upstream {
+            Some(upstream) => upstream.to_string(),
+
+            None => {
+                let tracked = match &head_name {
+                    Some(name) => branch_upstream(shorten_ref(name))?,
+
+                    None => None,
+                };
+
+                let Some(tracked) = tracked else {
+                    bail!("There is no tracking information for the current branch.\nPlease specify which branch you want to rebase against.");
+                };
+
+                tracked
+            }
+        };
+
+        let upstream = resolve_commit(&upstream_name)
+            .map_err(|_| anyhow!("invalid upstream '{upstream_name}'"))?;
+
+        let (onto_name, onto) = match onto {
+            Some(onto) => (onto.to_string(), resolve_commit(onto)?),
+
+            None => (upstream_name.clone(), upstream.clone()),
+        };
+
+        let head_tree = commit_tree(resolve_ref(HEAD)?.as_deref())?;
+
+        let index = read_index_or_tree(head_tree.as_deref())?;
+
+        for entry in &index.entries {
+            ensure!(
+                entry.stage == 0 && !is_worktree_modified(entry)?,
+                "cannot rebase: You have unstaged changes.\nPlease commit or stash them."
+            );
+        }
+
+        ensure!(
+            Some(index_tree(&index)?) == head_tree,
+            "cannot rebase: Your index contains uncommitted changes.\nPlease commit or stash them."
+        );
+
+        let mut walker = CommitWalker::new();
+
+        let display_name = head_name.as_deref().map_or(HEAD, shorten_ref);
+
+        if !interactive && walker.merge_bases(&upstream, &orig_head)? == [onto.clone()] {
+            if let Some(branch) = branch {
+                self.switch(Some(branch), None, false, false, "checkout")?;
+            }
+
+            println!("Current branch {display_name} is up to date.");
+
+            return Ok(());
+        }
+
+        let mut todo = Vec::new();
+
+        for commit in walker.commits_between(std::slice::from_ref(&upstream), &orig_head)? {
+            // Merge commits are dropped, as git does without --rebase-merges
+            if walker.parents(&commit)?.len() > 1 {
+                continue;
+            }
+
+            todo.push(TodoItem {
+                action: Action::Pick,
+                subject: commit_subject(&commit)?,
+                commit,
+            });
+        }
+
+        if autosquash {
+            todo = sequencer::autosquash(todo);
+        }
+
+        let state = RebaseState {
+            head_name,
+            onto,
+            orig_head,
+            interactive,
+        };
+
+        state.write()?;
+
+        if interactive {
+            let description = format!(
+                "{}..{} onto {}",
+                &upstream[..7],
+                &state.orig_head[..7],
+                &state.onto[..7]
+            );
+
+            let edited = sequencer::edit_todo(&todo, &description);
+
+            todo = match edited {
+                Ok(todo) if !todo.is_empty() => todo,
+
+                other => {
+                    std::fs::remove_dir_all(rebase_dir())?;
+
+                    other?;
+
+                    bail!("Nothing to do");
+                }
+            };
+        }
+
+        sequencer::write_todo(&rebase_path("git-rebase-todo"), &todo)?;
+        sequencer::write_todo(&rebase_path("done"), &[])?;
+
+        write_to_file(
+            &git_path("ORIG_HEAD"),
+            format!("{}\n", state.orig_head).as_bytes(),
+        )?;
+
+        checkout_tree(
+            head_tree.as_deref(),
+            Some(&resolve_tree(&state.onto)?),
+            false,
+            "checkout",
+        )?;
+
+        detach_head(
+            &state.onto,
+            &format!("rebase (start): checkout {onto_name}"),
+        )?;
+
+        self.run_rebase(&state)
+    }
+
+    /// Carries out the rebase todo list until it is done or an item stops it.
+    fn run_rebase(&self, state: &RebaseState) -> anyhow::Result<()> {
+        let merge_options = merge_options(HEAD, "")?;
+
+        let todo_path = rebase_path("git-rebase-todo");
+
+        let done_path = rebase_path("done");
+
+        loop {
+            let mut todo = sequencer::read_todo(&todo_path)?;
+
+            if todo.is_empty() {
+                return self.finish_rebase(state);
+            }
+
+            let item = todo.remove(0);
+
+            let mut done = sequencer::read_todo(&done_path)?;
+
+            done.push(item.clone());
+
+            sequencer::write_todo(&done_path, &done)?;
+            sequencer::write_todo(&todo_path, &todo)?;
+
+            match item.action {
+                Action::Drop => {}
+
+                Action::Break => return Ok(()),
+
+                Action::Exec => {
+                    println!("Executing: {}", item.subject);
+
+                    let status = Command::new("sh").arg("-c").arg(&item.subject).status()?;
+
+                    if !status.success() {
+                        eprintln!("warning: execution failed: {}", item.subject);
+                        eprintln!(
+                            "You can fix the problem, and then run\n\n  git rebase --continue\n"
+                        );
+
+                        std::process::exit(1);
+                    }
+                }
+
+                _ => {
+                    let ours_tree = sequence_base("rebase", false)?;
+
+                    let head = resolve_ref(HEAD)?.unwrap_or_default();
+
+                    // A commit already on top of HEAD is reused as it is
+                    if item.action == Action::Pick
+                        && CommitWalker::new().parents(&item.commit)? == [head]
+                    {
+                        checkout_tree(
+                            Some(&ours_tree),
+                            Some(&resolve_tree(&item.commit)?),
+                            false,
+                            "checkout",
+                        )?;
+
+                        detach_head(&item.commit, &format!("rebase (pick): {}", item.subject))?;
+
+                        continue;
+                    }
+
+                    let picked = pick_commit(
+                        Action::Pick,
+                        &item.commit,
+                        &ours_tree,
+                        &SequencerOptions::default(),
+                        &merge_options,
+                    )?;
+
+                    checkout_merge_result(Some(&ours_tree), &picked.result, "merge")?;
+
+                    for message in &picked.result.messages {
+                        println!("{message}");
+                    }
+
+                    let short = &item.commit[..7];
+
+                    if !picked.result.is_clean() {
+                        write_to_file(
+                            &rebase_path("stopped-sha"),
+                            format!("{}\n", item.commit).as_bytes(),
+                        )?;
+
+                        write_to_file(&rebase_path("message"), picked.message.as_bytes())?;
+
+                        eprintln!("error: could not apply {short}... {}", item.subject);
+                        eprintln!(
+                            "hint: Resolve all conflicts manually, mark them as resolved with"
+                        );
+                        eprintln!("hint: \"git add/rm <conflicted_files>\", then run \"git rebase --continue\".");
+                        eprintln!(
+                            "hint: You can instead skip this commit: run \"git rebase --skip\"."
+                        );
+                        eprintln!("hint: To abort and get back to the state before \"git rebase\", run \"git rebase --abort\".");
+                        eprintln!("Could not apply {short}... {}", item.subject);
+
+                        std::process::exit(1);
+                    }
+
+                    self.commit_rebase_item(&item, &picked.message, picked.author)?;
+
+                    if item.action == Action::Edit {
+                        let head = resolve_ref(HEAD)?.unwrap_or_default();
+
+                        write_to_file(&rebase_path("amend"), format!("{head}\n").as_bytes())?;
+
+                        println!("Stopped at {short}...  {}", item.subject);
+                        println!("You can amend the commit now, with\n\n  git commit --amend \n");
+                        println!("Once you are satisfied with your changes, run\n\n  git rebase --continue");
+
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Commits the index for a replayed commit, melding it into `HEAD` for squashes and
+    /// fixups. Commits whose changes are already there are dropped.
+    fn commit_rebase_item(
+        &self,
+        item: &TodoItem,
+        message: &str,
+        author: Option<Signature>,
+    ) -> anyhow::Result<()> {
+        let Some(head) = resolve_ref(HEAD)? else {
+            bail!("cannot {} without a current revision", item.action);
+        };
+
+        let tree = index_tree(&Index::read()?)?;
+
+        if item.action.is_squash() {
+            return self.squash_into_head(item, &head, &tree, message);
+        }
+
+        if tree == resolve_tree(&head)? {
+            return Ok(());
+        }
+
+        let message = match item.action {
+            Action::Reword => sequencer::edit_message(message)?,
+
+            _ => message.to_string(),
+        };
+
+        let author = match author {
+            Some(author) => author,
+
+            None => Signature::current("AUTHOR")?,
+        };
+
+        let commit = GitObject::new_commit(
+            &message,
+            &tree,
+            vec![head],
+            author,
+            Signature::current("COMMITTER")?,
+        )?;
+
+        commit.write_to_file()?;
+
+        let subject = message.lines().next().unwrap_or_default();
+
+        update_ref(
+            HEAD,
+            commit.get_hash(),
+            &format!("rebase ({}): {subject}", item.action),
+        )?;
+
+        Ok(())
+    }
+
+    /// Replaces `HEAD` with a commit of `tree` that combines its message with the
+    /// squashed one (fixups keep only `HEAD`'s). Messages build up in `message-squash`
+    /// until the last squash or fixup in a row, where squashes open the editor.
+    fn squash_into_head(
+        &self,
+        item: &TodoItem,
+        head: &str,
+        tree: &str,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let GitObject::Commit {
+            parents,
+            author,
+            message: head_message,
+            ..
+        } = GitObject::from_hash(head)?
+        else {
+            bail!("{head} is not a commit");
+        };
+
+        let squash_path = rebase_path("message-squash");
+
+        let (count, mut combined) = match std::fs::read_to_string(&squash_path) {
+            Ok(existing) => {
+                let (header, body) = existing.split_once('\n').unwrap_or((&existing, ""));
+
+                let count = header
+                    .trim_start_matches("# This is a combination of ")
+                    .split(' ')
+                    .next()
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1);
+
+                (count, body.to_string())
+            }
+
+            Err(_) => (
+                1,
+                format!("# This is the 1st commit message:\n\n{head_message}"),
+            ),
+        };
+
+        let count = count + 1;
+
+        if item.action == Action::Squash {
+            combined.push_str(&format!("\n# This is the commit message #{count}:\n\n"));
+
+            // An autosquash marker subject is left out, as a comment
+            if message.starts_with("squash! ") {
+                combined.push_str("# ");
+            }
+
+            combined.push_str(message);
+        } else {
+            combined.push_str(&format!(
+                "\n# The commit message #{count} will be skipped:\n\n"
+            ));
+
+            for line in message.lines() {
+                combined.push_str(&format!("# {line}\n"));
+            }
+        }
+
+        let combined = format!("# This is a combination of {count} commits.\n{combined}");
+
+        let chain_continues = sequencer::read_todo(&rebase_path("git-rebase-todo"))?
+            .first()
+            .is_some_and(|next| next.action.is_squash());
+
+        let message = if chain_continues {
+            write_to_file(&squash_path, combined.as_bytes())?;
+
+            sequencer::strip_comments(&combined)
+        } else {
+            let _ = std::fs::remove_file(&squash_path);
+
+            if combined.contains("\n# This is the commit message #") {
+                sequencer::edit_message(&combined)?
+            } else {
+                sequencer::strip_comments(&combined)
+            }
+        };
+
+        let commit = GitObject::new_commit(
+            &message,
+            tree,
+            parents,
+            author,
+            Signature::current("COMMITTER")?,
+        )?;
+
+        commit.write_to_file()?;
+
+        let subject = message.lines().next().unwrap_or_default();
+
+        update_ref(
+            HEAD,
+            commit.get_hash(),
+            &format!("rebase ({}): {subject}", item.action),
+        )
+    }
+
+    /// Points the rebased branch at the result and checks it out again.
+    fn finish_rebase(&self, state: &RebaseState) -> anyhow::Result<()> {
+        let head = resolve_ref(HEAD)?.unwrap_or_default();
+
+        if let Some(branch) = &state.head_name {
+            update_ref(
+                branch,
+                &head,
+                &format!("rebase (finish): {branch} onto {}", state.onto),
+            )?;
+
+            write_symbolic_ref(
+                HEAD,
+                branch,
+                &format!("rebase (finish): returning to {branch}"),
+            )?;
+        }
+
+        std::fs::remove_dir_all(rebase_dir())?;
+
+        println!(
+            "Successfully rebased and updated {}.",
+            state.head_name.as_deref().unwrap_or("detached HEAD")
+        );
+
+        Ok(())
+    }
+
+    /// Resumes a rebase: commits the resolution of a conflicted item, or any changes
+    /// staged while stopped to edit a commit, then carries on with the todo list.
+    fn rebase_continue(&self, state: &RebaseState) -> anyhow::Result<()> {
+        ensure!(
+            !Index::read()?.has_conflicts(),
+            "You must edit all merge conflicts and then\nmark them as resolved using git add"
+        );
+
+        let stopped = rebase_path("stopped-sha");
+
+        let amend = rebase_path("amend");
+
+        if Path::new(&stopped).exists() {
+            let done = sequencer::read_todo(&rebase_path("done"))?;
+
+            let Some(item) = done.last() else {
+                bail!("could not read the rebase todo list");
+            };
+
+            // Like git, the message goes through the editor before the commit is made
+            let message =
+                sequencer::edit_message(&std::fs::read_to_string(rebase_path("message"))?)?;
+
+            let author = match GitObject::from_hash(&item.commit)? {
+                GitObject::Commit { author, .. } => Some(author),
+
+                _ => None,
+            };
+
+            self.commit_rebase_item(item, &message, author)?;
+
+            std::fs::remove_file(&stopped)?;
+            std::fs::remove_file(rebase_path("message"))?;
+        } else if Path::new(&amend).exists() {
+            let head = resolve_ref(HEAD)?.unwrap_or_default();
+
+            let tree = index_tree(&Index::read()?)?;
+
+            if tree != resolve_tree(&head)? {
+                let GitObject::Commit {
+                    parents,
+                    author,
+                    message,
+                    ..
+                } = GitObject::from_hash(&head)?
+                else {
+                    bail!("{head} is not a commit");
+                };
+
+                let message = sequencer::edit_message(&message)?;
+
+                let commit = GitObject::new_commit(
+                    &message,
+                    &tree,
+                    parents,
+                    author,
+                    Signature::current("COMMITTER")?,
+                )?;
+
+                commit.write_to_file()?;
+
+                let subject = message.lines().next().unwrap_or_default();
+
+                update_ref(
+                    HEAD,
+                    commit.get_hash(),
+                    &format!("commit (amend): {subject}"),
+                )?;
+            }
+
+            std::fs::remove_file(&amend)?;
+        }
+
+        self.run_rebase(state)
+    }
+
+    /// Drops the current item, resetting the index and working tree to `HEAD`.
+    fn rebase_skip(&self, state: &RebaseState) -> anyhow::Result<()> {
+        let head_tree = commit_tree(resolve_ref(HEAD)?.as_deref())?;
+
+        checkout_tree(head_tree.as_deref(), head_tree.as_deref(), true, "reset")?;
+
+        for name in ["stopped-sha", "message", "amend", "message-squash"] {
+            let _ = std::fs::remove_file(rebase_path(name));
+        }
+
+        self.run_rebase(state)
+    }
+
+    /// Returns to the branch (or commit) as it was before the rebase started.
+    fn rebase_abort(&self, state: &RebaseState) -> anyhow::Result<()> {
+        checkout_tree(
+            commit_tree(resolve_ref(HEAD)?.as_deref())?.as_deref(),
+            Some(&resolve_tree(&state.orig_head)?),
+            true,
+            "reset",
+        )?;
+
+        match &state.head_name {
+            Some(branch) => write_symbolic_ref(
+                HEAD,
+                branch,
+                &format!("rebase (abort): returning to {branch}"),
+            )?,
+
+            None => detach_head(
+                &state.orig_head,
+                &format!("rebase (abort): returning to {}", state.orig_head),
+            )?,
+        }
+
+        std::fs::remove_dir_all(rebase_dir())?;
+
+        Ok(())
+    }
+
+    fn merge(
+        &self,
+        message: Option<&str>,
+        no_ff: bool,
+        ff_only: bool,
+        names: &[String],
+    ) -> anyhow::Result<()> {
+        ensure!(
+            !Path::new(&git_path("MERGE_HEAD")).exists(),
+            "You have not concluded your merge (MERGE_HEAD exists).\nPlease, commit your changes before you merge."
+        );
+
+        let Some(head) = resolve_ref(HEAD)? else {
+            bail!("No commits yet on the current branch, nothing to merge into");
+        };
+
+        let mut walker = CommitWalker::new();
+
+        let mut commits = Vec::new();
+
+        for name in names {
+            let hash = resolve_commit(name)?;
+
+            // Already merged heads are dropped, the way git reduces the list
+            if !walker.is_ancestor(&hash, &head)? {
+                commits.push((name.as_str(), hash));
+            }
+        }
+
+        if commits.is_empty() {
+            println!("Already up to date.");
+
+            return Ok(());
+        }
+
+        if commits.len() == 1 && !no_ff && walker.is_ancestor(&head, &commits[0].1)? {
+            let target = &commits[0].1;
+
+            checkout_tree(
+                Some(&walker.commit(&head)?.tree.clone()),
+                Some(&walker.commit(target)?.tree.clone()),
+                false,
+                "merge",
+            )?;
+
+            println!("Updating {}..{}", &head[..7], &target[..7]);
+            println!("Fast-forward");
+
+            update_ref(
+                HEAD,
+                target,
+                &format!("merge {}: Fast-forward", names.join(" ")),
+            )?;
+
+            return Ok(());
+        }
+
+        ensure!(!ff_only, "Not possible to fast-forward, aborting.");
+
+        let head_tree = walker.commit(&head)?.tree.clone();
+
+        let message = match message {
+            Some(message) => format!("{message}\n"),
+
+            None => default_merge_message(&commits.iter().map(|(n, _)| *n).collect::<Vec<_>>())?,
+        };
+
+        let mut tree = head_tree.clone();
+
+        let mut current = head.clone();
+
+        for (index, (name, hash)) in commits.iter().enumerate() {
+            let options = merge_options(HEAD, name)?;
+
+            let result = if index == 0 {
+                merge_commits(&mut walker, &current, hash, &options)?
+            } else {
+                // Octopus: merge each further head into the tree built so far
+                let bases = walker.merge_bases(&current, hash)?;
+
+                let base_tree = match bases.first() {
+                    Some(base) => Some(walker.commit(base)?.tree.clone()),
+
+                    None => None,
+                };
+
+                let theirs_tree = walker.commit(hash)?.tree.clone();
+
+                merge_trees(base_tree.as_deref(), &tree, &theirs_tree, &options)?
+            };
+
+            for message in &result.messages {
+                println!("{message}");
+            }
+
+            // Like git's octopus, only conflicts of the last head are left to resolve
+            if !result.is_clean() && index + 1 < commits.len() {
+                println!("Merge with strategy octopus failed.");
+
+                std::process::exit(2);
+            }
+
+            if !result.is_clean() {
+                checkout_merge_result(Some(&head_tree), &result, "merge")?;
+
+                let heads: String = commits
+                    .iter()
+                    .map(|(_, hash)| format!("{hash}\n"))
+                    .collect();
+
+                let mut message = format!("{message}\n# Conflicts:\n");
+
+                for conflict in &result.conflicts {
+                    message.push_str(&format!("#\t{}\n", conflict.path));
+                }
+
+                write_to_file(&git_path("MERGE_HEAD"), heads.as_bytes())?;
+                write_to_file(&git_path("MERGE_MSG"), message.as_bytes())?;
+                write_to_file(&git_path("MERGE_MODE"), if no_ff { b"no-ff" } else { b"" })?;
+
+                println!("Automatic merge failed; fix conflicts and then commit the result.");
+
+                std::process::exit(1);
+            }
+
+            tree = result.tree;
+
+            current = hash.clone();
+        }
+
+        let mut parents = vec![head];
+
+        parents.extend(commits.iter().map(|(_, hash)| hash.clone()));
+
+        let commit = GitObject::new_commit(
+            &message,
+            &tree,
+            parents,
+            Signature::current("AUTHOR")?,
+            Signature::current("COMMITTER")?,
+        )?;
+
+        checkout_tree(Some(&head_tree), Some(&tree), false, "merge")?;
+
+        commit.write_to_file()?;
+
+        let strategy = if commits.len() > 1 { "octopus" } else { "ort" };
+
+        update_ref(
+            HEAD,
+            commit.get_hash(),
+            &format!(
+                "merge {}: Merge made by the '{strategy}' strategy.",
+                names.join(" ")
+            ),
+        )?;
+
+        println!("Merge made by the '{strategy}' strategy.");
+
+        Ok(())
+    }
+
+    /// Commits a conflicted merge once the index is resolved, with `HEAD` and the heads
+    /// in `MERGE_HEAD` as parents.
+    fn merge_continue(&self) -> anyhow::Result<()> {
+        let Ok(heads) = std::fs::read_to_string(git_path("MERGE_HEAD")) else {
+            bail!("There is no merge in progress (MERGE_HEAD missing).");
+        };
+
+        let Some(head) = resolve_ref(HEAD)? else {
+            bail!("No commits yet on the current branch, nothing to merge into");
+        };
+
+        let index = Index::read()?;
+
+        ensure!(
+            !index.has_conflicts(),
+            "Committing is not possible because you have unmerged files.\nhint: Fix them up in the work tree, and then use 'git add/rm <file>'\nhint: as appropriate to mark resolution and make a commit."
+        );
+
+        let message = sequencer::strip_comments(
+            &std::fs::read_to_string(git_path("MERGE_MSG")).unwrap_or_default(),
+        );
+
+        let mut parents = vec![head];
+
+        parents.extend(heads.lines().map(str::to_string));
+
+        let commit = GitObject::new_commit(
+            &message,
+            &index_tree(&index)?,
+            parents,
+            Signature::current("AUTHOR")?,
+            Signature::current("COMMITTER")?,
+        )?;
+
+        commit.write_to_file()?;
+
+        let hash = commit.get_hash();
+
+        let subject = message.lines().next().unwrap_or_default();
+
+        update_ref(HEAD, hash, &format!("commit (merge): {subject}"))?;
+
+        remove_merge_state();
+
+        let branch = match head_target()? {
+            Some(name) => shorten_ref(&name).to_string(),
+
+            None => "detached HEAD".to_string(),
+        };
+
+        println!("[{branch} {}] {subject}", &hash[..7]);
+
+        Ok(())
+    }
+
+    /// Drops a conflicted merge, putting the index and working tree back to `HEAD`.
+    fn merge_abort(&self) -> anyhow::Result<()> {
+        ensure!(
+            Path::new(&git_path("MERGE_HEAD")).exists(),
+            "There is no merge to abort (MERGE_HEAD missing)."
+        );
+
+        let head_tree = commit_tree(resolve_ref(HEAD)?.as_deref())?;
+
+        checkout_tree(head_tree.as_deref(), head_tree.as_deref(), true, "reset")?;
+
+        remove_merge_state();
+
+        Ok(())
+    }
+
+    /// Clones a repository into a new directory and checks out the remote's default
+    /// branch. A failed clone leaves no directory behind. Objects of a repository given
+    /// by path are hardlinked when `local` is set, or borrowed when `shared` is, and
+    /// then come whole whatever `options` say.
+    fn clone(
+        &self,
+        url: &str,
+        directory: Option<&str>,
+        local: bool,
+        shared: bool,
+        single_branch: bool,
+        mut options: FetchOptions,
+    ) -> anyhow::Result<()> {
+        let directory = match directory {
+            Some(directory) => directory.to_string(),
+
+            None => clone_directory(url)?,
+        };
+
+        let path = Path::new(&directory);
+
+        let created = !path.exists();
+
+        ensure!(
+            created || path.read_dir()?.next().is_none(),
+            "destination path '{directory}' already exists and is not an empty directory."
+        );
+
+        // Local repositories are remembered by absolute path, and may skip the transport
+        let (url, source) = if let Some(path) = url.strip_prefix("file://") {
+            let source = shared.then(|| LocalTransport::new(path)).transpose()?;
+
+            (url.to_string(), source)
+        } else if url.contains("://") {
+            (url.to_string(), None)
+        } else if Bundle::is_bundle(url) {
+            (
+                std::fs::canonicalize(url)?.to_string_lossy().to_string(),
+                None,
+            )
+        } else {
+            let source = LocalTransport::new(url)?;
+
+            let url = std::fs::canonicalize(url)?.to_string_lossy().to_string();
+
+            (url, (local || shared).then_some(source))
+        };
+
+        if source.is_some() {
+            for (option, used) in [
+                ("--depth", options.depth.is_some()),
+                ("--shallow-since", options.deepen_since.is_some()),
+                ("--shallow-exclude", !options.deepen_not.is_empty()),
+                ("--filter", options.filter.is_some()),
+            ] {
+                if used {
+                    eprintln!("warning: {option} is ignored in local clones; use file:// instead.");
+                }
+            }
+
+            options = FetchOptions::default();
+        }
+
+        eprintln!("Cloning into '{directory}'...");
+
+        std::fs::create_dir_all(path)?;
+
+        let parent = std::env::current_dir()?;
+
+        std::env::set_current_dir(path)?;
+
+        set_git_dir(Some(".git"));
+
+        let result = self.clone_into(
+            &url,
+            source.as_ref().map(|source| (source.git_dir(), shared)),
+            single_branch,
+            &options,
+        );
+
+        if result.is_err() {
+            std::env::set_current_dir(&parent)?;
+
+            if created {
+                std::fs::remove_dir_all(&directory)?;
+            } else {
+                std::fs::remove_dir_all(Path::new(&directory).join(".git"))?;
+            }
+        }
+
+        result
+    }
+
+    fn clone_into(
+        &self,
+        url: &str,
+        source: Option<(&Path, bool)>,
+        single_branch: bool,
+        options: &FetchOptions,
+    ) -> anyhow::Result<()> {
+        init_repository()?;
+
+        match source {
+            Some((source, true)) => {
+                std::fs::create_dir_all(git_path("objects/info"))?;
+
+                write_to_file(
+                    &git_path("objects/info/alternates"),
+                    format!("{}\n", source.join("objects").display()).as_bytes(),
+                )?;
+
+                pack::reload_packs();
+            }
+
+            Some((source, false)) => link_objects(&source.join("objects"))?,
+
+            None => {}
+        }
+
+        // A single branch is the one the remote's HEAD points at
+        let branch = match single_branch {
+            true => Connection::open(open_transport(url)?)?
+                .list_refs(&[HEAD])?
+                .into_iter()
+                .find_map(|head| head.symref_target)
+                .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_string)),
+
+            false => None,
+        };
+
+        let branches = branch.as_deref().unwrap_or("*");
+
+        let fetch_refspec = format!("+refs/heads/{branches}:refs/remotes/origin/{branches}");
+
+        set_local("remote.origin.url", url)?;
+        set_local("remote.origin.fetch", &fetch_refspec)?;
+
+        if let Some(filter) = &options.filter {
+            promisor::register("origin", filter)?;
+        }
+
+        let mut refspecs = vec![Refspec::parse(&fetch_refspec)?];
+
+        // A single branch only brings the tags that point into its history
+        if branch.is_none() {
+            refspecs.push(Refspec::parse("+refs/tags/*:refs/tags/*")?);
+        }
+
+        let remote_refs = download(url, &refspecs, &["HEAD", "refs/tags/"], options)?;
+
+        let reason = format!("clone: from {url}");
+
+        for refspec in &refspecs {
+            for (remote_ref, dst) in refspec.select(&remote_refs) {
+                if let Some(dst) = dst {
+                    update_ref(&dst, &remote_ref.hash, &reason)?;
+                }
+            }
+        }
+
+        if branch.is_some() {
+            for remote_ref in &remote_refs {
+                if remote_ref.name.starts_with("refs/tags/") && object_exists(&remote_ref.hash) {
+                    update_ref(&remote_ref.name, &remote_ref.hash, &reason)?;
+                }
+            }
+        }
+
+        let head = remote_refs
+            .iter()
+            .find(|remote_ref| remote_ref.name == HEAD);
+
+        // Without a HEAD (as in a bundle) the branch our own HEAD names is checked out
+        let default = head_target()?;
+
+        let Some(head) = head.or_else(|| {
+            remote_refs
+                .iter()
+                .find(|remote_ref| Some(&remote_ref.name) == default.as_ref())
+        }) else {
+            match remote_refs.is_empty() {
+                true => eprintln!("warning: You appear to have cloned an empty repository."),
+
+                false => {
+                    eprintln!("warning: remote HEAD refers to nonexistent ref, unable to checkout")
+                }
+            }
+
+            return Ok(());
+        };
+
+        let target = match head.name.as_str() {
+            HEAD => head.symref_target.as_deref(),
+
+            name => Some(name),
+        };
+
+        let Some(branch) = target.and_then(|target| target.strip_prefix("refs/heads/")) else {
+            detach_head(&head.hash, &reason)?;
+
+            return checkout_clone(&head.hash);
+        };
+
+        if head.name == HEAD {
+            write_symbolic_ref(
+                "refs/remotes/origin/HEAD",
+                &format!("refs/remotes/origin/{branch}"),
+                &reason,
+            )?;
+        }
+
+        // HEAD first, so the branch update below is logged for both
+        write_symbolic_ref(HEAD, &format!("refs/heads/{branch}"), &reason)?;
+
+        update_ref(&format!("refs/heads/{branch}"), &head.hash, &reason)?;
+
+        set_local(&format!("branch.{branch}.remote"), "origin")?;
+        set_local(
+            &format!("branch.{branch}.merge"),
+            &format!("refs/heads/{branch}"),
+        )?;
+
+        checkout_clone(&head.hash)
+    }
+
+    /// Fetches from a remote, updating the refs its refspecs map to (or the ones given),
+    /// following tags that point into what was fetched, and recording the fetched refs in
+    /// `FETCH_HEAD`. A promisor remote keeps leaving out what its filter does.
+    fn fetch(
+        &self,
+        remote: Option<&str>,
+        args: &[String],
+        mut options: FetchOptions,
+    ) -> anyhow::Result<()> {
+        let name = remote.unwrap_or("origin");
+
+        let remote_config = remote_config(name)?;
+
+        match &options.filter {
+            Some(filter) if find_remote(name)?.is_some() => promisor::register(name, filter)?,
+
+            Some(_) => {}
+
+            None => {
+                let config = Config::load()?;
+
+                if config.get(&format!("remote.{name}.promisor")) == Some("true") {
+                    options.filter = config
+                        .get(&format!("remote.{name}.partialclonefilter"))
+                        .map(str::to_string);
+                }
+            }
+        }
+
+        let url = remote_config.url();
+
+        let mut configured = remote_config.fetch.clone();
+
+        if remote_config.tag_opt.as_deref() == Some("--tags") {
+            configured.push(Refspec::parse("refs/tags/*:refs/tags/*")?);
+        }
+
+        let mut requested = args
+            .iter()
+            .map(|arg| Refspec::parse(arg))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // A remote without fetch refspecs, such as a plain URL, gives its HEAD
+        if configured.is_empty() && requested.iter().all(|refspec| refspec.negative) {
+            requested.insert(0, Refspec::parse(HEAD)?);
+        }
+
+        // Negative refspecs alone narrow down the configured ones
+        let explicit = requested.iter().any(|refspec| !refspec.negative);
+
+        let refspecs = if explicit {
+            requested.clone()
+        } else {
+            [configured.clone(), requested].concat()
+        };
+
+        let refspecs = &refspecs;
+
+        let remote_refs = download(url, refspecs, &["refs/tags/"], &options)?;
+
+        let reason = std::iter::once("fetch")
+            .chain(remote)
+            .chain(args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let merge_ref = match head_target()?
+            .as_deref()
+            .and_then(|h| h.strip_prefix("refs/heads/"))
+        {
+            Some(branch) if !explicit => {
+                let config = Config::load()?;
+
+                match config.get(&format!("branch.{branch}.remote")) {
+                    Some(remote) if remote == name => config
+                        .get(&format!("branch.{branch}.merge"))
+                        .map(str::to_string),
+
+                    _ => None,
+                }
+            }
+
+            _ => None,
+        };
+
+        // (remote ref, local ref to update, whether it is for merging), for-merge first
+        let mut selected: Vec<(&RemoteRef, Option<String>, bool)> = Vec::new();
+
+        for refspec in refspecs.iter().filter(|refspec| !refspec.negative) {
+            let matches = refspec.select(&remote_refs);
+
+            ensure!(
+                refspec.is_glob() || !matches.is_empty(),
+                "couldn't find remote ref {}",
+                refspec.src
+            );
+
+            for (remote_ref, dst) in matches {
+                if is_excluded(refspecs, &remote_ref.name) {
+                    continue;
+                }
+
+                let for_merge = match &merge_ref {
+                    Some(merge_ref) => *merge_ref == remote_ref.name,
+
+                    None => explicit && !refspec.is_glob(),
+                };
+
+                if !selected
+                    .iter()
+                    .any(|(other, _, _)| other.name == remote_ref.name)
+                {
+                    selected.push((remote_ref, dst, for_merge));
+                }
+            }
+        }
+
+        // Tags are followed for refs stored locally, not for ones only in FETCH_HEAD
+        let follow_tags = remote_config.tag_opt.as_deref() != Some("--no-tags")
+            && (!explicit || refspecs.iter().any(|spec| spec.dst.is_some()));
+
+        for remote_ref in remote_refs.iter().filter(|_| follow_tags) {
+            let Some(tag) = remote_ref.name.strip_prefix("refs/tags/") else {
+                continue;
+            };
+
+            if !selected
+                .iter()
+                .any(|(other, _, _)| other.name == remote_ref.name)
+                && read_ref(&remote_ref.name)?.is_none()
+                && object_exists(&remote_ref.hash)
+            {
+                selected.push((remote_ref, Some(format!("refs/tags/{tag}")), false));
+            }
+        }
+
+        selected.sort_by_key(|(_, _, for_merge)| !for_merge);
+
+        let display = display_url(url);
+
+        let mut fetch_head = String::new();
+
+        let mut lines = Vec::new();
+
+        let mut rejected = false;
+
+        for (remote_ref, dst, for_merge) in &selected {
+            let short = shorten_ref(&remote_ref.name);
+
+            let kind = match remote_ref.name.as_str() {
+                name if name.starts_with("refs/heads/") => format!("branch '{short}' of "),
+
+                name if name.starts_with("refs/tags/") => format!("tag '{short}' of "),
+
+                HEAD => String::new(),
+
+                name => format!("'{name}' of "),
+            };
+
+            fetch_head.push_str(&format!(
+                "{}\t{}\t{kind}{display}\n",
+                remote_ref.hash,
+                if *for_merge { "" } else { "not-for-merge" }
+            ));
+
+            let force = refspecs
+                .iter()
+                .any(|spec| spec.force && spec.matches(&remote_ref.name))
+                || configured
+                    .iter()
+                    .any(|spec| spec.force && spec.matches(&remote_ref.name));
+
+            let targets = match dst {
+                Some(dst) => vec![dst.clone()],
+
+                None => {
+                    // Anything but a tag, `HEAD` included, is labelled a branch
+                    let summary = match kind.split(' ').next() {
+                        Some("tag") => "tag",
+
+                        _ => "branch",
+                    };
+
+                    lines.push((
+                        '*',
+                        summary.to_string(),
+                        short,
+                        "FETCH_HEAD".to_string(),
+                        "",
+                    ));
+
+                    // Refs fetched by name also update their remote-tracking refs
+                    configured
+                        .iter()
+                        .find_map(|spec| spec.map(&remote_ref.name))
+                        .into_iter()
+                        .collect()
+                }
+            };
+
+            for dst in targets {
+                let (flag, summary, suffix) =
+                    self.update_fetched_ref(&dst, &remote_ref.hash, force, &reason)?;
+
+                rejected |= flag == '!';
+
+                if flag != '=' {
+                    lines.push((flag, summary, short, shorten_ref(&dst).to_string(), suffix));
+                }
+            }
+        }
+
+        write_to_file(&git_path("FETCH_HEAD"), fetch_head.as_bytes())?;
+
+        if !lines.is_empty() {
+            eprintln!("From {display}");
+
+            // git keeps the column at least ten wide
+            let width = lines
+                .iter()
+                .map(|(_, _, from, _, _)| from.len())
+                .fold(10, usize::max);
+
+            for (flag, summary, from, to, suffix) in lines {
+                eprintln!(" {flag} {summary:<17} {from:<width$} -> {to}{suffix}");
+            }
+        }
+
+        if rejected {
+            eprintln!("error: some local refs could not be updated");
+
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    /// Moves a local ref to a fetched object if that is allowed, returning the status
+    /// flag, summary and suffix of the line fetch prints for it (`=` when unchanged).
+    fn update_fetched_ref(
+        &self,
+        name: &str,
+        new: &str,
+        force: bool,
+        reason: &str,
+    ) -> anyhow::Result<(char, String, &'static str)> {
+        let Some(old) = resolve_ref(name)? else {
+            let summary = match name {
+                name if name.starts_with("refs/heads/") || name.starts_with("refs/remotes/") => {
+                    "[new branch]"
+                }
+
+                name if name.starts_with("refs/tags/") => "[new tag]",
+
+                _ => "[new ref]",
+            };
+
+            update_ref(name, new, &format!("{reason}: storing head"))?;
+
+            return Ok(('*', summary.to_string(), ""));
+        };
+
+        if old == new {
+            return Ok(('=', "[up to date]".to_string(), ""));
+        }
+
+        if head_target()?.as_deref() == Some(name) {
+            bail!(
+                "refusing to fetch into branch '{name}' checked out at '{}'",
+                std::env::current_dir()?.display()
+            );
+        }
+
+        if name.starts_with("refs/tags/") && !force {
+            return Ok((
+                '!',
+                "[rejected]".to_string(),
+                "  (would clobber existing tag)",
+            ));
+        }
+
+        let range = |separator: &str| format!("{}{separator}{}", &old[..7], &new[..7]);
+
+        if CommitWalker::new().is_ancestor(&old, new)? {
+            update_ref(name, new, &format!("{reason}: fast-forward"))?;
+
+            Ok((' ', range(".."), ""))
+        } else if force {
+            update_ref(name, new, &format!("{reason}: forced-update"))?;
+
+            Ok(('+', range("..."), "  (forced update)"))
+        } else {
+            Ok(('!', "[rejected]".to_string(), "  (non-fast-forward)"))
+        }
+    }
+
+    /// Configures a new remote fetching every branch (or the `track`ed ones) into
+    /// `refs/remotes/<name>/`.
+    fn remote_add(
+        &self,
+        name: &str,
+        url: &str,
+        track: &[String],
+        master: Option<&str>,
+        tag_opt: Option<&str>,
+    ) -> anyhow::Result<()> {
+        ensure!(
+            check_ref_format(&format!("refs/remotes/{name}/test"), false),
+            "'{name}' is not a valid remote name"
+        );
+
+        ensure!(
+            find_remote(name)?.is_none(),
+            "remote {name} already exists."
+        );
+
+        set_local(&format!("remote.{name}.url"), url)?;
+
+        let branches = match track {
+            [] => vec!["*".to_string()],
+
+            track => track.to_vec(),
+        };
+
+        for branch in branches {
+            add_local(
+                &format!("remote.{name}.fetch"),
+                &format!("+refs/heads/{branch}:refs/remotes/{name}/{branch}"),
+            )?;
+        }
+
+        if let Some(tag_opt) = tag_opt {
+            set_local(&format!("remote.{name}.tagOpt"), tag_opt)?;
+        }
+
+        if let Some(master) = master {
+            write_symbolic_ref(
+                &format!("refs/remotes/{name}/HEAD"),
+                &format!("refs/remotes/{name}/{master}"),
+                "remote add",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Renames a remote along with its remote-tracking refs and the branches set up to
+    /// pull from it.
+    fn remote_rename(&self, old: &str, new: &str) -> anyhow::Result<()> {
+        ensure!(find_remote(old)?.is_some(), "No such remote: '{old}'");
+
+        ensure!(
+            check_ref_format(&format!("refs/remotes/{new}/test"), false),
+            "'{new}' is not a valid remote name"
+        );
+
+        ensure!(find_remote(new)?.is_none(), "remote {new} already exists.");
+
+        rename_section(&format!("remote.{old}"), Some(&format!("remote.{new}")))?;
+
+        let config = Config::load()?;
+
+        let old_prefix = format!("refs/remotes/{old}/");
+
+        let new_prefix = format!("refs/remotes/{new}/");
+
+        // Only destinations in the remote's own namespace follow the new name
+        let fetch_key = format!("remote.{new}.fetch");
+
+        let refspecs: Vec<String> = config
+            .get_all(&fetch_key)
+            .iter()
+            .map(|spec| spec.replace(&format!(":{old_prefix}"), &format!(":{new_prefix}")))
+            .collect();
+
+        unset_local(&fetch_key)?;
+
+        for refspec in refspecs {
+            add_local(&fetch_key, &refspec)?;
+        }
+
+        for branch in config.subsections("branch") {
+            let key = format!("branch.{branch}.remote");
+
+            if config.get(&key) == Some(old) {
+                set_local(&key, new)?;
+            }
+        }
+
+        // Symbolic refs are moved last, once what they point at has moved
+        let mut symbolic = Vec::new();
+
+        for (name, hash) in list_refs(&old_prefix)? {
+            let new_name = format!("{new_prefix}{}", &name[old_prefix.len()..]);
+
+            if let Some(target) =
+                read_ref(&name)?.and_then(|v| v.strip_prefix("ref: ").map(str::to_string))
+            {
+                symbolic.push((name, new_name, target));
+
+                continue;
+            }
+
+            move_reflog(&name, &new_name, false)?;
+
+            delete_ref(&name)?;
+
+            update_ref(
+                &new_name,
+                &hash,
+                &format!("remote: renamed {name} to {new_name}"),
+            )?;
+        }
+
+        for (name, new_name, target) in symbolic {
+            delete_ref(&name)?;
+
+            write_symbolic_ref(
+                &new_name,
+                &target.replacen(&old_prefix, &new_prefix, 1),
+                &format!("remote: renamed {name} to {new_name}"),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets a remote, its remote-tracking refs and the upstream settings of the
+    /// branches that pull from it.
+    fn remote_remove(&self, name: &str) -> anyhow::Result<()> {
+        let Some(remote) = find_remote(name)? else {
+            bail!("No such remote: '{name}'");
+        };
+
+        for (tracking, _) in list_refs("refs/remotes/")? {
+            if remote
+                .fetch
+                .iter()
+                .any(|spec| spec.unmap(&tracking).is_some())
+            {
+                delete_ref(&tracking)?;
+            }
+        }
+
+        let config = Config::load()?;
+
+        for branch in config.subsections("branch") {
+            if config.get(&format!("branch.{branch}.remote")) == Some(name) {
+                unset_local(&format!("branch.{branch}.remote"))?;
+                unset_local(&format!("branch.{branch}.merge"))?;
+            }
+        }
+
+        rename_section(&format!("remote.{name}"), None)
+    }
+
+    /// Changes the URLs of a remote under `key` (`url` or `pushurl`): replaces the one
+    /// containing `old` (or the only one), adds another, or deletes those containing `url`.
+    fn remote_set_url(
+        &self,
+        name: &str,
+        key: &str,
+        url: &str,
+        old: Option<&String>,
+        add: bool,
+        delete: bool,
+    ) -> anyhow::Result<()> {
+        ensure!(find_remote(name)?.is_some(), "No such remote '{name}'");
+
+        let key = format!("remote.{name}.{key}");
+
+        if add {
+            return add_local(&key, url);
+        }
+
+        let config = Config::load()?;
+
+        let current: Vec<String> = config
+            .get_all(&key)
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+
+        let pattern = if delete {
+            Some(url)
+        } else {
+            old.map(String::as_str)
+        };
+
+        let Some(pattern) = pattern else {
+            ensure!(
+                current.len() <= 1,
+                "cannot overwrite multiple values with a single value"
+            );
+
+            return set_local(&key, url);
+        };
+
+        let matches = |value: &str| value.contains(pattern);
+
+        ensure!(
+            current.iter().any(|value| matches(value)),
+            "No such URL found: {pattern}"
+        );
+
+        ensure!(
+            !delete || key.ends_with(".pushurl") || !current.iter().all(|value| matches(value)),
+            "Will not delete all non-push URLs"
+        );
+
+        replace_local(&key, matches, (!delete).then_some(url))
+    }
+
+    /// Describes a remote: its URLs and branches, and the local branches that pull from
+    /// or push to it. Without `query` the remote itself is not contacted.
+    fn remote_show(&self, name: &str, query: bool) -> anyhow::Result<()> {
+        let Some(remote) = find_remote(name)? else {
+            bail!("No such remote: '{name}'");
+        };
+
+        println!("* remote {name}");
+        println!("  Fetch URL: {}", remote.url());
+
+        for url in remote.push_targets() {
+            println!("  Push  URL: {url}");
+        }
+
+        let remote_refs = match query {
+            true => Some(list_remote_refs(&remote)?),
+
+            false => None,
+        };
+
+        let head = match &remote_refs {
+            Some(refs) => refs
+                .iter()
+                .find(|remote_ref| remote_ref.name == HEAD)
+                .and_then(|head| head.symref_target.as_deref())
+                .map_or("(unknown)", shorten_ref),
+
+            None => "(not queried)",
+        };
+
+        println!("  HEAD branch: {head}");
+
+        // (name, status)
+        let mut branches = Vec::new();
+
+        match &remote_refs {
+            Some(refs) => {
+                for remote_ref in refs {
+                    let Some(branch) = remote_ref.name.strip_prefix("refs/heads/") else {
+                        continue;
+                    };
+
+                    let Some(tracking) = remote
+                        .fetch
+                        .iter()
+                        .find_map(|spec| spec.map(&remote_ref.name))
+                        .filter(|_| !is_excluded(&remote.fetch, &remote_ref.name))
+                    else {
+                        continue;
+                    };
+
+                    let status = match read_ref(&tracking)? {
+                        Some(_) => "tracked".to_string(),
+
+                        None => format!("new (next fetch will store in remotes/{name})"),
+                    };
+
+                    branches.push((branch.to_string(), status));
+                }
+
+                for stale in stale_tracking_refs(&remote, refs)? {
+                    branches.push((
+                        stale,
+                        "stale (use 'git remote prune' to remove)".to_string(),
+                    ));
+                }
+            }
+
+            None => {
+                for (tracking, _) in list_refs("refs/")? {
+                    if let Some(src) = remote.fetch.iter().find_map(|spec| spec.unmap(&tracking)) {
+                        if !is_symbolic_ref(&tracking)? {
+                            branches.push((shorten_ref(&src).to_string(), String::new()));
+                        }
+                    }
+                }
+            }
+        }
+
+        branches.sort();
+
+        let not_queried = if query { "" } else { " (status not queried)" };
+
+        if !branches.is_empty() {
+            let title = if branches.len() == 1 {
+                "Remote branch"
+            } else {
+                "Remote branches"
+            };
+
+            println!("  {title}:{not_queried}");
+
+            let width = branches
+                .iter()
+                .map(|(name, _)| name.len())
+                .max()
+                .unwrap_or(0);
+
+            for (branch, status) in branches {
+                println!("    {}", format!("{branch:<width$} {status}").trim_end());
+            }
+        }
+
+        let config = Config::load()?;
+
+        let mut pulls = Vec::new();
+
+        for branch in config.subsections("branch") {
+            if config.get(&format!("branch.{branch}.remote")) != Some(name) {
+                continue;
+            }
+
+            if let Some(merge) = config.get(&format!("branch.{branch}.merge")) {
+                pulls.push((branch.to_string(), shorten_ref(merge).to_string()));
+            }
+        }
+
+        pulls.sort();
+
+        if !pulls.is_empty() {
+            let title = if pulls.len() == 1 {
+                "Local branch"
+            } else {
+                "Local branches"
+            };
+
+            println!("  {title} configured for 'git pull':");
+
+            let width = pulls
+                .iter()
+                .map(|(branch, _)| branch.len())
+                .max()
+                .unwrap_or(0);
+
+            for (branch, merge) in pulls {
+                println!("    {branch:<width$} merges with remote {merge}");
+            }
+        }
+
+        // (source, destination, forced, status)
+        let mut pushes = Vec::new();
+
+        match &remote_refs {
+            Some(refs) => {
+                let remote_hash = |dst: &str| {
+                    refs.iter()
+                        .find(|remote_ref| remote_ref.name == dst)
+                        .map(|remote_ref| remote_ref.hash.clone())
+                };
+
+                if remote.push.is_empty() {
+                    // Pushing by default matches branches of the same name
+                    for (branch, hash) in list_refs("refs/heads/")? {
+                        if let Some(theirs) = remote_hash(&branch) {
+                            pushes.push((branch, None, false, push_status(&hash, Some(&theirs))?));
+                        }
+                    }
+                }
+
+                for spec in remote.push.iter().filter(|spec| !spec.negative) {
+                    let mut sources = Vec::new();
+
+                    if spec.is_glob() {
+                        for (local, _) in list_refs("refs/")? {
+                            if let Some(dst) = spec.map(&local) {
+                                sources.push((local, dst));
+                            }
+                        }
+                    } else {
+                        let dst = spec.dst.clone().unwrap_or_else(|| spec.src.clone());
+
+                        sources.push((spec.src.clone(), dst));
+                    }
+
+                    for (src, dst) in sources {
+                        let Ok(hash) = resolve_revision(&src) else {
+                            continue;
+                        };
+
+                        let dst = match dst.starts_with("refs/") {
+                            true => dst,
+
+                            false => format!("refs/heads/{dst}"),
+                        };
+
+                        let status = push_status(&hash, remote_hash(&dst).as_deref())?;
+
+                        pushes.push((src, Some(dst), spec.force, status));
+                    }
+                }
+            }
+
+            None if remote.push.is_empty() => pushes.push((
+                "(matching)".to_string(),
+                Some("(matching)".to_string()),
+                false,
+                "",
+            )),
+
+            None => {
+                for spec in remote.push.iter().filter(|spec| !spec.negative) {
+                    let dst = spec.dst.clone().unwrap_or_else(|| spec.src.clone());
+
+                    pushes.push((spec.src.clone(), Some(dst), spec.force, ""));
+                }
+            }
+        }
+
+        if !pushes.is_empty() {
+            let title = if pushes.len() == 1 {
+                "Local ref"
+            } else {
+                "Local refs"
+            };
+
+            println!("  {title} configured for 'git push'{not_queried}:");
+
+            // Queried refs are shown by their short names
+            let names: Vec<(String, String, bool, &str)> = pushes
+                .into_iter()
+                .map(|(src, dst, forced, status)| {
+                    let dst = dst.unwrap_or_else(|| src.clone());
+
+                    match query {
+                        true => (
+                            shorten_ref(&src).to_string(),
+                            shorten_ref(&dst).to_string(),
+                            forced,
+                            status,
+                        ),
+
+                        false => (src, dst, forced, status),
+                    }
+                })
+                .collect();
+
+            let src_width = names.iter().map(|(src, ..)| src.len()).max().unwrap_or(0);
+
+            let dst_width = names
+                .iter()
+                .map(|(_, dst, ..)| dst.len())
+                .max()
+                .unwrap_or(0);
+
+            for (src, dst, forced, status) in names {
+                let verb = if forced { "forces to" } else { "pushes to" };
+
+                let line = match status {
+                    "" => format!("{src:<src_width$} {verb} {dst}"),
+
+                    status => format!("{src:<src_width$} {verb} {dst:<dst_width$} ({status})"),
+                };
+
+                println!("    {line}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the remote-tracking refs of branches that are gone from the remote.
+    fn remote_prune(&self, name: &str, dry_run: bool) -> anyhow::Result<()> {
+        let Some(remote) = find_remote(name)? else {
+            bail!("No such remote: '{name}'");
+        };
+
+        let stale = stale_tracking_refs(&remote, &list_remote_refs(&remote)?)?;
+
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        println!("Pruning {name}");
+        println!("URL: {}", remote.url());
+
+        for tracking in stale {
+            if !dry_run {
+                delete_ref(&tracking)?;
+            }
+
+            let action = if dry_run { "would prune" } else { "pruned" };
+
+            println!(" * [{action}] {}", shorten_ref(&tracking));
+        }
+
+        Ok(())
+    }
+
+    /// Sends local refs to a remote along with the objects it lacks, and prints what
+    /// became of each ref. Remote-tracking refs follow the refs that were updated.
+    fn push(
+        &self,
+        remote: Option<&str>,
+        args: &[String],
+        options: &PushOptions,
+    ) -> anyhow::Result<()> {
+        let config = Config::load()?;
+
+        let upstream_remote = head_target()?
+            .as_deref()
+            .and_then(|h| h.strip_prefix("refs/heads/"))
+            .and_then(|branch| config.get(&format!("branch.{branch}.remote")))
+            .map(str::to_string);
+
+        let name = remote.or(upstream_remote.as_deref()).unwrap_or("origin");
+
+        let remote_config = remote_config(name)?;
+
+        let refspecs = match args {
+            [] if !remote_config.push.is_empty() => remote_config.push.clone(),
+
+            [] => vec![default_push_refspec(name, remote.is_some())?],
+
+            args => args
+                .iter()
+                .map(|arg| Refspec::parse(arg))
+                .collect::<anyhow::Result<_>>()?,
+        };
+
+        for url in remote_config.push_targets() {
+            self.push_to(name, url, &refspecs, &remote_config.fetch, options)?;
+        }
+
+        Ok(())
+    }
+
+    fn push_to(
+        &self,
+        name: &str,
+        url: &str,
+        refspecs: &[Refspec],
+        fetch_refspecs: &[Refspec],
+        options: &PushOptions,
+    ) -> anyhow::Result<()> {
+        let mut connection = PushConnection::open(open_transport(url)?)?;
+
+        let mut updates = push_updates(refspecs, &connection.refs, options.force)?;
+
+        let tracking_ref = |name: &str| fetch_refspecs.iter().find_map(|spec| spec.map(name));
+
+        for lease in &options.leases {
+            let (lease_ref, expect) = match lease.split_once(':') {
+                // An empty value or the null id expects the ref not to exist
+                Some((lease_ref, "" | ZERO_HASH)) => (lease_ref, Some(ZERO_HASH.to_string())),
+
+                Some((lease_ref, expect)) => (lease_ref, Some(resolve_revision(expect)?)),
+
+                None => (lease.as_str(), None),
+            };
+
+            for update in updates
+                .iter_mut()
+                .filter(|u| lease_ref.is_empty() || ref_candidates(lease_ref).contains(&u.dst))
+            {
+                // Without an explicit value the remote ref has to be where we last saw it
+                let expect = match &expect {
+                    Some(expect) => Some(expect.clone()),
+
+                    None => match tracking_ref(&update.dst) {
+                        Some(tracking) => resolve_ref(&tracking)?,
+
+                        None => None,
+                    },
+                };
+
+                update.expect = Some(expect.unwrap_or_else(|| ZERO_HASH.to_string()));
+            }
+        }
+
+        let (current, pending): (Vec<_>, Vec<_>) = updates
+            .into_iter()
+            .partition(|update| update.old == update.new);
+
+        // Each update with the summary and reason it failed with, if it did
+        let mut results = Vec::new();
+
+        for update in pending {
+            let rejection = push_rejection(&update)?;
+
+            results.push((update, rejection.map(|r| ("[rejected]", r.to_string()))));
+        }
+
+        if options.atomic && results.iter().any(|(_, status)| status.is_some()) {
+            for (_, status) in results.iter_mut().filter(|(_, status)| status.is_none()) {
+                *status = Some(("[rejected]", "atomic push failed".to_string()));
+            }
+        }
+
+        let commands: Vec<RefUpdate> = results
+            .iter()
+            .filter(|(_, status)| status.is_none())
+            .map(|(update, _)| RefUpdate {
+                name: update.dst.clone(),
+                old: update.old.clone(),
+                new: update.new.clone(),
+            })
+            .collect();
+
+        if !commands.is_empty() {
+            let include: Vec<String> = commands
+                .iter()
+                .filter(|command| command.new != ZERO_HASH)
+                .map(|command| command.new.clone())
+                .collect();
+
+            let pack = if include.is_empty() {
+                None
+            } else {
+                let exclude: Vec<String> = connection
+                    .refs
+                    .iter()
+                    .map(|remote_ref| remote_ref.hash.clone())
+                    .filter(|hash| object_exists(hash))
+                    .collect();
+
+                let objects = CommitWalker::new().objects_between(&exclude, &include)?;
+
+                Some(pack::write_pack(&objects)?)
+            };
+
+            let statuses = connection.send_pack(
+                &commands,
+                pack.as_deref(),
+                options.atomic,
+                &options.push_options,
+            )?;
+
+            for (update, status) in results.iter_mut().filter(|(_, status)| status.is_none()) {
+                match statuses.iter().find(|(name, _)| *name == update.dst) {
+                    Some((_, None)) => {}
+
+                    Some((_, Some(reason))) => {
+                        *status = Some(("[remote rejected]", reason.clone()))
+                    }
+
+                    None => {
+                        *status = Some((
+                            "[remote failure]",
+                            "remote failed to report status".to_string(),
+                        ))
+                    }
+                }
+            }
+        }
+
+        if results.is_empty() {
+            eprintln!("Everything up-to-date");
+        } else {
+            eprintln!("To {url}");
+        }
+
+        // Successful updates are listed before failed ones
+        let (succeeded, failed): (Vec<_>, Vec<_>) =
+            results.iter().partition(|(_, status)| status.is_none());
+
+        for (update, status) in succeeded.iter().chain(&failed) {
+            let refs = match update.new.as_str() {
+                ZERO_HASH => shorten_ref(&update.dst).to_string(),
+
+                _ => format!(
+                    "{} -> {}",
+                    shorten_ref(&update.src),
+                    shorten_ref(&update.dst)
+                ),
+            };
+
+            if let Some((summary, reason)) = status {
+                eprintln!(" ! {summary:<17} {refs} ({reason})");
+
+                continue;
+            }
+
+            let range =
+                |separator: &str| format!("{}{separator}{}", &update.old[..7], &update.new[..7]);
+
+            let (flag, summary, suffix) = if update.new == ZERO_HASH {
+                ('-', "[deleted]".to_string(), "")
+            } else if update.old == ZERO_HASH {
+                let summary = match &update.dst {
+                    dst if dst.starts_with("refs/heads/") => "[new branch]",
+
+                    dst if dst.starts_with("refs/tags/") => "[new tag]",
+
+                    _ => "[new reference]",
+                };
+
+                ('*', summary.to_string(), "")
+            } else if is_fast_forward(&update.old, &update.new)? {
+                (' ', range(".."), "")
+            } else {
+                ('+', range("..."), " (forced update)")
+            };
+
+            eprintln!(" {flag} {summary:<17} {refs}{suffix}");
+        }
+
+        for update in current
+            .iter()
+            .chain(succeeded.iter().map(|(update, _)| update))
+        {
+            if let Some(tracking) = tracking_ref(&update.dst) {
+                if update.new == ZERO_HASH {
+                    delete_ref(&tracking)?;
+                } else if resolve_ref(&tracking)?.as_deref() != Some(update.new.as_str()) {
+                    update_ref(&tracking, &update.new, "update by push")?;
+                }
+            }
+
+            if options.set_upstream && update.dst.starts_with("refs/heads/") {
+                let local = match update.src.as_str() {
+                    HEAD => head_target()?,
+
+                    src => Some(src.to_string()),
+                };
+
+                if let Some(branch) = local.as_deref().and_then(|l| l.strip_prefix("refs/heads/")) {
+                    set_local(&format!("branch.{branch}.remote"), name)?;
+                    set_local(&format!("branch.{branch}.merge"), &update.dst)?;
+
+                    println!(
+                        "branch '{branch}' set up to track '{name}/{}'.",
+                        shorten_ref(&update.dst)
+                    );
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        eprintln!("error: failed to push some refs to '{url}'");
+
+        let head = head_target()?;
+
+        let rejected_for = |wanted: &str, on_head: bool| {
+            failed.iter().any(|(update, status)| {
+                matches!(status, Some(("[rejected]", reason)) if reason == wanted)
+                    && (!on_head || update.src == HEAD || head.as_deref() == Some(&update.src))
+            })
+        };
+
+        let advice = if rejected_for("non-fast-forward", true) {
+            Some(ADVICE_PULL_BEFORE_PUSH)
+        } else if rejected_for("non-fast-forward", false) {
+            Some(ADVICE_CHECKOUT_PULL_PUSH)
+        } else if rejected_for("fetch first", false) {
+            Some(ADVICE_FETCH_FIRST)
+        } else if rejected_for("already exists", false) {
+            Some(ADVICE_REF_ALREADY_EXISTS)
+        } else {
+            None
+        };
+
+        for line in advice.iter().flat_map(|advice| advice.lines()) {
+            eprintln!("hint: {line}");
+        }
+
+        std::process::exit(1);
+    }
+}
+
+/// Creates an empty repository in the current directory.
+fn init_repository() -> anyhow::Result<()> {
+    create_directory(".git")?;
+    create_directory(".git/refs")?;
+    create_directory(".git/objects")?;
+
+    write_to_file(".git/HEAD", b"ref: refs/heads/main\n")
+}
+
+/// Hardlinks every object file of another repository into this one, copying where
+/// links are not possible (such as across file systems).
+fn link_objects(source: &Path) -> anyhow::Result<()> {
+    let mut directories = vec![source.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory)?.flatten() {
+            let path = entry.path();
+
+            let target = Path::new(&git_path("objects")).join(path.strip_prefix(source)?);
+
+            if path.is_dir() {
+                std::fs::create_dir_all(&target)?;
+
+                directories.push(path);
+            } else if std::fs::hard_link(&path, &target).is_err() {
+                std::fs::copy(&path, &target)?;
+            }
+        }
+    }
+
+    pack::reload_packs();
+
+    Ok(())
+}
+
+/// Directory a clone goes into when none is given: the last part of the URL without
+/// its `.git` suffix.
+/// What `bundle verify` reports about a bundle: its refs, filter and prerequisites (whose
+/// comments git leaves out).
+fn print_bundle_summary(bundle: &Bundle) {
+    let print_refs = |verb: &str, refs: Vec<(&str, &str)>| {
+        match refs.len() {
+            1 => println!("The bundle {verb} this ref:"),
+
+            count => println!("The bundle {verb} these {count} refs:"),
+        }
+
+        for (hash, name) in refs {
+            println!("{hash} {name}");
+        }
+    };
+
+    print_refs(
+        "contains",
+        bundle
+            .refs
+            .iter()
+            .map(|(hash, name)| (hash.as_str(), name.as_str()))
+            .collect(),
+    );
+
+    for capability in &bundle.capabilities {
+        if let Some(filter) = capability.strip_prefix("filter=") {
+            println!("The bundle uses this filter: {filter}");
+        }
+    }
+
+    match bundle.prerequisites.is_empty() {
+        true => println!("The bundle records a complete history."),
+
+        false => print_refs(
+            "requires",
+            bundle
+                .prerequisites
+                .iter()
+                .map(|(hash, _)| (hash.as_str(), ""))
+                .collect(),
+        ),
+    }
+
+    println!("The bundle uses this hash algorithm: sha1");
+}
+
+/// Prints the bundle's refs, or those of them named in `names`.
+fn print_bundle_heads(bundle: &Bundle, names: &[String]) {
+    for (hash, name) in &bundle.refs {
+        if names.is_empty() || names.contains(name) {
+            println!("{hash} {name}");
+        }
+    }
+}
+
+fn clone_directory(url: &str) -> anyhow::Result<String> {
+    let url = display_url(url);
+
+    let name = url
+        .strip_suffix(".bundle")
+        .unwrap_or(&url)
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    ensure!(
+        !name.is_empty(),
+        "No directory name could be guessed.\nPlease specify a directory on the command line"
+    );
+
+    Ok(name)
+}
+
+/// Lists the remote's refs under the refspecs' sources (and `extra_prefixes`), then
+/// fetches and stores a pack with the objects of the selected refs that are missing here.
+fn download(
+    url: &str,
+    refspecs: &[Refspec],
+    extra_prefixes: &[&str],
+    options: &FetchOptions,
+) -> anyhow::Result<Vec<RemoteRef>> {
+    let mut connection = Connection::open(open_transport(url)?)?;
+
+    let mut prefixes: Vec<String> = refspecs.iter().flat_map(Refspec::prefixes).collect();
+
+    prefixes.extend(extra_prefixes.iter().map(|prefix| prefix.to_string()));
+
+    let prefixes: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+
+    let remote_refs = connection.list_refs(&prefixes)?;
+
+    let mut options = options.clone();
+
+    options.shallow = shallow_commits().into_iter().collect();
+
+    options.shallow.sort();
+
+    // Deepening history asks for tips that are here already
+    let wanted = |hash: &str, wants: &[String]| {
+        (options.deepens() || !object_exists(hash)) && !wants.iter().any(|want| want == hash)
+    };
+
+    let mut wants: Vec<String> = Vec::new();
+
+    for refspec in refspecs {
+        for (remote_ref, _) in refspec.select(&remote_refs) {
+            if is_excluded(refspecs, &remote_ref.name) {
+                continue;
+            }
+
+            if wanted(&remote_ref.hash, &wants) {
+                wants.push(remote_ref.hash.clone());
+            }
+        }
+    }
+
+    for remote_ref in &remote_refs {
+        if remote_ref.name == HEAD && wanted(&remote_ref.hash, &wants) {
+            wants.push(remote_ref.hash.clone());
+        }
+    }
+
+    if !wants.is_empty() {
+        let fetched = connection.fetch_pack(&wants, &local_haves()?, &options)?;
+
+        let wanted_refs: Vec<&RemoteRef> = remote_refs
+            .iter()
+            .filter(|remote_ref| wants.contains(&remote_ref.hash))
+            .collect();
+
+        fetched.store(options.filter.is_some().then_some(wanted_refs.as_slice()))?;
+    }
+
+    Ok(remote_refs)
+}
+
+/// Options for the flags clone and fetch share to limit what they bring: history by
+/// depth, date and excluded refs, and objects by a filter spec.
+fn fetch_options(
+    depth: Option<usize>,
+    since: Option<&str>,
+    exclude: &[String],
+    filter: &Option<String>,
+) -> anyhow::Result<FetchOptions> {
+    ensure!(depth != Some(0), "depth 0 is not a positive number");
+
+    let deepen_since = since
+        .map(|since| {
+            parse_date(since, current_timestamp())
+                .ok_or_else(|| anyhow!("'{since}' is not a valid date"))
+        })
+        .transpose()?;
+
+    Ok(FetchOptions {
+        depth,
+        deepen_since,
+        deepen_not: exclude.to_vec(),
+        filter: filter.clone(),
+        ..FetchOptions::default()
+    })
+}
+
+/// Checks out a freshly cloned commit, fetching the blobs a partial clone left out in a
+/// single request rather than one at a time.
+fn checkout_clone(commit: &str) -> anyhow::Result<()> {
+    let tree = commit_tree(Some(commit))?;
+
+    if let Some(tree) = tree.as_deref().filter(|_| promisor_remote().is_some()) {
+        fetch_missing(&missing_blobs(tree)?)?;
+    }
+
+    checkout_tree(None, tree.as_deref(), true, "clone")
+}
+
+/// `HEAD` and the refs a remote's fetch refspecs select, as the remote advertises them.
+fn list_remote_refs(remote: &RemoteConfig) -> anyhow::Result<Vec<RemoteRef>> {
+    let mut connection = Connection::open(open_transport(remote.url())?)?;
+
+    let mut prefixes: Vec<String> = remote.fetch.iter().flat_map(Refspec::prefixes).collect();
+
+    prefixes.push(HEAD.to_string());
+
+    let prefixes: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+
+    connection.list_refs(&prefixes)
+}
+
+/// Remote-tracking refs of `remote` whose source is no longer among `remote_refs`.
+fn stale_tracking_refs(
+    remote: &RemoteConfig,
+    remote_refs: &[RemoteRef],
+) -> anyhow::Result<Vec<String>> {
+    let mut stale = Vec::new();
+
+    for (tracking, _) in list_refs("refs/")? {
+        let Some(src) = remote.fetch.iter().find_map(|spec| spec.unmap(&tracking)) else {
+            continue;
+        };
+
+        if is_symbolic_ref(&tracking)? || is_excluded(&remote.fetch, &src) {
+            continue;
+        }
+
+        if !remote_refs.iter().any(|remote_ref| remote_ref.name == src) {
+            stale.push(tracking);
+        }
+    }
+
+    Ok(stale)
+}
+
+fn is_symbolic_ref(name: &str) -> anyhow::Result<bool> {
+    Ok(read_ref(name)?.is_some_and(|value| value.starts_with("ref: ")))
+}
+
+/// How a local ref compares to the remote ref it pushes to, as `remote show` puts it.
+fn push_status(local: &str, remote: Option<&str>) -> anyhow::Result<&'static str> {
+    let Some(remote) = remote else {
+        return Ok("create");
+    };
+
+    if local == remote {
+        return Ok("up to date");
+    }
+
+    if object_exists(remote) && CommitWalker::new().is_ancestor(remote, local)? {
+        return Ok("fast-forwardable");
+    }
+
+    Ok("local out of date")
+}
+
+/// Commits reachable from local refs, newest first, to offer as `have`s.
+fn local_haves() -> anyhow::Result<Vec<String>> {
+    let mut walker = CommitWalker::new();
+
+    let mut queue = BinaryHeap::new();
+
+    let mut seen = HashSet::new();
+
+    let mut tips: Vec<String> = list_refs("refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+
+    tips.extend(resolve_ref(HEAD)?);
+
+    for tip in tips {
+        let Ok(commit) = GitObject::peel(&tip) else {
+            continue;
+        };
+
+        if seen.insert(commit.clone()) {
+            if let Ok(info) = walker.commit(&commit) {
+                queue.push((info.date, commit));
+            }
+        }
+    }
+
+    let mut haves = Vec::new();
+
+    while let Some((_, commit)) = queue.pop() {
+        if haves.len() == MAX_HAVES {
+            break;
+        }
+
+        for parent in walker.parents(&commit)? {
+            if seen.insert(parent.clone()) {
+                queue.push((walker.date(&parent)?, parent));
+            }
+        }
+
+        haves.push(commit);
+    }
+
+    Ok(haves)
+}
+
+/// What a bare `push` sends: the current branch to the branch of the same name, which
+/// has to be its upstream when it has one on that remote.
+fn default_push_refspec(remote: &str, remote_given: bool) -> anyhow::Result<Refspec> {
+    let Some(branch) =
+        head_target()?.and_then(|h| h.strip_prefix("refs/heads/").map(str::to_string))
+    else {
+        bail!(
+            "You are not currently on a branch.\nTo push the history leading to the current (detached HEAD)\nstate now, use\n\n    git push {remote} HEAD:<name-of-remote-branch>\n"
+        );
+    };
+
+    let config = Config::load()?;
+
+    let merge = match config.get(&format!("branch.{branch}.remote")) {
+        Some(upstream_remote) if upstream_remote == remote => {
+            config.get(&format!("branch.{branch}.merge"))
+        }
+
+        _ => None,
+    };
+
+    match merge {
+        Some(merge) if merge != format!("refs/heads/{branch}") => bail!(
+            "The upstream branch of your current branch does not match\nthe name of your current branch.  To push to the upstream branch\non the remote, use\n\n    git push {remote} HEAD:{}\n\nTo push to the branch of the same name on the remote, use\n\n    git push {remote} HEAD\n",
+            shorten_ref(merge)
+        ),
+
+        None if !remote_given => bail!(
+            "The current branch {branch} has no upstream branch.\nTo push the current branch and set the remote as upstream, use\n\n    git push --set-upstream {remote} {branch}\n"
+        ),
+
+        _ => Refspec::parse(&format!("refs/heads/{branch}:refs/heads/{branch}")),
+    }
+}
+
+/// Why push refuses an update without sending it, if it does.
+fn push_rejection(update: &PushUpdate) -> anyhow::Result<Option<&'static str>> {
+    if let Some(expect) = &update.expect {
+        return Ok((*expect != update.old).then_some("stale info"));
+    }
+
+    if update.force || update.old == ZERO_HASH || update.new == ZERO_HASH {
+        return Ok(None);
+    }
+
+    if update.dst.starts_with("refs/tags/") {
+        return Ok(Some("already exists"));
+    }
+
+    if !object_exists(&update.old) {
+        return Ok(Some("fetch first"));
+    }
+
+    if !is_fast_forward(&update.old, &update.new)? {
+        return Ok(Some("non-fast-forward"));
+    }
+
+    Ok(None)
+}
+
+/// Remote-tracking (or local) ref a branch is configured to track, from its
+/// `branch.<name>.remote` and `branch.<name>.merge` settings.
+fn branch_upstream(branch: &str) -> anyhow::Result<Option<String>> {
+    let config = Config::load()?;
+
+    let (Some(remote), Some(merge)) = (
+        config.get(&format!("branch.{branch}.remote")),
+        config.get(&format!("branch.{branch}.merge")),
+    ) else {
+        return Ok(None);
+    };
+
+    if remote == "." {
+        return Ok(Some(merge.to_string()));
+    }
+
+    let name = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+
+    Ok(Some(format!("refs/remotes/{remote}/{name}")))
+}
+
+/// Lists tracked files whose working tree copy differs from the index.
+fn print_unstaged_changes(index: &Index) -> anyhow::Result<()> {
+    let mut changes = Vec::new();
+
+    for entry in index.entries.iter().filter(|entry| entry.stage == 0) {
+        if std::fs::symlink_metadata(&entry.path).is_err() {
+            changes.push(format!("D\t{}", entry.path));
+        } else if is_worktree_modified(entry)? {
+            changes.push(format!("M\t{}", entry.path));
+        }
+    }
+
+    if !changes.is_empty() {
+        println!("Unstaged changes after reset:");
+
+        for change in changes {
+            println!("{change}");
+        }
+    }
+
+    Ok(())
+}
+
+/// `stash@{<n>}` and its position for a `stash@{<n>}` or `<n>` argument, the latest
+/// stash by default.
+fn stash_entry(selector: Option<&String>) -> anyhow::Result<(String, usize)> {
+    let Some(selector) = selector else {
+        return Ok(("stash@{0}".to_string(), 0));
+    };
+
+    let position = selector
+        .strip_prefix("stash@{")
+        .and_then(|s| s.strip_suffix('}'))
+        .unwrap_or(selector)
+        .parse::<usize>()
+        .map_err(|_| anyhow!("{selector} is not a valid reference"))?;
+
+    Ok((format!("stash@{{{position}}}"), position))
+}
+
+/// Full name of the ref whose reflog a command-line name refers to.
+fn reflog_ref_name(name: &str) -> anyhow::Result<String> {
+    if name == HEAD {
+        return Ok(HEAD.to_string());
+    }
+
+    expand_ref(name)?.ok_or_else(|| anyhow!("reflog could not find ref {name}"))
+}
+
+/// Cut-off time for `--expire` style options; `never` keeps everything, `all` and `now`
+/// drop it.
+fn parse_expiry(value: &str) -> anyhow::Result<i64> {
+    match value {
+        "never" | "false" => Ok(i64::MIN),
+
+        "all" | "now" => Ok(i64::MAX),
+
+        _ => parse_date(value, current_timestamp())
+            .ok_or_else(|| anyhow!("'{value}' is not a valid expiry date")),
+    }
+}
+
+/// Commits named by cherry-pick or revert arguments. Ranges (`A..B`, `^A B`) are
+/// walked oldest first for picking and newest first for reverting.
+fn sequence_commits(action: Action, revisions: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut includes = Vec::new();
+
+    let mut excludes = Vec::new();
+
+    for revision in revisions {
+        let or_head = |name: &str| {
+            if name.is_empty() {
+                HEAD.to_string()
+            } else {
+                name.to_string()
+            }
+        };
+
+        if let Some((from, to)) = revision.split_once("..") {
+            excludes.push(resolve_commit(&or_head(from))?);
+            includes.push(resolve_commit(&or_head(to))?);
+        } else if let Some(excluded) = revision.strip_prefix('^') {
+            excludes.push(resolve_commit(excluded)?);
+        } else {
+            includes.push(resolve_commit(revision)?);
+        }
+    }
+
+    if excludes.is_empty() {
+        return Ok(includes);
+    }
+
+    let mut walker = CommitWalker::new();
+
+    let mut commits: Vec<String> = Vec::new();
+
+    for include in &includes {
+        for commit in walker.commits_between(&excludes, include)? {
+            if !commits.contains(&commit) {
+                commits.push(commit);
+            }
+        }
+    }
+
+    if action == Action::Revert {
+        commits.reverse();
+    }
+
+    Ok(commits)
+}
+
+/// Tree of the index to apply the next commit to, which must match `HEAD` unless
+/// the changes are only being applied (`-n`).
+fn sequence_base(command: &str, no_commit: bool) -> anyhow::Result<String> {
+    let head_tree = commit_tree(resolve_ref(HEAD)?.as_deref())?;
+
+    let index = read_index_or_tree(head_tree.as_deref())?;
+
+    ensure!(
+        !index.has_conflicts(),
+        "you need to resolve your current index first"
+    );
+
+    let tree = index_tree(&index)?;
+
+    ensure!(
+        no_commit || head_tree.as_deref() == Some(tree.as_str()),
+        "your local changes would be overwritten by {command}.\nhint: commit your changes or stash them to proceed."
+    );
+
+    Ok(tree)
+}
+
+/// Removes what a conflicted merge leaves for `merge --continue` to commit.
+fn remove_merge_state() {
+    for state in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"] {
+        let _ = std::fs::remove_file(git_path(state));
+    }
+}
+
+/// Writes the tree of the merged index entries.
+fn index_tree(index: &Index) -> anyhow::Result<String> {
+    let files: BTreeMap<String, FileEntry> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage == 0)
+        .map(|entry| (entry.path.clone(), entry.file_entry()))
+        .collect();
+
+    write_tree_from_paths(&files)
+}
+
+/// Tree of a commit, `None` for no commit (an unborn branch).
+fn commit_tree(commit: Option<&str>) -> anyhow::Result<Option<String>> {
+    commit.map(resolve_tree).transpose()
+}
+
+fn commit_subject(hash: &str) -> anyhow::Result<String> {
+    match GitObject::from_hash(hash)? {
+        GitObject::Commit { message, .. } => {
+            Ok(message.lines().next().unwrap_or_default().to_string())
+        }
+
+        other => bail!("{hash} is a {other}, not a commit"),
+    }
+}
+
+fn merge_options(ours: &str, theirs: &str) -> anyhow::Result<MergeOptions> {
+    let config = Config::load()?;
+
+    let style = match config.get("merge.conflictStyle") {
+        Some(style) => ConflictStyle::try_from(style)?,
+
+        None => ConflictStyle::Merge,
+    };
+
+    Ok(MergeOptions {
+        style,
+        ours_label: ours.to_string(),
+        theirs_label: theirs.to_string(),
+        base_label: String::new(),
+    })
+}
+
+/// `Merge branch 'topic'`, naming branches, tags or commits the way git does, plus
+/// `into <branch>` when merging into something other than main or master.
+fn default_merge_message(names: &[&str]) -> anyhow::Result<String> {
+    let mut kinds: Vec<(&str, Vec<&str>)> = Vec::new();
+
+    for name in names {
+        let kind = match expand_ref(name)? {
+            Some(full) if full.starts_with("refs/heads/") => "branch",
+
+            Some(full) if full.starts_with("refs/tags/") => "tag",
+
+            Some(full) if full.starts_with("refs/remotes/") => "remote-tracking branch",
+
+            _ => "commit",
+        };
+
+        match kinds.iter_mut().find(|(k, _)| *k == kind) {
+            Some((_, list)) => list.push(name),
+
+            None => kinds.push((kind, vec![name])),
+        }
+    }
+
+    let parts: Vec<String> = kinds
+        .into_iter()
+        .map(|(kind, list)| {
+            let quoted: Vec<String> = list.iter().map(|n| format!("'{n}'")).collect();
+
+            if quoted.len() == 1 {
+                format!("{kind} {}", quoted[0])
+            } else {
+                let (last, rest) = quoted.split_last().expect("list is not empty");
+
+                format!("{kind}es {} and {last}", rest.join(", "))
+            }
+        })
+        .collect();
+
+    let mut message = format!("Merge {}", parts.join(", "));
+
+    if let Some(target) = head_target()? {
+        let branch = shorten_ref(&target);
+
+        if branch != "main" && branch != "master" {
+            message.push_str(&format!(" into {branch}"));
+        }
+    }
+
+    Ok(format!("{message}\n"))
 }
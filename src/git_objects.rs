@@ -1,10 +1,12 @@
-use std::fs;
+use std::{env, fs};
 
 use anyhow::bail;
 
+use crate::config::Config;
 use crate::utils::{
-    compress, create_object_directory, decompress, filter_hidden_files, from_hex,
-    generate_object_id, list_directory, read_file, read_object, to_hex_string, write_to_file,
+    compress, create_object_directory, current_timestamp, filter_hidden_files, format_utc_offset,
+    from_hex, generate_object_id, list_directory, local_utc_offset, object_exists, parse_date,
+    parse_utc_offset, read_file, read_object, to_hex_string, write_to_file,
 };
 
 #[derive(Debug)]
@@ -17,7 +19,8 @@ pub struct TreeObject {
 
     object_type: String,
 
-    pub git_object: GitObject,
+    /// `None` for a submodule commit, which lives in another repository.
+    pub git_object: Option<GitObject>,
 }
 
 impl TreeObject {
@@ -25,7 +28,7 @@ impl TreeObject {
         hash: String,
         name: String,
         mode: TreeFileModes,
-        git_object: GitObject,
+        git_object: Option<GitObject>,
     ) -> TreeObject {
         Self {
             hash,
@@ -33,6 +36,8 @@ impl TreeObject {
             object_type: match &mode {
                 TreeFileModes::Directory => "tree".to_string(),
 
+                TreeFileModes::Gitlink => "commit".to_string(),
+
                 _ => "blob".to_string(),
             },
             mode,
@@ -51,7 +56,7 @@ impl std::fmt::Display for TreeObject {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TreeFileModes {
     Regular,
 
@@ -60,21 +65,53 @@ pub enum TreeFileModes {
     SymbolicLink,
 
     Directory,
+
+    /// A submodule: the commit checked out in it.
+    Gitlink,
 }
 
-impl From<&str> for TreeFileModes {
-    fn from(value: &str) -> Self {
-        match value {
-            "100644" => TreeFileModes::Regular,
+impl TreeFileModes {
+    /// Bits of a mode that tell the kind of entry, as in `st_mode`.
+    pub const TYPE_MASK: u32 = 0o170000;
+
+    /// The mode as git writes it in trees and the index.
+    pub fn bits(self) -> u32 {
+        match self {
+            TreeFileModes::Regular => 0o100644,
+            TreeFileModes::Executable => 0o100755,
+            TreeFileModes::SymbolicLink => 0o120000,
+            TreeFileModes::Directory => 0o040000,
+            TreeFileModes::Gitlink => 0o160000,
+        }
+    }
 
-            "100755" => TreeFileModes::Executable,
+    /// Reads a mode by its kind, regular files being executable when anyone may run them.
+    pub fn from_bits(mode: u32) -> anyhow::Result<Self> {
+        Ok(match mode & Self::TYPE_MASK {
+            0o100000 if mode & 0o111 != 0 => TreeFileModes::Executable,
 
-            "120000" => TreeFileModes::SymbolicLink,
+            0o100000 => TreeFileModes::Regular,
 
-            "040000" | "40000" => TreeFileModes::Directory,
+            0o120000 => TreeFileModes::SymbolicLink,
 
-            _ => TreeFileModes::Regular,
-        }
+            0o040000 => TreeFileModes::Directory,
+
+            0o160000 => TreeFileModes::Gitlink,
+
+            _ => bail!("malformed mode in tree entry: {mode:o}"),
+        })
+    }
+}
+
+impl TryFrom<&str> for TreeFileModes {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> anyhow::Result<Self> {
+        let Ok(mode) = u32::from_str_radix(value, 8) else {
+            bail!("malformed mode in tree entry: {value}");
+        };
+
+        Self::from_bits(mode)
     }
 }
 
@@ -92,14 +129,7 @@ impl From<fs::FileType> for TreeFileModes {
 
 impl std::fmt::Display for TreeFileModes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let value = match self {
-            TreeFileModes::Regular => "100644",
-            TreeFileModes::Executable => "100755",
-            TreeFileModes::SymbolicLink => "120000",
-            TreeFileModes::Directory => "40000",
-        };
-
-        write!(f, "{value}")
+        write!(f, "{:o}", self.bits())
     }
 }
 
@@ -117,20 +147,181 @@ pub enum GitObject {
         objects: Vec<TreeObject>,
     },
 
-    #[allow(dead_code)]
     Commit {
         hash: String,
         message: String,
-        tree: Box<GitObject>,
-        parent: Option<Vec<GitObject>>,
+        tree: String,
+        parents: Vec<String>,
+
+        author: Signature,
+        committer: Signature,
+    },
 
-        author_name: String,
-        author_email: String,
-        committer_name: String,
-        committer_email: String,
+    Tag {
+        hash: String,
+        object: String,
+        object_type: String,
+        tag: String,
+        tagger: Option<Signature>,
+        message: String,
     },
 }
 
+/// Identity and time stamp of a commit author or committer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+
+    pub email: String,
+
+    pub timestamp: i64,
+
+    pub timezone: String,
+}
+
+impl Signature {
+    /// Parses `Name <email> 1730371859 +0300`.
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let (Some(email_start), Some(email_end)) = (value.find('<'), value.rfind('>')) else {
+            bail!("Invalid signature: {value}");
+        };
+
+        let mut time = value[email_end + 1..].split_whitespace();
+
+        Ok(Signature {
+            name: value[..email_start].trim().to_string(),
+            email: value[email_start + 1..email_end].to_string(),
+            timestamp: time.next().and_then(|t| t.parse().ok()).unwrap_or(0),
+            timezone: time.next().unwrap_or("+0000").to_string(),
+        })
+    }
+
+    /// Identity for a new author (`role` = "AUTHOR") or committer (`role` = "COMMITTER"),
+    /// taken from `GIT_<role>_*` variables, then `author.*` or `committer.*` config, then
+    /// `user.*` config and `EMAIL`. Fails as git does when either part is not set.
+    pub fn current(role: &str) -> anyhow::Result<Self> {
+        let (name, email) = configured_identity(role)?;
+
+        let (Some(name), Some(email)) = (name, email.clone()) else {
+            let kind = match role {
+                "AUTHOR" => "Author",
+
+                _ => "Committer",
+            };
+
+            let reason = match email {
+                Some(email) => format!("empty ident name (for <{email}>) not allowed"),
+
+                None => String::from("unable to auto-detect email address"),
+            };
+
+            bail!(
+                "{kind} identity unknown\n\n*** Please tell me who you are.\n\nRun\n\n  git config --global user.email \"you@example.com\"\n  git config --global user.name \"Your Name\"\n\nto set your account's default identity.\nOmit --global to set the identity only in this repository.\n\n{reason}"
+            );
+        };
+
+        Self::stamped(role, name, email)
+    }
+
+    /// Like [`Self::current`], but falling back to the login name and host name, as git
+    /// does for reflog entries.
+    pub fn current_or_default(role: &str) -> anyhow::Result<Self> {
+        let (name, email) = configured_identity(role)?;
+
+        let user = env::var("USER")
+            .or_else(|_| env::var("LOGNAME"))
+            .unwrap_or_else(|_| String::from("unknown"));
+
+        let email = email.unwrap_or_else(|| {
+            let host = fs::read_to_string("/proc/sys/kernel/hostname")
+                .or_else(|_| fs::read_to_string("/etc/hostname"))
+                .map(|host| host.trim().to_string())
+                .unwrap_or_else(|_| String::from("(none)"));
+
+            format!("{user}@{host}")
+        });
+
+        Self::stamped(role, name.unwrap_or(user), email)
+    }
+
+    /// Adds the time, from `GIT_<role>_DATE` or the clock, in the local time zone unless
+    /// the date gives one. The date is a timestamp, or a `YYYY-MM-DD[ HH:MM[:SS]]` date
+    /// in that zone, optionally followed by the zone as `+HHMM`.
+    fn stamped(role: &str, name: String, email: String) -> anyhow::Result<Self> {
+        let (timestamp, timezone) = match env::var(format!("GIT_{role}_DATE")) {
+            Ok(date) => {
+                let date = date.trim();
+
+                let (date, offset) = match date.rsplit_once(' ') {
+                    Some((rest, zone)) if parse_utc_offset(zone).is_some() => {
+                        (rest, parse_utc_offset(zone))
+                    }
+
+                    _ => (date, None),
+                };
+
+                let timestamp = match date.trim_start_matches('@').parse::<i64>() {
+                    Ok(timestamp) => timestamp,
+
+                    Err(_) => {
+                        let Some(local) = parse_date(date, 0).filter(|_| date.contains('-')) else {
+                            bail!("invalid date format: {date}");
+                        };
+
+                        local - offset.unwrap_or_else(|| local_utc_offset(local))
+                    }
+                };
+
+                let offset = offset.unwrap_or_else(|| local_utc_offset(timestamp));
+
+                (timestamp, format_utc_offset(offset))
+            }
+
+            Err(_) => {
+                let timestamp = current_timestamp();
+
+                (timestamp, format_utc_offset(local_utc_offset(timestamp)))
+            }
+        };
+
+        Ok(Signature {
+            name,
+            email,
+            timestamp,
+            timezone,
+        })
+    }
+}
+
+/// Name and email for `role` as far as they are configured.
+fn configured_identity(role: &str) -> anyhow::Result<(Option<String>, Option<String>)> {
+    let config = Config::load()?;
+
+    let section = role.to_lowercase();
+
+    let setting = |variable: &str, key: &str| {
+        env::var(format!("GIT_{role}_{variable}"))
+            .ok()
+            .or_else(|| config.get(&format!("{section}.{key}")).map(str::to_string))
+            .or_else(|| config.get(&format!("user.{key}")).map(str::to_string))
+            .filter(|value| !value.is_empty())
+    };
+
+    let email = setting("EMAIL", "email").or_else(|| env::var("EMAIL").ok());
+
+    Ok((setting("NAME", "name"), email))
+}
+
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name, self.email, self.timestamp, self.timezone
+        )
+    }
+}
+
 impl std::fmt::Display for GitObject {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         #[allow(unused_assignments)]
@@ -140,6 +331,7 @@ impl std::fmt::Display for GitObject {
             GitObject::Blob { .. } => object_name = "blob",
             GitObject::Tree { .. } => object_name = "tree",
             GitObject::Commit { .. } => object_name = "commit",
+            GitObject::Tag { .. } => object_name = "tag",
         }
 
         write!(f, "{object_name}")
@@ -149,34 +341,73 @@ impl std::fmt::Display for GitObject {
 impl GitObject {
     pub fn new_commit(
         message: &str,
-        hash: &str,
-        tree: GitObject,
-        parent: Option<Vec<GitObject>>,
-    ) -> Self {
-        let username = String::from("Yassen Higazi");
-        let email = String::from("yassenka28@gmail.com");
-
-        GitObject::Commit {
-            parent,
-            tree: Box::new(tree),
-            hash: hash.to_string(),
+        tree: &str,
+        parents: Vec<String>,
+        author: Signature,
+        committer: Signature,
+    ) -> anyhow::Result<Self> {
+        let content = GitObject::commit_content(tree, &parents, &author, &committer, message);
+
+        Ok(GitObject::Commit {
+            hash: GitObject::get_or_generate_hash("commit", None, &content)?,
+            tree: tree.to_string(),
+            parents,
             message: message.to_string(),
-            author_name: username.clone(),
-            author_email: email.clone(),
-            committer_name: username,
-            committer_email: email,
-        }
+            author,
+            committer,
+        })
     }
 
-    pub fn from_file_content(
-        hash: String,
-        compressed_content: Vec<u8>,
-    ) -> anyhow::Result<GitObject> {
-        let content = decompress(&compressed_content)?;
+    pub fn new_tag(
+        object: &str,
+        object_type: &str,
+        tag: &str,
+        tagger: Signature,
+        message: &str,
+    ) -> anyhow::Result<Self> {
+        let tagger = Some(tagger);
 
-        let (obj_type, final_content) = GitObject::parse_content_header(&content)?;
+        let content = GitObject::tag_content(object, object_type, tag, &tagger, message);
 
-        GitObject::from_file_content_and_type(obj_type.as_str(), final_content, Some(hash))
+        Ok(GitObject::Tag {
+            hash: GitObject::get_or_generate_hash("tag", None, &content)?,
+            object: object.to_string(),
+            object_type: object_type.to_string(),
+            tag: tag.to_string(),
+            tagger,
+            message: message.to_string(),
+        })
+    }
+
+    /// Follows annotated tags down to the object they finally point at.
+    pub fn peel(hash: &str) -> anyhow::Result<String> {
+        let mut current = hash.to_string();
+
+        loop {
+            let (obj_type, content) = GitObject::read_raw(&current)?;
+
+            if obj_type != "tag" {
+                return Ok(current);
+            }
+
+            let content = String::from_utf8_lossy(&content);
+
+            let Some(object) = content
+                .lines()
+                .next()
+                .and_then(|l| l.strip_prefix("object "))
+            else {
+                bail!("Malformed tag object {current}");
+            };
+
+            current = object.to_string();
+        }
+    }
+
+    pub fn from_hash(hash: &str) -> anyhow::Result<GitObject> {
+        let (obj_type, content) = read_object(hash)?;
+
+        GitObject::from_file_content_and_type(&obj_type, &content, Some(hash.to_string()))
     }
 
     pub fn from_file_content_and_type(
@@ -200,62 +431,14 @@ impl GitObject {
 
                 let mut objects = Vec::<TreeObject>::new();
 
-                let mut iter = content.iter();
-
-                while let Some(&byte) = iter.next() {
-                    // Parse mode: Read bytes until space (' ')
-                    let mut mode = Vec::new();
-
-                    mode.push(byte);
-
-                    for &b in iter.by_ref() {
-                        if b == b' ' {
-                            break;
-                        }
-
-                        mode.push(b);
-                    }
-
-                    let mode_str = String::from_utf8_lossy(&mode);
-
-                    // Parse filename: Read bytes until null byte ('\0')
-                    let mut filename = Vec::new();
-
-                    for &b in iter.by_ref() {
-                        if b == 0 {
-                            break;
-                        }
-
-                        filename.push(b);
-                    }
-
-                    let filename_str = String::from_utf8_lossy(&filename);
-
-                    // Parse the SHA-1 hash (next 20 bytes)
-                    let sha1_hash: Vec<u8> = iter.by_ref().take(20).cloned().collect();
-
-                    let hash_str = to_hex_string(sha1_hash.as_slice());
-
-                    let mode_enum = TreeFileModes::from(mode_str.to_string().as_str());
-
-                    let git_object_content = read_object(hash_str.as_str())?;
-
-                    let git_object =
-                        GitObject::from_file_content(hash_str.clone(), git_object_content)?;
-
-                    let object = TreeObject {
-                        hash: hash_str,
-                        name: filename_str.to_string(),
-                        object_type: match mode_enum {
-                            TreeFileModes::Directory => "tree".to_string(),
+                for (mode_enum, filename, hash_str) in GitObject::parse_tree_entries(content)? {
+                    let git_object = match mode_enum {
+                        TreeFileModes::Gitlink => None,
 
-                            _ => "blob".to_string(),
-                        },
-                        git_object,
-                        mode: mode_enum,
+                        _ => Some(GitObject::from_hash(&hash_str)?),
                     };
 
-                    objects.push(object);
+                    objects.push(TreeObject::new(hash_str, filename, mode_enum, git_object));
                 }
 
                 Ok(GitObject::Tree {
@@ -268,87 +451,86 @@ impl GitObject {
             "commit" => {
                 let hash = GitObject::get_or_generate_hash(obj_type, hash, content)?;
 
-                let content_str = String::from_utf8(content.to_vec())?;
+                let content_str = String::from_utf8_lossy(content);
 
-                let content_split: Vec<&str> = content_str.split("\n").collect();
+                let (headers, message) = content_str
+                    .split_once("\n\n")
+                    .unwrap_or((content_str.as_ref(), ""));
 
-                let tree_line: Vec<&str> = content_split[0].split(" ").collect();
+                let mut tree = None;
 
-                let tree_hash = tree_line[1];
+                let mut parents = Vec::new();
 
-                let tree_object_content = read_object(tree_hash)?;
+                let mut author = None;
 
-                let tree_object =
-                    GitObject::from_file_content(tree_hash.to_string(), tree_object_content)?;
+                let mut committer = None;
 
-                let mut line_index = 1;
-
-                #[allow(unused_assignments)]
-                let mut message = String::new();
-
-                let mut parents: Vec<GitObject> = vec![];
-
-                let mut author_name = String::new();
-
-                let mut author_email = String::new();
-
-                let mut committer_name = String::new();
-
-                let mut committer_email = String::new();
-
-                loop {
-                    let current_line = content_split[line_index];
+                for line in headers.lines() {
+                    if let Some(tree_hash) = line.strip_prefix("tree ") {
+                        tree = Some(tree_hash.to_string());
+                    } else if let Some(parent_hash) = line.strip_prefix("parent ") {
+                        parents.push(parent_hash.to_string());
+                    } else if let Some(value) = line.strip_prefix("author ") {
+                        author = Some(Signature::parse(value)?);
+                    } else if let Some(value) = line.strip_prefix("committer ") {
+                        committer = Some(Signature::parse(value)?);
+                    }
+                }
 
-                    if current_line.starts_with("parent") {
-                        let parent_line: Vec<&str> = current_line.split(" ").collect();
+                let (Some(tree), Some(author), Some(committer)) = (tree, author, committer) else {
+                    bail!("Malformed commit object {hash}");
+                };
 
-                        let parent_hash = parent_line[1];
+                Ok(GitObject::Commit {
+                    hash,
+                    tree,
+                    parents,
+                    author,
+                    committer,
+                    message: message.to_string(),
+                })
+            }
 
-                        let parent_object_content = read_object(parent_hash)?;
+            "tag" => {
+                let hash = GitObject::get_or_generate_hash(obj_type, hash, content)?;
 
-                        let parent_object = GitObject::from_file_content(
-                            parent_hash.to_string(),
-                            parent_object_content,
-                        )?;
+                let content_str = String::from_utf8_lossy(content);
 
-                        parents.push(parent_object);
-                    } else if current_line.starts_with("author") {
-                        let author_line: Vec<&str> = current_line.split(" ").collect();
+                let (headers, message) = content_str
+                    .split_once("\n\n")
+                    .unwrap_or((content_str.as_ref(), ""));
 
-                        author_name = author_line[1].to_string();
+                let mut object = None;
 
-                        author_email = author_line[2].to_string().replace("<", "").replace(">", "");
-                    } else if current_line.starts_with("committer") {
-                        let committer_line: Vec<&str> = current_line.split(" ").collect();
+                let mut object_type = None;
 
-                        committer_name = committer_line[1].to_string();
+                let mut tag = String::new();
 
-                        committer_email = committer_line[2]
-                            .to_string()
-                            .replace("<", "")
-                            .replace(">", "");
-                    } else {
-                        message = content_split[line_index + 1].to_string();
+                let mut tagger = None;
 
-                        break;
+                for line in headers.lines() {
+                    if let Some(value) = line.strip_prefix("object ") {
+                        object = Some(value.to_string());
+                    } else if let Some(value) = line.strip_prefix("type ") {
+                        object_type = Some(value.to_string());
+                    } else if let Some(value) = line.strip_prefix("tag ") {
+                        tag = value.to_string();
+                    } else if let Some(value) = line.strip_prefix("tagger ") {
+                        tagger = Some(Signature::parse(value)?);
                     }
-
-                    line_index += 1;
                 }
 
-                Ok(GitObject::Commit {
+                let (Some(object), Some(object_type)) = (object, object_type) else {
+                    bail!("Malformed tag object {hash}");
+                };
+
+                Ok(GitObject::Tag {
                     hash,
-                    message,
-                    author_name,
-                    author_email,
-                    committer_name,
-                    committer_email,
-                    tree: Box::new(tree_object),
-                    parent: if parents.is_empty() {
-                        None
-                    } else {
-                        Some(parents)
-                    },
+                    object,
+                    object_type,
+                    tag,
+                    tagger,
+                    message: message.to_string(),
                 })
             }
 
@@ -398,7 +580,7 @@ impl GitObject {
                 git_object.get_hash().to_string(),
                 file_name,
                 TreeFileModes::from(file_type),
-                git_object,
+                Some(git_object),
             );
 
             let object_buf = [
@@ -446,26 +628,27 @@ impl GitObject {
             GitObject::Commit {
                 message,
                 tree,
-                parent,
-                author_name,
-                author_email,
-                committer_name,
-                committer_email,
+                parents,
+                author,
+                committer,
                 ..
             } => {
-                println!("tree {}", tree.get_hash());
-
-                if let Some(parents) = parent {
-                    for parent in parents {
-                        println!("parent {}", parent.get_hash());
-                    }
-                }
+                let content = GitObject::commit_content(tree, parents, author, committer, message);
 
-                println!("author {author_name} <{author_email}> 1730371859 +0300");
+                print!("{}", String::from_utf8_lossy(&content));
+            }
 
-                println!("committer {committer_name} <{committer_email}> 1730371859 +0300\n");
+            GitObject::Tag {
+                object,
+                object_type,
+                tag,
+                tagger,
+                message,
+                ..
+            } => {
+                let content = GitObject::tag_content(object, object_type, tag, tagger, message);
 
-                println!("{message}");
+                print!("{}", String::from_utf8_lossy(&content));
             }
         }
     }
@@ -484,13 +667,39 @@ impl GitObject {
 
             GitObject::Tree { size, .. } => print!("{size}"),
 
-            _ => bail!("Not Implemented"),
+            GitObject::Commit {
+                message,
+                tree,
+                parents,
+                author,
+                committer,
+                ..
+            } => print!(
+                "{}",
+                GitObject::commit_content(tree, parents, author, committer, message).len()
+            ),
+
+            GitObject::Tag {
+                object,
+                object_type,
+                tag,
+                tagger,
+                message,
+                ..
+            } => print!(
+                "{}",
+                GitObject::tag_content(object, object_type, tag, tagger, message).len()
+            ),
         };
 
         Ok(())
     }
 
     pub fn write_to_file(&self) -> anyhow::Result<()> {
+        if object_exists(self.get_hash()) {
+            return Ok(());
+        }
+
         match self {
             GitObject::Blob {
                 hash,
@@ -518,7 +727,9 @@ impl GitObject {
                 let mut objects_vec = vec![format!("tree {size}\0").as_bytes().to_vec()];
 
                 for object in objects {
-                    object.git_object.write_to_file()?;
+                    if let Some(git_object) = &object.git_object {
+                        git_object.write_to_file()?;
+                    }
 
                     let object_buf = [
                         format!("{} {}\0", object.mode, object.name).as_bytes(),
@@ -539,70 +750,132 @@ impl GitObject {
             GitObject::Commit {
                 hash,
                 tree,
-                parent,
+                parents,
                 message,
-                author_name,
-                author_email,
-                committer_name,
-                committer_email,
+                author,
+                committer,
             } => {
                 let path = create_object_directory(hash)?;
 
-                let mut content: Vec<Vec<u8>> = vec![
-                    b"tree ".to_vec(),
-                    tree.get_hash().as_bytes().to_vec(),
-                    b"\n".to_vec(),
-                ];
+                let uncomposed_content =
+                    GitObject::commit_content(tree, parents, author, committer, message);
 
-                let mut parents: Vec<Vec<u8>> = vec![];
+                let final_content = [
+                    format!("commit {}\0", uncomposed_content.len()).as_bytes(),
+                    uncomposed_content.as_slice(),
+                ]
+                .concat();
 
-                if let Some(parent_commits) = parent {
-                    for commit in parent_commits {
-                        parents.push(b"parent ".to_vec());
+                let compressed_content = compress(&final_content)?;
 
-                        parents.push(commit.get_hash().as_bytes().to_vec());
+                write_to_file(path.as_str(), compressed_content.as_slice())?;
 
-                        parents.push(b"\n".to_vec());
-                    }
-                }
+                Ok(())
+            }
 
-                content.push(parents.concat());
+            GitObject::Tag {
+                object,
+                object_type,
+                tag,
+                tagger,
+                message,
+                ..
+            } => {
+                let content = GitObject::tag_content(object, object_type, tag, tagger, message);
 
-                content.push(
-                    format!("author {author_name} <{author_email}>")
-                        .as_bytes()
-                        .to_vec(),
-                );
+                GitObject::write_raw("tag", &content)?;
 
-                content.push(b"\n".to_vec());
+                Ok(())
+            }
+        }
+    }
 
-                content.push(
-                    format!("committer {committer_name} <{committer_email}>")
-                        .as_bytes()
-                        .to_vec(),
-                );
+    fn commit_content(
+        tree: &str,
+        parents: &[String],
+        author: &Signature,
+        committer: &Signature,
+        message: &str,
+    ) -> Vec<u8> {
+        let mut content = format!("tree {tree}\n");
 
-                content.push(b"\n\n".to_vec());
+        for parent in parents {
+            content.push_str(&format!("parent {parent}\n"));
+        }
 
-                content.push(message.as_bytes().to_vec());
+        content.push_str(&format!(
+            "author {author}\ncommitter {committer}\n\n{message}"
+        ));
 
-                content.push(b"\n".to_vec());
+        content.into_bytes()
+    }
 
-                let uncomposed_content = content.concat();
+    fn tag_content(
+        object: &str,
+        object_type: &str,
+        tag: &str,
+        tagger: &Option<Signature>,
+        message: &str,
+    ) -> Vec<u8> {
+        let mut content = format!("object {object}\ntype {object_type}\ntag {tag}\n");
 
-                let final_content = [
-                    format!("commit {}\0", uncomposed_content.len()).as_bytes(),
-                    uncomposed_content.as_slice(),
-                ]
-                .concat();
+        if let Some(tagger) = tagger {
+            content.push_str(&format!("tagger {tagger}\n"));
+        }
 
-                let compressed_content = compress(&final_content)?;
+        content.push_str(&format!("\n{message}"));
 
-                write_to_file(path.as_str(), compressed_content.as_slice())?;
+        content.into_bytes()
+    }
 
-                Ok(())
+    /// Writes an object of any type straight from its content, returning its hash.
+    /// Objects already in the store are left untouched.
+    pub fn write_raw(obj_type: &str, content: &[u8]) -> anyhow::Result<String> {
+        let hash = GitObject::get_or_generate_hash(obj_type, None, content)?;
+
+        if object_exists(&hash) {
+            return Ok(hash);
+        }
+
+        let path = create_object_directory(&hash)?;
+
+        let final_content = [
+            format!("{obj_type} {}\0", content.len()).as_bytes(),
+            content,
+        ]
+        .concat();
+
+        write_to_file(path.as_str(), compress(&final_content)?.as_slice())?;
+
+        Ok(hash)
+    }
+
+    /// Writes a tree object from `(mode, name, hash)` entries, sorting them the way git
+    /// does, where a directory sorts as if its name ended with a slash.
+    pub fn write_tree_entries(
+        entries: &[(TreeFileModes, String, String)],
+    ) -> anyhow::Result<String> {
+        let mut sorted: Vec<&(TreeFileModes, String, String)> = entries.iter().collect();
+
+        sorted.sort_by_key(|(mode, name, _)| {
+            let mut key = name.as_bytes().to_vec();
+
+            if *mode == TreeFileModes::Directory {
+                key.push(b'/');
             }
+
+            key
+        });
+
+        let mut content = Vec::new();
+
+        for (mode, name, hash) in sorted {
+            content.extend_from_slice(format!("{mode} {name}\0").as_bytes());
+
+            content.extend_from_slice(&from_hex(hash)?);
         }
+
+        GitObject::write_raw("tree", &content)
     }
 
     pub fn get_hash(&self) -> &String {
@@ -610,6 +883,7 @@ impl GitObject {
             GitObject::Blob { hash, .. } => hash,
             GitObject::Tree { hash, .. } => hash,
             GitObject::Commit { hash, .. } => hash,
+            GitObject::Tag { hash, .. } => hash,
         }
     }
 
@@ -621,6 +895,61 @@ impl GitObject {
         matches!(self, GitObject::Commit { .. })
     }
 
+    /// Reads an object from the store without parsing it, returning its type and raw content.
+    pub fn read_raw(hash: &str) -> anyhow::Result<(String, Vec<u8>)> {
+        read_object(hash)
+    }
+
+    /// Splits raw tree content into `(mode, name, hash)` entries without loading the children.
+    pub fn parse_tree_entries(
+        content: &[u8],
+    ) -> anyhow::Result<Vec<(TreeFileModes, String, String)>> {
+        let mut entries = Vec::new();
+
+        let mut iter = content.iter();
+
+        while let Some(&byte) = iter.next() {
+            // Parse mode: Read bytes until space (' ')
+            let mut mode = Vec::new();
+
+            mode.push(byte);
+
+            for &b in iter.by_ref() {
+                if b == b' ' {
+                    break;
+                }
+
+                mode.push(b);
+            }
+
+            let mode_str = String::from_utf8_lossy(&mode);
+
+            // Parse filename: Read bytes until null byte ('\0')
+            let mut filename = Vec::new();
+
+            for &b in iter.by_ref() {
+                if b == 0 {
+                    break;
+                }
+
+                filename.push(b);
+            }
+
+            let filename_str = String::from_utf8_lossy(&filename);
+
+            // Parse the SHA-1 hash (next 20 bytes)
+            let sha1_hash: Vec<u8> = iter.by_ref().take(20).cloned().collect();
+
+            entries.push((
+                TreeFileModes::try_from(mode_str.as_ref())?,
+                filename_str.to_string(),
+                to_hex_string(sha1_hash.as_slice()),
+            ));
+        }
+
+        Ok(entries)
+    }
+
     fn get_or_generate_hash(
         object_type: &str,
         hash: Option<String>,
@@ -638,31 +967,4 @@ impl GitObject {
             }
         }
     }
-
-    fn parse_content_header(content: &[u8]) -> anyhow::Result<(String, &[u8])> {
-        let mut type_len = 4;
-        let mut object_type = &content[0..type_len];
-
-        if object_type == b"comm" {
-            type_len = 6;
-            object_type = &content[0..type_len];
-        }
-
-        let object_type_str = String::from_utf8(object_type.to_vec())?;
-
-        let mut content_index = type_len + 1;
-
-        while content_index < content.len() {
-            if &content[content_index..content_index + 1] == b"\0" {
-                content_index += 1;
-                break;
-            }
-
-            content_index += 1;
-        }
-
-        let final_content = &content[content_index..];
-
-        Ok((object_type_str, final_content))
-    }
 }
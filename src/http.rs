@@ -0,0 +1,360 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use anyhow::{anyhow, bail, ensure, Context};
+
+/// Largest request body a server reads, and what a compressed one may inflate to.
+/// Requests are held in memory, so this bounds what each connection can cost.
+pub const MAX_REQUEST_BODY: u64 = 64 << 20;
+
+/// Largest response body a client reads, which may be the pack of a whole clone.
+const MAX_RESPONSE_BODY: u64 = 4 << 30;
+
+/// Longest request, status, header or chunk size line.
+const MAX_LINE: u64 = 8 << 10;
+
+/// Most header lines in a request or response.
+const MAX_HEADERS: usize = 100;
+
+/// The parts of an `http://` URL a request needs.
+#[derive(Debug, Clone)]
+pub struct Url {
+    pub host: String,
+
+    pub port: u16,
+
+    /// Path without a trailing slash, e.g. `/repo.git`.
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> anyhow::Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            if url.starts_with("https://") {
+                bail!("https is not supported, unable to access '{url}'");
+            }
+
+            bail!("unsupported URL '{url}'");
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+
+            None => (rest, ""),
+        };
+
+        // Credentials in the URL are not used
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| anyhow!("invalid port number in '{url}'"))?,
+            ),
+
+            None => (authority, 80),
+        };
+
+        ensure!(!host.is_empty(), "no host in URL '{url}'");
+
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+
+    pub headers: Vec<(String, String)>,
+
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Value of a header, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+/// Sends a single HTTP/1.1 request on a fresh connection and reads the whole response.
+pub fn request(
+    method: &str,
+    url: &Url,
+    path_and_query: &str,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+) -> anyhow::Result<Response> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port))
+        .with_context(|| format!("Failed to connect to {} port {}", url.host, url.port))?;
+
+    let mut request = format!(
+        "{method} {path_and_query} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: git/codecrafters\r\nConnection: close\r\n",
+        url.host, url.port
+    );
+
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    if let Some(body) = body {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes())?;
+
+    if let Some(body) = body {
+        stream.write_all(body)?;
+    }
+
+    stream.flush()?;
+
+    read_response(BufReader::new(stream))
+}
+
+fn read_response(mut reader: impl BufRead) -> anyhow::Result<Response> {
+    let mut status_line = String::new();
+
+    read_line(&mut reader, &mut status_line)?;
+
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| anyhow!("invalid HTTP response: {}", status_line.trim_end()))?;
+
+    let headers = read_headers(&mut reader)?;
+
+    // Without a length the body runs until the server closes the connection
+    let body = match read_body(&mut reader, &headers, MAX_RESPONSE_BODY)? {
+        Some(body) => body,
+
+        None => {
+            let mut body = Vec::new();
+
+            reader.take(MAX_RESPONSE_BODY + 1).read_to_end(&mut body)?;
+
+            ensure!(
+                body.len() as u64 <= MAX_RESPONSE_BODY,
+                "response body is too large"
+            );
+
+            body
+        }
+    };
+
+    Ok(Response {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// An HTTP request as a server receives it.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+
+    /// Path without the query string, e.g. `/repo.git/info/refs`.
+    pub path: String,
+
+    pub query: String,
+
+    pub headers: Vec<(String, String)>,
+
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// Value of a `name=value` query parameter.
+    pub fn query_parameter(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Reads a request from a client connection, or `None` when it closes without sending one.
+pub fn read_request(mut reader: impl BufRead) -> anyhow::Result<Option<Request>> {
+    let mut request_line = String::new();
+
+    if read_line(&mut reader, &mut request_line)? == 0 {
+        return Ok(None);
+    }
+
+    let [method, target, _] = request_line.split_whitespace().collect::<Vec<_>>()[..] else {
+        bail!("invalid HTTP request: {}", request_line.trim_end());
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let headers = read_headers(&mut reader)?;
+
+    let body = read_body(&mut reader, &headers, MAX_REQUEST_BODY)?.unwrap_or_default();
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body,
+    }))
+}
+
+/// Writes a complete response, closing the connection after it.
+pub fn write_response(mut writer: impl Write, response: &Response) -> anyhow::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.body.len()
+    );
+
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    head.push_str("\r\n");
+
+    writer.write_all(head.as_bytes())?;
+    writer.write_all(&response.body)?;
+
+    Ok(writer.flush()?)
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+
+        400 => "Bad Request",
+
+        403 => "Forbidden",
+
+        404 => "Not Found",
+
+        405 => "Method Not Allowed",
+
+        413 => "Content Too Large",
+
+        415 => "Unsupported Media Type",
+
+        503 => "Service Unavailable",
+
+        _ => "Internal Server Error",
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Header lines up to the blank line ending them.
+fn read_headers(reader: &mut impl BufRead) -> anyhow::Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+
+    loop {
+        let mut line = String::new();
+
+        read_line(reader, &mut line)?;
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            return Ok(headers);
+        }
+
+        ensure!(headers.len() < MAX_HEADERS, "too many header lines");
+
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+}
+
+/// Reads one line of at most [`MAX_LINE`] bytes, returning how many were read.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> anyhow::Result<usize> {
+    let read = reader.by_ref().take(MAX_LINE).read_line(line)?;
+
+    ensure!(
+        read as u64 != MAX_LINE || line.ends_with('\n'),
+        "line is too long"
+    );
+
+    Ok(read)
+}
+
+/// A chunked or `Content-Length` body of at most `limit` bytes, or `None` when the
+/// headers give neither. The body is read as it arrives, so a length the other side
+/// claims costs nothing upfront.
+fn read_body(
+    reader: &mut impl BufRead,
+    headers: &[(String, String)],
+    limit: u64,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let chunked = find_header(headers, "Transfer-Encoding")
+        .is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+
+    let mut body = Vec::new();
+
+    if chunked {
+        loop {
+            let mut size_line = String::new();
+
+            read_line(reader, &mut size_line)?;
+
+            let size = u64::from_str_radix(size_line.trim().split(';').next().unwrap_or(""), 16)
+                .ok()
+                .filter(|size| body.len() as u64 + size <= limit)
+                .ok_or_else(|| anyhow!("invalid chunk size: {}", size_line.trim()))?;
+
+            let read = reader.by_ref().take(size).read_to_end(&mut body)?;
+
+            ensure!(read as u64 == size, "truncated chunk");
+
+            // Each chunk ends its line, and the last one any trailer lines
+            if size == 0 {
+                read_headers(reader)?;
+
+                return Ok(Some(body));
+            }
+
+            let mut end = String::new();
+
+            read_line(reader, &mut end)?;
+
+            ensure!(end.trim_end().is_empty(), "malformed chunk");
+        }
+    }
+
+    let Some(length) = find_header(headers, "Content-Length") else {
+        return Ok(None);
+    };
+
+    let length = length
+        .parse()
+        .ok()
+        .filter(|length| *length <= limit)
+        .ok_or_else(|| anyhow!("invalid Content-Length: {length}"))?;
+
+    let read = reader.by_ref().take(length).read_to_end(&mut body)?;
+
+    ensure!(read as u64 == length, "truncated body");
+
+    Ok(Some(body))
+}
@@ -0,0 +1,363 @@
+use std::io::{BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::ensure;
+use flate2::read::GzDecoder;
+
+use crate::commit_graph::reload_commit_graph;
+use crate::config::Config;
+use crate::http::{self, reason_phrase, Request, Response, MAX_REQUEST_BODY};
+use crate::pack::reload_packs;
+use crate::pkt_line::PacketWriter;
+use crate::refs::{find_repository, git_dir, in_repository, set_git_dir};
+use crate::transport::{RECEIVE_PACK, UPLOAD_PACK};
+use crate::{receive_pack, upload_pack};
+
+/// How long a connection may stall in a read or a write before it is dropped.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Most connections served at once. Each may hold a request body in memory, so more
+/// are turned away until one finishes.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Serves every repository under `root` over smart HTTP until the process is killed.
+/// Each connection has its own thread, but requests are handled one at a time since
+/// handling one enters its repository, and at most [`MAX_CONNECTIONS`] are open.
+/// `push_by_default` enables receive-pack where `http.receivepack` is not set.
+pub fn serve(address: &str, root: &Path, push_by_default: bool) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    eprintln!(
+        "Serving {} at http://{}/",
+        root.display(),
+        listener.local_addr()?
+    );
+
+    let handling = Arc::new(Mutex::new(()));
+
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        // A client that sends garbage or goes away is no reason to stop serving others
+        let stream = match stream {
+            Ok(stream) => stream,
+
+            Err(error) => {
+                eprintln!("error: {error}");
+
+                continue;
+            }
+        };
+
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+
+            if let Err(error) = refuse_connection(stream) {
+                eprintln!("error: {error}");
+            }
+
+            continue;
+        }
+
+        let root = root.to_path_buf();
+
+        let handling = Arc::clone(&handling);
+        let connections = Arc::clone(&connections);
+
+        thread::spawn(move || {
+            if let Err(error) = serve_connection(stream, &root, push_by_default, &handling) {
+                eprintln!("error: {error}");
+            }
+
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    Ok(())
+}
+
+/// Reads one request from `stream` and writes the answer, handling it once no other
+/// connection is.
+fn serve_connection(
+    mut stream: TcpStream,
+    root: &Path,
+    push_by_default: bool,
+    handling: &Mutex<()>,
+) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let Some(request) = http::read_request(BufReader::new(&mut stream))? else {
+        return Ok(());
+    };
+
+    let response = {
+        let _handling = handling.lock().unwrap_or_else(PoisonError::into_inner);
+
+        handle_isolated(root, &request, push_by_default, true)
+    };
+
+    eprintln!("{} {} {}", request.method, request.path, response.status);
+
+    http::write_response(&mut stream, &response)
+}
+
+/// Answers a connection beyond [`MAX_CONNECTIONS`] without reading its request.
+fn refuse_connection(mut stream: TcpStream) -> anyhow::Result<()> {
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    http::write_response(&mut stream, &text_response(503, "Too many connections"))
+}
+
+/// [`handle_request`], answering a panic with an error instead of passing it on. The
+/// panic skips leaving the repository, so that is done here.
+fn handle_isolated(
+    root: &Path,
+    request: &Request,
+    push_by_default: bool,
+    export_all: bool,
+) -> Response {
+    let working_directory = std::env::current_dir();
+
+    let current = git_dir();
+
+    let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+        handle_request(root, request, push_by_default, export_all)
+    }));
+
+    handled.unwrap_or_else(|_| {
+        if let Ok(directory) = &working_directory {
+            let _ = std::env::set_current_dir(directory);
+        }
+
+        set_git_dir(Some(&current));
+        reload_packs();
+        reload_commit_graph();
+
+        text_response(500, "Internal Server Error")
+    })
+}
+
+/// Answers a single request as a CGI program, the way a web server runs
+/// `git http-backend`: the request comes from the environment and stdin, and the
+/// repositories are under `$GIT_PROJECT_ROOT`. Only those with a `git-daemon-export-ok`
+/// file are served unless `$GIT_HTTP_EXPORT_ALL` is set, and pushing needs an
+/// authenticated user.
+pub fn cgi() -> anyhow::Result<()> {
+    let variable = |name: &str| std::env::var(name).unwrap_or_default();
+
+    let mut headers = Vec::new();
+
+    // Servers are usually set up to pass `Git-Protocol` on as `GIT_PROTOCOL`
+    for (header, name) in [
+        ("Content-Type", "CONTENT_TYPE"),
+        ("Content-Encoding", "HTTP_CONTENT_ENCODING"),
+        ("Git-Protocol", "GIT_PROTOCOL"),
+        ("Git-Protocol", "HTTP_GIT_PROTOCOL"),
+    ] {
+        if let Ok(value) = std::env::var(name) {
+            headers.push((header.to_string(), value));
+        }
+    }
+
+    let mut body = Vec::new();
+
+    match variable("CONTENT_LENGTH").parse::<u64>() {
+        Ok(length) => std::io::stdin().take(length).read_to_end(&mut body)?,
+
+        Err(_) => std::io::stdin().read_to_end(&mut body)?,
+    };
+
+    let request = Request {
+        method: variable("REQUEST_METHOD"),
+        path: variable("PATH_INFO"),
+        query: variable("QUERY_STRING"),
+        headers,
+        body,
+    };
+
+    let root = variable("GIT_PROJECT_ROOT");
+
+    ensure!(!root.is_empty(), "GIT_PROJECT_ROOT is not set");
+
+    let response = handle_request(
+        Path::new(&root),
+        &request,
+        !variable("REMOTE_USER").is_empty(),
+        std::env::var_os("GIT_HTTP_EXPORT_ALL").is_some(),
+    );
+
+    let mut output = format!(
+        "Status: {} {}\r\n",
+        response.status,
+        reason_phrase(response.status)
+    );
+
+    for (name, value) in &response.headers {
+        output.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    output.push_str("\r\n");
+
+    let mut stdout = std::io::stdout().lock();
+
+    stdout.write_all(output.as_bytes())?;
+    stdout.write_all(&response.body)?;
+
+    Ok(stdout.flush()?)
+}
+
+/// Answers a smart HTTP request for one of the repositories under `root`:
+/// `GET <repo>/info/refs?service=<service>` for the advertisement, and
+/// `POST <repo>/<service>` for what follows it. Without `export_all`, a repository is
+/// only served when it has a `git-daemon-export-ok` file.
+pub fn handle_request(
+    root: &Path,
+    request: &Request,
+    push_by_default: bool,
+    export_all: bool,
+) -> Response {
+    match route(root, request, push_by_default, export_all) {
+        Ok(response) => response,
+
+        Err(error) => text_response(500, &format!("{error}")),
+    }
+}
+
+fn route(
+    root: &Path,
+    request: &Request,
+    push_by_default: bool,
+    export_all: bool,
+) -> anyhow::Result<Response> {
+    let (repository, service, advertise) =
+        if let Some(repository) = request.path.strip_suffix("/info/refs") {
+            let Some(service) = request.query_parameter("service") else {
+                return Ok(text_response(403, "Dumb HTTP is not supported"));
+            };
+
+            (repository, service, true)
+        } else if let Some((repository, service)) = request.path.rsplit_once('/') {
+            (repository, service, false)
+        } else {
+            return Ok(text_response(404, "Not Found"));
+        };
+
+    if ![UPLOAD_PACK, RECEIVE_PACK].contains(&service) {
+        return Ok(text_response(404, "Not Found"));
+    }
+
+    let method = if advertise { "GET" } else { "POST" };
+
+    if request.method != method {
+        return Ok(text_response(405, "Method Not Allowed"));
+    }
+
+    if !advertise
+        && request.header("Content-Type") != Some(&format!("application/x-{service}-request"))
+    {
+        return Ok(text_response(415, "Unsupported Media Type"));
+    }
+
+    let path = Path::new(repository.trim_start_matches('/'));
+
+    // Only what is under the root is served
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Ok(text_response(404, "Not Found"));
+    }
+
+    let Ok(git_dir) = find_repository(&root.join(path).to_string_lossy()) else {
+        return Ok(text_response(404, "Repository not found"));
+    };
+
+    if !export_all && !git_dir.join("git-daemon-export-ok").exists() {
+        return Ok(text_response(404, "Repository not exported"));
+    }
+
+    let body = match request.header("Content-Encoding") {
+        Some("gzip" | "x-gzip") => {
+            let mut body = Vec::new();
+
+            GzDecoder::new(request.body.as_slice())
+                .take(MAX_REQUEST_BODY + 1)
+                .read_to_end(&mut body)?;
+
+            if body.len() as u64 > MAX_REQUEST_BODY {
+                return Ok(text_response(413, "Request body is too large"));
+            }
+
+            body
+        }
+
+        _ => request.body.clone(),
+    };
+
+    let version = upload_pack::protocol_version(request.header("Git-Protocol").unwrap_or(""));
+
+    in_repository(&git_dir, || {
+        let name = service.trim_start_matches("git-");
+
+        let enabled = match Config::load()?.get(&format!("http.{}", name.replace('-', ""))) {
+            Some(value) => value == "true",
+
+            None => service == UPLOAD_PACK || push_by_default,
+        };
+
+        if !enabled {
+            return Ok(text_response(
+                403,
+                &format!("Service not enabled: '{name}'"),
+            ));
+        }
+
+        let mut output = Vec::new();
+
+        // Version 2 has no room for the service line older clients look for
+        if advertise && !(service == UPLOAD_PACK && version == 2) {
+            let mut preamble = PacketWriter::new();
+
+            preamble.line(&format!("# service={service}")).flush();
+
+            output.extend(preamble.into_bytes());
+        }
+
+        let mut input = body.as_slice();
+
+        if service == UPLOAD_PACK {
+            upload_pack::serve(&mut input, &mut output, version, true, advertise)?;
+        } else {
+            receive_pack::serve(&mut input, &mut output, true, advertise)?;
+        }
+
+        let kind = if advertise { "advertisement" } else { "result" };
+
+        Ok(Response {
+            status: 200,
+            headers: vec![
+                (
+                    "Content-Type".to_string(),
+                    format!("application/x-{service}-{kind}"),
+                ),
+                ("Cache-Control".to_string(), "no-cache".to_string()),
+            ],
+            body: output,
+        })
+    })
+}
+
+fn text_response(status: u16, message: &str) -> Response {
+    Response {
+        status,
+        headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body: format!("{message}\n").into_bytes(),
+    }
+}
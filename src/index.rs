@@ -0,0 +1,302 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use anyhow::{bail, ensure};
+use sha1::{Digest, Sha1};
+
+use crate::diff::{flatten_tree, FileEntry};
+use crate::git_objects::TreeFileModes;
+use crate::refs::git_path;
+use crate::utils::{be_u32, from_hex, read_file, to_hex_string, write_to_file};
+
+pub fn index_file() -> String {
+    git_path("index")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+
+    pub mtime: (u32, u32),
+
+    pub dev: u32,
+
+    pub ino: u32,
+
+    pub mode: TreeFileModes,
+
+    pub uid: u32,
+
+    pub gid: u32,
+
+    pub size: u32,
+
+    pub hash: String,
+
+    /// 0 for merged entries, 1-3 for the base, ours and theirs versions of a conflict.
+    pub stage: u8,
+
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// Entry without any stat data, which makes the file look modified until it is
+    /// hashed and compared.
+    pub fn new(path: &str, entry: &FileEntry) -> Self {
+        IndexEntry {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode: entry.mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash: entry.hash.clone(),
+            stage: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// Entry for a file of the working tree, with its current stat data.
+    pub fn from_file(path: &str, entry: &FileEntry) -> anyhow::Result<Self> {
+        let metadata = fs::symlink_metadata(path)?;
+
+        let mut index_entry = IndexEntry::new(path, entry);
+
+        index_entry.ctime = (metadata.ctime() as u32, metadata.ctime_nsec() as u32);
+        index_entry.mtime = (metadata.mtime() as u32, metadata.mtime_nsec() as u32);
+        index_entry.dev = metadata.dev() as u32;
+        index_entry.ino = metadata.ino() as u32;
+        index_entry.uid = metadata.uid();
+        index_entry.gid = metadata.gid();
+        index_entry.size = metadata.len() as u32;
+
+        Ok(index_entry)
+    }
+
+    /// Whether the file's stat data still matches, meaning it can't have changed.
+    pub fn is_stat_clean(&self, metadata: &fs::Metadata) -> bool {
+        self.mtime != (0, 0)
+            && self.size == metadata.len() as u32
+            && self.mtime == (metadata.mtime() as u32, metadata.mtime_nsec() as u32)
+            && self.ctime == (metadata.ctime() as u32, metadata.ctime_nsec() as u32)
+            && self.ino == metadata.ino() as u32
+    }
+
+    pub fn file_entry(&self) -> FileEntry {
+        FileEntry {
+            mode: self.mode,
+            hash: self.hash.clone(),
+        }
+    }
+}
+
+/// The staging area, kept sorted by path and stage like git's `DIRC` version 2 file.
+#[derive(Debug, Default)]
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+}
+
+impl Index {
+    pub fn exists() -> bool {
+        std::path::Path::new(&index_file()).exists()
+    }
+
+    /// Reads `.git/index`; a missing index is an empty one. Extensions are skipped.
+    pub fn read() -> anyhow::Result<Self> {
+        if !Index::exists() {
+            return Ok(Index::default());
+        }
+
+        let content = read_file(&index_file())?;
+
+        ensure!(
+            content.len() >= 32 && &content[..4] == b"DIRC",
+            "index file is corrupt"
+        );
+
+        let (body, checksum) = content.split_at(content.len() - 20);
+
+        ensure!(
+            Sha1::digest(body).as_slice() == checksum,
+            "index file checksum mismatch"
+        );
+
+        let version = be_u32(&content[4..8]);
+
+        if version != 2 && version != 3 {
+            bail!("unsupported index version {version}");
+        }
+
+        let count = be_u32(&content[8..12]) as usize;
+
+        let mut entries = Vec::with_capacity(count);
+
+        let mut offset = 12;
+
+        for _ in 0..count {
+            let field = |n: usize| be_u32(&content[offset + n * 4..offset + n * 4 + 4]);
+
+            let mode_bits = field(6);
+
+            let hash = to_hex_string(&content[offset + 40..offset + 60]);
+
+            let flags = u16::from_be_bytes([content[offset + 60], content[offset + 61]]);
+
+            let mut path_start = offset + 62;
+
+            // Version 3 entries with the extended flag carry two more bytes of flags
+            if version == 3 && flags & 0x4000 != 0 {
+                path_start += 2;
+            }
+
+            let path_end = path_start
+                + content[path_start..]
+                    .iter()
+                    .position(|&b| b == 0)
+                    .unwrap_or(0);
+
+            entries.push(IndexEntry {
+                ctime: (field(0), field(1)),
+                mtime: (field(2), field(3)),
+                dev: field(4),
+                ino: field(5),
+                mode: TreeFileModes::from_bits(mode_bits)?,
+                uid: field(7),
+                gid: field(8),
+                size: field(9),
+                hash,
+                stage: ((flags >> 12) & 0x3) as u8,
+                path: String::from_utf8_lossy(&content[path_start..path_end]).to_string(),
+            });
+
+            // Entries are padded with 1-8 NUL bytes to a multiple of eight
+            let entry_len = path_end - offset;
+
+            offset += (entry_len + 8) & !7;
+        }
+
+        Ok(Index { entries })
+    }
+
+    pub fn write(&self) -> anyhow::Result<()> {
+        let mut content = Vec::new();
+
+        content.extend_from_slice(b"DIRC");
+        content.extend_from_slice(&2u32.to_be_bytes());
+        content.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        for entry in &self.entries {
+            let start = content.len();
+
+            let mode = u32::from_str_radix(&entry.mode.to_string(), 8)?;
+
+            for value in [
+                entry.ctime.0,
+                entry.ctime.1,
+                entry.mtime.0,
+                entry.mtime.1,
+                entry.dev,
+                entry.ino,
+                mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                content.extend_from_slice(&value.to_be_bytes());
+            }
+
+            content.extend_from_slice(&from_hex(&entry.hash)?);
+
+            let flags = ((entry.stage as u16) << 12) | entry.path.len().min(0xfff) as u16;
+
+            content.extend_from_slice(&flags.to_be_bytes());
+            content.extend_from_slice(entry.path.as_bytes());
+
+            let entry_len = content.len() - start;
+
+            content.resize(start + ((entry_len + 8) & !7), 0);
+        }
+
+        let checksum = Sha1::digest(&content);
+
+        content.extend_from_slice(checksum.as_slice());
+
+        write_to_file(&index_file(), &content)
+    }
+
+    /// Index listing every file of a tree, with no stat data.
+    pub fn from_tree(tree: Option<&str>) -> anyhow::Result<Self> {
+        let mut index = Index::default();
+
+        if let Some(tree) = tree {
+            for (path, entry) in flatten_tree(tree)? {
+                index.entries.push(IndexEntry::new(&path, &entry));
+            }
+        }
+
+        index.sort();
+
+        Ok(index)
+    }
+
+    /// Merged (stage 0) entry for a path.
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries[self.path_range(path)]
+            .iter()
+            .find(|entry| entry.stage == 0)
+    }
+
+    /// Adds an entry, replacing every stage recorded for the same path.
+    pub fn add(&mut self, entry: IndexEntry) {
+        let range = self.path_range(&entry.path);
+
+        self.entries.splice(range, [entry]);
+    }
+
+    /// Records a conflict: the given base (1), ours (2) and theirs (3) versions replace
+    /// whatever was staged for the path.
+    pub fn add_conflict(&mut self, path: &str, stages: &[(u8, FileEntry)]) {
+        let range = self.path_range(path);
+
+        let entries = stages.iter().map(|(stage, file)| IndexEntry {
+            stage: *stage,
+            ..IndexEntry::new(path, file)
+        });
+
+        self.entries.splice(range, entries);
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        let range = self.path_range(path);
+
+        self.entries.drain(range);
+    }
+
+    /// Positions of every stage of a path, or where it would be inserted.
+    fn path_range(&self, path: &str) -> std::ops::Range<usize> {
+        let start = self
+            .entries
+            .partition_point(|e| e.path.as_bytes() < path.as_bytes());
+
+        let end = self
+            .entries
+            .partition_point(|e| e.path.as_bytes() <= path.as_bytes());
+
+        start..end
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.stage != 0)
+    }
+
+    fn sort(&mut self) {
+        self.entries.sort_by(|a, b| {
+            a.path
+                .as_bytes()
+                .cmp(b.path.as_bytes())
+                .then(a.stage.cmp(&b.stage))
+        });
+    }
+}
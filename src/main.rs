@@ -3,13 +3,39 @@ use clap::Parser;
 use cmd_options::CmdOptions;
 use git::Git;
 
+mod bitmap;
+mod bundle;
+mod chunk_file;
 mod cmd_options;
+mod commit_graph;
+mod config;
+mod diff;
+mod fsck;
+mod gc;
 mod git;
 mod git_objects;
+mod http;
+mod http_backend;
+mod index;
+mod merge;
+mod midx;
+mod pack;
+mod pkt_line;
+mod promisor;
+mod receive_pack;
+mod reflog;
+mod refs;
+mod remote;
+mod revision;
+mod sequencer;
+mod stats;
+mod transport;
+mod upload_pack;
 mod utils;
+mod worktree;
 
 fn main() -> anyhow::Result<()> {
-    let options = CmdOptions::parse();
+    let options = CmdOptions::parse_from(cmd_options::normalize_args(std::env::args()));
 
     let git = Git::new();
 
@@ -0,0 +1,777 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::bail;
+
+use crate::diff::{
+    diff_lines, diff_trees, flatten_tree, read_blob, split_lines, DetectMode, DiffStatus,
+    FileEntry, Hunk, RenameOptions,
+};
+use crate::git_objects::{GitObject, Signature, TreeFileModes};
+use crate::revision::CommitWalker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictStyle {
+    Merge,
+
+    Diff3,
+
+    ZDiff3,
+}
+
+impl TryFrom<&str> for ConflictStyle {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "merge" => Ok(ConflictStyle::Merge),
+
+            "diff3" => Ok(ConflictStyle::Diff3),
+
+            "zdiff3" => Ok(ConflictStyle::ZDiff3),
+
+            _ => bail!("unknown conflict style '{value}'"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub style: ConflictStyle,
+
+    pub ours_label: String,
+
+    pub theirs_label: String,
+
+    pub base_label: String,
+}
+
+/// An unmerged path, with the versions from the base (stage 1), ours (stage 2)
+/// and theirs (stage 3) that are available.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: String,
+
+    pub stages: Vec<(u8, FileEntry)>,
+}
+
+#[derive(Debug, Default)]
+pub struct MergeResult {
+    pub tree: String,
+
+    pub conflicts: Vec<Conflict>,
+
+    pub messages: Vec<String>,
+}
+
+impl MergeResult {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Merges two commits, building a virtual merge base first when there is more than
+/// one best common ancestor (criss-cross merges).
+pub fn merge_commits(
+    walker: &mut CommitWalker,
+    ours: &str,
+    theirs: &str,
+    options: &MergeOptions,
+) -> anyhow::Result<MergeResult> {
+    let bases = walker.merge_bases(ours, theirs)?;
+
+    let mut options = options.clone();
+
+    let base_tree = match bases.len() {
+        0 => None,
+
+        1 => {
+            options.base_label = bases[0][..7].to_string();
+
+            Some(walker.commit(&bases[0])?.tree.clone())
+        }
+
+        _ => {
+            options.base_label = String::from("merged common ancestors");
+
+            let virtual_base = merge_virtual_bases(walker, &bases, &options)?;
+
+            Some(walker.commit(&virtual_base)?.tree.clone())
+        }
+    };
+
+    let ours_tree = walker.commit(ours)?.tree.clone();
+    let theirs_tree = walker.commit(theirs)?.tree.clone();
+
+    merge_trees(base_tree.as_deref(), &ours_tree, &theirs_tree, &options)
+}
+
+/// Folds several merge bases into one commit, merging them pairwise the same way
+/// as the outer merge. Conflicts are kept in the virtual base as conflict markers.
+fn merge_virtual_bases(
+    walker: &mut CommitWalker,
+    bases: &[String],
+    options: &MergeOptions,
+) -> anyhow::Result<String> {
+    let mut sorted = bases.to_vec();
+
+    let mut dates = HashMap::new();
+
+    for base in &sorted {
+        dates.insert(base.clone(), walker.date(base)?);
+    }
+
+    sorted.sort_by_key(|hash| dates[hash]);
+
+    let mut current = sorted[0].clone();
+
+    for next in &sorted[1..] {
+        let inner_options = MergeOptions {
+            style: options.style,
+            ours_label: String::from("Temporary merge branch 1"),
+            theirs_label: String::from("Temporary merge branch 2"),
+            base_label: options.base_label.clone(),
+        };
+
+        let result = merge_commits(walker, &current, next, &inner_options)?;
+
+        let signature = Signature::current_or_default("COMMITTER")?;
+
+        let commit = GitObject::new_commit(
+            "merged tree\n",
+            &result.tree,
+            vec![current.clone(), next.clone()],
+            signature.clone(),
+            signature,
+        )?;
+
+        commit.write_to_file()?;
+
+        current = commit.get_hash().clone();
+    }
+
+    Ok(current)
+}
+
+fn is_blob(entry: &FileEntry) -> bool {
+    matches!(
+        entry.mode,
+        TreeFileModes::Regular | TreeFileModes::Executable
+    )
+}
+
+fn same(a: Option<&FileEntry>, b: Option<&FileEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.hash == b.hash && a.mode == b.mode,
+
+        (None, None) => true,
+
+        _ => false,
+    }
+}
+
+/// Paths renamed between two trees, old path to new path.
+fn find_renames(base: Option<&str>, side: &str) -> anyhow::Result<HashMap<String, String>> {
+    let options = RenameOptions {
+        detect: DetectMode::Renames,
+        ..Default::default()
+    };
+
+    Ok(diff_trees(base, Some(side), true, &options)?
+        .into_iter()
+        .filter(|entry| entry.status == DiffStatus::Renamed)
+        .map(|entry| (entry.old_path, entry.new_path))
+        .collect())
+}
+
+/// Three-way merge of trees, path by path, following renames made on either side.
+pub fn merge_trees(
+    base_tree: Option<&str>,
+    ours_tree: &str,
+    theirs_tree: &str,
+    options: &MergeOptions,
+) -> anyhow::Result<MergeResult> {
+    let mut base = match base_tree {
+        Some(tree) => flatten_tree(tree)?,
+
+        None => BTreeMap::new(),
+    };
+
+    let mut ours = flatten_tree(ours_tree)?;
+    let mut theirs = flatten_tree(theirs_tree)?;
+
+    let ours_renames = find_renames(base_tree, ours_tree)?;
+    let theirs_renames = find_renames(base_tree, theirs_tree)?;
+
+    let mut result = MergeResult::default();
+
+    // Conflicts found while following renames, with the paths they cover
+    let mut settled: BTreeMap<String, FileEntry> = BTreeMap::new();
+
+    for (old_path, ours_path) in &ours_renames {
+        let Some(base_entry) = base.remove(old_path) else {
+            continue;
+        };
+
+        match theirs_renames.get(old_path) {
+            Some(theirs_path) if theirs_path == ours_path => {
+                base.insert(ours_path.clone(), base_entry);
+
+                theirs.remove(old_path);
+            }
+
+            Some(theirs_path) => {
+                result.messages.push(format!(
+                    "CONFLICT (rename/rename): {old_path} renamed to {ours_path} in {} and to {theirs_path} in {}.",
+                    options.ours_label, options.theirs_label
+                ));
+
+                for (stage, path, side) in [(2, ours_path, &ours), (3, theirs_path, &theirs)] {
+                    if let Some(entry) = side.get(path) {
+                        settled.insert(path.clone(), entry.clone());
+
+                        result.conflicts.push(Conflict {
+                            path: path.clone(),
+                            stages: vec![(1, base_entry.clone()), (stage, entry.clone())],
+                        });
+                    }
+                }
+
+                ours.remove(theirs_path);
+                theirs.remove(ours_path);
+            }
+
+            None => match theirs.remove(old_path) {
+                Some(theirs_entry) if !theirs.contains_key(ours_path) => {
+                    base.insert(ours_path.clone(), base_entry);
+
+                    theirs.insert(ours_path.clone(), theirs_entry);
+                }
+
+                Some(theirs_entry) => {
+                    theirs.insert(old_path.clone(), theirs_entry);
+
+                    base.insert(old_path.clone(), base_entry);
+                }
+
+                None => {
+                    result.messages.push(format!(
+                        "CONFLICT (rename/delete): {old_path} renamed to {ours_path} in {}, but deleted in {}.",
+                        options.ours_label, options.theirs_label
+                    ));
+
+                    if let Some(entry) = ours.get(ours_path) {
+                        settled.insert(ours_path.clone(), entry.clone());
+
+                        result.conflicts.push(Conflict {
+                            path: ours_path.clone(),
+                            stages: vec![(1, base_entry), (2, entry.clone())],
+                        });
+                    }
+                }
+            },
+        }
+    }
+
+    for (old_path, theirs_path) in &theirs_renames {
+        if ours_renames.contains_key(old_path) {
+            continue;
+        }
+
+        let Some(base_entry) = base.remove(old_path) else {
+            continue;
+        };
+
+        match ours.remove(old_path) {
+            Some(ours_entry) if !ours.contains_key(theirs_path) => {
+                base.insert(theirs_path.clone(), base_entry);
+
+                ours.insert(theirs_path.clone(), ours_entry);
+            }
+
+            Some(ours_entry) => {
+                ours.insert(old_path.clone(), ours_entry);
+
+                base.insert(old_path.clone(), base_entry);
+            }
+
+            None => {
+                result.messages.push(format!(
+                    "CONFLICT (rename/delete): {old_path} renamed to {theirs_path} in {}, but deleted in {}.",
+                    options.theirs_label, options.ours_label
+                ));
+
+                if let Some(entry) = theirs.get(theirs_path) {
+                    settled.insert(theirs_path.clone(), entry.clone());
+
+                    result.conflicts.push(Conflict {
+                        path: theirs_path.clone(),
+                        stages: vec![(1, base_entry), (3, entry.clone())],
+                    });
+                }
+            }
+        }
+    }
+
+    let paths: BTreeSet<String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .filter(|path| !settled.contains_key(*path))
+        .cloned()
+        .collect();
+
+    let mut merged: BTreeMap<String, FileEntry> = settled;
+
+    // Which side each merged path came from, for file/directory conflicts
+    let mut from_theirs: BTreeSet<String> = BTreeSet::new();
+
+    for path in paths {
+        let b = base.get(&path);
+        let o = ours.get(&path);
+        let t = theirs.get(&path);
+
+        if same(o, t) || same(t, b) {
+            if let Some(o) = o {
+                merged.insert(path, o.clone());
+            }
+
+            continue;
+        }
+
+        if same(o, b) {
+            if let Some(t) = t {
+                merged.insert(path.clone(), t.clone());
+
+                from_theirs.insert(path);
+            }
+
+            continue;
+        }
+
+        match (b, o, t) {
+            (_, Some(o), Some(t)) => {
+                let entry = merge_entry(&path, b, o, t, options, &mut result)?;
+
+                merged.insert(path, entry);
+            }
+
+            (Some(b), Some(o), None) => {
+                result.messages.push(format!(
+                    "CONFLICT (modify/delete): {path} deleted in {} and modified in {}.  Version {} of {path} left in tree.",
+                    options.theirs_label, options.ours_label, options.ours_label
+                ));
+
+                result.conflicts.push(Conflict {
+                    path: path.clone(),
+                    stages: vec![(1, b.clone()), (2, o.clone())],
+                });
+
+                merged.insert(path, o.clone());
+            }
+
+            (Some(b), None, Some(t)) => {
+                result.messages.push(format!(
+                    "CONFLICT (modify/delete): {path} deleted in {} and modified in {}.  Version {} of {path} left in tree.",
+                    options.ours_label, options.theirs_label, options.theirs_label
+                ));
+
+                result.conflicts.push(Conflict {
+                    path: path.clone(),
+                    stages: vec![(1, b.clone()), (3, t.clone())],
+                });
+
+                merged.insert(path.clone(), t.clone());
+
+                from_theirs.insert(path);
+            }
+
+            _ => {}
+        }
+    }
+
+    resolve_directory_conflicts(&mut merged, &from_theirs, options, &mut result);
+
+    result.tree = write_tree_from_paths(&merged)?;
+
+    result.conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(result)
+}
+
+/// Merges a path that exists on both sides with different contents or modes.
+fn merge_entry(
+    path: &str,
+    base: Option<&FileEntry>,
+    ours: &FileEntry,
+    theirs: &FileEntry,
+    options: &MergeOptions,
+    result: &mut MergeResult,
+) -> anyhow::Result<FileEntry> {
+    let kind = if base.is_some() { "content" } else { "add/add" };
+
+    let mut stages = Vec::new();
+
+    if let Some(base) = base {
+        stages.push((1, base.clone()));
+    }
+
+    stages.push((2, ours.clone()));
+    stages.push((3, theirs.clone()));
+
+    let mut conflicted = false;
+
+    let mode = match base {
+        Some(base) if base.mode == ours.mode => theirs.mode,
+
+        Some(base) if base.mode == theirs.mode => ours.mode,
+
+        _ if ours.mode == theirs.mode => ours.mode,
+
+        _ => {
+            result.messages.push(format!(
+                "CONFLICT (mode change): {path} had different modes in {} and {}.",
+                options.ours_label, options.theirs_label
+            ));
+
+            conflicted = true;
+
+            ours.mode
+        }
+    };
+
+    let hash = if ours.hash == theirs.hash {
+        ours.hash.clone()
+    } else if base.is_some_and(|b| b.hash == ours.hash) {
+        theirs.hash.clone()
+    } else if base.is_some_and(|b| b.hash == theirs.hash) {
+        ours.hash.clone()
+    } else if !is_blob(ours) || !is_blob(theirs) {
+        result
+            .messages
+            .push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+
+        conflicted = true;
+
+        ours.hash.clone()
+    } else {
+        let base_content = match base.filter(|b| is_blob(b)) {
+            Some(base) => read_blob(&base.hash)?,
+
+            None => Vec::new(),
+        };
+
+        let ours_content = read_blob(&ours.hash)?;
+        let theirs_content = read_blob(&theirs.hash)?;
+
+        result.messages.push(format!("Auto-merging {path}"));
+
+        if [&base_content, &ours_content, &theirs_content]
+            .iter()
+            .any(|content| content.contains(&0))
+        {
+            result.messages.push(format!(
+                "warning: Cannot merge binary files: {path} ({} vs. {})",
+                options.ours_label, options.theirs_label
+            ));
+
+            result
+                .messages
+                .push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+
+            conflicted = true;
+
+            ours.hash.clone()
+        } else {
+            let (content, conflicts) =
+                merge_file(&base_content, &ours_content, &theirs_content, options);
+
+            if conflicts > 0 {
+                result
+                    .messages
+                    .push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+
+                conflicted = true;
+            }
+
+            GitObject::write_raw("blob", &content)?
+        }
+    };
+
+    if conflicted {
+        result.conflicts.push(Conflict {
+            path: path.to_string(),
+            stages,
+        });
+    }
+
+    Ok(FileEntry { mode, hash })
+}
+
+/// Moves a file out of the way of a directory with the same path, to `path~<branch>`.
+fn resolve_directory_conflicts(
+    merged: &mut BTreeMap<String, FileEntry>,
+    from_theirs: &BTreeSet<String>,
+    options: &MergeOptions,
+    result: &mut MergeResult,
+) {
+    let in_the_way: Vec<String> = merged
+        .keys()
+        .filter(|path| {
+            let prefix = format!("{path}/");
+
+            merged
+                .range(prefix.clone()..)
+                .next()
+                .is_some_and(|(other, _)| other.starts_with(&prefix))
+        })
+        .cloned()
+        .collect();
+
+    for path in in_the_way {
+        let Some(entry) = merged.remove(&path) else {
+            continue;
+        };
+
+        let label = if from_theirs.contains(&path) {
+            &options.theirs_label
+        } else {
+            &options.ours_label
+        };
+
+        let new_path = format!("{path}~{}", label.replace('/', "_"));
+
+        result.messages.push(format!(
+            "CONFLICT (file/directory): directory in the way of {path} from {label}; moving it to {new_path} instead."
+        ));
+
+        let stage = if from_theirs.contains(&path) { 3 } else { 2 };
+
+        result.conflicts.push(Conflict {
+            path: new_path.clone(),
+            stages: vec![(stage, entry.clone())],
+        });
+
+        merged.insert(new_path, entry);
+    }
+}
+
+/// Builds the nested tree objects for a flat `path -> entry` listing.
+pub fn write_tree_from_paths(files: &BTreeMap<String, FileEntry>) -> anyhow::Result<String> {
+    let mut entries: Vec<(TreeFileModes, String, String)> = Vec::new();
+
+    let mut subdirectories: BTreeMap<String, BTreeMap<String, FileEntry>> = BTreeMap::new();
+
+    for (path, entry) in files {
+        match path.split_once('/') {
+            Some((directory, rest)) => {
+                subdirectories
+                    .entry(directory.to_string())
+                    .or_default()
+                    .insert(rest.to_string(), entry.clone());
+            }
+
+            None => entries.push((entry.mode, path.clone(), entry.hash.clone())),
+        }
+    }
+
+    for (directory, files) in subdirectories {
+        let hash = write_tree_from_paths(&files)?;
+
+        entries.push((TreeFileModes::Directory, directory, hash));
+    }
+
+    GitObject::write_tree_entries(&entries)
+}
+
+/// Line based three-way merge of file contents. Returns the merged content, with
+/// conflict markers around overlapping changes, and the number of conflicts.
+pub fn merge_file(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    options: &MergeOptions,
+) -> (Vec<u8>, usize) {
+    let base_lines = split_lines(base);
+    let ours_lines = split_lines(ours);
+    let theirs_lines = split_lines(theirs);
+
+    // Changes from both sides, ordered by where they start in the base
+    let mut changes: Vec<(Hunk, bool)> = diff_lines(&base_lines, &ours_lines)
+        .into_iter()
+        .map(|hunk| (hunk, true))
+        .chain(
+            diff_lines(&base_lines, &theirs_lines)
+                .into_iter()
+                .map(|hunk| (hunk, false)),
+        )
+        .collect();
+
+    changes.sort_by_key(|(hunk, is_ours)| (hunk.old_start, !is_ours));
+
+    let mut output: Vec<u8> = Vec::new();
+
+    let mut conflicts = 0;
+
+    let mut base_pos = 0;
+
+    let mut index = 0;
+
+    // How far each side's line numbers have drifted from the base so far
+    let (mut ours_offset, mut theirs_offset) = (0isize, 0isize);
+
+    while index < changes.len() {
+        // Group every change overlapping (or touching) the current region
+        let start = changes[index].0.old_start;
+        let mut end = start + changes[index].0.old_len;
+
+        let mut group_end = index + 1;
+
+        while group_end < changes.len() && changes[group_end].0.old_start <= end {
+            let hunk = changes[group_end].0;
+
+            end = end.max(hunk.old_start + hunk.old_len);
+
+            group_end += 1;
+        }
+
+        let group = &changes[index..group_end];
+
+        for line in &base_lines[base_pos..start] {
+            output.extend_from_slice(line);
+        }
+
+        let ours_range = side_range(group, true, start, end, ours_offset);
+        let theirs_range = side_range(group, false, start, end, theirs_offset);
+
+        ours_offset += ours_range.len() as isize - (end - start) as isize;
+        theirs_offset += theirs_range.len() as isize - (end - start) as isize;
+
+        let ours_chunk = &ours_lines[ours_range];
+        let theirs_chunk = &theirs_lines[theirs_range];
+
+        if !group.iter().any(|(_, is_ours)| !is_ours) {
+            ours_chunk
+                .iter()
+                .for_each(|line| output.extend_from_slice(line));
+        } else if !group.iter().any(|(_, is_ours)| *is_ours) || ours_chunk == theirs_chunk {
+            theirs_chunk
+                .iter()
+                .for_each(|line| output.extend_from_slice(line));
+        } else {
+            conflicts += 1;
+
+            write_conflict(
+                &mut output,
+                &base_lines[start..end],
+                ours_chunk,
+                theirs_chunk,
+                options,
+            );
+        }
+
+        base_pos = end;
+
+        index = group_end;
+    }
+
+    for line in &base_lines[base_pos..] {
+        output.extend_from_slice(line);
+    }
+
+    (output, conflicts)
+}
+
+/// Lines of one side covering the base region `start..end`.
+fn side_range(
+    group: &[(Hunk, bool)],
+    is_ours: bool,
+    start: usize,
+    end: usize,
+    offset: isize,
+) -> std::ops::Range<usize> {
+    let hunks: Vec<&Hunk> = group
+        .iter()
+        .filter(|(_, side)| *side == is_ours)
+        .map(|(hunk, _)| hunk)
+        .collect();
+
+    match (hunks.first(), hunks.last()) {
+        (Some(first), Some(last)) => {
+            let side_start = first.new_start - (first.old_start - start);
+            let side_end = last.new_start + last.new_len + (end - (last.old_start + last.old_len));
+
+            side_start..side_end
+        }
+
+        // Side unchanged here, its lines line up with the base
+        _ => (start as isize + offset) as usize..(end as isize + offset) as usize,
+    }
+}
+
+fn write_conflict(
+    output: &mut Vec<u8>,
+    base: &[&[u8]],
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+    options: &MergeOptions,
+) {
+    // Lines both sides agree on are moved out of the conflict, except in diff3 style
+    // where the conflict has to line up with the base
+    let (prefix, suffix) = if options.style == ConflictStyle::Diff3 {
+        (0, 0)
+    } else {
+        let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+
+        let suffix = ours[prefix..]
+            .iter()
+            .rev()
+            .zip(theirs[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        (prefix, suffix)
+    };
+
+    for line in &ours[..prefix] {
+        output.extend_from_slice(line);
+    }
+
+    let push_lines = |output: &mut Vec<u8>, lines: &[&[u8]]| {
+        for line in lines {
+            output.extend_from_slice(line);
+        }
+
+        if output.last().is_some_and(|b| *b != b'\n') {
+            output.push(b'\n');
+        }
+    };
+
+    push_lines(
+        output,
+        &[format!("<<<<<<< {}\n", options.ours_label).as_bytes()],
+    );
+
+    push_lines(output, &ours[prefix..ours.len() - suffix]);
+
+    if options.style != ConflictStyle::Merge {
+        push_lines(
+            output,
+            &[format!("||||||| {}\n", options.base_label).as_bytes()],
+        );
+
+        push_lines(output, base);
+    }
+
+    push_lines(output, &[b"=======\n"]);
+
+    push_lines(output, &theirs[prefix..theirs.len() - suffix]);
+
+    push_lines(
+        output,
+        &[format!(">>>>>>> {}\n", options.theirs_label).as_bytes()],
+    );
+
+    for line in &ours[ours.len() - suffix..] {
+        output.extend_from_slice(line);
+    }
+}
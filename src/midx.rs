@@ -0,0 +1,664 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, ensure};
+use sha1::{Digest, Sha1};
+
+use crate::chunk_file::{find_id, read_chunks, write_chunks};
+use crate::pack::{index_pack, pack_dir, reload_packs, write_pack, PackIndex};
+use crate::utils::{be_u32, be_u64, to_hex_string, write_to_file};
+
+const SIGNATURE: &[u8] = b"MIDX";
+
+const CHUNK_PACK_NAMES: [u8; 4] = *b"PNAM";
+const CHUNK_OID_FANOUT: [u8; 4] = *b"OIDF";
+const CHUNK_OID_LOOKUP: [u8; 4] = *b"OIDL";
+const CHUNK_OBJECT_OFFSETS: [u8; 4] = *b"OOFF";
+const CHUNK_LARGE_OFFSETS: [u8; 4] = *b"LOFF";
+
+/// Set on an object offset that is the index of a 64-bit offset in the large offsets.
+const LARGE_OFFSET: u32 = 0x8000_0000;
+
+/// Extensions of the files of a pack that `expire` removes.
+const PACK_EXTENSIONS: [&str; 7] = ["idx", "pack", "rev", "keep", "bitmap", "promisor", "mtimes"];
+
+/// A `multi-pack-index`: the objects of all the packs of a pack directory in one sorted
+/// table, each with the one pack it is read from.
+pub struct MultiPackIndex {
+    directory: String,
+
+    /// The `.idx` files of the packs, sorted, a pack's id being its position here.
+    pub pack_names: Vec<String>,
+
+    data: Vec<u8>,
+
+    /// Start and end of each chunk by id.
+    chunks: HashMap<[u8; 4], (usize, usize)>,
+
+    pub count: usize,
+
+    /// Indexes of the packs, read when first needed, `None` when that fails.
+    packs: Vec<OnceLock<Option<Arc<PackIndex>>>>,
+}
+
+impl MultiPackIndex {
+    /// Reads the multi-pack-index of a pack directory.
+    pub fn read(directory: &str) -> anyhow::Result<Self> {
+        let data = fs::read(midx_path(directory))?;
+
+        ensure!(
+            data.len() >= 12 + 12 + 20,
+            "multi-pack-index file is too small"
+        );
+
+        ensure!(
+            &data[..4] == SIGNATURE,
+            "multi-pack-index signature 0x{:08x} does not match signature 0x{:08x}",
+            be_u32(&data[..4]),
+            be_u32(SIGNATURE)
+        );
+
+        ensure!(
+            data[4] == 1,
+            "multi-pack-index version {} not recognized",
+            data[4]
+        );
+
+        ensure!(
+            data[5] == 1,
+            "multi-pack-index hash version {} does not match version 1",
+            data[5]
+        );
+
+        let pack_count = be_u32(&data[8..]) as usize;
+
+        let chunks = read_chunks(&data, 12, data[6] as usize)?;
+
+        let mut midx = MultiPackIndex {
+            directory: directory.to_string(),
+            pack_names: Vec::new(),
+            data,
+            chunks,
+            count: 0,
+            packs: Vec::new(),
+        };
+
+        let Some(names) = midx.chunk(CHUNK_PACK_NAMES) else {
+            bail!("multi-pack-index required pack-name chunk missing or corrupted");
+        };
+
+        let pack_names: Vec<String> = names
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+            .take(pack_count)
+            .map(|name| String::from_utf8_lossy(name).to_string())
+            .collect();
+
+        ensure!(
+            pack_names.len() == pack_count,
+            "multi-pack-index pack-name chunk is too short"
+        );
+
+        for pair in pack_names.windows(2) {
+            ensure!(
+                pair[0] < pair[1],
+                "multi-pack-index pack names out of order: '{}' before '{}'",
+                pair[0],
+                pair[1]
+            );
+        }
+
+        let Some(fanout) = midx.chunk(CHUNK_OID_FANOUT).filter(|c| c.len() == 1024) else {
+            bail!("multi-pack-index required OID fanout chunk missing or corrupted");
+        };
+
+        let count = be_u32(&fanout[1020..]) as usize;
+
+        ensure!(
+            midx.chunk(CHUNK_OID_LOOKUP)
+                .is_some_and(|chunk| chunk.len() == count * 20),
+            "multi-pack-index required OID lookup chunk missing or corrupted"
+        );
+
+        ensure!(
+            midx.chunk(CHUNK_OBJECT_OFFSETS)
+                .is_some_and(|chunk| chunk.len() == count * 8),
+            "multi-pack-index required object offsets chunk missing or corrupted"
+        );
+
+        midx.packs = (0..pack_names.len()).map(|_| OnceLock::new()).collect();
+
+        midx.pack_names = pack_names;
+
+        midx.count = count;
+
+        Ok(midx)
+    }
+
+    fn chunk(&self, id: [u8; 4]) -> Option<&[u8]> {
+        self.chunks
+            .get(&id)
+            .map(|&(start, end)| &self.data[start..end])
+    }
+
+    pub fn id(&self, index: usize) -> &[u8] {
+        let (start, _) = self.chunks[&CHUNK_OID_LOOKUP];
+
+        &self.data[start + index * 20..start + index * 20 + 20]
+    }
+
+    /// Id of the pack the object at `index` is read from, and where it starts in it.
+    pub fn entry(&self, index: usize) -> (usize, u64) {
+        let (start, _) = self.chunks[&CHUNK_OBJECT_OFFSETS];
+
+        let pack_id = be_u32(&self.data[start + index * 8..]) as usize;
+
+        let offset = be_u32(&self.data[start + index * 8 + 4..]);
+
+        // Without large offsets every offset fits in 32 bits
+        let Some(large_offsets) = self
+            .chunk(CHUNK_LARGE_OFFSETS)
+            .filter(|_| offset & LARGE_OFFSET != 0)
+        else {
+            return (pack_id, offset as u64);
+        };
+
+        let large = (offset & !LARGE_OFFSET) as usize * 8;
+
+        let offset = large_offsets.get(large..large + 8).map_or(0, be_u64);
+
+        (pack_id, offset)
+    }
+
+    /// The index of a pack by id.
+    pub fn pack(&self, pack_id: usize) -> Option<Arc<PackIndex>> {
+        self.packs
+            .get(pack_id)?
+            .get_or_init(|| {
+                let path = format!("{}/{}", self.directory, self.pack_names[pack_id]);
+
+                PackIndex::read(&path).ok().map(Arc::new)
+            })
+            .clone()
+    }
+
+    /// The pack holding an object and where the object starts in it.
+    pub fn find(&self, id: &[u8]) -> Option<(Arc<PackIndex>, u64)> {
+        let index = find_id(
+            self.chunk(CHUNK_OID_FANOUT)?,
+            self.chunk(CHUNK_OID_LOOKUP)?,
+            id,
+        )?;
+
+        let (pack_id, offset) = self.entry(index);
+
+        Some((self.pack(pack_id)?, offset))
+    }
+
+    fn checksum_valid(&self) -> bool {
+        let (content, trailer) = self.data.split_at(self.data.len() - 20);
+
+        Sha1::digest(content).as_slice() == trailer
+    }
+
+    /// How many of the objects are read from each pack.
+    fn referenced(&self) -> Vec<usize> {
+        let mut referenced = vec![0; self.pack_names.len()];
+
+        for index in 0..self.count {
+            if let Some(count) = referenced.get_mut(self.entry(index).0) {
+                *count += 1;
+            }
+        }
+
+        referenced
+    }
+}
+
+pub fn midx_path(directory: &str) -> String {
+    format!("{directory}/multi-pack-index")
+}
+
+/// Writes the multi-pack-index of every pack in the repository's pack directory. An
+/// object in several packs is read from the `preferred` one, else from the newest pack
+/// the existing multi-pack-index does not cover, else from the one it reads it from.
+pub fn write(preferred: Option<&str>) -> anyhow::Result<()> {
+    write_without(preferred, &[])
+}
+
+/// [`write`], leaving out the packs of the existing multi-pack-index named in `dropped`.
+fn write_without(preferred: Option<&str>, dropped: &[String]) -> anyhow::Result<()> {
+    let directory = pack_dir();
+
+    let existing = MultiPackIndex::read(&directory).ok().filter(|midx| {
+        let valid = midx.checksum_valid();
+
+        if !valid {
+            eprintln!("warning: ignoring existing multi-pack-index; checksum mismatch");
+        }
+
+        valid
+    });
+
+    // The packs of the existing multi-pack-index keep their ids, new ones come after
+    let mut names = existing
+        .as_ref()
+        .map_or_else(Vec::new, |midx| midx.pack_names.clone());
+
+    let known = names.len();
+
+    if let Ok(entries) = fs::read_dir(&directory) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if name.ends_with(".idx") && !names[..known].contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    ensure!(
+        names.iter().any(|name| !dropped.contains(name)),
+        "no pack files to index."
+    );
+
+    // Nothing to do when the existing one covers the same packs
+    if existing.is_some() && names.len() == known && dropped.is_empty() {
+        return Ok(());
+    }
+
+    let preferred_id = preferred.and_then(|preferred| {
+        let found = names
+            .iter()
+            .position(|name| name == preferred || name.replace(".idx", ".pack") == preferred);
+
+        if found.is_none() {
+            eprintln!("warning: unknown preferred pack: '{preferred}'");
+        }
+
+        found
+    });
+
+    let mut packs = Vec::with_capacity(names.len());
+
+    for (pack_id, name) in names.iter().enumerate() {
+        let pack = match &existing {
+            Some(midx) if pack_id < known => midx.pack(pack_id),
+
+            _ => Some(Arc::new(PackIndex::read(&format!("{directory}/{name}"))?)),
+        };
+
+        let modified = pack
+            .as_ref()
+            .and_then(|pack| fs::metadata(&pack.pack_path).ok())
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |age| age.as_secs());
+
+        packs.push((pack, modified));
+    }
+
+    // Sorted so that the copy of each object to keep comes first
+    let mut entries = Vec::new();
+
+    let add_pack = |entries: &mut Vec<_>, pack_id: usize| {
+        if let (Some(pack), modified) = &packs[pack_id] {
+            let preference = Some(pack_id) != preferred_id;
+
+            for (id, &offset) in pack.hashes.iter().zip(&pack.offsets) {
+                entries.push((*id, preference, Reverse(*modified), pack_id, offset));
+            }
+        }
+    };
+
+    if let Some(midx) = &existing {
+        for index in 0..midx.count {
+            let (pack_id, offset) = midx.entry(index);
+
+            if Some(pack_id) == preferred_id || pack_id >= known {
+                continue;
+            }
+
+            let id = midx.id(index).try_into()?;
+
+            // Counted as older than any new pack
+            entries.push((id, true, Reverse(0), pack_id, offset));
+        }
+    }
+
+    for pack_id in known..names.len() {
+        add_pack(&mut entries, pack_id);
+    }
+
+    if let Some(pack_id) = preferred_id.filter(|&pack_id| pack_id < known) {
+        add_pack(&mut entries, pack_id);
+    }
+
+    entries.sort();
+
+    entries.dedup_by_key(|entry| entry.0);
+
+    // Ids of the packs kept, in the order of their names
+    let mut kept: Vec<usize> = (0..names.len())
+        .filter(|&pack_id| !dropped.contains(&names[pack_id]))
+        .collect();
+
+    kept.sort_by(|&a, &b| names[a].cmp(&names[b]));
+
+    let mut new_ids = vec![0; names.len()];
+
+    for (new_id, &pack_id) in kept.iter().enumerate() {
+        new_ids[pack_id] = new_id;
+    }
+
+    entries.retain(|entry| !dropped.contains(&names[entry.3]));
+
+    let mut pack_names = Vec::new();
+
+    for &pack_id in &kept {
+        pack_names.extend(names[pack_id].as_bytes());
+
+        pack_names.push(0);
+    }
+
+    pack_names.resize(pack_names.len().next_multiple_of(4), 0);
+
+    let mut fanout = Vec::with_capacity(1024);
+
+    for first in 0..256 {
+        let count = entries.partition_point(|entry| (entry.0[0] as usize) <= first);
+
+        fanout.extend((count as u32).to_be_bytes());
+    }
+
+    let lookup: Vec<u8> = entries.iter().flat_map(|entry| entry.0).collect();
+
+    let needs_large = entries.iter().any(|entry| entry.4 > u32::MAX as u64);
+
+    let mut offsets = Vec::with_capacity(entries.len() * 8);
+
+    let mut large_offsets = Vec::new();
+
+    for &(_, _, _, pack_id, offset) in &entries {
+        offsets.extend((new_ids[pack_id] as u32).to_be_bytes());
+
+        if needs_large && offset >= LARGE_OFFSET as u64 {
+            offsets.extend((LARGE_OFFSET | (large_offsets.len() / 8) as u32).to_be_bytes());
+
+            large_offsets.extend(offset.to_be_bytes());
+        } else {
+            offsets.extend((offset as u32).to_be_bytes());
+        }
+    }
+
+    let mut chunks = vec![
+        (CHUNK_PACK_NAMES, pack_names),
+        (CHUNK_OID_FANOUT, fanout),
+        (CHUNK_OID_LOOKUP, lookup),
+        (CHUNK_OBJECT_OFFSETS, offsets),
+    ];
+
+    if needs_large {
+        chunks.push((CHUNK_LARGE_OFFSETS, large_offsets));
+    }
+
+    let header = [
+        SIGNATURE,
+        &[1, 1, chunks.len() as u8, 0],
+        &(kept.len() as u32).to_be_bytes(),
+    ]
+    .concat();
+
+    write_to_file(&midx_path(&directory), &write_chunks(&header, &chunks))?;
+
+    reload_packs();
+
+    Ok(())
+}
+
+/// Checks the multi-pack-index against its checksum and the packs, reporting problems
+/// on stderr. Returns whether there were none.
+pub fn verify() -> anyhow::Result<bool> {
+    let directory = pack_dir();
+
+    if !Path::new(&midx_path(&directory)).exists() {
+        return Ok(true);
+    }
+
+    let midx = MultiPackIndex::read(&directory)?;
+
+    let mut ok = true;
+
+    let mut report = |problem: String| {
+        eprintln!("{problem}");
+
+        ok = false;
+    };
+
+    if !midx.checksum_valid() {
+        report("incorrect checksum".into());
+    }
+
+    for pack_id in 0..midx.pack_names.len() {
+        if midx.pack(pack_id).is_none() {
+            report(format!("failed to load pack in position {pack_id}"));
+        }
+    }
+
+    let fanout = midx.chunk(CHUNK_OID_FANOUT).unwrap_or_default();
+
+    for first in 0..255 {
+        let (here, next) = (
+            be_u32(&fanout[first * 4..]),
+            be_u32(&fanout[first * 4 + 4..]),
+        );
+
+        if here > next {
+            report(format!(
+                "oid fanout out of order: fanout[{first}] = {here:x} > {next:x} = fanout[{}]",
+                first + 1
+            ));
+        }
+    }
+
+    if midx.count == 0 {
+        report("the midx contains no oid".into());
+
+        return Ok(ok);
+    }
+
+    for index in 1..midx.count {
+        if midx.id(index - 1) >= midx.id(index) {
+            report(format!(
+                "oid lookup out of order: oid[{}] = {} >= {} = oid[{index}]",
+                index - 1,
+                to_hex_string(midx.id(index - 1)),
+                to_hex_string(midx.id(index))
+            ));
+        }
+    }
+
+    // By pack, so that each pack is gone through at once
+    let mut indexes: Vec<usize> = (0..midx.count).collect();
+
+    indexes.sort_by_key(|&index| midx.entry(index).0);
+
+    for index in indexes {
+        let hash = to_hex_string(midx.id(index));
+
+        // Looked up by id, as readers do
+        let found = find_id(
+            midx.chunk(CHUNK_OID_FANOUT).unwrap_or_default(),
+            midx.chunk(CHUNK_OID_LOOKUP).unwrap_or_default(),
+            midx.id(index),
+        );
+
+        let Some((pack_id, offset)) = found.map(|position| midx.entry(position)) else {
+            report(format!(
+                "failed to load pack entry for oid[{index}] = {hash}"
+            ));
+
+            continue;
+        };
+
+        ensure!(
+            pack_id < midx.pack_names.len(),
+            "bad pack-int-id: {pack_id} ({} total packs)",
+            midx.pack_names.len()
+        );
+
+        let Some(pack) = midx.pack(pack_id) else {
+            report(format!(
+                "failed to load pack entry for oid[{index}] = {hash}"
+            ));
+
+            continue;
+        };
+
+        let found = pack.find(midx.id(index)).unwrap_or(0);
+
+        if found != offset {
+            report(format!(
+                "incorrect object offset for oid[{index}] = {hash}: {offset:x} != {found:x}"
+            ));
+        }
+    }
+
+    Ok(ok)
+}
+
+/// Deletes the packs the multi-pack-index reads no object from, unless a `.keep` file
+/// protects them, and rewrites it without them.
+pub fn expire() -> anyhow::Result<()> {
+    let directory = pack_dir();
+
+    let Ok(midx) = MultiPackIndex::read(&directory) else {
+        return Ok(());
+    };
+
+    let referenced = midx.referenced();
+
+    let dropped: Vec<String> = midx
+        .pack_names
+        .iter()
+        .enumerate()
+        .filter(|&(pack_id, name)| {
+            let keep = format!("{directory}/{}.keep", name.trim_end_matches(".idx"));
+
+            referenced[pack_id] == 0 && !Path::new(&keep).exists()
+        })
+        .map(|(_, name)| name.clone())
+        .collect();
+
+    if dropped.is_empty() {
+        return Ok(());
+    }
+
+    drop(midx);
+
+    write_without(None, &dropped)?;
+
+    for name in dropped {
+        let base = format!("{directory}/{}", name.trim_end_matches(".idx"));
+
+        for extension in PACK_EXTENSIONS {
+            let path = format!("{base}.{extension}");
+
+            if Path::new(&path).exists() {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+
+    reload_packs();
+
+    Ok(())
+}
+
+/// Puts the objects the multi-pack-index reads from some of the packs without a `.keep`
+/// file into a new pack, and adds it to the multi-pack-index. With a `batch_size` of 0
+/// that is every such pack; otherwise, oldest first, packs whose objects in use come to
+/// less than `batch_size` bytes until together they reach it. Fewer than two packs are
+/// left alone.
+pub fn repack(batch_size: u64) -> anyhow::Result<()> {
+    let directory = pack_dir();
+
+    let Ok(midx) = MultiPackIndex::read(&directory) else {
+        return Ok(());
+    };
+
+    let referenced = midx.referenced();
+
+    // (modification time, id, size in use) of the packs that may be repacked
+    let mut candidates = Vec::new();
+
+    for (pack_id, name) in midx.pack_names.iter().enumerate() {
+        let base = format!("{directory}/{}", name.trim_end_matches(".idx"));
+
+        if Path::new(&format!("{base}.keep")).exists() {
+            continue;
+        }
+
+        let Some(pack) = midx.pack(pack_id).filter(|pack| !pack.hashes.is_empty()) else {
+            continue;
+        };
+
+        let metadata = fs::metadata(&pack.pack_path)?;
+
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |age| age.as_secs());
+
+        let size = metadata.len() * referenced[pack_id] as u64 / pack.hashes.len() as u64;
+
+        candidates.push((modified, pack_id, size));
+    }
+
+    let mut included = vec![false; midx.pack_names.len()];
+
+    if batch_size == 0 {
+        for &(_, pack_id, _) in &candidates {
+            included[pack_id] = true;
+        }
+    } else {
+        candidates.sort();
+
+        let mut total = 0;
+
+        for (_, pack_id, size) in candidates {
+            if total >= batch_size {
+                break;
+            }
+
+            if size < batch_size {
+                included[pack_id] = true;
+
+                total += size;
+            }
+        }
+    }
+
+    if included.iter().filter(|&&included| included).count() < 2 {
+        return Ok(());
+    }
+
+    let objects: Vec<String> = (0..midx.count)
+        .filter(|&index| included[midx.entry(index).0])
+        .map(|index| to_hex_string(midx.id(index)))
+        .collect();
+
+    if objects.is_empty() {
+        return Ok(());
+    }
+
+    index_pack(&write_pack(&objects)?)?;
+
+    reload_packs();
+
+    write(None)
+}
@@ -0,0 +1,1383 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use flate2::{Crc, Decompress, FlushDecompress, Status};
+use sha1::{Digest, Sha1};
+
+use crate::config::Config;
+use crate::git_objects::GitObject;
+use crate::midx::MultiPackIndex;
+use crate::refs::git_path;
+use crate::utils::{
+    be_u32, be_u64, compress, from_hex, generate_object_id, object_directories,
+    reload_object_directories, to_hex_string, write_to_file,
+};
+
+pub fn pack_dir() -> String {
+    git_path("objects/pack")
+}
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+const IDX_MAGIC: &[u8] = b"\xfftOc";
+
+/// Packs whose indexes have been loaded, `None` until the pack directory is read.
+static PACKS: Mutex<Option<Vec<Arc<PackIndex>>>> = Mutex::new(None);
+
+/// Where objects are looked up, `None` until the pack directories are read.
+static LOOKUP: Mutex<Option<Arc<PackLookup>>> = Mutex::new(None);
+
+/// The `.idx` of a pack: sorted object ids and where each object starts in the pack.
+#[derive(Debug)]
+pub struct PackIndex {
+    pub pack_path: String,
+
+    pub hashes: Vec<[u8; 20]>,
+
+    pub offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    /// Reads a version 1 or version 2 index file.
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let content = fs::read(path).with_context(|| format!("Could not read {path}"))?;
+
+        ensure!(content.len() >= 1072, "index file {path} is too small");
+
+        let (table_start, version) = if content.starts_with(IDX_MAGIC) {
+            (8, be_u32(&content[4..8]))
+        } else {
+            (0, 1)
+        };
+
+        ensure!(
+            version == 1 || version == 2,
+            "index file {path} has unsupported version {version}"
+        );
+
+        let count = be_u32(&content[table_start + 1020..table_start + 1024]) as usize;
+
+        // Ids with offsets (and CRCs in version 2), then the two checksums
+        let entry_size = if version == 1 { 24 } else { 28 };
+
+        ensure!(
+            (content.len() - table_start - 1024 - 40) / entry_size >= count,
+            "index file {path} is truncated"
+        );
+
+        let mut hashes = Vec::with_capacity(count);
+
+        let mut offsets = Vec::with_capacity(count);
+
+        let entries = table_start + 1024;
+
+        if version == 1 {
+            for index in 0..count {
+                let start = entries + index * 24;
+
+                offsets.push(be_u32(&content[start..start + 4]) as u64);
+
+                hashes.push(content[start + 4..start + 24].try_into()?);
+            }
+        } else {
+            let offset_table = entries + count * 24;
+
+            let large_table = offset_table + count * 4;
+
+            for index in 0..count {
+                let start = entries + index * 20;
+
+                hashes.push(content[start..start + 20].try_into()?);
+
+                let offset =
+                    be_u32(&content[offset_table + index * 4..offset_table + index * 4 + 4]);
+
+                // The high bit points into the table of 64-bit offsets
+                offsets.push(if offset & 0x8000_0000 != 0 {
+                    let large = large_table + (offset & 0x7fff_ffff) as usize * 8;
+
+                    let Some(bytes) = content[..content.len() - 40].get(large..large + 8) else {
+                        bail!("index file {path} has a bad large offset");
+                    };
+
+                    be_u64(bytes)
+                } else {
+                    offset as u64
+                });
+            }
+        }
+
+        Ok(PackIndex {
+            pack_path: format!("{}.pack", path.trim_end_matches(".idx")),
+            hashes,
+            offsets,
+        })
+    }
+
+    pub fn find(&self, hash: &[u8]) -> Option<u64> {
+        self.hashes
+            .binary_search_by(|candidate| candidate.as_slice().cmp(hash))
+            .ok()
+            .map(|position| self.offsets[position])
+    }
+
+    /// Type and content of the object starting at `offset` in this pack.
+    pub fn read_object(&self, offset: u64) -> anyhow::Result<(String, Vec<u8>)> {
+        let mut file = File::open(&self.pack_path)
+            .with_context(|| format!("Could not open {}", self.pack_path))?;
+
+        let (kind, content) = read_entry(&mut file, offset, self)?;
+
+        Ok((type_name(kind)?.to_string(), content))
+    }
+
+    /// Type of the object starting at `offset`, found by following its deltas down to
+    /// the base without inflating anything.
+    pub fn object_type(&self, mut offset: u64) -> anyhow::Result<String> {
+        let mut file = File::open(&self.pack_path)
+            .with_context(|| format!("Could not open {}", self.pack_path))?;
+
+        loop {
+            file.seek(SeekFrom::Start(offset))?;
+
+            let mut header = [0u8; 32];
+
+            let read = file.read(&mut header)?;
+
+            let (kind, _, used) = parse_entry_header(&header[..read])?;
+
+            offset = match kind {
+                OBJ_OFS_DELTA => ofs_base(offset, parse_ofs_distance(&header[used..read])?.0)?,
+
+                OBJ_REF_DELTA => {
+                    let base_id = ref_base(&header[used..read])?;
+
+                    match self.find(base_id) {
+                        Some(base) => base,
+
+                        None => return Ok(GitObject::read_raw(&to_hex_string(base_id))?.0),
+                    }
+                }
+
+                _ => return Ok(type_name(kind)?.to_string()),
+            };
+        }
+    }
+
+    /// How many deltas rebuilding the object at `offset` takes, 0 for a whole object.
+    /// A base in another pack ends the chain.
+    pub fn delta_depth(&self, mut offset: u64) -> anyhow::Result<usize> {
+        let mut file = File::open(&self.pack_path)
+            .with_context(|| format!("Could not open {}", self.pack_path))?;
+
+        let mut depth = 0;
+
+        loop {
+            file.seek(SeekFrom::Start(offset))?;
+
+            let mut header = [0u8; 32];
+
+            let read = file.read(&mut header)?;
+
+            let (kind, _, used) = parse_entry_header(&header[..read])?;
+
+            offset = match kind {
+                OBJ_OFS_DELTA => ofs_base(offset, parse_ofs_distance(&header[used..read])?.0)?,
+
+                OBJ_REF_DELTA => match self.find(ref_base(&header[used..read])?) {
+                    Some(base) => base,
+
+                    None => return Ok(depth + 1),
+                },
+
+                _ => return Ok(depth),
+            };
+
+            depth += 1;
+        }
+    }
+}
+
+/// Indexes of every pack in the repository and its alternates, loaded once.
+pub fn packs() -> anyhow::Result<Vec<Arc<PackIndex>>> {
+    let mut cache = PACKS
+        .lock()
+        .map_err(|_| anyhow!("pack cache is poisoned"))?;
+
+    if let Some(packs) = cache.as_ref() {
+        return Ok(packs.clone());
+    }
+
+    let mut packs = Vec::new();
+
+    for directory in object_directories() {
+        for path in index_paths(&format!("{directory}/pack")) {
+            packs.push(Arc::new(PackIndex::read(&path)?));
+        }
+    }
+
+    *cache = Some(packs.clone());
+
+    Ok(packs)
+}
+
+/// Paths of the `.idx` files in a pack directory, sorted. Like git, an index without
+/// its `.pack` is left out.
+pub fn index_paths(directory: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.path().to_string_lossy().to_string())
+        .filter(|path| {
+            path.strip_suffix(".idx")
+                .is_some_and(|base| Path::new(&format!("{base}.pack")).is_file())
+        })
+        .collect();
+
+    paths.sort();
+
+    paths
+}
+
+/// Where packed objects are looked up: the multi-pack-index of each object directory
+/// that has one, unless `core.multiPackIndex` is off, and the packs none covers.
+struct PackLookup {
+    midxes: Vec<MultiPackIndex>,
+
+    packs: Vec<Arc<PackIndex>>,
+}
+
+fn pack_lookup() -> anyhow::Result<Arc<PackLookup>> {
+    let mut cache = LOOKUP
+        .lock()
+        .map_err(|_| anyhow!("pack cache is poisoned"))?;
+
+    if let Some(lookup) = cache.as_ref() {
+        return Ok(lookup.clone());
+    }
+
+    let enabled = Config::load().map_or(true, |config| {
+        config.get("core.multiPackIndex") != Some("false")
+    });
+
+    let mut lookup = PackLookup {
+        midxes: Vec::new(),
+        packs: Vec::new(),
+    };
+
+    for directory in object_directories() {
+        let directory = format!("{directory}/pack");
+
+        let midx = match enabled {
+            true => MultiPackIndex::read(&directory).ok(),
+
+            false => None,
+        };
+
+        for path in index_paths(&directory) {
+            let name = path.rsplit('/').next().unwrap_or_default().to_string();
+
+            if !midx
+                .as_ref()
+                .is_some_and(|midx| midx.pack_names.binary_search(&name).is_ok())
+            {
+                lookup.packs.push(Arc::new(PackIndex::read(&path)?));
+            }
+        }
+
+        lookup.midxes.extend(midx);
+    }
+
+    let lookup = Arc::new(lookup);
+
+    *cache = Some(lookup.clone());
+
+    Ok(lookup)
+}
+
+/// The pack holding an object and where the object starts in it.
+fn find_packed(id: &[u8]) -> anyhow::Result<Option<(Arc<PackIndex>, u64)>> {
+    let lookup = pack_lookup()?;
+
+    for midx in &lookup.midxes {
+        if let Some(found) = midx.find(id) {
+            return Ok(Some(found));
+        }
+    }
+
+    Ok(lookup
+        .packs
+        .iter()
+        .find_map(|pack| Some((pack.clone(), pack.find(id)?))))
+}
+
+/// Forgets the loaded indexes, and the alternates they came from, so that new or removed
+/// packs are noticed.
+pub fn reload_packs() {
+    reload_object_directories();
+
+    if let Ok(mut cache) = PACKS.lock() {
+        *cache = None;
+    }
+
+    if let Ok(mut cache) = LOOKUP.lock() {
+        *cache = None;
+    }
+}
+
+/// Type and content of an object stored in one of the packs.
+pub fn read_packed_object(hash: &str) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+    let Some(id) = object_id(hash) else {
+        return Ok(None);
+    };
+
+    match find_packed(&id)? {
+        Some((pack, offset)) => Ok(Some(pack.read_object(offset)?)),
+
+        None => Ok(None),
+    }
+}
+
+pub fn packed_object_exists(hash: &str) -> bool {
+    let Some(id) = object_id(hash) else {
+        return false;
+    };
+
+    find_packed(&id).is_ok_and(|found| found.is_some())
+}
+
+/// Hashes of packed objects starting with `prefix`.
+pub fn packed_objects_with_prefix(prefix: &str) -> anyhow::Result<Vec<String>> {
+    let lookup = pack_lookup()?;
+
+    let midx_ids = lookup
+        .midxes
+        .iter()
+        .flat_map(|midx| (0..midx.count).map(|index| midx.id(index)));
+
+    let pack_ids = lookup
+        .packs
+        .iter()
+        .flat_map(|pack| pack.hashes.iter().map(|id| id.as_slice()));
+
+    let mut matches = Vec::new();
+
+    for id in midx_ids.chain(pack_ids) {
+        let hash = to_hex_string(id);
+
+        if hash.starts_with(prefix) && !matches.contains(&hash) {
+            matches.push(hash);
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Reads the object starting at `offset`, applying deltas down to the base object.
+fn read_entry(file: &mut File, offset: u64, pack: &PackIndex) -> anyhow::Result<(u8, Vec<u8>)> {
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut header = [0u8; 32];
+
+    let read = file.read(&mut header)?;
+
+    let (kind, size, mut used) = parse_entry_header(&header[..read])?;
+
+    let base = match kind {
+        OBJ_OFS_DELTA => {
+            let (distance, length) = parse_ofs_distance(&header[used..read])?;
+
+            used += length;
+
+            Some(read_entry(file, ofs_base(offset, distance)?, pack)?)
+        }
+
+        OBJ_REF_DELTA => {
+            let base_id = ref_base(&header[used..read])?;
+
+            used += 20;
+
+            // Stored packs are never thin, so the base is always in the same pack
+            let Some(base_offset) = pack.find(base_id) else {
+                bail!("delta base {} is not in the pack", to_hex_string(base_id));
+            };
+
+            Some(read_entry(file, base_offset, pack)?)
+        }
+
+        _ => None,
+    };
+
+    let start = offset + used as u64;
+
+    let available = file.metadata()?.len().saturating_sub(start);
+
+    file.seek(SeekFrom::Start(start))?;
+
+    let decoder = flate2::read::ZlibDecoder::new(std::io::BufReader::new(&mut *file));
+
+    // The header's size is only a hint until the data bears it out
+    let mut data = Vec::with_capacity(size.min(available as usize));
+
+    decoder.take(size as u64 + 1).read_to_end(&mut data)?;
+
+    ensure!(data.len() == size, "pack entry size mismatch");
+
+    match base {
+        Some((base_kind, base_content)) => Ok((base_kind, apply_delta(&base_content, &data)?)),
+
+        None => Ok((kind, data)),
+    }
+}
+
+/// Type and inflated size of a pack entry, and the length of its header.
+fn parse_entry_header(bytes: &[u8]) -> anyhow::Result<(u8, usize, usize)> {
+    let Some(&first) = bytes.first() else {
+        bail!("truncated pack entry");
+    };
+
+    let kind = (first >> 4) & 0x7;
+
+    let mut size = (first & 0x0f) as usize;
+
+    let mut shift = 4;
+
+    let mut used = 1;
+
+    let mut byte = first;
+
+    while byte & 0x80 != 0 {
+        let Some(&next) = bytes.get(used) else {
+            bail!("truncated pack entry");
+        };
+
+        byte = next;
+
+        ensure!(shift < usize::BITS - 7, "pack entry size overflows");
+
+        size |= ((byte & 0x7f) as usize) << shift;
+
+        shift += 7;
+
+        used += 1;
+    }
+
+    Ok((kind, size, used))
+}
+
+/// Distance back to the base of an offset delta, and the bytes it took.
+fn parse_ofs_distance(bytes: &[u8]) -> anyhow::Result<(u64, usize)> {
+    let mut used = 0;
+
+    let mut byte = *bytes
+        .first()
+        .ok_or_else(|| anyhow!("truncated delta offset"))?;
+
+    let mut distance = (byte & 0x7f) as u64;
+
+    used += 1;
+
+    while byte & 0x80 != 0 {
+        byte = *bytes
+            .get(used)
+            .ok_or_else(|| anyhow!("truncated delta offset"))?;
+
+        ensure!(distance < 1 << 56, "delta offset overflows");
+
+        distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+
+        used += 1;
+    }
+
+    Ok((distance, used))
+}
+
+/// Where the base of an offset delta at `offset` starts, which must be before it.
+fn ofs_base(offset: u64, distance: u64) -> anyhow::Result<u64> {
+    offset
+        .checked_sub(distance)
+        .filter(|_| distance > 0)
+        .ok_or_else(|| anyhow!("delta base offset out of range at {offset}"))
+}
+
+/// The base id at the start of a ref delta's data.
+fn ref_base(bytes: &[u8]) -> anyhow::Result<&[u8]> {
+    bytes
+        .get(..20)
+        .ok_or_else(|| anyhow!("truncated delta base id"))
+}
+
+/// Rebuilds an object from its base and a delta of copy and insert instructions.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut position = 0;
+
+    let mut varint = || {
+        let mut value = 0usize;
+
+        let mut shift = 0;
+
+        loop {
+            let byte = *delta
+                .get(position)
+                .ok_or_else(|| anyhow!("truncated delta"))?;
+
+            position += 1;
+
+            ensure!(shift < usize::BITS - 7, "delta size overflows");
+
+            value |= ((byte & 0x7f) as usize) << shift;
+
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                return anyhow::Ok(value);
+            }
+        }
+    };
+
+    let base_size = varint()?;
+
+    let result_size = varint()?;
+
+    ensure!(base_size == base.len(), "delta base size mismatch");
+
+    // Copies can repeat the base, but most deltas rebuild something about its size
+    let mut result = Vec::with_capacity(result_size.min(base.len() + delta.len()));
+
+    while position < delta.len() {
+        let op = delta[position];
+
+        position += 1;
+
+        if op & 0x80 != 0 {
+            let mut offset = 0usize;
+
+            let mut size = 0usize;
+
+            for bit in 0..4 {
+                if op & (1 << bit) != 0 {
+                    offset |= (*delta
+                        .get(position)
+                        .ok_or_else(|| anyhow!("truncated delta"))?
+                        as usize)
+                        << (bit * 8);
+
+                    position += 1;
+                }
+            }
+
+            for bit in 0..3 {
+                if op & (0x10 << bit) != 0 {
+                    size |= (*delta
+                        .get(position)
+                        .ok_or_else(|| anyhow!("truncated delta"))?
+                        as usize)
+                        << (bit * 8);
+
+                    position += 1;
+                }
+            }
+
+            if size == 0 {
+                size = 0x10000;
+            }
+
+            let chunk = base
+                .get(offset..offset + size)
+                .ok_or_else(|| anyhow!("delta copies outside of its base"))?;
+
+            result.extend_from_slice(chunk);
+        } else if op != 0 {
+            let chunk = delta
+                .get(position..position + op as usize)
+                .ok_or_else(|| anyhow!("truncated delta"))?;
+
+            result.extend_from_slice(chunk);
+
+            position += op as usize;
+        } else {
+            bail!("invalid delta instruction");
+        }
+    }
+
+    ensure!(result.len() == result_size, "delta result size mismatch");
+
+    Ok(result)
+}
+
+/// An entry of a received pack, before its deltas are resolved.
+struct RawEntry {
+    offset: u64,
+
+    kind: u8,
+
+    data: Vec<u8>,
+
+    /// Offset of the base for offset deltas, hash of the base for ref deltas.
+    base_offset: Option<u64>,
+
+    base_hash: Option<String>,
+
+    crc: u32,
+}
+
+/// Checks a pack received from a remote, resolves its deltas to learn every object's
+/// id, and stores it in `.git/objects/pack` along with a version 2 index. Bases of
+/// ref deltas may also come from the local object store; like `index-pack --fix-thin`,
+/// those are appended to the stored pack. Returns the pack's checksum.
+pub fn index_pack(data: &[u8]) -> anyhow::Result<String> {
+    index_pack_into(data, &pack_dir())
+}
+
+/// [`index_pack`] into another pack directory, such as a push's quarantine.
+pub fn index_pack_into(data: &[u8], directory: &str) -> anyhow::Result<String> {
+    ensure!(
+        data.len() >= 32 && &data[..4] == b"PACK",
+        "protocol error: bad pack header"
+    );
+
+    let version = be_u32(&data[4..8]);
+
+    ensure!(
+        version == 2 || version == 3,
+        "unsupported pack version {version}"
+    );
+
+    let count = be_u32(&data[8..12]) as usize;
+
+    let (body, trailer) = data.split_at(data.len() - 20);
+
+    ensure!(
+        Sha1::digest(body).as_slice() == trailer,
+        "pack checksum mismatch"
+    );
+
+    // An empty pack, as sent by a push that only moves refs, leaves nothing to store
+    if count == 0 {
+        return Ok(to_hex_string(trailer));
+    }
+
+    // Every entry takes a few bytes, so the body bounds how many there can be
+    let mut entries = Vec::with_capacity(count.min(body.len()));
+
+    let mut position = 12;
+
+    for _ in 0..count {
+        let start = position;
+
+        let (kind, size, used) = parse_entry_header(&body[position..])?;
+
+        position += used;
+
+        let (mut base_offset, mut base_hash) = (None, None);
+
+        match kind {
+            OBJ_OFS_DELTA => {
+                let (distance, length) = parse_ofs_distance(&body[position..])?;
+
+                base_offset = Some(ofs_base(start as u64, distance)?);
+
+                position += length;
+            }
+
+            OBJ_REF_DELTA => {
+                base_hash = Some(to_hex_string(ref_base(&body[position..])?));
+
+                position += 20;
+            }
+
+            OBJ_COMMIT | OBJ_TREE | OBJ_BLOB | OBJ_TAG => {}
+
+            other => bail!("unknown object type {other} in pack"),
+        }
+
+        let (content, consumed) = inflate(&body[position..], size)?;
+
+        position += consumed;
+
+        let mut crc = Crc::new();
+
+        crc.update(&body[start..position]);
+
+        entries.push(RawEntry {
+            offset: start as u64,
+            kind,
+            data: content,
+            base_offset,
+            base_hash,
+            crc: crc.sum(),
+        });
+    }
+
+    ensure!(position == body.len(), "pack has trailing garbage");
+
+    // Resolved (type, content, hash) per entry, filled in as bases become available,
+    // followed by any bases taken from the local store
+    let mut resolved: Vec<Option<(u8, Vec<u8>, String)>> = vec![None; entries.len()];
+
+    let by_offset: HashMap<u64, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.offset, index))
+        .collect();
+
+    let mut by_hash: HashMap<String, usize> = HashMap::new();
+
+    loop {
+        let mut progress = false;
+
+        for index in 0..entries.len() {
+            if resolved[index].is_some() {
+                continue;
+            }
+
+            let entry = &entries[index];
+
+            let object = if let Some(base) = entry.base_offset {
+                let base_index = *by_offset
+                    .get(&base)
+                    .ok_or_else(|| anyhow!("delta base offset {base} is not in the pack"))?;
+
+                match &resolved[base_index] {
+                    Some((kind, content, _)) => Some((*kind, apply_delta(content, &entry.data)?)),
+
+                    None => None,
+                }
+            } else if let Some(base) = &entry.base_hash {
+                match by_hash.get(base) {
+                    Some(&base_index) => {
+                        let (kind, content, _) = resolved[base_index]
+                            .as_ref()
+                            .expect("indexed entries are resolved");
+
+                        Some((*kind, apply_delta(content, &entry.data)?))
+                    }
+
+                    None => None,
+                }
+            } else {
+                Some((entry.kind, entry.data.clone()))
+            };
+
+            if let Some((kind, content)) = object {
+                let hash = object_hash(kind, &content)?;
+
+                by_hash.insert(hash.clone(), index);
+
+                resolved[index] = Some((kind, content, hash));
+
+                progress = true;
+            }
+        }
+
+        if progress {
+            continue;
+        }
+
+        // Whatever is stuck must be a delta against an object we already have. A thin
+        // pack leaves those out, so they are appended after the received entries.
+        for index in 0..entries.len() {
+            let Some(base) = &entries[index].base_hash else {
+                continue;
+            };
+
+            if resolved[index].is_some() || by_hash.contains_key(base) {
+                continue;
+            }
+
+            if let Ok((kind, content)) = GitObject::read_raw(base) {
+                by_hash.insert(base.clone(), resolved.len());
+
+                resolved.push(Some((type_code(&kind)?, content, base.clone())));
+
+                progress = true;
+            }
+        }
+
+        if !progress {
+            break;
+        }
+    }
+
+    if let Some(entry) = (0..entries.len())
+        .filter(|&index| resolved[index].is_none())
+        .map(|index| &entries[index])
+        .next()
+    {
+        match &entry.base_hash {
+            Some(base) if !by_hash.contains_key(base) => bail!("missing delta base {base}"),
+
+            _ => bail!("could not resolve delta at offset {}", entry.offset),
+        }
+    }
+
+    let mut objects: Vec<([u8; 20], u32, u64)> = Vec::with_capacity(resolved.len());
+
+    for (entry, object) in entries.iter().zip(&resolved) {
+        let (_, _, hash) = object.as_ref().expect("every entry is resolved");
+
+        objects.push((raw_id(hash)?, entry.crc, entry.offset));
+    }
+
+    let mut completed = None;
+
+    if resolved.len() > entries.len() {
+        let mut pack = body.to_vec();
+
+        for object in &resolved[entries.len()..] {
+            let (kind, content, hash) = object.as_ref().expect("bases are resolved");
+
+            let offset = pack.len();
+
+            push_entry(&mut pack, *kind, content)?;
+
+            let mut crc = Crc::new();
+
+            crc.update(&pack[offset..]);
+
+            objects.push((raw_id(hash)?, crc.sum(), offset as u64));
+        }
+
+        pack[8..12].copy_from_slice(&(resolved.len() as u32).to_be_bytes());
+
+        let checksum = Sha1::digest(&pack);
+
+        pack.extend_from_slice(&checksum);
+
+        completed = Some(pack);
+    }
+
+    let data = completed.as_deref().unwrap_or(data);
+
+    let trailer = &data[data.len() - 20..];
+
+    let checksum = to_hex_string(trailer);
+
+    fs::create_dir_all(directory)?;
+
+    let base = format!("{directory}/pack-{checksum}");
+
+    write_to_file(&format!("{base}.pack"), data)?;
+
+    write_to_file(&format!("{base}.idx"), &build_index(objects, trailer))?;
+
+    reload_packs();
+
+    Ok(checksum)
+}
+
+/// Reads one pack from a stream that may go on after it, such as a push over a pipe,
+/// returning the pack's bytes. Its end can only be found by inflating every entry.
+pub fn read_pack_stream(input: &mut impl BufRead) -> anyhow::Result<Vec<u8>> {
+    let mut recorder = Recorder {
+        input,
+        data: Vec::new(),
+    };
+
+    let mut header = [0; 12];
+
+    recorder.read_exact(&mut header)?;
+
+    ensure!(&header[..4] == b"PACK", "protocol error: bad pack header");
+
+    for _ in 0..be_u32(&header[8..12]) {
+        let mut byte = [0; 1];
+
+        recorder.read_exact(&mut byte)?;
+
+        let kind = (byte[0] >> 4) & 7;
+
+        while byte[0] & 0x80 != 0 {
+            recorder.read_exact(&mut byte)?;
+        }
+
+        match kind {
+            OBJ_OFS_DELTA => loop {
+                recorder.read_exact(&mut byte)?;
+
+                if byte[0] & 0x80 == 0 {
+                    break;
+                }
+            },
+
+            OBJ_REF_DELTA => recorder.read_exact(&mut [0; 20])?,
+
+            _ => {}
+        }
+
+        // The decoder only consumes the compressed bytes it needs
+        std::io::copy(
+            &mut flate2::bufread::ZlibDecoder::new(&mut recorder),
+            &mut std::io::sink(),
+        )?;
+    }
+
+    recorder.read_exact(&mut [0; 20])?;
+
+    Ok(recorder.data)
+}
+
+/// Passes reads through while keeping a copy of every byte consumed.
+struct Recorder<'a, R> {
+    input: &'a mut R,
+
+    data: Vec<u8>,
+}
+
+impl<R: BufRead> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+
+        let amount = available.len().min(buf.len());
+
+        buf[..amount].copy_from_slice(&available[..amount]);
+
+        self.consume(amount);
+
+        Ok(amount)
+    }
+}
+
+impl<R: BufRead> BufRead for Recorder<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.input.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if let Ok(buffer) = self.input.fill_buf() {
+            self.data.extend_from_slice(&buffer[..amount]);
+        }
+
+        self.input.consume(amount);
+    }
+}
+
+/// Builds a version 2 pack holding the given objects, reusing what the stored packs
+/// have: entries are copied as they are, and deltas kept when their base is in the new
+/// pack too. Everything else is written whole.
+pub fn write_pack(hashes: &[String]) -> anyhow::Result<Vec<u8>> {
+    Ok(write_pack_counting(hashes)?.0)
+}
+
+/// [`write_pack`], also returning how many of the objects were copied from the stored
+/// packs, and how many of those as deltas.
+pub fn write_pack_counting(hashes: &[String]) -> anyhow::Result<(Vec<u8>, usize, usize)> {
+    let ids = hashes
+        .iter()
+        .map(|hash| raw_id(hash))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut writer = PackWriter {
+        pack: Vec::new(),
+        wanted: ids.iter().copied().collect(),
+        written: HashMap::new(),
+        pending: HashSet::new(),
+        sources: HashMap::new(),
+        reused: 0,
+        deltas: 0,
+    };
+
+    writer.pack.extend_from_slice(b"PACK");
+    writer.pack.extend_from_slice(&2u32.to_be_bytes());
+    writer
+        .pack
+        .extend_from_slice(&(writer.wanted.len() as u32).to_be_bytes());
+
+    for id in ids {
+        writer.add(id)?;
+    }
+
+    let checksum = Sha1::digest(&writer.pack);
+
+    writer.pack.extend_from_slice(&checksum);
+
+    Ok((writer.pack, writer.reused, writer.deltas))
+}
+
+/// A pack being written by [`write_pack`].
+struct PackWriter {
+    pack: Vec<u8>,
+
+    /// Every object going into the pack.
+    wanted: HashSet<[u8; 20]>,
+
+    /// Where each object written so far starts.
+    written: HashMap<[u8; 20], u64>,
+
+    /// Objects waiting on their delta base, which must not in turn wait on them.
+    pending: HashSet<[u8; 20]>,
+
+    /// Stored packs entries are copied from, by path.
+    sources: HashMap<String, SourcePack>,
+
+    reused: usize,
+
+    deltas: usize,
+}
+
+/// A stored pack and its entries in pack order, so that each one's end is known.
+struct SourcePack {
+    file: File,
+
+    entries: Vec<(u64, [u8; 20])>,
+
+    end: u64,
+}
+
+/// A stored entry to copy: type, size, delta base, and the compressed data.
+struct StoredEntry {
+    kind: u8,
+
+    size: usize,
+
+    base: Option<[u8; 20]>,
+
+    data: Vec<u8>,
+}
+
+impl PackWriter {
+    /// Appends an object, after its delta base when the entry copied is a delta.
+    fn add(&mut self, id: [u8; 20]) -> anyhow::Result<()> {
+        if self.written.contains_key(&id) {
+            return Ok(());
+        }
+
+        self.pending.insert(id);
+
+        let stored = self.stored_entry(&id)?;
+
+        self.pending.remove(&id);
+
+        let offset = self.pack.len() as u64;
+
+        match stored {
+            Some(StoredEntry {
+                size,
+                base: Some(base),
+                data,
+                ..
+            }) => {
+                let distance = offset - self.written[&base];
+
+                push_header(&mut self.pack, OBJ_OFS_DELTA, size);
+
+                // Seven bits at a time from the most significant, each continuation one
+                // less than it says
+                let mut encoded = vec![(distance & 0x7f) as u8];
+
+                let mut rest = distance >> 7;
+
+                while rest > 0 {
+                    rest -= 1;
+
+                    encoded.push(0x80 | (rest & 0x7f) as u8);
+
+                    rest >>= 7;
+                }
+
+                encoded.reverse();
+
+                self.pack.extend_from_slice(&encoded);
+                self.pack.extend_from_slice(&data);
+
+                self.reused += 1;
+                self.deltas += 1;
+            }
+
+            Some(StoredEntry {
+                kind, size, data, ..
+            }) => {
+                push_header(&mut self.pack, kind, size);
+
+                self.pack.extend_from_slice(&data);
+
+                self.reused += 1;
+            }
+
+            None => {
+                let (kind, content) = GitObject::read_raw(&to_hex_string(&id))?;
+
+                push_entry(&mut self.pack, type_code(&kind)?, &content)?;
+            }
+        }
+
+        self.written.insert(id, offset);
+
+        Ok(())
+    }
+
+    /// The stored entry of an object that can be copied, writing its delta base first.
+    /// `None` for a loose object, or a delta whose base is not going into the pack.
+    fn stored_entry(&mut self, id: &[u8; 20]) -> anyhow::Result<Option<StoredEntry>> {
+        let Some((pack, offset)) = find_packed(id)? else {
+            return Ok(None);
+        };
+
+        if !self.sources.contains_key(&pack.pack_path) {
+            let file = File::open(&pack.pack_path)
+                .with_context(|| format!("Could not open {}", pack.pack_path))?;
+
+            let mut entries: Vec<(u64, [u8; 20])> = pack
+                .offsets
+                .iter()
+                .copied()
+                .zip(pack.hashes.iter().copied())
+                .collect();
+
+            entries.sort();
+
+            let end = file.metadata()?.len().saturating_sub(20);
+
+            self.sources
+                .insert(pack.pack_path.clone(), SourcePack { file, entries, end });
+        }
+
+        let source = self
+            .sources
+            .get_mut(&pack.pack_path)
+            .ok_or_else(|| anyhow!("{} is not open", pack.pack_path))?;
+
+        let position = source
+            .entries
+            .partition_point(|(start, _)| *start <= offset);
+
+        let end = source
+            .entries
+            .get(position)
+            .map_or(source.end, |(start, _)| *start);
+
+        ensure!(end > offset, "pack entry out of range at {offset}");
+
+        let mut entry = vec![0; (end - offset) as usize];
+
+        source.file.seek(SeekFrom::Start(offset))?;
+        source.file.read_exact(&mut entry)?;
+
+        let (kind, size, mut used) = parse_entry_header(&entry)?;
+
+        let base = match kind {
+            OBJ_OFS_DELTA => {
+                let (distance, length) = parse_ofs_distance(&entry[used..])?;
+
+                used += length;
+
+                let base_offset = ofs_base(offset, distance)?;
+
+                let Ok(found) = source
+                    .entries
+                    .binary_search_by_key(&base_offset, |(start, _)| *start)
+                else {
+                    bail!("delta base offset out of range at {offset}");
+                };
+
+                Some(source.entries[found].1)
+            }
+
+            OBJ_REF_DELTA => {
+                let base = ref_base(&entry[used..])?.try_into()?;
+
+                used += 20;
+
+                Some(base)
+            }
+
+            _ => None,
+        };
+
+        // Copied data must hold exactly the entry, as it is not inflated again
+        ensure!(
+            inflate(&entry[used..], size)?.1 == entry.len() - used,
+            "corrupt pack entry at {offset} in {}",
+            pack.pack_path
+        );
+
+        if let Some(base) = base {
+            if !self.wanted.contains(&base) || self.pending.contains(&base) {
+                return Ok(None);
+            }
+
+            self.add(base)?;
+        }
+
+        Ok(Some(StoredEntry {
+            kind,
+            size,
+            base,
+            data: entry.split_off(used),
+        }))
+    }
+}
+
+/// Appends one whole object to a pack being written.
+fn push_entry(pack: &mut Vec<u8>, kind: u8, content: &[u8]) -> anyhow::Result<()> {
+    push_header(pack, kind, content.len());
+
+    pack.extend_from_slice(&compress(content)?);
+
+    Ok(())
+}
+
+/// Appends the type and inflated size starting an entry: the low four bits of the size
+/// first, then seven bits at a time.
+fn push_header(pack: &mut Vec<u8>, kind: u8, mut size: usize) {
+    let mut byte = (kind << 4) | (size & 0x0f) as u8;
+
+    size >>= 4;
+
+    while size > 0 {
+        pack.push(byte | 0x80);
+
+        byte = (size & 0x7f) as u8;
+
+        size >>= 7;
+    }
+
+    pack.push(byte);
+}
+
+/// Inflates one zlib stream from the start of `input`, returning the data and how many
+/// input bytes the stream took.
+fn inflate(input: &[u8], size: usize) -> anyhow::Result<(Vec<u8>, usize)> {
+    let mut decompress = Decompress::new(true);
+
+    // The header's size is only a hint until the data bears it out
+    let mut output = Vec::with_capacity(size.min(input.len()) + 1);
+
+    loop {
+        let consumed = decompress.total_in() as usize;
+
+        let produced = output.len();
+
+        let status = decompress
+            .decompress_vec(&input[consumed..], &mut output, FlushDecompress::None)
+            .map_err(|e| anyhow!("corrupt pack entry: {e}"))?;
+
+        match status {
+            Status::StreamEnd => break,
+
+            _ if output.len() > size => bail!("pack entry size mismatch"),
+
+            _ if output.len() == output.capacity() => output.reserve(4096),
+
+            // Once all input is taken, what it holds may still be coming out
+            _ if decompress.total_in() as usize == consumed && output.len() == produced => {
+                bail!("truncated pack entry")
+            }
+
+            _ => {}
+        }
+    }
+
+    ensure!(output.len() == size, "pack entry size mismatch");
+
+    Ok((output, decompress.total_in() as usize))
+}
+
+/// Builds a version 2 index for `(id, crc32, offset)` entries of a pack.
+pub fn build_index(mut objects: Vec<([u8; 20], u32, u64)>, pack_checksum: &[u8]) -> Vec<u8> {
+    objects.sort_by_key(|(id, _, _)| *id);
+
+    let mut content = Vec::new();
+
+    content.extend_from_slice(IDX_MAGIC);
+    content.extend_from_slice(&2u32.to_be_bytes());
+
+    for byte in 0..=255u8 {
+        let below = objects.partition_point(|(id, _, _)| id[0] <= byte);
+
+        content.extend_from_slice(&(below as u32).to_be_bytes());
+    }
+
+    for (id, _, _) in &objects {
+        content.extend_from_slice(id);
+    }
+
+    for (_, crc, _) in &objects {
+        content.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    let mut large = Vec::new();
+
+    for (_, _, offset) in &objects {
+        if *offset < 0x8000_0000 {
+            content.extend_from_slice(&(*offset as u32).to_be_bytes());
+        } else {
+            content.extend_from_slice(&(0x8000_0000 | large.len() as u32).to_be_bytes());
+
+            large.push(*offset);
+        }
+    }
+
+    for offset in large {
+        content.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    content.extend_from_slice(pack_checksum);
+
+    let checksum = Sha1::digest(&content);
+
+    content.extend_from_slice(&checksum);
+
+    content
+}
+
+/// Binary form of a full hex object id.
+fn object_id(hash: &str) -> Option<Vec<u8>> {
+    if hash.len() != 40 {
+        return None;
+    }
+
+    from_hex(hash).ok()
+}
+
+fn raw_id(hash: &str) -> anyhow::Result<[u8; 20]> {
+    from_hex(hash)?
+        .try_into()
+        .map_err(|_| anyhow!("bad hash {hash}"))
+}
+
+fn object_hash(kind: u8, content: &[u8]) -> anyhow::Result<String> {
+    let header = format!("{} {}\0", type_name(kind)?, content.len());
+
+    generate_object_id(&[header.as_bytes(), content].concat())
+}
+
+fn type_name(kind: u8) -> anyhow::Result<&'static str> {
+    match kind {
+        OBJ_COMMIT => Ok("commit"),
+
+        OBJ_TREE => Ok("tree"),
+
+        OBJ_BLOB => Ok("blob"),
+
+        OBJ_TAG => Ok("tag"),
+
+        other => bail!("unknown object type {other}"),
+    }
+}
+
+fn type_code(name: &str) -> anyhow::Result<u8> {
+    match name {
+        "commit" => Ok(OBJ_COMMIT),
+
+        "tree" => Ok(OBJ_TREE),
+
+        "blob" => Ok(OBJ_BLOB),
+
+        "tag" => Ok(OBJ_TAG),
+
+        other => bail!("unknown object type {other}"),
+    }
+}
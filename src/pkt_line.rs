@@ -0,0 +1,276 @@
+use std::io::Read;
+
+use anyhow::{anyhow, bail, ensure};
+
+/// One unit of git's pkt-line framing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+
+    /// `0000`, ends a message or list.
+    Flush,
+
+    /// `0001`, separates sections of a protocol v2 message.
+    Delim,
+
+    /// `0002`, ends a protocol v2 response in stateless connections.
+    ResponseEnd,
+}
+
+impl Packet {
+    /// Payload as text without the trailing newline, for line-oriented packets.
+    pub fn text(&self) -> Option<String> {
+        match self {
+            Packet::Data(data) => Some(
+                String::from_utf8_lossy(data)
+                    .trim_end_matches('\n')
+                    .to_string(),
+            ),
+
+            _ => None,
+        }
+    }
+}
+
+/// Builds a sequence of pkt-lines.
+#[derive(Debug, Default)]
+pub struct PacketWriter {
+    buffer: Vec<u8>,
+}
+
+impl PacketWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn data(&mut self, data: &[u8]) -> &mut Self {
+        self.buffer
+            .extend_from_slice(format!("{:04x}", data.len() + 4).as_bytes());
+
+        self.buffer.extend_from_slice(data);
+
+        self
+    }
+
+    /// Writes a line, adding the newline git expects at the end of text packets.
+    pub fn line(&mut self, line: &str) -> &mut Self {
+        self.data(format!("{line}\n").as_bytes())
+    }
+
+    pub fn flush(&mut self) -> &mut Self {
+        self.buffer.extend_from_slice(b"0000");
+
+        self
+    }
+
+    pub fn delim(&mut self) -> &mut Self {
+        self.buffer.extend_from_slice(b"0001");
+
+        self
+    }
+
+    /// Writes a packet read elsewhere back out unchanged.
+    pub fn packet(&mut self, packet: &Packet) -> &mut Self {
+        match packet {
+            Packet::Data(data) => self.data(data),
+
+            Packet::Flush => self.flush(),
+
+            Packet::Delim => self.delim(),
+
+            Packet::ResponseEnd => {
+                self.buffer.extend_from_slice(b"0002");
+
+                self
+            }
+        }
+    }
+
+    /// Splits `data` over as many packets as needed, each on sideband `band`.
+    pub fn sideband(&mut self, band: u8, data: &[u8]) -> &mut Self {
+        for chunk in data.chunks(MAX_PACKET_DATA - 1) {
+            self.data(&[&[band], chunk].concat());
+        }
+
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Largest payload a single packet can carry.
+pub const MAX_PACKET_DATA: usize = 65516;
+
+/// Reads one packet from a stream, or `None` when the stream ends before it starts.
+pub fn read_packet(input: &mut impl Read) -> anyhow::Result<Option<Packet>> {
+    let mut header = [0; 4];
+
+    let mut filled = 0;
+
+    while filled < header.len() {
+        match input.read(&mut header[filled..])? {
+            0 if filled == 0 => return Ok(None),
+
+            0 => bail!("protocol error: unexpected end of stream"),
+
+            read => filled += read,
+        }
+    }
+
+    let length = parse_length(&header)?;
+
+    if let Some(packet) = control_packet(length) {
+        return Ok(Some(packet));
+    }
+
+    let mut payload = vec![0; length - 4];
+
+    input.read_exact(&mut payload)?;
+
+    Ok(Some(Packet::Data(payload)))
+}
+
+/// Length of a packet from its four hex digit header.
+fn parse_length(header: &[u8]) -> anyhow::Result<usize> {
+    let length = std::str::from_utf8(header)
+        .ok()
+        .and_then(|header| usize::from_str_radix(header, 16).ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "protocol error: bad line length character: {}",
+                String::from_utf8_lossy(header)
+            )
+        })?;
+
+    ensure!(length != 3, "protocol error: bad line length {length}");
+
+    Ok(length)
+}
+
+/// The packet a length too short for any payload stands for.
+fn control_packet(length: usize) -> Option<Packet> {
+    match length {
+        0 => Some(Packet::Flush),
+
+        1 => Some(Packet::Delim),
+
+        2 => Some(Packet::ResponseEnd),
+
+        _ => None,
+    }
+}
+
+/// Reads pkt-lines from a buffer received in full.
+#[derive(Debug)]
+pub struct PacketReader<'a> {
+    data: &'a [u8],
+
+    position: usize,
+}
+
+impl<'a> PacketReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    /// Bytes that have not been read as packets yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+
+    pub fn next_packet(&mut self) -> anyhow::Result<Packet> {
+        let header = self
+            .data
+            .get(self.position..self.position + 4)
+            .ok_or_else(|| anyhow!("protocol error: unexpected end of stream"))?;
+
+        let length = parse_length(header)?;
+
+        self.position += 4;
+
+        if let Some(packet) = control_packet(length) {
+            return Ok(packet);
+        }
+
+        let payload = self
+            .data
+            .get(self.position..self.position + length - 4)
+            .ok_or_else(|| anyhow!("protocol error: unexpected end of stream"))?;
+
+        self.position += length - 4;
+
+        Ok(Packet::Data(payload.to_vec()))
+    }
+
+    /// Text lines up to the next flush, delim or response-end, which is returned too.
+    pub fn read_lines(&mut self) -> anyhow::Result<(Vec<String>, Packet)> {
+        let mut lines = Vec::new();
+
+        loop {
+            let packet = self.next_packet()?;
+
+            match packet.text() {
+                Some(line) => lines.push(line),
+
+                None => return Ok((lines, packet)),
+            }
+        }
+    }
+
+    /// Collects sideband data up to a flush: band 1 is returned, band 2 progress goes to
+    /// stderr and band 3 is a fatal error from the remote.
+    pub fn read_sideband(&mut self, progress: bool) -> anyhow::Result<Vec<u8>> {
+        let mut data = Vec::new();
+
+        // Progress messages are split across packets anywhere, so `remote: ` goes at the
+        // start of each line (or `\r`-rewritten line) rather than each packet
+        let mut line_start = true;
+
+        while !self.is_empty() {
+            let packet = self.next_packet()?;
+
+            let Packet::Data(payload) = packet else {
+                break;
+            };
+
+            ensure!(!payload.is_empty(), "protocol error: empty sideband packet");
+
+            match payload[0] {
+                1 => data.extend_from_slice(&payload[1..]),
+
+                2 if progress => {
+                    let mut message = String::new();
+
+                    for c in String::from_utf8_lossy(&payload[1..]).chars() {
+                        if line_start {
+                            message.push_str("remote: ");
+                        }
+
+                        message.push(c);
+
+                        line_start = c == '\n' || c == '\r';
+                    }
+
+                    eprint!("{message}");
+                }
+
+                2 => {}
+
+                3 => bail!(
+                    "remote error: {}",
+                    String::from_utf8_lossy(&payload[1..]).trim_end()
+                ),
+
+                band => bail!("protocol error: bad band #{band}"),
+            }
+        }
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::ensure;
+
+use crate::config::{set_local, Config};
+use crate::git_objects::{GitObject, TreeFileModes};
+use crate::remote::{open_transport, remote_config};
+use crate::transport::{Connection, FetchOptions};
+use crate::utils::object_exists;
+
+/// Set while objects are being fetched, so that a miss then fails instead of fetching
+/// again.
+static FETCHING: AtomicBool = AtomicBool::new(false);
+
+/// The remote a partial clone gets the objects it left out from: the one marked as a
+/// promisor, or the one older versions named in `extensions.partialclone`.
+pub fn promisor_remote() -> Option<String> {
+    let config = Config::load().ok()?;
+
+    if let Some(remote) = config.get("extensions.partialclone") {
+        return Some(remote.to_string());
+    }
+
+    config
+        .subsections("remote")
+        .into_iter()
+        .find(|remote| config.get(&format!("remote.{remote}.promisor")) == Some("true"))
+        .map(str::to_string)
+}
+
+/// Makes `remote` the promisor remote of a partial clone that leaves out what `filter`
+/// does.
+pub fn register(remote: &str, filter: &str) -> anyhow::Result<()> {
+    set_local("core.repositoryformatversion", "1")?;
+    set_local(&format!("remote.{remote}.promisor"), "true")?;
+    set_local(&format!("remote.{remote}.partialclonefilter"), filter)
+}
+
+/// Fetches objects a partial clone is missing from its promisor remote, returning
+/// whether there was a remote to ask.
+pub fn fetch_missing(hashes: &[String]) -> anyhow::Result<bool> {
+    let Some(name) = promisor_remote() else {
+        return Ok(false);
+    };
+
+    if hashes.is_empty() || FETCHING.swap(true, Ordering::SeqCst) {
+        return Ok(false);
+    }
+
+    let result = fetch_from(&name, hashes);
+
+    FETCHING.store(false, Ordering::SeqCst);
+
+    result?;
+
+    for hash in hashes {
+        ensure!(
+            object_exists(hash),
+            "could not fetch {hash} from promisor remote"
+        );
+    }
+
+    Ok(true)
+}
+
+fn fetch_from(name: &str, hashes: &[String]) -> anyhow::Result<()> {
+    let url = remote_config(name)?.url().to_string();
+
+    let mut connection = Connection::open(open_transport(&url)?)?;
+
+    // Trees come without their blobs, which are fetched once they are needed
+    let options = FetchOptions {
+        filter: Some("blob:none".to_string()),
+        ..FetchOptions::default()
+    };
+
+    connection
+        .fetch_pack(hashes, &[], &options)?
+        .store(Some(&[]))
+}
+
+/// Blobs under `tree` that are not in the repository, such as those a checkout is about
+/// to read and can fetch in one go. Missing trees are fetched on the way.
+pub fn missing_blobs(tree: &str) -> anyhow::Result<Vec<String>> {
+    let mut missing = Vec::new();
+
+    let mut trees = vec![tree.to_string()];
+
+    while let Some(tree) = trees.pop() {
+        let (_, content) = GitObject::read_raw(&tree)?;
+
+        for (mode, _, hash) in GitObject::parse_tree_entries(&content)? {
+            match mode {
+                TreeFileModes::Directory => trees.push(hash),
+
+                // A submodule's commit is never in this repository to begin with
+                TreeFileModes::Gitlink => {}
+
+                _ if !object_exists(&hash) && !missing.contains(&hash) => missing.push(hash),
+
+                _ => {}
+            }
+        }
+    }
+
+    Ok(missing)
+}
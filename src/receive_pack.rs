@@ -0,0 +1,494 @@
+use std::fs;
+use std::io::{BufRead, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, ensure};
+
+use crate::config::Config;
+use crate::pack::{index_pack_into, pack_dir, read_pack_stream, reload_packs};
+use crate::pkt_line::{read_packet, Packet, PacketReader, PacketWriter};
+use crate::refs::{
+    check_ref_format, delete_ref, git_dir, git_path, head_target, is_bare, list_refs, resolve_ref,
+    update_ref,
+};
+use crate::revision::{is_fast_forward, CommitWalker};
+use crate::transport::{RefUpdate, AGENT, ZERO_HASH};
+use crate::utils::{is_object_id, object_exists};
+
+const CAPABILITIES: &str =
+    "report-status delete-refs side-band-64k atomic ofs-delta push-options object-format=sha1";
+
+/// Version 0 ref advertisement of the current repository, the only version receive-pack
+/// speaks. An empty repository still needs a line to carry the capabilities.
+pub fn advertisement() -> anyhow::Result<Vec<u8>> {
+    let capabilities = format!("{CAPABILITIES} {AGENT}");
+
+    let refs = list_refs("refs/")?;
+
+    let mut response = PacketWriter::new();
+
+    if refs.is_empty() {
+        response.line(&format!("{ZERO_HASH} capabilities^{{}}\0{capabilities}"));
+    }
+
+    for (index, (name, hash)) in refs.iter().enumerate() {
+        match index {
+            0 => response.line(&format!("{hash} {name}\0{capabilities}")),
+
+            _ => response.line(&format!("{hash} {name}")),
+        };
+    }
+
+    response.flush();
+
+    Ok(response.into_bytes())
+}
+
+/// Runs receive-pack for the current repository over a connection such as a pipe: the
+/// advertisement, then one push. Stateless mode leaves the advertisement to its own
+/// request, as behind an HTTP server.
+pub fn serve(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    stateless: bool,
+    advertise_only: bool,
+) -> anyhow::Result<()> {
+    if advertise_only || !stateless {
+        output.write_all(&advertisement()?)?;
+        output.flush()?;
+    }
+
+    if advertise_only {
+        return Ok(());
+    }
+
+    let mut request = PacketWriter::new();
+
+    let mut lines = Vec::new();
+
+    loop {
+        let Some(packet) = read_packet(input)? else {
+            ensure!(lines.is_empty(), "protocol error: unexpected end of stream");
+
+            return Ok(());
+        };
+
+        request.packet(&packet);
+
+        match packet.text() {
+            Some(line) => lines.push(line),
+
+            None => break,
+        }
+    }
+
+    // A client with nothing to push just flushes
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let (updates, capabilities) = parse_commands(&lines)?;
+
+    if capabilities.iter().any(|c| c == "push-options") {
+        while let Some(packet) = read_packet(input)? {
+            request.packet(&packet);
+
+            if packet == Packet::Flush {
+                break;
+            }
+        }
+    }
+
+    let mut request = request.into_bytes();
+
+    if updates.iter().any(|update| update.new != ZERO_HASH) {
+        request.extend(read_pack_stream(input)?);
+    }
+
+    output.write_all(&handle_request(&request)?)?;
+
+    Ok(output.flush()?)
+}
+
+/// Stores the pack of a push request and applies its ref updates to the current
+/// repository, answering with a status report. The pack waits in a quarantine directory
+/// until the `pre-receive` hook accepts it, and each ref goes through the `update` hook.
+pub fn handle_request(request: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut reader = PacketReader::new(request);
+
+    let (lines, _) = reader.read_lines()?;
+
+    let (updates, capabilities) = parse_commands(&lines)?;
+
+    let requested = |name: &str| capabilities.iter().any(|c| c == name);
+
+    let mut hooks = Hooks {
+        push_options: None,
+        progress: requested("side-band-64k").then(PacketWriter::new),
+    };
+
+    if requested("push-options") {
+        hooks.push_options = Some(reader.read_lines()?.0);
+    }
+
+    let pack = (!updates.iter().all(|update| update.new == ZERO_HASH)).then(|| reader.remaining());
+
+    let quarantine = pack.map(|_| {
+        format!(
+            "{}/tmp_objdir-incoming-{}",
+            git_path("objects"),
+            std::process::id()
+        )
+    });
+
+    let unpack = match (pack, &quarantine) {
+        (Some(pack), Some(quarantine)) => fs::create_dir_all(format!("{quarantine}/pack"))
+            .map_err(anyhow::Error::from)
+            .and_then(|_| index_pack_into(pack, &format!("{quarantine}/pack")))
+            .map(|_| ()),
+
+        _ => Ok(()),
+    };
+
+    let mut errors: Vec<Option<String>> = match &unpack {
+        Ok(()) => with_quarantine(quarantine.as_deref(), || {
+            let mut errors: Vec<Option<String>> = updates
+                .iter()
+                .map(check_update)
+                .collect::<anyhow::Result<_>>()?;
+
+            let accepted = command_lines(&updates, &errors);
+
+            if !accepted.is_empty() && !hooks.run("pre-receive", &[], &accepted)? {
+                for error in errors.iter_mut().filter(|error| error.is_none()) {
+                    *error = Some("pre-receive hook declined".to_string());
+                }
+            }
+
+            Ok(errors)
+        })?,
+
+        Err(_) => vec![Some("unpacker error".to_string()); updates.len()],
+    };
+
+    if let Some(quarantine) = &quarantine {
+        if errors.iter().any(Option::is_none) {
+            migrate_objects(quarantine)?;
+        }
+
+        if Path::new(quarantine).exists() {
+            fs::remove_dir_all(quarantine)?;
+        }
+
+        reload_packs();
+    }
+
+    for (update, error) in updates.iter().zip(errors.iter_mut()) {
+        if error.is_none() && !hooks.run("update", &[&update.name, &update.old, &update.new], "")? {
+            *error = Some("hook declined".to_string());
+        }
+    }
+
+    let failed = errors.iter().any(Option::is_some);
+
+    if failed && requested("atomic") {
+        for error in errors.iter_mut().filter(|error| error.is_none()) {
+            *error = Some("atomic push failure".to_string());
+        }
+    }
+
+    for (update, error) in updates.iter().zip(&errors) {
+        if error.is_some() {
+            continue;
+        }
+
+        if update.new == ZERO_HASH {
+            delete_ref(&update.name)?;
+        } else {
+            update_ref(&update.name, &update.new, "push")?;
+        }
+    }
+
+    let mut report = PacketWriter::new();
+
+    match &unpack {
+        Ok(()) => report.line("unpack ok"),
+
+        Err(error) => report.line(&format!("unpack {error}")),
+    };
+
+    for (update, error) in updates.iter().zip(&errors) {
+        match error {
+            Some(error) => report.line(&format!("ng {} {error}", update.name)),
+
+            None => report.line(&format!("ok {}", update.name)),
+        };
+    }
+
+    report.flush();
+
+    let report = report.into_bytes();
+
+    if let Some(progress) = hooks.progress.as_mut() {
+        progress.sideband(1, &report);
+    }
+
+    let applied = command_lines(&updates, &errors);
+
+    if !applied.is_empty() {
+        hooks.run("post-receive", &[], &applied)?;
+    }
+
+    match hooks.progress {
+        Some(mut response) => {
+            response.flush();
+
+            Ok(response.into_bytes())
+        }
+
+        None => Ok(report),
+    }
+}
+
+/// Ref updates of a push request, with the capabilities following the first one.
+fn parse_commands(lines: &[String]) -> anyhow::Result<(Vec<RefUpdate>, Vec<String>)> {
+    let mut capabilities = Vec::new();
+
+    let mut updates = Vec::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        let line = match line.split_once('\0') {
+            Some((line, caps)) if index == 0 => {
+                capabilities = caps.split(' ').map(str::to_string).collect();
+
+                line
+            }
+
+            _ => line.as_str(),
+        };
+
+        let [old, new, name] = line.split(' ').collect::<Vec<_>>()[..] else {
+            bail!("protocol error: expected old/new/ref, got '{line}'");
+        };
+
+        ensure!(
+            is_object_id(old) && is_object_id(new),
+            "protocol error: expected old/new/ref, got '{line}'"
+        );
+
+        updates.push(RefUpdate {
+            name: name.to_string(),
+            old: old.to_string(),
+            new: new.to_string(),
+        });
+    }
+
+    Ok((updates, capabilities))
+}
+
+/// `<old> <new> <ref>` lines of the updates without an error, as hooks read them.
+fn command_lines(updates: &[RefUpdate], errors: &[Option<String>]) -> String {
+    updates
+        .iter()
+        .zip(errors)
+        .filter(|(_, error)| error.is_none())
+        .map(|(update, _)| format!("{} {} {}\n", update.old, update.new, update.name))
+        .collect()
+}
+
+/// Runs `f` with the objects of `quarantine` visible next to the repository's own, the
+/// way hooks see them too.
+fn with_quarantine<T>(
+    quarantine: Option<&str>,
+    f: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let Some(quarantine) = quarantine else {
+        return f();
+    };
+
+    let quarantine = fs::canonicalize(quarantine)?;
+
+    let objects = fs::canonicalize(git_path("objects"))?;
+
+    let variables = [
+        ("GIT_QUARANTINE_PATH", quarantine.as_os_str()),
+        ("GIT_OBJECT_DIRECTORY", quarantine.as_os_str()),
+        ("GIT_ALTERNATE_OBJECT_DIRECTORIES", objects.as_os_str()),
+    ];
+
+    let saved: Vec<_> = variables
+        .iter()
+        .map(|(name, _)| (*name, std::env::var_os(name)))
+        .collect();
+
+    for (name, value) in variables {
+        std::env::set_var(name, value);
+    }
+
+    reload_packs();
+
+    let result = f();
+
+    for (name, value) in saved {
+        match value {
+            Some(value) => std::env::set_var(name, value),
+
+            None => std::env::remove_var(name),
+        }
+    }
+
+    reload_packs();
+
+    result
+}
+
+/// Moves the packs of an accepted push out of quarantine, each index after its pack so
+/// that readers never find an index without one.
+fn migrate_objects(quarantine: &str) -> anyhow::Result<()> {
+    let mut files: Vec<_> = fs::read_dir(format!("{quarantine}/pack"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+
+    files.sort_by_key(|path| path.extension().is_some_and(|extension| extension == "idx"));
+
+    fs::create_dir_all(pack_dir())?;
+
+    for file in files {
+        if let Some(name) = file.file_name() {
+            fs::rename(&file, Path::new(&pack_dir()).join(name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the repository's receive hooks. What they print goes to the client on the
+/// progress sideband when it has one, and to stderr otherwise.
+struct Hooks {
+    /// Push options the client sent, when it negotiated them.
+    push_options: Option<Vec<String>>,
+
+    progress: Option<PacketWriter>,
+}
+
+impl Hooks {
+    /// Runs hook `name`, returning whether it succeeded; a missing hook always does.
+    fn run(&mut self, name: &str, args: &[&str], input: &str) -> anyhow::Result<bool> {
+        let path = git_path(&format!("hooks/{name}"));
+
+        let executable = fs::metadata(&path)
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false);
+
+        if !executable {
+            return Ok(true);
+        }
+
+        let git_dir = fs::canonicalize(git_dir())?;
+
+        // Hooks of a repository with a working tree run at its top
+        let directory = match is_bare()? {
+            true => git_dir.clone(),
+
+            false => git_dir.parent().unwrap_or(&git_dir).to_path_buf(),
+        };
+
+        let mut command = Command::new("sh");
+
+        command
+            .arg("-c")
+            .arg("exec \"$0\" \"$@\" 2>&1")
+            .arg(fs::canonicalize(&path)?)
+            .args(args)
+            .current_dir(directory)
+            .env("GIT_DIR", &git_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+
+        if let Some(options) = &self.push_options {
+            command.env("GIT_PUSH_OPTION_COUNT", options.len().to_string());
+
+            for (index, option) in options.iter().enumerate() {
+                command.env(format!("GIT_PUSH_OPTION_{index}"), option);
+            }
+        }
+
+        let mut child = command.spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook is free to ignore its input and exit early
+            let _ = stdin.write_all(input.as_bytes());
+        }
+
+        let output = child.wait_with_output()?;
+
+        match self.progress.as_mut() {
+            Some(progress) => {
+                progress.sideband(2, &output.stdout);
+            }
+
+            None => std::io::stderr().write_all(&output.stdout)?,
+        }
+
+        Ok(output.status.success())
+    }
+}
+
+/// Why an update may not be applied, if it may not.
+fn check_update(update: &RefUpdate) -> anyhow::Result<Option<String>> {
+    let refuse = |reason: &str| Ok(Some(reason.to_string()));
+
+    if !update.name.starts_with("refs/") || !check_ref_format(&update.name, false) {
+        return refuse("funny refname");
+    }
+
+    let deleting = update.new == ZERO_HASH;
+
+    if !is_bare()? && head_target()?.as_deref() == Some(update.name.as_str()) {
+        if deleting {
+            return refuse("deletion of the current branch prohibited");
+        }
+
+        return refuse("branch is currently checked out");
+    }
+
+    if !deleting && !is_connected(&update.new)? {
+        return refuse("missing necessary objects");
+    }
+
+    let current = resolve_ref(&update.name)?;
+
+    if current.as_deref().unwrap_or(ZERO_HASH) != update.old {
+        return refuse("failed to lock");
+    }
+
+    if Config::load()?.get("receive.denynonfastforwards") == Some("true")
+        && update.old != ZERO_HASH
+        && !deleting
+        && !is_fast_forward(&update.old, &update.new)?
+    {
+        return refuse("non-fast-forward");
+    }
+
+    Ok(None)
+}
+
+/// Whether every object `new` reaches is here. What the existing refs reach is taken to
+/// be complete, so only the history the push adds is walked.
+fn is_connected(new: &str) -> anyhow::Result<bool> {
+    let existing: Vec<String> = list_refs("refs/")?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+
+    let objects = match CommitWalker::new().objects_between(&existing, &[new.to_string()]) {
+        Ok(objects) => objects,
+
+        // Reading a commit, tag or tree that is not here
+        Err(_) => return Ok(false),
+    };
+
+    Ok(objects.iter().all(|hash| object_exists(hash)))
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::diff::NULL_HASH;
+use crate::git_objects::Signature;
+use crate::refs::git_path;
+use crate::utils::write_to_file;
+
+/// One line of `.git/logs/<ref>`: a ref moving from `old` to `new`.
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+
+    pub new: String,
+
+    pub identity: Signature,
+
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// Parses `<old> <new> <name> <<email>> <timestamp> <tz>\t<message>`.
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+
+        let mut parts = head.splitn(3, ' ');
+
+        let (Some(old), Some(new), Some(identity)) = (parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("Invalid reflog entry: {line}");
+        };
+
+        Ok(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            identity: Signature::parse(identity)?,
+            message: message.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for ReflogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}\t{}",
+            self.old, self.new, self.identity, self.message
+        )
+    }
+}
+
+fn reflog_path(name: &str) -> String {
+    git_path(&format!("logs/{name}"))
+}
+
+pub fn reflog_exists(name: &str) -> bool {
+    Path::new(&reflog_path(name)).is_file()
+}
+
+/// Whether updates to a ref are logged: `HEAD`, `refs/stash` (whose log is the stash
+/// stack), branches, remote-tracking refs and notes always are (as with
+/// `core.logAllRefUpdates`), other refs once they have a log.
+pub fn should_log(name: &str) -> bool {
+    name == "HEAD"
+        || name == "refs/stash"
+        || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        || reflog_exists(name)
+}
+
+/// Entries of a ref's reflog, oldest first; empty when it has none.
+pub fn read_reflog(name: &str) -> anyhow::Result<Vec<ReflogEntry>> {
+    let Ok(content) = fs::read(reflog_path(name)) else {
+        return Ok(Vec::new());
+    };
+
+    String::from_utf8_lossy(&content)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(ReflogEntry::parse)
+        .collect()
+}
+
+/// Records a ref moving from `old` (`None` when it is created) to `new`.
+pub fn append_reflog(
+    name: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let entry = ReflogEntry {
+        old: old.unwrap_or(NULL_HASH).to_string(),
+        new: new.to_string(),
+        identity: Signature::current_or_default("COMMITTER")?,
+        // Messages are kept on one line
+        message: message.lines().collect::<Vec<_>>().join(" "),
+    };
+
+    let path = reflog_path(name);
+
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Could not create {parent:?}"))?;
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Could not open {path}"))?;
+
+    writeln!(file, "{entry}")?;
+
+    Ok(())
+}
+
+/// Replaces a ref's reflog with the given entries.
+pub fn write_reflog(name: &str, entries: &[ReflogEntry]) -> anyhow::Result<()> {
+    let content: String = entries.iter().map(|entry| format!("{entry}\n")).collect();
+
+    write_to_file(&reflog_path(name), content.as_bytes())
+}
+
+/// Names of every ref that has a reflog.
+pub fn list_reflogs() -> anyhow::Result<Vec<String>> {
+    let root = reflog_path("");
+
+    let mut names = Vec::new();
+
+    let mut directories = vec![root.clone()];
+
+    while let Some(directory) = directories.pop() {
+        let Ok(entries) = fs::read_dir(&directory) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                directories.push(path.to_string_lossy().to_string());
+            } else {
+                names.push(path.to_string_lossy()[root.len()..].to_string());
+            }
+        }
+    }
+
+    names.sort();
+
+    Ok(names)
+}
@@ -0,0 +1,818 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::{bail, Context};
+
+use crate::commit_graph::reload_commit_graph;
+use crate::config::Config;
+use crate::git_objects::GitObject;
+use crate::pack;
+use crate::promisor::promisor_remote;
+use crate::reflog::{append_reflog, read_reflog, should_log};
+use crate::revision::CommitWalker;
+use crate::utils::{
+    current_timestamp, object_directories, object_exists, parse_date, read_file, write_to_file,
+};
+
+pub const HEAD: &str = "HEAD";
+
+/// Repository directory in use, once discovered or chosen.
+static GIT_DIR: RwLock<Option<String>> = RwLock::new(None);
+
+/// The repository directory: `$GIT_DIR` when set, `.git` in a working tree, or the
+/// current directory itself when it is a bare repository.
+pub fn git_dir() -> String {
+    if let Some(dir) = GIT_DIR.read().ok().and_then(|dir| dir.clone()) {
+        return dir;
+    }
+
+    let dir = match std::env::var("GIT_DIR") {
+        Ok(dir) if !dir.is_empty() => dir,
+
+        _ if !Path::new(".git").exists() && is_repository(Path::new(".")) => ".".to_string(),
+
+        _ => ".git".to_string(),
+    };
+
+    set_git_dir(Some(&dir));
+
+    dir
+}
+
+/// Whether the repository has no working tree, by `core.bare` or else its directory.
+pub fn is_bare() -> anyhow::Result<bool> {
+    match Config::load()?.get("core.bare") {
+        Some(value) => Ok(value == "true"),
+
+        // A repository directory named `.git` has a working tree around it
+        None => Ok(fs::canonicalize(git_dir())?.file_name() != Some(".git".as_ref())),
+    }
+}
+
+/// Switches to another repository directory, or back to discovering it with `None`.
+pub fn set_git_dir(dir: Option<&str>) {
+    if let Ok(mut current) = GIT_DIR.write() {
+        *current = dir.map(str::to_string);
+    }
+}
+
+/// Runs `f` with the repository directory `path` as the current repository (and working
+/// directory), then switches back.
+pub fn in_repository<T>(path: &Path, f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let working_directory = std::env::current_dir()?;
+
+    let current = git_dir();
+
+    std::env::set_current_dir(path)
+        .with_context(|| format!("Could not enter {}", path.display()))?;
+
+    set_git_dir(Some("."));
+    pack::reload_packs();
+    reload_commit_graph();
+
+    let result = f();
+
+    std::env::set_current_dir(&working_directory)?;
+
+    set_git_dir(Some(&current));
+    pack::reload_packs();
+    reload_commit_graph();
+
+    result
+}
+
+/// Path of a file inside the repository directory.
+pub fn git_path(path: &str) -> String {
+    format!("{}/{path}", git_dir())
+}
+
+/// Whether `path` is a repository directory, i.e. holds `HEAD`, `objects` and `refs`.
+pub fn is_repository(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+/// Finds the repository directory at `path`, which may be a working tree or a bare
+/// repository and may leave out a `.git` suffix, as an absolute path.
+pub fn find_repository(path: &str) -> anyhow::Result<PathBuf> {
+    for candidate in [path.to_string(), format!("{path}.git")] {
+        let candidate = Path::new(&candidate);
+
+        for git_dir in [candidate.join(".git"), candidate.to_path_buf()] {
+            if is_repository(&git_dir) {
+                return Ok(git_dir.canonicalize()?);
+            }
+        }
+    }
+
+    bail!("'{path}' does not appear to be a git repository")
+}
+
+/// Commits this repository has without their parents, as listed in `shallow`.
+pub fn shallow_commits() -> HashSet<String> {
+    fs::read_to_string(git_path("shallow"))
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Records the shallow commits, removing `shallow` once history is complete.
+pub fn write_shallow_commits(commits: &HashSet<String>) -> anyhow::Result<()> {
+    let path = git_path("shallow");
+
+    if commits.is_empty() {
+        if Path::new(&path).exists() {
+            fs::remove_file(&path)?;
+        }
+
+        return Ok(());
+    }
+
+    let mut commits: Vec<&String> = commits.iter().collect();
+
+    commits.sort();
+
+    let content: String = commits.iter().map(|commit| format!("{commit}\n")).collect();
+
+    write_to_file(&path, content.as_bytes())
+}
+
+/// Reads a ref from its loose file or `packed-refs`, without following symbolic refs.
+/// Symbolic refs come back as `ref: <target>`.
+pub fn read_ref(name: &str) -> anyhow::Result<Option<String>> {
+    if let Ok(content) = read_file(&git_path(name)) {
+        return Ok(Some(String::from_utf8_lossy(&content).trim().to_string()));
+    }
+
+    Ok(read_packed_refs()?
+        .into_iter()
+        .find(|(_, ref_name)| ref_name == name)
+        .map(|(hash, _)| hash))
+}
+
+/// `(hash, name)` pairs listed in `.git/packed-refs`.
+pub fn read_packed_refs() -> anyhow::Result<Vec<(String, String)>> {
+    let Ok(content) = read_file(&git_path("packed-refs")) else {
+        return Ok(Vec::new());
+    };
+
+    Ok(String::from_utf8_lossy(&content)
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| line.split_once(' '))
+        .map(|(hash, name)| (hash.to_string(), name.to_string()))
+        .collect())
+}
+
+/// Every ref under `prefix` (e.g. `refs/heads/`) with the hash it resolves to, sorted
+/// by name. Loose refs take precedence over packed ones.
+pub fn list_refs(prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut refs = std::collections::BTreeMap::new();
+
+    for (hash, name) in read_packed_refs()? {
+        if name.starts_with(prefix) {
+            refs.insert(name, hash);
+        }
+    }
+
+    let mut directories = vec![git_path("refs")];
+
+    while let Some(directory) = directories.pop() {
+        let Ok(entries) = fs::read_dir(&directory) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                directories.push(path.to_string_lossy().to_string());
+
+                continue;
+            }
+
+            let path = path.to_string_lossy().to_string();
+
+            let name = path[git_dir().len() + 1..].to_string();
+
+            if name.starts_with(prefix) && !name.ends_with(".lock") {
+                if let Some(hash) = resolve_ref(&name)? {
+                    refs.insert(name, hash);
+                }
+            }
+        }
+    }
+
+    Ok(refs.into_iter().collect())
+}
+
+/// Follows symbolic refs down to an object hash; `None` for a missing (or unborn) ref.
+pub fn resolve_ref(name: &str) -> anyhow::Result<Option<String>> {
+    let mut current = name.to_string();
+
+    for _ in 0..5 {
+        match read_ref(&current)? {
+            Some(value) => match value.strip_prefix("ref: ") {
+                Some(target) => current = target.to_string(),
+
+                None => return Ok(Some(value)),
+            },
+
+            None => return Ok(None),
+        }
+    }
+
+    bail!("Too many levels of symbolic refs for {name}")
+}
+
+/// Ref that `HEAD` points to, or `None` when `HEAD` is detached.
+pub fn head_target() -> anyhow::Result<Option<String>> {
+    Ok(read_ref(HEAD)?.and_then(|value| value.strip_prefix("ref: ").map(str::to_string)))
+}
+
+/// Full ref names a short name may refer to, in the order git tries them.
+pub fn ref_candidates(name: &str) -> Vec<String> {
+    vec![
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ]
+}
+
+/// Full name of the ref a short name refers to, if any.
+pub fn expand_ref(name: &str) -> anyhow::Result<Option<String>> {
+    for candidate in ref_candidates(name) {
+        if read_ref(&candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Points a ref (or the ref behind a symbolic ref such as `HEAD`) at a new object,
+/// recording `reason` in its reflog. A ref that doesn't exist yet is created.
+pub fn update_ref(name: &str, hash: &str, reason: &str) -> anyhow::Result<()> {
+    let mut current = name.to_string();
+
+    while let Some(target) =
+        read_ref(&current)?.and_then(|v| v.strip_prefix("ref: ").map(str::to_string))
+    {
+        current = target;
+    }
+
+    let old = resolve_ref(&current)?;
+
+    write_ref(&current, &format!("{hash}\n"))?;
+
+    log_ref_update(&current, old.as_deref(), hash, reason)?;
+
+    // Moving the checked out branch moves HEAD too
+    if current != HEAD && head_target()?.as_deref() == Some(current.as_str()) {
+        log_ref_update(HEAD, old.as_deref(), hash, reason)?;
+    }
+
+    Ok(())
+}
+
+/// Makes `name` a symbolic ref pointing at `target`, e.g. `HEAD` at `refs/heads/main`.
+pub fn write_symbolic_ref(name: &str, target: &str, reason: &str) -> anyhow::Result<()> {
+    let old = resolve_ref(name)?;
+
+    write_ref(name, &format!("ref: {target}\n"))?;
+
+    // Pointing at an unborn branch doesn't move to any commit, so there is nothing to log
+    match resolve_ref(target)? {
+        Some(new) => log_ref_update(name, old.as_deref(), &new, reason),
+
+        None => Ok(()),
+    }
+}
+
+/// Points `HEAD` straight at a commit instead of a branch.
+pub fn detach_head(hash: &str, reason: &str) -> anyhow::Result<()> {
+    let old = resolve_ref(HEAD)?;
+
+    write_ref(HEAD, &format!("{hash}\n"))?;
+
+    log_ref_update(HEAD, old.as_deref(), hash, reason)
+}
+
+fn log_ref_update(name: &str, old: Option<&str>, new: &str, reason: &str) -> anyhow::Result<()> {
+    if should_log(name) {
+        append_reflog(name, old, new, reason)?;
+    }
+
+    Ok(())
+}
+
+/// Removes a ref, whether loose or packed, along with its reflog.
+pub fn delete_ref(name: &str) -> anyhow::Result<()> {
+    let path = git_path(name);
+
+    if Path::new(&path).is_file() {
+        fs::remove_file(&path).with_context(|| format!("Could not delete {name}"))?;
+    }
+
+    let packed_path = git_path("packed-refs");
+
+    if let Ok(content) = read_file(&packed_path) {
+        let content = String::from_utf8_lossy(&content).to_string();
+
+        let mut kept = String::new();
+
+        let mut removed = false;
+
+        for line in content.lines() {
+            // Peeled values belong to the ref on the line before
+            if line.starts_with('^') && removed {
+                continue;
+            }
+
+            removed = line
+                .split_once(' ')
+                .is_some_and(|(_, ref_name)| ref_name == name);
+
+            if !removed {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+
+        if kept != content {
+            write_to_file(&packed_path, kept.as_bytes())?;
+        }
+    }
+
+    let log_path = git_path(&format!("logs/{name}"));
+
+    if Path::new(&log_path).is_file() {
+        fs::remove_file(&log_path)?;
+    }
+
+    remove_empty_parents(name);
+
+    Ok(())
+}
+
+/// Removes the directories a deleted ref and its reflog leave empty, sparing `refs/`
+/// and the directories right below it.
+fn remove_empty_parents(name: &str) {
+    let mut name = name;
+
+    while let Some((parent, _)) = name.rsplit_once('/') {
+        if parent.matches('/').count() < 2 {
+            break;
+        }
+
+        let removed = fs::remove_dir(git_path(parent)).is_ok();
+        let log_removed = fs::remove_dir(git_path(&format!("logs/{parent}"))).is_ok();
+
+        if !removed && !log_removed {
+            break;
+        }
+
+        name = parent;
+    }
+}
+
+/// Moves loose tags (or with `all`, every loose ref) into `packed-refs`, recording what
+/// annotated tags peel to. Symbolic refs and refs to missing objects stay loose. With
+/// `prune` the loose files of the packed refs are removed.
+pub fn pack_refs(all: bool, prune: bool) -> anyhow::Result<()> {
+    let mut packed: BTreeMap<String, String> = read_packed_refs()?
+        .into_iter()
+        .map(|(hash, name)| (name, hash))
+        .collect();
+
+    let mut loose = Vec::new();
+
+    for (name, _) in list_refs("refs/")? {
+        let Ok(content) = read_file(&git_path(&name)) else {
+            continue;
+        };
+
+        let value = String::from_utf8_lossy(&content).trim().to_string();
+
+        if value.starts_with("ref: ")
+            || !(all || name.starts_with("refs/tags/"))
+            || !object_exists(&value)
+        {
+            continue;
+        }
+
+        packed.insert(name.clone(), value);
+
+        loose.push(name);
+    }
+
+    let mut content = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+
+    for (name, hash) in &packed {
+        content.push_str(&format!("{hash} {name}\n"));
+
+        match GitObject::peel(hash) {
+            Ok(peeled) if peeled != *hash => content.push_str(&format!("^{peeled}\n")),
+
+            _ => {}
+        }
+    }
+
+    write_to_file(&git_path("packed-refs"), content.as_bytes())?;
+
+    for name in loose.iter().filter(|_| prune) {
+        fs::remove_file(git_path(name))?;
+
+        // Empty directories under refs/heads/ and the like go too
+        let mut directory = Path::new(name).parent();
+
+        while let Some(parent) = directory.filter(|parent| parent.components().count() > 2) {
+            if fs::remove_dir(git_path(&parent.to_string_lossy())).is_err() {
+                break;
+            }
+
+            directory = parent.parent();
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves (or with `keep_old`, copies) the reflog of a ref to a new name.
+pub fn move_reflog(old: &str, new: &str, keep_old: bool) -> anyhow::Result<()> {
+    let old_path = git_path(&format!("logs/{old}"));
+
+    if !Path::new(&old_path).is_file() {
+        return Ok(());
+    }
+
+    let new_path = git_path(&format!("logs/{new}"));
+
+    if let Some(parent) = Path::new(&new_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if keep_old {
+        fs::copy(&old_path, &new_path)?;
+    } else {
+        fs::rename(&old_path, &new_path)?;
+    }
+
+    Ok(())
+}
+
+/// Checks a full ref name against git's `check-ref-format` rules: no empty or
+/// dot-leading components, no `..`, `@{`, control characters, spaces or any of
+/// `~^:?*[\`, and no trailing `.`, `/` or `.lock`.
+pub fn check_ref_format(name: &str, allow_onelevel: bool) -> bool {
+    if name.is_empty() || name == "@" || name.ends_with('.') {
+        return false;
+    }
+
+    if name.contains("..") || name.contains("@{") {
+        return false;
+    }
+
+    if name
+        .chars()
+        .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+    {
+        return false;
+    }
+
+    let components: Vec<&str> = name.split('/').collect();
+
+    if !allow_onelevel && components.len() < 2 {
+        return false;
+    }
+
+    components.iter().all(|component| {
+        !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+    })
+}
+
+/// Whether a short name can be used for a branch, i.e. `refs/heads/<name>` is valid.
+pub fn is_valid_branch_name(name: &str) -> bool {
+    !name.starts_with('-') && name != HEAD && check_ref_format(&format!("refs/heads/{name}"), false)
+}
+
+fn write_ref(name: &str, content: &str) -> anyhow::Result<()> {
+    let path = git_path(name);
+
+    if !Path::new(&path).is_file() {
+        check_ref_conflicts(name)?;
+    }
+
+    if let Some(parent) = Path::new(&path).parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Could not create {parent:?}"))?;
+    }
+
+    write_to_file(&path, content.as_bytes())
+}
+
+/// Refuses a new ref whose name would put it beside an existing ref as a directory
+/// beside a file, like `refs/heads/a` and `refs/heads/a/b`.
+fn check_ref_conflicts(name: &str) -> anyhow::Result<()> {
+    let packed = read_packed_refs()?;
+
+    let exists = |other: &str| {
+        Path::new(&git_path(other)).is_file() || packed.iter().any(|(_, packed)| packed == other)
+    };
+
+    let below = format!("{name}/");
+
+    let mut conflict = name
+        .match_indices('/')
+        .map(|(end, _)| &name[..end])
+        .find(|above| exists(above))
+        .map(str::to_string)
+        .or_else(|| {
+            packed
+                .iter()
+                .map(|(_, packed)| packed)
+                .find(|packed| packed.starts_with(&below))
+                .cloned()
+        });
+
+    if conflict.is_none() && Path::new(&git_path(name)).is_dir() {
+        conflict = list_refs(&below)?
+            .into_iter()
+            .next()
+            .map(|(other, _)| other);
+    }
+
+    if let Some(other) = conflict {
+        bail!("cannot lock ref '{name}': '{other}' exists; cannot create '{name}'");
+    }
+
+    Ok(())
+}
+
+/// Resolves a revision such as `HEAD`, `main`, `refs/tags/v1`, a full or abbreviated
+/// hash or a reflog entry (`main@{1}`, `@{yesterday}`), optionally followed by `^`,
+/// `^<n>`, `~<n>`, `^{}` or `^{<type>}` suffixes, to an object hash. Tags are peeled
+/// before walking to parents.
+pub fn resolve_revision(revision: &str) -> anyhow::Result<String> {
+    let reflog_end = revision
+        .find("@{")
+        .and_then(|start| revision[start..].find('}').map(|end| start + end + 1));
+
+    let suffix_start = revision[reflog_end.unwrap_or(0)..]
+        .find(['^', '~'])
+        .map_or(revision.len(), |index| index + reflog_end.unwrap_or(0));
+
+    let (base, mut suffix) = revision.split_at(suffix_start);
+
+    let mut hash = resolve_base_revision(base)?;
+
+    // Parents come from the commit-graph when there is one
+    let mut walker = None;
+
+    while !suffix.is_empty() {
+        let operator = suffix.as_bytes()[0];
+
+        if let Some(peel) = suffix.strip_prefix("^{") {
+            let Some((wanted, rest)) = peel.split_once('}') else {
+                bail!("Invalid revision {revision}");
+            };
+
+            hash = peel_to_type(&hash, wanted, revision)?;
+
+            suffix = rest;
+
+            continue;
+        }
+
+        let digits_len = suffix[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(suffix.len() - 1);
+
+        let number: usize = match &suffix[1..1 + digits_len] {
+            "" => 1,
+
+            digits => digits.parse()?,
+        };
+
+        suffix = &suffix[1 + digits_len..];
+
+        // `^0` and `~0` stay put, but still name a commit
+        if number == 0 {
+            hash = peel_to_type(&hash, "commit", revision)?;
+
+            continue;
+        }
+
+        if operator == b'^' {
+            let walker = walker.get_or_insert_with(CommitWalker::new);
+
+            hash = nth_parent(walker, &hash, number, revision)?;
+        } else {
+            let walker = walker.get_or_insert_with(CommitWalker::new);
+
+            for _ in 0..number {
+                hash = nth_parent(walker, &hash, 1, revision)?;
+            }
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Resolves a revision that has to name a commit, peeling tags down to it.
+pub fn resolve_commit(revision: &str) -> anyhow::Result<String> {
+    resolve_revision(&format!("{revision}^{{commit}}"))
+}
+
+/// Peels tags (and commits, for `tree`) until an object of the wanted type is found.
+/// An empty type peels tags only, `object` accepts anything.
+fn peel_to_type(hash: &str, wanted: &str, revision: &str) -> anyhow::Result<String> {
+    let mut current = hash.to_string();
+
+    loop {
+        let (obj_type, content) = GitObject::read_raw(&current)?;
+
+        if obj_type == wanted || wanted == "object" || (wanted.is_empty() && obj_type != "tag") {
+            return Ok(current);
+        }
+
+        let content = String::from_utf8_lossy(&content);
+
+        let first_line = content.lines().next().unwrap_or_default();
+
+        current = match (obj_type.as_str(), wanted) {
+            ("tag", _) => first_line.strip_prefix("object "),
+
+            ("commit", "tree") => first_line.strip_prefix("tree "),
+
+            _ => None,
+        }
+        .map(str::to_string)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "{revision}: expected {wanted} type, but the object dereferences to {obj_type} type"
+            )
+        })?;
+    }
+}
+
+fn nth_parent(
+    walker: &mut CommitWalker,
+    hash: &str,
+    number: usize,
+    revision: &str,
+) -> anyhow::Result<String> {
+    // Only a tag needs reading to get at its commit
+    let commit = match walker.commit(hash) {
+        Ok(_) => hash.to_string(),
+
+        Err(_) => GitObject::peel(hash)?,
+    };
+
+    let Ok(commit) = walker.commit(&commit) else {
+        bail!("Invalid revision {revision}: {hash} is not a commit");
+    };
+
+    match commit.parents.get(number - 1) {
+        Some(parent) => Ok(parent.clone()),
+
+        None => bail!("Invalid revision {revision}: commit {hash} has no parent {number}"),
+    }
+}
+
+fn resolve_base_revision(name: &str) -> anyhow::Result<String> {
+    if let Some((ref_name, selector)) = name.strip_suffix('}').and_then(|n| n.split_once("@{")) {
+        return resolve_reflog_entry(ref_name, selector);
+    }
+
+    let name = if name == "@" || name.is_empty() {
+        HEAD
+    } else {
+        name
+    };
+
+    for candidate in ref_candidates(name) {
+        if let Some(hash) = resolve_ref(&candidate)? {
+            return Ok(hash);
+        }
+    }
+
+    if name.len() >= 4 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+        let matches = find_objects_by_prefix(&name.to_lowercase())?;
+
+        match matches.len() {
+            // A partial clone names objects it has yet to fetch
+            0 if name.len() == 40 && promisor_remote().is_some() => return Ok(name.to_lowercase()),
+
+            0 => {}
+
+            1 => return Ok(matches[0].clone()),
+
+            _ => bail!("short object ID {name} is ambiguous"),
+        }
+    }
+
+    bail!("unknown revision '{name}'")
+}
+
+/// `<ref>@{<n>}` is the value a ref had `n` updates ago, `<ref>@{<date>}` the value it
+/// had at that time. A bare `@{...}` refers to the current branch.
+fn resolve_reflog_entry(ref_name: &str, selector: &str) -> anyhow::Result<String> {
+    let full_name = match ref_name {
+        "" => head_target()?.unwrap_or_else(|| HEAD.to_string()),
+
+        HEAD => HEAD.to_string(),
+
+        other => expand_ref(other)?.ok_or_else(|| anyhow::anyhow!("unknown revision '{other}'"))?,
+    };
+
+    let entries = read_reflog(&full_name)?;
+
+    let display_name = if ref_name.is_empty() {
+        shorten_ref(&full_name)
+    } else {
+        ref_name
+    };
+
+    if let Ok(count) = selector.parse::<usize>() {
+        if count == 0 {
+            if let Some(hash) = resolve_ref(&full_name)? {
+                return Ok(hash);
+            }
+        }
+
+        return match entries.len().checked_sub(count + 1) {
+            Some(index) => Ok(entries[index].new.clone()),
+
+            None => bail!(
+                "log for '{display_name}' only has {} entries",
+                entries.len()
+            ),
+        };
+    }
+
+    let Some(date) = parse_date(selector, current_timestamp()) else {
+        bail!("invalid date in '{display_name}@{{{selector}}}'");
+    };
+
+    match entries
+        .iter()
+        .rev()
+        .find(|entry| entry.identity.timestamp <= date)
+    {
+        Some(entry) => Ok(entry.new.clone()),
+
+        // Before the log starts, the ref had the value the first entry moved it from
+        None => match entries.first() {
+            Some(first) => {
+                eprintln!(
+                    "warning: log for '{display_name}' only goes back to {}",
+                    first.identity.timestamp
+                );
+
+                Ok(first.old.clone())
+            }
+
+            None => bail!("log for '{display_name}' is empty"),
+        },
+    }
+}
+
+/// Loose and packed objects (including borrowed ones) whose hash starts with the
+/// given prefix.
+pub fn find_objects_by_prefix(prefix: &str) -> anyhow::Result<Vec<String>> {
+    let (folder_name, rest) = prefix.split_at(2);
+
+    let mut matches = pack::packed_objects_with_prefix(prefix)?;
+
+    for directory in object_directories() {
+        let Ok(entries) = fs::read_dir(format!("{directory}/{folder_name}")) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+
+            let hash = format!("{folder_name}{file_name}");
+
+            if file_name.starts_with(rest) && !matches.contains(&hash) {
+                matches.push(hash);
+            }
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Short name for display, e.g. `main` for `refs/heads/main`.
+pub fn shorten_ref(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
@@ -0,0 +1,457 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure};
+
+use crate::bundle::{Bundle, BundleTransport};
+use crate::config::Config;
+use crate::refs::{expand_ref, head_target, list_refs, ref_candidates, resolve_revision, HEAD};
+use crate::transport::{HttpTransport, LocalTransport, RemoteRef, Transport, ZERO_HASH};
+
+/// A `[+]<src>:<dst>` mapping between remote and local refs, where both sides may hold a
+/// single `*` matching the same text, or a `^<src>` negative refspec leaving out the refs
+/// it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    pub force: bool,
+
+    pub negative: bool,
+
+    pub src: String,
+
+    /// `None` when only the source is given, as in `git fetch origin main`.
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        if let Some(src) = spec.strip_prefix('^') {
+            ensure!(
+                !src.is_empty() && !src.contains(':') && src.matches('*').count() <= 1,
+                "invalid refspec '{spec}'"
+            );
+
+            return Ok(Refspec {
+                force: false,
+                negative: true,
+                src: src.to_string(),
+                dst: None,
+            });
+        }
+
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+
+            None => (false, spec),
+        };
+
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (
+                src.to_string(),
+                Some(dst.to_string()).filter(|d| !d.is_empty()),
+            ),
+
+            None => (spec.to_string(), None),
+        };
+
+        let globs = |side: &str| side.matches('*').count();
+
+        ensure!(
+            globs(&src) <= 1 && dst.as_deref().map_or(true, |dst| globs(dst) == globs(&src)),
+            "invalid refspec '{spec}'"
+        );
+
+        Ok(Refspec {
+            force,
+            negative: false,
+            src,
+            dst,
+        })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+
+    /// The part of `name` a glob source's `*` matches, or the whole name for an exact match.
+    fn matched<'a>(&self, name: &'a str) -> Option<&'a str> {
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => name
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(suffix)),
+
+            None => (name == self.src).then_some(name),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.matched(name).is_some()
+    }
+
+    /// Local ref that the remote ref `name` maps to.
+    pub fn map(&self, name: &str) -> Option<String> {
+        let matched = self.matched(name)?;
+
+        let dst = self.dst.as_ref()?;
+
+        Some(dst.replacen('*', matched, 1))
+    }
+
+    /// Remote ref that the local ref `name` was mapped from, the reverse of [`Self::map`].
+    pub fn unmap(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_ref()?;
+
+        let matched = match dst.split_once('*') {
+            Some((prefix, suffix)) => name.strip_prefix(prefix)?.strip_suffix(suffix)?,
+
+            None => (name == dst).then_some(name)?,
+        };
+
+        Some(self.src.replacen('*', matched, 1))
+    }
+
+    /// Ref name prefixes to ask the remote about, for `ref-prefix`.
+    pub fn prefixes(&self) -> Vec<String> {
+        if self.negative {
+            return Vec::new();
+        }
+
+        match self.src.split_once('*') {
+            Some((prefix, _)) => vec![prefix.to_string()],
+
+            None => ref_candidates(&self.src),
+        }
+    }
+
+    /// Remote refs this refspec selects, each with the local ref it updates. An exact
+    /// source is a short name resolved the way git resolves local ones, and an exact
+    /// destination outside `refs/` names a branch.
+    pub fn select<'a>(&self, refs: &'a [RemoteRef]) -> Vec<(&'a RemoteRef, Option<String>)> {
+        if self.negative {
+            return Vec::new();
+        }
+
+        if self.is_glob() {
+            return refs
+                .iter()
+                .filter(|remote_ref| self.matches(&remote_ref.name))
+                .map(|remote_ref| (remote_ref, self.map(&remote_ref.name)))
+                .collect();
+        }
+
+        let found = ref_candidates(&self.src)
+            .into_iter()
+            .find_map(|candidate| refs.iter().find(|remote_ref| remote_ref.name == candidate));
+
+        let dst = self.dst.as_ref().map(|dst| {
+            if dst.starts_with("refs/") {
+                dst.clone()
+            } else {
+                format!("refs/heads/{dst}")
+            }
+        });
+
+        found
+            .map(|remote_ref| (remote_ref, dst))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Whether a negative refspec among `refspecs` leaves out the ref `name`. An exact
+/// negative source is a short name resolved the way git resolves local ones.
+pub fn is_excluded(refspecs: &[Refspec], name: &str) -> bool {
+    refspecs.iter().filter(|spec| spec.negative).any(|spec| {
+        if spec.is_glob() {
+            spec.matches(name)
+        } else {
+            ref_candidates(&spec.src)
+                .iter()
+                .any(|candidate| candidate == name)
+        }
+    })
+}
+
+/// How `push` may change refs on the remote.
+#[derive(Debug, Default)]
+pub struct PushOptions {
+    pub force: bool,
+
+    /// `--force-with-lease` values: empty to protect every ref with its remote-tracking
+    /// ref, `<ref>` for one ref, or `<ref>:<expect>` to give the expected value, which is
+    /// empty (or the null id) for a ref that must not exist yet.
+    pub leases: Vec<String>,
+
+    pub atomic: bool,
+
+    pub push_options: Vec<String>,
+
+    pub set_upstream: bool,
+}
+
+/// A ref `push` means to change on the remote.
+#[derive(Debug, Clone)]
+pub struct PushUpdate {
+    /// Local ref, `HEAD` or other revision the new value comes from, empty when deleting.
+    pub src: String,
+
+    pub dst: String,
+
+    /// Value the remote advertised, or the zero hash for a new ref.
+    pub old: String,
+
+    /// The zero hash when deleting.
+    pub new: String,
+
+    pub force: bool,
+
+    /// Where `--force-with-lease` expects the remote ref to be.
+    pub expect: Option<String>,
+}
+
+/// Remote refs that push refspecs update, with the local values they take. A source
+/// is any revision, with an empty one deleting the destination, and a destination
+/// outside `refs/` is either a ref the remote has or of the same kind as the source.
+pub fn push_updates(
+    refspecs: &[Refspec],
+    remote_refs: &[RemoteRef],
+    force: bool,
+) -> anyhow::Result<Vec<PushUpdate>> {
+    let on_remote = |name: &str| {
+        ref_candidates(name)
+            .into_iter()
+            .find(|candidate| remote_refs.iter().any(|r| r.name == *candidate))
+    };
+
+    let mut updates: Vec<PushUpdate> = Vec::new();
+
+    for refspec in refspecs.iter().filter(|spec| !spec.negative) {
+        // (source, destination, new value)
+        let mut matched = Vec::new();
+
+        if refspec.is_glob() {
+            for (name, hash) in list_refs("refs/")? {
+                if is_excluded(refspecs, &name) {
+                    continue;
+                }
+
+                let dst = match &refspec.dst {
+                    Some(_) => refspec.map(&name),
+
+                    None => refspec.matches(&name).then(|| name.clone()),
+                };
+
+                if let Some(dst) = dst {
+                    matched.push((name, dst, hash));
+                }
+            }
+        } else if refspec.src.is_empty() {
+            let Some(dst) = &refspec.dst else {
+                bail!("invalid refspec ':'");
+            };
+
+            let Some(found) = on_remote(dst) else {
+                bail!("unable to delete '{dst}': remote ref does not exist");
+            };
+
+            matched.push((String::new(), found, ZERO_HASH.to_string()));
+        } else {
+            let local = match refspec.src.as_str() {
+                HEAD => head_target()?,
+
+                src => expand_ref(src)?,
+            };
+
+            if local
+                .as_deref()
+                .is_some_and(|local| is_excluded(refspecs, local))
+            {
+                continue;
+            }
+
+            let new = resolve_revision(&refspec.src)
+                .map_err(|_| anyhow!("src refspec {} does not match any", refspec.src))?;
+
+            let kind = local.as_deref().and_then(|local| {
+                ["refs/heads/", "refs/tags/"]
+                    .into_iter()
+                    .find(|k| local.starts_with(k))
+            });
+
+            let dst = match &refspec.dst {
+                Some(dst) if dst.starts_with("refs/") => Some(dst.clone()),
+
+                Some(dst) => on_remote(dst).or_else(|| kind.map(|kind| format!("{kind}{dst}"))),
+
+                None => local.clone(),
+            };
+
+            let Some(dst) = dst else {
+                bail!(
+                    "The destination you provided is not a full refname (i.e., starting with \"refs/\")"
+                );
+            };
+
+            // `HEAD` is shown as given rather than as the branch behind it
+            let src = match local {
+                Some(local) if refspec.src != HEAD => local,
+
+                _ => refspec.src.clone(),
+            };
+
+            matched.push((src, dst, new));
+        }
+
+        for (src, dst, new) in matched {
+            if updates.iter().any(|update| update.dst == dst) {
+                continue;
+            }
+
+            let old = remote_refs
+                .iter()
+                .find(|remote_ref| remote_ref.name == dst)
+                .map_or(ZERO_HASH.to_string(), |remote_ref| remote_ref.hash.clone());
+
+            updates.push(PushUpdate {
+                src,
+                dst,
+                old,
+                new,
+                force: force || refspec.force,
+                expect: None,
+            });
+        }
+    }
+
+    Ok(updates)
+}
+
+/// A remote as configured in `[remote "<name>"]`.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteConfig {
+    /// Where fetches come from (the first one) and pushes go when there is no `pushurl`.
+    pub urls: Vec<String>,
+
+    pub push_urls: Vec<String>,
+
+    pub fetch: Vec<Refspec>,
+
+    /// What a push without refspecs sends, instead of the current branch.
+    pub push: Vec<Refspec>,
+
+    /// `tagOpt`: `--tags` to fetch every tag, `--no-tags` to follow none.
+    pub tag_opt: Option<String>,
+}
+
+impl RemoteConfig {
+    pub fn url(&self) -> &str {
+        self.urls.first().map_or("", String::as_str)
+    }
+
+    /// URLs a push goes to.
+    pub fn push_targets(&self) -> &[String] {
+        if self.push_urls.is_empty() {
+            &self.urls
+        } else {
+            &self.push_urls
+        }
+    }
+}
+
+/// Names of the configured remotes, sorted as git lists them.
+pub fn remote_names() -> anyhow::Result<Vec<String>> {
+    let config = Config::load()?;
+
+    let mut names: Vec<String> = config
+        .subsections("remote")
+        .into_iter()
+        .filter(|name| config.get(&format!("remote.{name}.url")).is_some())
+        .map(str::to_string)
+        .collect();
+
+    names.sort();
+
+    Ok(names)
+}
+
+/// The configured remote `name`, or `None` when there is no such remote.
+pub fn find_remote(name: &str) -> anyhow::Result<Option<RemoteConfig>> {
+    let config = Config::load()?;
+
+    let values = |key: &str| {
+        config
+            .get_all(&format!("remote.{name}.{key}"))
+            .into_iter()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    let urls = values("url");
+
+    if urls.is_empty() {
+        return Ok(None);
+    }
+
+    let refspecs = |key: &str| {
+        values(key)
+            .iter()
+            .map(|spec| Refspec::parse(spec))
+            .collect::<anyhow::Result<Vec<_>>>()
+    };
+
+    Ok(Some(RemoteConfig {
+        push_urls: values("pushurl"),
+        fetch: refspecs("fetch")?,
+        push: refspecs("push")?,
+        tag_opt: config
+            .get(&format!("remote.{name}.tagOpt"))
+            .map(str::to_string),
+        urls,
+    }))
+}
+
+/// A configured remote, or a name that is not configured taken as a URL with no
+/// refspecs.
+pub fn remote_config(name: &str) -> anyhow::Result<RemoteConfig> {
+    if let Some(remote) = find_remote(name)? {
+        return Ok(remote);
+    }
+
+    if name.contains(['/', ':']) || Path::new(name).exists() {
+        return Ok(RemoteConfig {
+            urls: vec![name.to_string()],
+            ..RemoteConfig::default()
+        });
+    }
+
+    bail!("'{name}' does not appear to be a git repository");
+}
+
+/// URL as git shows it in fetch output and `FETCH_HEAD`, without a `.git` suffix.
+pub fn display_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+
+    url.strip_suffix(".git").unwrap_or(url).to_string()
+}
+
+/// Picks the transport for a URL: smart HTTP, or a repository or bundle file on this
+/// machine given as a `file://` URL or a plain path.
+pub fn open_transport(url: &str) -> anyhow::Result<Box<dyn Transport>> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Ok(Box::new(HttpTransport::new(url)?));
+    }
+
+    if let Some((scheme, _)) = url
+        .split_once("://")
+        .filter(|(scheme, _)| *scheme != "file")
+    {
+        bail!("unable to find remote helper for '{scheme}'");
+    }
+
+    let path = url.strip_prefix("file://").unwrap_or(url);
+
+    if Bundle::is_bundle(path) {
+        return Ok(Box::new(BundleTransport::new(path)?));
+    }
+
+    Ok(Box::new(LocalTransport::new(path)?))
+}
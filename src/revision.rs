@@ -0,0 +1,882 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use anyhow::{bail, ensure};
+
+use crate::commit_graph::{commit_graph, CommitGraph, GENERATION_INFINITY};
+use crate::git_objects::{GitObject, TreeFileModes};
+use crate::refs::shallow_commits;
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+#[derive(Debug, Clone)]
+pub struct CommitInfo {
+    pub tree: String,
+
+    pub parents: Vec<String>,
+
+    /// Committer time stamp, used to walk newer commits first.
+    pub date: i64,
+
+    /// Generation number from the commit-graph, never below those of the parents;
+    /// `GENERATION_INFINITY` for commits it does not know.
+    pub generation: u64,
+}
+
+/// Walks commit history, parsing each commit at most once.
+#[derive(Debug, Default)]
+pub struct CommitWalker {
+    commits: HashMap<String, CommitInfo>,
+
+    /// Commits whose history is cut off, walked as if they had no parents.
+    shallow: HashSet<String>,
+
+    /// Where commits are looked up before parsing them, unless history is shallow.
+    graph: Option<Arc<CommitGraph>>,
+}
+
+impl CommitWalker {
+    /// A walker that stops where the repository's shallow history ends.
+    pub fn new() -> Self {
+        Self::with_shallow(HashSet::new())
+    }
+
+    /// A walker that also stops at the given shallow commits.
+    pub fn with_shallow(mut shallow: HashSet<String>) -> Self {
+        shallow.extend(shallow_commits());
+
+        let graph = match shallow.is_empty() {
+            true => commit_graph(),
+
+            false => None,
+        };
+
+        CommitWalker {
+            shallow,
+            graph,
+            ..Self::default()
+        }
+    }
+
+    pub fn commit(&mut self, hash: &str) -> anyhow::Result<&CommitInfo> {
+        if !self.commits.contains_key(hash) {
+            let info = match self.graph.as_ref().and_then(|graph| graph.commit(hash)) {
+                Some(info) => info,
+
+                None => self.parse_commit(hash)?,
+            };
+
+            self.commits.insert(hash.to_string(), info);
+        }
+
+        Ok(&self.commits[hash])
+    }
+
+    fn parse_commit(&self, hash: &str) -> anyhow::Result<CommitInfo> {
+        match GitObject::from_hash(hash)? {
+            GitObject::Commit {
+                tree,
+                parents,
+                committer,
+                ..
+            } => Ok(CommitInfo {
+                tree,
+                parents: if self.shallow.contains(hash) {
+                    Vec::new()
+                } else {
+                    parents
+                },
+                date: committer.timestamp,
+                generation: GENERATION_INFINITY,
+            }),
+
+            other => bail!("{hash} is a {other}, not a commit"),
+        }
+    }
+
+    pub fn parents(&mut self, hash: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self.commit(hash)?.parents.clone())
+    }
+
+    pub fn date(&mut self, hash: &str) -> anyhow::Result<i64> {
+        Ok(self.commit(hash)?.date)
+    }
+
+    fn generation(&mut self, hash: &str) -> anyhow::Result<u64> {
+        Ok(self.commit(hash)?.generation)
+    }
+
+    /// Marks everything reachable from `one` and from `twos`, highest generation and
+    /// then newest first, stopping once only commits below an already found common
+    /// ancestor remain, or below `min_generation`.
+    fn paint_down_to_common(
+        &mut self,
+        one: &str,
+        twos: &[String],
+        min_generation: u64,
+    ) -> anyhow::Result<(Vec<String>, HashMap<String, u8>)> {
+        let mut flags: HashMap<String, u8> = HashMap::new();
+
+        let mut queue = BinaryHeap::new();
+
+        let mut sequence = 0usize;
+
+        // Queue entries of each commit, and how many entries are of commits not stale, so
+        // that the walk ends once only stale ones are left without scanning the queue
+        let mut entries: HashMap<String, usize> = HashMap::new();
+
+        let mut not_stale = 0usize;
+
+        flags.insert(one.to_string(), PARENT1);
+
+        queue.push(self.queued(one, usize::MAX)?);
+
+        for two in twos {
+            *flags.entry(two.clone()).or_insert(0) |= PARENT2;
+
+            queue.push(self.queued(two, usize::MAX - 1 - sequence)?);
+
+            sequence += 1;
+        }
+
+        for hash in std::iter::once(one).chain(twos.iter().map(String::as_str)) {
+            *entries.entry(hash.to_string()).or_insert(0) += 1;
+
+            not_stale += 1;
+        }
+
+        let mut result = Vec::new();
+
+        while not_stale > 0 {
+            let Some((generation, _, _, hash)) = queue.pop() else {
+                break;
+            };
+
+            if let Some(count) = entries.get_mut(&hash) {
+                *count -= 1;
+            }
+
+            if flags.get(&hash).copied().unwrap_or(0) & STALE == 0 {
+                not_stale -= 1;
+            }
+
+            if generation < min_generation {
+                break;
+            }
+
+            let mut current = flags.get(&hash).copied().unwrap_or(0) & (PARENT1 | PARENT2 | STALE);
+
+            if current == PARENT1 | PARENT2 {
+                let entry = flags.entry(hash.clone()).or_insert(0);
+
+                if *entry & RESULT == 0 {
+                    *entry |= RESULT;
+
+                    result.push(hash.clone());
+                }
+
+                // Ancestors of a common commit can't be the best common ancestor
+                current |= STALE;
+            }
+
+            for parent in self.parents(&hash)? {
+                let parent_flags = flags.entry(parent.clone()).or_insert(0);
+
+                if *parent_flags & current == current {
+                    continue;
+                }
+
+                let was_stale = *parent_flags & STALE != 0;
+
+                *parent_flags |= current;
+
+                let count = entries.entry(parent.clone()).or_insert(0);
+
+                // Entries already queued go stale along with the commit
+                if !was_stale && current & STALE != 0 {
+                    not_stale -= *count;
+                }
+
+                *count += 1;
+
+                if *parent_flags & STALE == 0 {
+                    not_stale += 1;
+                }
+
+                sequence += 1;
+
+                queue.push(self.queued(&parent, usize::MAX - 1 - sequence)?);
+            }
+        }
+
+        Ok((result, flags))
+    }
+
+    /// Queue entry of a commit for [`Self::paint_down_to_common`], `sequence` breaking
+    /// ties in the order commits were queued.
+    fn queued(&mut self, hash: &str, sequence: usize) -> anyhow::Result<(u64, i64, usize, String)> {
+        let info = self.commit(hash)?;
+
+        Ok((info.generation, info.date, sequence, hash.to_string()))
+    }
+
+    /// Best common ancestors of `one` and all of `twos` taken together.
+    pub fn merge_bases_many(&mut self, one: &str, twos: &[String]) -> anyhow::Result<Vec<String>> {
+        if twos.iter().any(|two| two == one) {
+            return Ok(vec![one.to_string()]);
+        }
+
+        let (result, flags) = self.paint_down_to_common(one, twos, 0)?;
+
+        let candidates: Vec<String> = result
+            .into_iter()
+            .filter(|hash| flags.get(hash).copied().unwrap_or(0) & STALE == 0)
+            .collect();
+
+        self.remove_redundant(candidates)
+    }
+
+    pub fn merge_bases(&mut self, one: &str, two: &str) -> anyhow::Result<Vec<String>> {
+        self.merge_bases_many(one, &[two.to_string()])
+    }
+
+    /// Drops candidates that are ancestors of other candidates.
+    fn remove_redundant(&mut self, candidates: Vec<String>) -> anyhow::Result<Vec<String>> {
+        if candidates.len() < 2 {
+            return Ok(candidates);
+        }
+
+        let mut redundant = vec![false; candidates.len()];
+
+        for (index, candidate) in candidates.iter().enumerate() {
+            if redundant[index] {
+                continue;
+            }
+
+            let others: Vec<String> = candidates
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != index && !redundant[*other])
+                .map(|(_, hash)| hash.clone())
+                .collect();
+
+            // Nothing below the lowest of them decides whether one reaches another
+            let mut min_generation = self.generation(candidate)?;
+
+            for other in &others {
+                min_generation = min_generation.min(self.generation(other)?);
+            }
+
+            let (_, flags) = self.paint_down_to_common(candidate, &others, min_generation)?;
+
+            // Others reached from this candidate are its ancestors
+            for (other, hash) in candidates.iter().enumerate() {
+                if other != index && flags.get(hash).copied().unwrap_or(0) & PARENT1 != 0 {
+                    redundant[other] = true;
+                }
+            }
+
+            if flags.get(candidate).copied().unwrap_or(0) & PARENT2 != 0 {
+                redundant[index] = true;
+            }
+        }
+
+        let mut result: Vec<String> = candidates
+            .into_iter()
+            .zip(redundant)
+            .filter(|(_, redundant)| !redundant)
+            .map(|(hash, _)| hash)
+            .collect();
+
+        let mut dates = HashMap::new();
+
+        for hash in &result {
+            dates.insert(hash.clone(), self.date(hash)?);
+        }
+
+        result.sort_by_key(|hash| std::cmp::Reverse(dates[hash]));
+
+        Ok(result)
+    }
+
+    /// Common ancestors of all the commits at once, found by folding them in one by one.
+    pub fn merge_bases_octopus(&mut self, commits: &[String]) -> anyhow::Result<Vec<String>> {
+        let Some((first, rest)) = commits.split_first() else {
+            return Ok(Vec::new());
+        };
+
+        let mut result = vec![first.clone()];
+
+        for commit in rest {
+            let mut next: Vec<String> = Vec::new();
+
+            for base in &result {
+                for found in self.merge_bases(base, commit)? {
+                    if !next.contains(&found) {
+                        next.push(found);
+                    }
+                }
+            }
+
+            result = self.remove_redundant(next)?;
+        }
+
+        Ok(result)
+    }
+
+    /// Where `commit` forked from a branch whose tip has moved through `history` (the
+    /// reflog entries of the branch): the single merge base of them all, provided it is
+    /// itself one of the recorded tips.
+    pub fn fork_point(
+        &mut self,
+        commit: &str,
+        history: &[String],
+    ) -> anyhow::Result<Option<String>> {
+        let mut candidates: Vec<String> = Vec::new();
+
+        for hash in history {
+            if !candidates.contains(hash) && self.commit(hash).is_ok() {
+                candidates.push(hash.clone());
+            }
+        }
+
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+
+        let bases = self.merge_bases_many(commit, &candidates)?;
+
+        match bases.as_slice() {
+            [base] if candidates.contains(base) => Ok(Some(base.clone())),
+
+            _ => Ok(None),
+        }
+    }
+
+    /// Every commit reachable from `hash`, itself included.
+    pub fn ancestors(&mut self, hash: &str) -> anyhow::Result<HashSet<String>> {
+        let mut seen = HashSet::new();
+
+        let mut stack = vec![hash.to_string()];
+
+        while let Some(current) = stack.pop() {
+            if seen.insert(current.clone()) {
+                stack.extend(self.parents(&current)?);
+            }
+        }
+
+        Ok(seen)
+    }
+
+    /// Commits reachable from `include` but not from any of `exclude`, oldest first.
+    pub fn commits_between(
+        &mut self,
+        exclude: &[String],
+        include: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut excluded = HashSet::new();
+
+        for hash in exclude {
+            excluded.extend(self.ancestors(hash)?);
+        }
+
+        let mut seen = HashSet::new();
+
+        let mut queue = BinaryHeap::new();
+
+        seen.insert(include.to_string());
+
+        queue.push((self.date(include)?, include.to_string()));
+
+        let mut result = Vec::new();
+
+        while let Some((_, hash)) = queue.pop() {
+            if excluded.contains(&hash) {
+                continue;
+            }
+
+            for parent in self.parents(&hash)? {
+                if seen.insert(parent.clone()) {
+                    queue.push((self.date(&parent)?, parent));
+                }
+            }
+
+            result.push(hash);
+        }
+
+        result.reverse();
+
+        Ok(result)
+    }
+
+    /// Commits reachable from `exclude` that are parents of commits reachable from
+    /// `include` but not from `exclude`: what a pack of the latter builds upon.
+    pub fn boundary(
+        &mut self,
+        exclude: &[String],
+        include: &[String],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut excluded = HashSet::new();
+
+        for hash in exclude {
+            excluded.extend(self.ancestors(&GitObject::peel(hash)?)?);
+        }
+
+        let mut seen = HashSet::new();
+
+        let mut stack = Vec::new();
+
+        for hash in include {
+            let commit = GitObject::peel(hash)?;
+
+            if !excluded.contains(&commit) && self.commit(&commit).is_ok() {
+                stack.push(commit);
+            }
+        }
+
+        let mut boundary = Vec::new();
+
+        while let Some(commit) = stack.pop() {
+            if !seen.insert(commit.clone()) {
+                continue;
+            }
+
+            for parent in self.parents(&commit)? {
+                if !excluded.contains(&parent) {
+                    stack.push(parent);
+                } else if !boundary.contains(&parent) {
+                    boundary.push(parent);
+                }
+            }
+        }
+
+        Ok(boundary)
+    }
+
+    /// Every object reachable from `include` (commits, tags, trees or blobs) but not from
+    /// `exclude`, each commit followed by the new trees and blobs of its snapshot. These are
+    /// the objects a pack must carry for a remote that has `exclude`.
+    pub fn objects_between(
+        &mut self,
+        exclude: &[String],
+        include: &[String],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut objects = Vec::new();
+
+        let mut seen = HashSet::new();
+
+        let mut tips = Vec::new();
+
+        let mut trees = Vec::new();
+
+        for hash in include {
+            let mut current = hash.clone();
+
+            loop {
+                let (kind, content) = GitObject::read_raw(&current)?;
+
+                match kind.as_str() {
+                    "tag" => {
+                        if seen.insert(current.clone()) {
+                            objects.push(current.clone());
+                        }
+
+                        let Some(target) = String::from_utf8_lossy(&content)
+                            .lines()
+                            .next()
+                            .and_then(|line| line.strip_prefix("object "))
+                            .map(str::to_string)
+                        else {
+                            bail!("Malformed tag object {current}");
+                        };
+
+                        current = target;
+                    }
+
+                    "commit" => break tips.push(current),
+
+                    "tree" => break trees.push(current),
+
+                    _ => {
+                        if seen.insert(current.clone()) {
+                            objects.push(current);
+                        }
+
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut excluded = HashSet::new();
+
+        for hash in exclude {
+            if let Ok(commit) = GitObject::peel(hash) {
+                if self.commit(&commit).is_ok() {
+                    excluded.extend(self.ancestors(&commit)?);
+                }
+            }
+        }
+
+        let mut commits = Vec::new();
+
+        let mut boundary = HashSet::new();
+
+        let mut stack = tips;
+
+        while let Some(commit) = stack.pop() {
+            if excluded.contains(&commit) {
+                boundary.insert(commit);
+
+                continue;
+            }
+
+            if !seen.insert(commit.clone()) {
+                continue;
+            }
+
+            stack.extend(self.parents(&commit)?);
+
+            commits.push(commit);
+        }
+
+        // Whatever the remote's commits at the edge hold, it already has
+        for commit in boundary.iter().chain(exclude.iter()) {
+            if let Ok(info) = self.commit(commit) {
+                let tree = info.tree.clone();
+
+                collect_tree(&tree, &mut seen, None)?;
+            }
+        }
+
+        for commit in commits {
+            let tree = self.commit(&commit)?.tree.clone();
+
+            objects.push(commit);
+
+            collect_tree(&tree, &mut seen, Some(&mut objects))?;
+        }
+
+        for tree in trees {
+            collect_tree(&tree, &mut seen, Some(&mut objects))?;
+        }
+
+        Ok(objects)
+    }
+
+    /// What `rev-list` lists: commits reachable from `include` but not from `exclude`,
+    /// newest first, then with `objects` the tags named, and the trees and blobs the
+    /// commits bring that those at the edge of `exclude` lack, each with its path.
+    pub fn rev_list(
+        &mut self,
+        exclude: &[String],
+        include: &[String],
+        objects: bool,
+    ) -> anyhow::Result<Vec<(String, Option<String>)>> {
+        let mut pending = Vec::new();
+
+        let mut tips = Vec::new();
+
+        for hash in include {
+            let mut current = hash.clone();
+
+            loop {
+                let (kind, content) = GitObject::read_raw(&current)?;
+
+                match kind.as_str() {
+                    "tag" => {
+                        let text = String::from_utf8_lossy(&content);
+
+                        let field = |name: &str| {
+                            text.lines()
+                                .take_while(|line| !line.is_empty())
+                                .find_map(|line| line.strip_prefix(name))
+                                .map(str::to_string)
+                        };
+
+                        let Some(target) = field("object ") else {
+                            bail!("Malformed tag object {current}");
+                        };
+
+                        pending.push((current, field("tag ").unwrap_or_default()));
+
+                        current = target;
+                    }
+
+                    "commit" => break tips.push(current),
+
+                    _ => break pending.push((current, String::new())),
+                }
+            }
+        }
+
+        let mut excluded = HashSet::new();
+
+        for hash in exclude {
+            excluded.extend(self.ancestors(&GitObject::peel(hash)?)?);
+        }
+
+        let mut queue = BinaryHeap::new();
+
+        let mut queued = HashSet::new();
+
+        for tip in tips {
+            if queued.insert(tip.clone()) {
+                queue.push((self.date(&tip)?, Reverse(queued.len()), tip));
+            }
+        }
+
+        let mut commits = Vec::new();
+
+        let mut boundary = HashSet::new();
+
+        while let Some((_, _, commit)) = queue.pop() {
+            if excluded.contains(&commit) {
+                boundary.insert(commit);
+
+                continue;
+            }
+
+            for parent in self.parents(&commit)? {
+                if queued.insert(parent.clone()) {
+                    queue.push((self.date(&parent)?, Reverse(queued.len()), parent));
+                }
+            }
+
+            commits.push(commit);
+        }
+
+        let mut listed: Vec<(String, Option<String>)> = commits
+            .iter()
+            .map(|commit| (commit.clone(), None))
+            .collect();
+
+        if !objects {
+            return Ok(listed);
+        }
+
+        let mut seen = HashSet::new();
+
+        for commit in boundary.iter().chain(exclude.iter()) {
+            if let Ok(info) = self.commit(commit) {
+                let tree = info.tree.clone();
+
+                collect_tree(&tree, &mut seen, None)?;
+            }
+        }
+
+        for commit in &commits {
+            pending.push((self.commit(commit)?.tree.clone(), String::new()));
+        }
+
+        for (hash, name) in pending {
+            let (kind, _) = GitObject::read_raw(&hash)?;
+
+            match kind.as_str() {
+                "tree" => list_tree(&hash, &name, &mut seen, &mut listed)?,
+
+                _ => {
+                    if seen.insert(hash.clone()) {
+                        listed.push((hash, Some(name)));
+                    }
+                }
+            }
+        }
+
+        Ok(listed)
+    }
+
+    /// The commits a shallow fetch of `tips` covers: those within `depth` generations,
+    /// committed at or after `since` and not reachable from `not`. Returns them along
+    /// with the new shallow commits: those at the depth limit or with parents left out.
+    pub fn shallow_cut(
+        &mut self,
+        tips: &[String],
+        depth: Option<usize>,
+        since: Option<i64>,
+        not: &[String],
+    ) -> anyhow::Result<(HashSet<String>, HashSet<String>)> {
+        let mut excluded = HashSet::new();
+
+        for hash in not {
+            excluded.extend(self.ancestors(hash)?);
+        }
+
+        let mut covered = HashSet::new();
+
+        // Commits at the depth limit, which stay shallow however else they are reached
+        let mut deepest = HashSet::new();
+
+        // Breadth first, so each commit is met at its smallest generation first
+        let mut queue: VecDeque<(String, usize)> =
+            tips.iter().map(|tip| (tip.clone(), 1)).collect();
+
+        while let Some((commit, generation)) = queue.pop_front() {
+            if covered.contains(&commit) || excluded.contains(&commit) {
+                continue;
+            }
+
+            if since.is_some_and(|since| self.date(&commit).is_ok_and(|date| date < since)) {
+                continue;
+            }
+
+            covered.insert(commit.clone());
+
+            if depth.map_or(true, |depth| generation < depth) {
+                for parent in self.parents(&commit)? {
+                    queue.push_back((parent, generation + 1));
+                }
+            } else {
+                deepest.insert(commit);
+            }
+        }
+
+        ensure!(
+            !covered.is_empty(),
+            "no commits selected for shallow requests"
+        );
+
+        let mut boundary = HashSet::new();
+
+        for commit in &covered {
+            let parents = self.parents(commit)?;
+
+            if (deepest.contains(commit) && !parents.is_empty())
+                || parents.iter().any(|p| !covered.contains(p))
+            {
+                boundary.insert(commit.clone());
+            }
+        }
+
+        Ok((covered, boundary))
+    }
+
+    /// Commits only reachable from `one`, and only reachable from `two`.
+    pub fn ahead_behind(&mut self, one: &str, two: &str) -> anyhow::Result<(usize, usize)> {
+        let ones = self.ancestors(one)?;
+        let twos = self.ancestors(two)?;
+
+        Ok((
+            ones.difference(&twos).count(),
+            twos.difference(&ones).count(),
+        ))
+    }
+
+    pub fn is_ancestor(&mut self, ancestor: &str, descendant: &str) -> anyhow::Result<bool> {
+        if ancestor == descendant {
+            return Ok(true);
+        }
+
+        // A commit is never reachable from one of a lower generation
+        let generation = self.generation(ancestor)?;
+
+        if generation > self.generation(descendant)? {
+            return Ok(false);
+        }
+
+        let (_, flags) =
+            self.paint_down_to_common(ancestor, &[descendant.to_string()], generation)?;
+
+        Ok(flags.get(ancestor).copied().unwrap_or(0) & PARENT2 != 0)
+    }
+}
+
+/// Whether moving a ref from `old` to `new` keeps every commit it reached: both peel to
+/// commits and `old` is an ancestor of `new`.
+pub fn is_fast_forward(old: &str, new: &str) -> anyhow::Result<bool> {
+    let (Ok(old), Ok(new)) = (GitObject::peel(old), GitObject::peel(new)) else {
+        return Ok(false);
+    };
+
+    let mut walker = CommitWalker::new();
+
+    if walker.commit(&old).is_err() || walker.commit(&new).is_err() {
+        return Ok(false);
+    }
+
+    walker.is_ancestor(&old, &new)
+}
+
+/// Adds a tree and everything in it that is not in `seen` yet to `seen`, and to
+/// `objects` when given.
+fn collect_tree(
+    tree: &str,
+    seen: &mut HashSet<String>,
+    mut objects: Option<&mut Vec<String>>,
+) -> anyhow::Result<()> {
+    let mut stack = vec![tree.to_string()];
+
+    while let Some(tree) = stack.pop() {
+        if !seen.insert(tree.clone()) {
+            continue;
+        }
+
+        let (_, content) = GitObject::read_raw(&tree)?;
+
+        if let Some(objects) = objects.as_deref_mut() {
+            objects.push(tree);
+        }
+
+        for (mode, _, hash) in GitObject::parse_tree_entries(&content)? {
+            match mode {
+                TreeFileModes::Directory => stack.push(hash),
+
+                // Submodule commits live in their own repositories
+                TreeFileModes::Gitlink => {}
+
+                _ => {
+                    if seen.insert(hash.clone()) {
+                        if let Some(objects) = objects.as_deref_mut() {
+                            objects.push(hash);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists a tree not in `seen` yet under `path`, then depth-first in entry order whatever
+/// in it is not in `seen` either, each with its path.
+fn list_tree(
+    tree: &str,
+    path: &str,
+    seen: &mut HashSet<String>,
+    listed: &mut Vec<(String, Option<String>)>,
+) -> anyhow::Result<()> {
+    if !seen.insert(tree.to_string()) {
+        return Ok(());
+    }
+
+    listed.push((tree.to_string(), Some(path.to_string())));
+
+    let (_, content) = GitObject::read_raw(tree)?;
+
+    for (mode, name, hash) in GitObject::parse_tree_entries(&content)? {
+        let entry_path = match path {
+            "" => name,
+
+            _ => format!("{path}/{name}"),
+        };
+
+        match mode {
+            TreeFileModes::Directory => list_tree(&hash, &entry_path, seen, listed)?,
+
+            TreeFileModes::Gitlink => {}
+
+            _ => {
+                if seen.insert(hash.clone()) {
+                    listed.push((hash, Some(entry_path)));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
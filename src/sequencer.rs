@@ -0,0 +1,700 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{anyhow, bail, ensure};
+
+use crate::config::Config;
+use crate::diff::resolve_tree;
+use crate::git_objects::{GitObject, Signature};
+use crate::merge::{merge_trees, write_tree_from_paths, MergeOptions, MergeResult};
+use crate::refs::{git_path, resolve_commit};
+use crate::utils::write_to_file;
+
+pub fn sequencer_dir() -> String {
+    git_path("sequencer")
+}
+
+pub fn rebase_dir() -> String {
+    git_path("rebase-merge")
+}
+
+/// Help appended to the todo list of an interactive rebase.
+const TODO_HELP: &str = "\
+#
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\" but keep only the previous
+#                    commit's log message
+# x, exec <command> = run command (the rest of the line) using shell
+# b, break = stop here (continue rebase later with 'git rebase --continue')
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Pick,
+
+    Revert,
+
+    Reword,
+
+    Edit,
+
+    Squash,
+
+    Fixup,
+
+    Drop,
+
+    Exec,
+
+    Break,
+}
+
+impl Action {
+    /// Command name, as used in reflog messages and hints.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+
+            Action::Revert => "revert",
+
+            _ => "rebase",
+        }
+    }
+
+    /// `CHERRY_PICK_HEAD` or `REVERT_HEAD`, naming the commit being applied.
+    pub fn head_file(&self) -> String {
+        match self {
+            Action::Revert => git_path("REVERT_HEAD"),
+
+            _ => git_path("CHERRY_PICK_HEAD"),
+        }
+    }
+
+    /// Whether the commit is melded into the one before it.
+    pub fn is_squash(&self) -> bool {
+        matches!(self, Action::Squash | Action::Fixup)
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Action::Pick => "pick",
+
+            Action::Revert => "revert",
+
+            Action::Reword => "reword",
+
+            Action::Edit => "edit",
+
+            Action::Squash => "squash",
+
+            Action::Fixup => "fixup",
+
+            Action::Drop => "drop",
+
+            Action::Exec => "exec",
+
+            Action::Break => "break",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// One line of a todo list: `pick <abbreviated hash> <subject>`, `exec <command>` or
+/// `break`.
+#[derive(Debug, Clone)]
+pub struct TodoItem {
+    pub action: Action,
+
+    /// Empty for `exec` and `break`.
+    pub commit: String,
+
+    /// The commit's subject, or the command to run for `exec`.
+    pub subject: String,
+}
+
+impl TodoItem {
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let line = line.trim();
+
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        let action = match name {
+            "pick" | "p" => Action::Pick,
+
+            "revert" => Action::Revert,
+
+            "reword" | "r" => Action::Reword,
+
+            "edit" | "e" => Action::Edit,
+
+            "squash" | "s" => Action::Squash,
+
+            "fixup" | "f" => Action::Fixup,
+
+            "drop" | "d" => Action::Drop,
+
+            "exec" | "x" => Action::Exec,
+
+            "break" | "b" => Action::Break,
+
+            _ => bail!("invalid line in todo list: {line}"),
+        };
+
+        match action {
+            Action::Exec => {
+                ensure!(!rest.trim().is_empty(), "missing command: {line}");
+
+                Ok(TodoItem {
+                    action,
+                    commit: String::new(),
+                    subject: rest.trim().to_string(),
+                })
+            }
+
+            Action::Break => Ok(TodoItem {
+                action,
+                commit: String::new(),
+                subject: String::new(),
+            }),
+
+            _ => {
+                let (commit, subject) = rest.split_once(' ').unwrap_or((rest, ""));
+
+                ensure!(!commit.is_empty(), "missing commit in todo list: {line}");
+
+                Ok(TodoItem {
+                    action,
+                    commit: resolve_commit(commit)?,
+                    subject: subject.to_string(),
+                })
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for TodoItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.action {
+            Action::Exec => write!(f, "exec {}", self.subject),
+
+            Action::Break => write!(f, "break"),
+
+            action => write!(f, "{action} {} {}", &self.commit[..7], self.subject),
+        }
+    }
+}
+
+/// Options a sequence was started with, kept in `.git/sequencer/opts` so that
+/// `--continue` carries on the same way.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequencerOptions {
+    /// `-x`: note the original commit in the message.
+    pub record_origin: bool,
+
+    /// `-m <parent>`: the parent a merge commit is compared against.
+    pub mainline: Option<usize>,
+
+    /// `-n`: apply the changes to the index and working tree without committing.
+    pub no_commit: bool,
+}
+
+impl SequencerOptions {
+    pub fn read() -> anyhow::Result<Self> {
+        let mut options = SequencerOptions::default();
+
+        let Ok(content) = fs::read_to_string(format!("{}/opts", sequencer_dir())) else {
+            return Ok(options);
+        };
+
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            match key.trim() {
+                "record-origin" => options.record_origin = value.trim() == "true",
+
+                "no-commit" => options.no_commit = value.trim() == "true",
+
+                "mainline" => options.mainline = Some(value.trim().parse()?),
+
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Writes the options in git's config format; nothing is written without any.
+    pub fn write(&self) -> anyhow::Result<()> {
+        let mut content = String::new();
+
+        if self.no_commit {
+            content.push_str("\tno-commit = true\n");
+        }
+
+        if let Some(mainline) = self.mainline {
+            content.push_str(&format!("\tmainline = {mainline}\n"));
+        }
+
+        if self.record_origin {
+            content.push_str("\trecord-origin = true\n");
+        }
+
+        if content.is_empty() {
+            return Ok(());
+        }
+
+        write_to_file(
+            &format!("{}/opts", sequencer_dir()),
+            format!("[options]\n{content}").as_bytes(),
+        )
+    }
+}
+
+pub fn in_progress() -> bool {
+    Path::new(&sequencer_dir()).is_dir()
+}
+
+/// Starts a sequence: the todo list and the commit `HEAD` pointed to beforehand.
+pub fn start(head: &str, todo: &[TodoItem], options: &SequencerOptions) -> anyhow::Result<()> {
+    fs::create_dir_all(sequencer_dir())?;
+
+    write_to_file(
+        &format!("{}/head", sequencer_dir()),
+        format!("{head}\n").as_bytes(),
+    )?;
+
+    write_todo(&todo_path(), todo)?;
+
+    options.write()
+}
+
+/// Commit `HEAD` pointed to when the sequence started.
+pub fn original_head() -> anyhow::Result<String> {
+    let head = fs::read_to_string(format!("{}/head", sequencer_dir()))
+        .map_err(|_| anyhow!("could not read {}/head", sequencer_dir()))?;
+
+    Ok(head.trim().to_string())
+}
+
+/// Todo list of a cherry-pick or revert.
+pub fn todo_path() -> String {
+    format!("{}/todo", sequencer_dir())
+}
+
+/// Reads a todo list, skipping blank and comment lines.
+pub fn read_todo(path: &str) -> anyhow::Result<Vec<TodoItem>> {
+    let content = fs::read_to_string(path).map_err(|_| anyhow!("could not read {path}"))?;
+
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(TodoItem::parse)
+        .collect()
+}
+
+pub fn write_todo(path: &str, todo: &[TodoItem]) -> anyhow::Result<()> {
+    let content: String = todo.iter().map(|item| format!("{item}\n")).collect();
+
+    write_to_file(path, content.as_bytes())
+}
+
+/// Removes the sequencer along with the files describing the commit being applied.
+pub fn remove_state() -> anyhow::Result<()> {
+    if in_progress() {
+        fs::remove_dir_all(sequencer_dir())?;
+    }
+
+    remove_pick_files();
+
+    Ok(())
+}
+
+/// Removes `CHERRY_PICK_HEAD`, `REVERT_HEAD` and the prepared `MERGE_MSG`.
+pub fn remove_pick_files() {
+    for file in [
+        Action::Pick.head_file(),
+        Action::Revert.head_file(),
+        git_path("MERGE_MSG"),
+    ] {
+        let _ = fs::remove_file(file);
+    }
+}
+
+/// Outcome of applying one commit on top of a tree.
+#[derive(Debug)]
+pub struct Picked {
+    pub result: MergeResult,
+
+    /// Message for the new commit.
+    pub message: String,
+
+    /// The original author for a cherry-pick; `None` (the current user) for a revert.
+    pub author: Option<Signature>,
+}
+
+/// Parent a commit's changes are taken relative to: the first, or the `-m` one for a
+/// merge, which requires it.
+pub fn mainline_parent(
+    commit: &str,
+    parents: &[String],
+    mainline: Option<usize>,
+) -> anyhow::Result<Option<String>> {
+    match (parents.len(), mainline) {
+        (count, None) if count > 1 => {
+            bail!("commit {commit} is a merge but no -m option was given.")
+        }
+
+        (count, Some(_)) if count < 2 => {
+            bail!("mainline was specified but commit {commit} is not a merge.")
+        }
+
+        (_, Some(mainline)) => parents
+            .get(mainline.wrapping_sub(1))
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| anyhow!("commit {commit} does not have parent {mainline}")),
+
+        (_, None) => Ok(parents.first().cloned()),
+    }
+}
+
+/// Applies the changes a commit made relative to its parent (or reverts them) to
+/// `ours_tree`, as a three-way merge whose base is the parent (or the commit itself).
+pub fn pick_commit(
+    action: Action,
+    commit: &str,
+    ours_tree: &str,
+    options: &SequencerOptions,
+    merge_options: &MergeOptions,
+) -> anyhow::Result<Picked> {
+    let GitObject::Commit {
+        tree,
+        parents,
+        message,
+        author,
+        ..
+    } = GitObject::from_hash(commit)?
+    else {
+        bail!("{commit} is not a commit");
+    };
+
+    let parent = mainline_parent(commit, &parents, options.mainline)?;
+
+    let parent_tree = match &parent {
+        Some(parent) => resolve_tree(parent)?,
+
+        None => write_tree_from_paths(&Default::default())?,
+    };
+
+    let subject = message.lines().next().unwrap_or_default().to_string();
+
+    let label = format!("{} ({subject})", &commit[..7]);
+
+    let parent_label = format!("parent of {label}");
+
+    let mut merge_options = merge_options.clone();
+
+    let (base, theirs) = match action {
+        Action::Revert => {
+            merge_options.base_label = label;
+            merge_options.theirs_label = parent_label;
+
+            (tree, parent_tree)
+        }
+
+        _ => {
+            merge_options.base_label = parent_label;
+            merge_options.theirs_label = label;
+
+            (parent_tree, tree)
+        }
+    };
+
+    let result = merge_trees(Some(&base), ours_tree, &theirs, &merge_options)?;
+
+    let (message, author) = match action {
+        Action::Revert => {
+            let mut message = format!("Revert \"{subject}\"\n\nThis reverts commit {commit}");
+
+            match (options.mainline, parent) {
+                (Some(_), Some(parent)) => {
+                    message.push_str(&format!(", reversing\nchanges made to {parent}.\n"))
+                }
+
+                _ => message.push_str(".\n"),
+            }
+
+            (message, None)
+        }
+
+        _ => {
+            let mut message = message.clone();
+
+            if options.record_origin {
+                if !message.ends_with('\n') {
+                    message.push('\n');
+                }
+
+                // Stacked origin lines form one paragraph
+                let last = message.trim_end().lines().last().unwrap_or_default();
+
+                if !last.starts_with("(cherry picked from commit ") {
+                    message.push('\n');
+                }
+
+                message.push_str(&format!("(cherry picked from commit {commit})\n"));
+            }
+
+            (message, Some(author))
+        }
+    };
+
+    Ok(Picked {
+        result,
+        message,
+        author,
+    })
+}
+
+/// State of a rebase, kept in `.git/rebase-merge` between stops.
+#[derive(Debug, Clone)]
+pub struct RebaseState {
+    /// The branch being rebased, `None` when rebasing a detached `HEAD`.
+    pub head_name: Option<String>,
+
+    pub onto: String,
+
+    pub orig_head: String,
+
+    pub interactive: bool,
+}
+
+impl RebaseState {
+    pub fn read() -> anyhow::Result<Self> {
+        let read = |name: &str| {
+            fs::read_to_string(format!("{}/{name}", rebase_dir()))
+                .map(|value| value.trim().to_string())
+                .map_err(|_| anyhow!("could not read {}/{name}", rebase_dir()))
+        };
+
+        let head_name = read("head-name")?;
+
+        Ok(RebaseState {
+            head_name: (head_name != "detached HEAD").then_some(head_name),
+            onto: read("onto")?,
+            orig_head: read("orig-head")?,
+            interactive: Path::new(&format!("{}/interactive", rebase_dir())).exists(),
+        })
+    }
+
+    pub fn write(&self) -> anyhow::Result<()> {
+        fs::create_dir_all(rebase_dir())?;
+
+        let head_name = self.head_name.as_deref().unwrap_or("detached HEAD");
+
+        for (name, value) in [
+            ("head-name", head_name),
+            ("onto", &self.onto),
+            ("orig-head", &self.orig_head),
+        ] {
+            write_to_file(
+                &format!("{}/{name}", rebase_dir()),
+                format!("{value}\n").as_bytes(),
+            )?;
+        }
+
+        if self.interactive {
+            write_to_file(&format!("{}/interactive", rebase_dir()), b"")?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn rebase_in_progress() -> bool {
+    Path::new(&rebase_dir()).is_dir()
+}
+
+/// Path of a file in the rebase state directory.
+pub fn rebase_path(name: &str) -> String {
+    format!("{}/{name}", rebase_dir())
+}
+
+/// Writes the todo list of an interactive rebase with its help text, lets the user
+/// edit it with the sequence editor, then reads it back.
+pub fn edit_todo(todo: &[TodoItem], description: &str) -> anyhow::Result<Vec<TodoItem>> {
+    let path = rebase_path("git-rebase-todo");
+
+    let mut content: String = todo.iter().map(|item| format!("{item}\n")).collect();
+
+    content.push_str(&format!(
+        "\n# Rebase {description} ({} commands)\n{TODO_HELP}",
+        todo.len()
+    ));
+
+    write_to_file(&path, content.as_bytes())?;
+
+    launch_editor(&path, true)?;
+
+    read_todo(&path)
+}
+
+/// Moves `fixup! <subject>` and `squash! <subject>` commits right after the commit
+/// they name (by subject or hash prefix), turning them into fixups and squashes.
+pub fn autosquash(todo: Vec<TodoItem>) -> Vec<TodoItem> {
+    let mut result: Vec<TodoItem> = Vec::new();
+
+    let mut pending: Vec<(usize, TodoItem)> = Vec::new();
+
+    for mut item in todo {
+        let target = [("fixup! ", Action::Fixup), ("squash! ", Action::Squash)]
+            .iter()
+            .find_map(|(prefix, action)| {
+                let mut name = item.subject.strip_prefix(prefix)?;
+
+                // `fixup! fixup! x` targets x as well
+                while let Some(rest) = ["fixup! ", "squash! "]
+                    .iter()
+                    .find_map(|prefix| name.strip_prefix(prefix))
+                {
+                    name = rest;
+                }
+
+                let position = result.iter().position(|other| {
+                    !other.action.is_squash()
+                        && (other.subject == name
+                            || (name.len() >= 4 && other.commit.starts_with(name)))
+                })?;
+
+                Some((position, *action))
+            });
+
+        match target {
+            Some((position, action)) => {
+                item.action = action;
+
+                pending.push((position, item));
+            }
+
+            None => result.push(item),
+        }
+    }
+
+    // Inserted back to front so that earlier positions stay valid, each group in order
+    pending.sort_by_key(|(position, _)| std::cmp::Reverse(*position));
+
+    let mut index = 0;
+
+    while index < pending.len() {
+        let position = pending[index].0;
+
+        let group: Vec<TodoItem> = pending[index..]
+            .iter()
+            .take_while(|(other, _)| *other == position)
+            .map(|(_, item)| item.clone())
+            .collect();
+
+        index += group.len();
+
+        result.splice(position + 1..position + 1, group);
+    }
+
+    result
+}
+
+/// Opens a file in the user's editor: for todo lists (`sequence`) `GIT_SEQUENCE_EDITOR`
+/// or `sequence.editor` first, then `GIT_EDITOR`, `core.editor`, `VISUAL` and `EDITOR`.
+pub fn launch_editor(path: &str, sequence: bool) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    let from_env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+    let sequence_editor = if sequence {
+        from_env("GIT_SEQUENCE_EDITOR")
+            .or_else(|| config.get("sequence.editor").map(str::to_string))
+    } else {
+        None
+    };
+
+    let editor = sequence_editor
+        .or_else(|| from_env("GIT_EDITOR"))
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| from_env("VISUAL"))
+        .or_else(|| from_env("EDITOR"))
+        .unwrap_or_else(|| "vi".to_string());
+
+    if editor == ":" {
+        return Ok(());
+    }
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(path)
+        .status()
+        .map_err(|_| anyhow!("unable to start editor '{editor}'"))?;
+
+    ensure!(
+        status.success(),
+        "there was a problem with the editor '{editor}'"
+    );
+
+    Ok(())
+}
+
+/// Lets the user edit a commit message in `.git/COMMIT_EDITMSG`, returning it without
+/// comment lines; an empty message aborts.
+pub fn edit_message(message: &str) -> anyhow::Result<String> {
+    let path = git_path("COMMIT_EDITMSG");
+
+    write_to_file(&path, message.as_bytes())?;
+
+    launch_editor(&path, false)?;
+
+    let message = strip_comments(&fs::read_to_string(&path)?);
+
+    ensure!(
+        !message.trim().is_empty(),
+        "Aborting commit due to empty commit message."
+    );
+
+    Ok(message)
+}
+
+/// Cleans up a message the way git does: drops `#` comment lines and trailing
+/// whitespace, and collapses runs of blank lines.
+pub fn strip_comments(message: &str) -> String {
+    let mut kept = String::new();
+
+    for line in message.lines().filter(|line| !line.starts_with('#')) {
+        let line = line.trim_end();
+
+        if line.is_empty() && (kept.is_empty() || kept.ends_with("\n\n")) {
+            continue;
+        }
+
+        kept.push_str(line);
+        kept.push('\n');
+    }
+
+    format!("{}\n", kept.trim_end())
+}
@@ -0,0 +1,430 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::gc::loose_objects;
+use crate::git_objects::GitObject;
+use crate::pack::{pack_dir, packed_object_exists, packs, PackIndex};
+use crate::refs::{git_path, list_refs, resolve_ref, HEAD};
+use crate::utils::{object_directories, to_hex_string};
+
+/// Extensions of the files that make up a pack.
+const PACK_EXTENSIONS: [&str; 7] = ["pack", "idx", "keep", "promisor", "bitmap", "rev", "mtimes"];
+
+/// What `count-objects` reports about the repository's own object directory, sizes
+/// in bytes.
+#[derive(Default)]
+pub struct ObjectCounts {
+    /// Loose objects, and the disk space they take.
+    pub count: usize,
+
+    pub size: u64,
+
+    /// Objects in packs, how many packs there are and the size of their `.pack` and
+    /// `.idx` files.
+    pub in_pack: usize,
+
+    pub packs: usize,
+
+    pub size_pack: u64,
+
+    /// Loose objects that a pack holds as well.
+    pub prune_packable: usize,
+
+    /// `(what is wrong, path)` of files that are neither objects nor part of a complete
+    /// pack, and their total size.
+    pub garbage: Vec<(&'static str, String)>,
+
+    pub size_garbage: u64,
+}
+
+pub fn count_objects() -> anyhow::Result<ObjectCounts> {
+    let mut counts = ObjectCounts::default();
+
+    let mut entries: Vec<_> = fs::read_dir(pack_dir())
+        .map(|entries| entries.flatten().collect())
+        .unwrap_or_default();
+
+    entries.sort_by_key(|entry| entry.file_name());
+
+    // Files of each pack by extension, the name without extension being the key
+    let mut groups: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        let path = entry.path().to_string_lossy().to_string();
+
+        match name.rsplit_once('.') {
+            Some((base, extension)) if PACK_EXTENSIONS.contains(&extension) => groups
+                .entry(base.to_string())
+                .or_default()
+                .push((extension.to_string(), path)),
+
+            _ if name == "multi-pack-index" => {}
+
+            _ => {
+                counts.size_garbage += entry.metadata()?.len();
+
+                counts.garbage.push(("garbage found", path));
+            }
+        }
+    }
+
+    for files in groups.values() {
+        let path_of = |wanted: &str| {
+            files
+                .iter()
+                .find(|(extension, _)| extension == wanted)
+                .map(|(_, path)| path)
+        };
+
+        let problem = match (path_of("idx"), path_of("pack")) {
+            (Some(index), Some(pack)) => {
+                counts.packs += 1;
+
+                counts.in_pack += PackIndex::read(index)?.hashes.len();
+
+                counts.size_pack += fs::metadata(pack)?.len() + fs::metadata(index)?.len();
+
+                continue;
+            }
+
+            (Some(_), None) => "no corresponding .pack",
+
+            (None, Some(_)) => "no corresponding .idx",
+
+            (None, None) => "no corresponding .idx or .pack",
+        };
+
+        for (_, path) in files {
+            counts.size_garbage += fs::metadata(path)?.len();
+
+            counts.garbage.push((problem, path.clone()));
+        }
+    }
+
+    // Reported once the loose objects are looked up in the packs
+    let mut pack_garbage = std::mem::take(&mut counts.garbage);
+
+    let mut directories: Vec<_> = fs::read_dir(git_path("objects"))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+
+    directories.sort();
+
+    for directory in directories {
+        let prefix = directory.file_name().unwrap_or_default().to_string_lossy();
+
+        if prefix.len() != 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+
+        let mut files: Vec<_> = fs::read_dir(&directory)?.flatten().collect();
+
+        files.sort_by_key(|file| file.file_name());
+
+        for file in files {
+            let rest = file.file_name().to_string_lossy().to_string();
+
+            let metadata = file.metadata()?;
+
+            if rest.len() != 38 || !rest.chars().all(|c| c.is_ascii_hexdigit()) {
+                counts.size_garbage += metadata.len();
+
+                counts
+                    .garbage
+                    .push(("garbage found", file.path().to_string_lossy().to_string()));
+
+                continue;
+            }
+
+            // Like git, which finds out about the packs on the first lookup in them
+            counts.garbage.append(&mut pack_garbage);
+
+            counts.count += 1;
+
+            counts.size += metadata.blocks() * 512;
+
+            if packed_object_exists(&format!("{prefix}{rest}")) {
+                counts.prune_packable += 1;
+            }
+        }
+    }
+
+    counts.garbage.append(&mut pack_garbage);
+
+    Ok(counts)
+}
+
+/// What `stats` reports: totals per object type, where objects are stored, and the
+/// objects and paths that take the most room. Lists are the largest first and hold
+/// at most the number of entries asked for.
+pub struct Stats {
+    /// `(type, count, total size)` for commits, trees, blobs and tags.
+    pub kinds: Vec<(&'static str, usize, u64)>,
+
+    /// Loose and packed copies of objects, and the number of packs.
+    pub loose: usize,
+
+    pub packed: usize,
+
+    pub packs: usize,
+
+    /// `(size, hash, path)` of the biggest blobs, with a path they are found at.
+    pub largest_blobs: Vec<(u64, String, Option<String>)>,
+
+    /// `(depth, hash, path)` of the most deeply nested directories.
+    pub deepest_trees: Vec<(usize, String, String)>,
+
+    /// `(length, hash, type, path)` of the longest delta chains in the packs.
+    pub delta_chains: Vec<(usize, String, String, Option<String>)>,
+
+    /// `(total size, versions, path)` of the files whose versions take the most room.
+    pub path_growth: Vec<(u64, usize, String)>,
+}
+
+/// Gathers [`Stats`] over every object directory, following the history of the refs
+/// for paths.
+pub fn stats(limit: usize) -> anyhow::Result<Stats> {
+    // Type and size of every object
+    let mut objects: HashMap<String, (String, u64)> = HashMap::new();
+
+    let mut loose = 0;
+
+    for directory in object_directories() {
+        if !Path::new(&directory).is_dir() {
+            continue;
+        }
+
+        for (hash, _) in loose_objects(&directory)? {
+            loose += 1;
+
+            if let Entry::Vacant(entry) = objects.entry(hash) {
+                let (kind, content) = GitObject::read_raw(entry.key())?;
+
+                entry.insert((kind, content.len() as u64));
+            }
+        }
+    }
+
+    let packs = packs()?;
+
+    let mut packed = 0;
+
+    let mut delta_chains = Vec::new();
+
+    for pack in &packs {
+        for (id, &offset) in pack.hashes.iter().zip(&pack.offsets) {
+            let hash = to_hex_string(id);
+
+            packed += 1;
+
+            let (kind, content) = pack.read_object(offset)?;
+
+            let depth = pack.delta_depth(offset)?;
+
+            if depth > 0 {
+                delta_chains.push((depth, hash.clone(), kind.clone()));
+            }
+
+            objects.entry(hash).or_insert((kind, content.len() as u64));
+        }
+    }
+
+    let history = History::walk(&objects)?;
+
+    let kinds = ["commit", "tree", "blob", "tag"]
+        .into_iter()
+        .map(|kind| {
+            let sizes: Vec<u64> = objects
+                .values()
+                .filter(|(other, _)| other == kind)
+                .map(|(_, size)| *size)
+                .collect();
+
+            (kind, sizes.len(), sizes.iter().sum())
+        })
+        .collect();
+
+    let mut largest_blobs: Vec<_> = objects
+        .iter()
+        .filter(|(_, (kind, _))| kind == "blob")
+        .map(|(hash, (_, size))| (*size, hash.clone(), history.paths.get(hash).cloned()))
+        .collect();
+
+    largest_blobs.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    largest_blobs.truncate(limit);
+
+    let mut deepest_trees: Vec<_> = history
+        .directories
+        .into_iter()
+        .filter(|(path, _)| !path.is_empty())
+        .map(|(path, hash)| (path.split('/').count(), hash, path))
+        .collect();
+
+    deepest_trees.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.2.cmp(&b.2)));
+
+    deepest_trees.truncate(limit);
+
+    delta_chains.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    delta_chains.truncate(limit);
+
+    let delta_chains = delta_chains
+        .into_iter()
+        .map(|(depth, hash, kind)| {
+            let path = history.paths.get(&hash).cloned();
+
+            (depth, hash, kind, path)
+        })
+        .collect();
+
+    let mut path_growth: Vec<_> = history
+        .versions
+        .into_iter()
+        .map(|(path, blobs)| {
+            let total: u64 = blobs.iter().map(|blob| objects[blob].1).sum();
+
+            (total, blobs.len(), path)
+        })
+        .collect();
+
+    path_growth.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.2.cmp(&b.2)));
+
+    path_growth.truncate(limit);
+
+    Ok(Stats {
+        kinds,
+        loose,
+        packed,
+        packs: packs.len(),
+        largest_blobs,
+        deepest_trees,
+        delta_chains,
+        path_growth,
+    })
+}
+
+/// Where trees and blobs appear in the history of the refs.
+struct History {
+    /// A path each tree or blob is found at.
+    paths: HashMap<String, String>,
+
+    /// The first tree found at each directory path, the root being `""`.
+    directories: BTreeMap<String, String>,
+
+    /// The blobs found at each file path.
+    versions: BTreeMap<String, HashSet<String>>,
+}
+
+impl History {
+    /// Walks the commits reachable from the refs and `HEAD` and their trees. Objects
+    /// that are not there, as in shallow and partial clones, are skipped.
+    fn walk(objects: &HashMap<String, (String, u64)>) -> anyhow::Result<Self> {
+        let mut history = History {
+            paths: HashMap::new(),
+            directories: BTreeMap::new(),
+            versions: BTreeMap::new(),
+        };
+
+        let mut queue: VecDeque<String> = list_refs("refs/")?
+            .into_iter()
+            .map(|(_, hash)| hash)
+            .collect();
+
+        queue.extend(resolve_ref(HEAD)?);
+
+        let mut seen = HashSet::new();
+
+        let mut trees = HashSet::new();
+
+        while let Some(hash) = queue.pop_front() {
+            let Some((kind, _)) = objects.get(&hash) else {
+                continue;
+            };
+
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+
+            match kind.as_str() {
+                "commit" => {
+                    if let GitObject::Commit { tree, parents, .. } = GitObject::from_hash(&hash)? {
+                        history.walk_tree(objects, &mut trees, &tree)?;
+
+                        queue.extend(parents);
+                    }
+                }
+
+                "tag" => {
+                    if let GitObject::Tag { object, .. } = GitObject::from_hash(&hash)? {
+                        queue.push_back(object);
+                    }
+                }
+
+                "tree" => history.walk_tree(objects, &mut trees, &hash)?,
+
+                _ => {}
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// Records the directories and files of a root tree. `seen` holds the trees already
+    /// walked at each path.
+    fn walk_tree(
+        &mut self,
+        objects: &HashMap<String, (String, u64)>,
+        seen: &mut HashSet<(String, String)>,
+        root: &str,
+    ) -> anyhow::Result<()> {
+        let mut stack = vec![(root.to_string(), String::new())];
+
+        while let Some((tree, path)) = stack.pop() {
+            if !seen.insert((tree.clone(), path.clone())) {
+                continue;
+            }
+
+            self.directories
+                .entry(path.clone())
+                .or_insert_with(|| tree.clone());
+
+            let (_, content) = GitObject::read_raw(&tree)?;
+
+            if !path.is_empty() {
+                self.paths.entry(tree).or_insert_with(|| path.clone());
+            }
+
+            for (_, name, child) in GitObject::parse_tree_entries(&content)? {
+                let child_path = match path.is_empty() {
+                    true => name,
+
+                    false => format!("{path}/{name}"),
+                };
+
+                match objects.get(&child).map(|(kind, _)| kind.as_str()) {
+                    Some("tree") => stack.push((child, child_path)),
+
+                    Some("blob") => {
+                        self.paths
+                            .entry(child.clone())
+                            .or_insert_with(|| child_path.clone());
+
+                        self.versions.entry(child_path).or_default().insert(child);
+                    }
+
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
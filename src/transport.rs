@@ -0,0 +1,885 @@
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure};
+
+use crate::http::{self, Url};
+use crate::pack::{index_pack, pack_dir};
+use crate::pkt_line::{Packet, PacketReader, PacketWriter};
+use crate::refs::{find_repository, in_repository, shallow_commits, write_shallow_commits};
+use crate::utils::write_to_file;
+use crate::{receive_pack, upload_pack};
+
+pub const UPLOAD_PACK: &str = "git-upload-pack";
+
+pub const RECEIVE_PACK: &str = "git-receive-pack";
+
+pub const AGENT: &str = "agent=git/codecrafters";
+
+pub const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// Number of `have` lines sent in each negotiation round.
+const HAVES_PER_ROUND: usize = 32;
+
+/// Moves protocol messages to and from a remote service in the stateless style of the
+/// smart HTTP protocol: one advertisement, then whole requests answered by whole responses.
+pub trait Transport {
+    /// The service's initial advertisement, as pkt-lines.
+    fn advertisement(&mut self, service: &str) -> anyhow::Result<Vec<u8>>;
+
+    fn request(&mut self, service: &str, body: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Smart HTTP, asking for protocol version 2.
+pub struct HttpTransport {
+    url: Url,
+
+    display_url: String,
+}
+
+impl HttpTransport {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        Ok(HttpTransport {
+            url: Url::parse(url)?,
+            display_url: url.to_string(),
+        })
+    }
+
+    fn check_status(&self, response: &http::Response) -> anyhow::Result<()> {
+        match response.status {
+            200 => Ok(()),
+
+            404 => bail!("repository '{}' not found", self.display_url),
+
+            status => bail!(
+                "unable to access '{}': The requested URL returned error: {status}",
+                self.display_url
+            ),
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn advertisement(&mut self, service: &str) -> anyhow::Result<Vec<u8>> {
+        let response = http::request(
+            "GET",
+            &self.url,
+            &format!("{}/info/refs?service={service}", self.url.path),
+            &[("Git-Protocol", "version=2"), ("Pragma", "no-cache")],
+            None,
+        )?;
+
+        self.check_status(&response)?;
+
+        ensure!(
+            response.header("Content-Type")
+                == Some(&format!("application/x-{service}-advertisement")),
+            "{}/info/refs not valid: is this a git repository?",
+            self.display_url
+        );
+
+        // Version 0 advertisements open with `# service=<service>` and a flush
+        let mut reader = PacketReader::new(&response.body);
+
+        let first = reader.next_packet()?.text().unwrap_or_default();
+
+        if first != format!("# service={service}") {
+            return Ok(response.body);
+        }
+
+        ensure!(
+            reader.next_packet()? == Packet::Flush,
+            "invalid server response; expected flush after ref listing"
+        );
+
+        Ok(reader.remaining().to_vec())
+    }
+
+    fn request(&mut self, service: &str, body: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let content_type = format!("application/x-{service}-request");
+
+        let accept = format!("application/x-{service}-result");
+
+        let response = http::request(
+            "POST",
+            &self.url,
+            &format!("{}/{service}", self.url.path),
+            &[
+                ("Content-Type", &content_type),
+                ("Accept", &accept),
+                ("Git-Protocol", "version=2"),
+            ],
+            Some(body),
+        )?;
+
+        self.check_status(&response)?;
+
+        Ok(response.body)
+    }
+}
+
+/// A repository on this machine, served by running upload-pack or receive-pack in this
+/// process.
+pub struct LocalTransport {
+    /// Absolute path of the repository directory (`.git` or a bare repository).
+    git_dir: PathBuf,
+}
+
+impl LocalTransport {
+    pub fn new(path: &str) -> anyhow::Result<Self> {
+        Ok(LocalTransport {
+            git_dir: find_repository(path)?,
+        })
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+}
+
+impl Transport for LocalTransport {
+    fn advertisement(&mut self, service: &str) -> anyhow::Result<Vec<u8>> {
+        match service {
+            UPLOAD_PACK => in_repository(&self.git_dir, || Ok(upload_pack::advertisement())),
+
+            RECEIVE_PACK => in_repository(&self.git_dir, receive_pack::advertisement),
+
+            _ => bail!("unsupported service '{service}'"),
+        }
+    }
+
+    fn request(&mut self, service: &str, body: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match service {
+            UPLOAD_PACK => in_repository(&self.git_dir, || upload_pack::handle_request(body)),
+
+            RECEIVE_PACK => in_repository(&self.git_dir, || receive_pack::handle_request(body)),
+
+            _ => bail!("unsupported service '{service}'"),
+        }
+    }
+}
+
+/// A ref as the remote advertises it.
+#[derive(Debug, Clone)]
+pub struct RemoteRef {
+    pub name: String,
+
+    pub hash: String,
+
+    /// What an annotated tag points at.
+    pub peeled: Option<String>,
+
+    /// Target of a symbolic ref such as `HEAD`.
+    pub symref_target: Option<String>,
+}
+
+/// Depth passed to deepen a shallow repository's history all the way.
+pub const INFINITE_DEPTH: usize = 0x7fffffff;
+
+/// What a fetch asks for besides its wants: how much history to send, and which objects
+/// a partial clone leaves out.
+#[derive(Debug, Default, Clone)]
+pub struct FetchOptions {
+    /// Commits the repository has without their parents.
+    pub shallow: Vec<String>,
+
+    pub depth: Option<usize>,
+
+    /// Whether `depth` counts on from the shallow commits rather than from the wants.
+    pub deepen_relative: bool,
+
+    pub deepen_since: Option<i64>,
+
+    /// Refs whose history is not wanted.
+    pub deepen_not: Vec<String>,
+
+    /// Filter spec such as `blob:none`.
+    pub filter: Option<String>,
+}
+
+impl FetchOptions {
+    pub fn deepens(&self) -> bool {
+        self.depth.is_some() || self.deepen_since.is_some() || !self.deepen_not.is_empty()
+    }
+
+    /// Whether the fetch involves shallow history at all.
+    fn is_shallow(&self) -> bool {
+        self.deepens() || !self.shallow.is_empty()
+    }
+
+    /// Request lines for the shallow commits and the history cut.
+    fn shallow_lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .shallow
+            .iter()
+            .map(|commit| format!("shallow {commit}"))
+            .collect();
+
+        lines.extend(self.depth.map(|depth| format!("deepen {depth}")));
+
+        lines.extend(
+            self.deepen_since
+                .map(|since| format!("deepen-since {since}")),
+        );
+
+        lines.extend(
+            self.deepen_not
+                .iter()
+                .map(|name| format!("deepen-not {name}")),
+        );
+
+        lines
+    }
+}
+
+/// A pack fetched from upload-pack, with the changes to the shallow commits it comes with.
+#[derive(Debug, Default)]
+pub struct FetchedPack {
+    pack: Vec<u8>,
+
+    /// Commits to record as shallow.
+    shallow: Vec<String>,
+
+    /// Shallow commits whose parents are now in the repository.
+    unshallow: Vec<String>,
+}
+
+impl FetchedPack {
+    /// Stores the pack and records the new shallow commits. A pack from a promisor remote,
+    /// whose objects may be left out, is marked with the refs it was fetched for.
+    pub fn store(&self, promisor_refs: Option<&[&RemoteRef]>) -> anyhow::Result<()> {
+        let checksum = index_pack(&self.pack)?;
+
+        if let Some(refs) = promisor_refs {
+            let content: String = refs
+                .iter()
+                .map(|remote_ref| format!("{} {}\n", remote_ref.hash, remote_ref.name))
+                .collect();
+
+            write_to_file(
+                &format!("{}/pack-{checksum}.promisor", pack_dir()),
+                content.as_bytes(),
+            )?;
+        }
+
+        if self.shallow.is_empty() && self.unshallow.is_empty() {
+            return Ok(());
+        }
+
+        let mut shallow = shallow_commits();
+
+        shallow.extend(self.shallow.iter().cloned());
+
+        for commit in &self.unshallow {
+            shallow.remove(commit);
+        }
+
+        write_shallow_commits(&shallow)
+    }
+
+    /// Takes a `shallow` or `unshallow` line, returning whether it was one.
+    fn parse_shallow(&mut self, line: &str) -> bool {
+        if let Some(commit) = line.strip_prefix("shallow ") {
+            self.shallow.push(commit.to_string());
+        } else if let Some(commit) = line.strip_prefix("unshallow ") {
+            self.unshallow.push(commit.to_string());
+        } else {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// A conversation with a remote upload-pack, in whichever protocol version it speaks.
+pub struct Connection {
+    transport: Box<dyn Transport>,
+
+    version: u8,
+
+    capabilities: Vec<String>,
+
+    /// Refs from a version 0 advertisement; version 2 lists them on request.
+    advertised: Vec<RemoteRef>,
+}
+
+impl Connection {
+    pub fn open(mut transport: Box<dyn Transport>) -> anyhow::Result<Self> {
+        let advertisement = transport.advertisement(UPLOAD_PACK)?;
+
+        let mut reader = PacketReader::new(&advertisement);
+
+        let (lines, _) = reader.read_lines()?;
+
+        if lines.first().map(String::as_str) == Some("version 2") {
+            return Ok(Connection {
+                transport,
+                version: 2,
+                capabilities: lines[1..].to_vec(),
+                advertised: Vec::new(),
+            });
+        }
+
+        let (advertised, capabilities) = parse_v0_advertisement(&lines)?;
+
+        Ok(Connection {
+            transport,
+            version: 0,
+            capabilities,
+            advertised,
+        })
+    }
+
+    fn has_capability(&self, name: &str) -> bool {
+        has_capability(&self.capabilities, name)
+    }
+
+    /// Whether the remote's fetch takes `feature`, which version 2 lists as a value of
+    /// the `fetch` capability and version 0 as a capability of its own.
+    fn supports(&self, feature: &str) -> bool {
+        if self.version == 0 {
+            return self.has_capability(feature);
+        }
+
+        self.capabilities
+            .iter()
+            .filter_map(|capability| capability.strip_prefix("fetch="))
+            .any(|features| features.split(' ').any(|f| f == feature))
+    }
+
+    /// Drops what the remote cannot do from `options`, failing when that would change
+    /// how much history it sends.
+    fn check_options(&self, options: &FetchOptions) -> anyhow::Result<FetchOptions> {
+        let mut options = options.clone();
+
+        ensure!(
+            !options.is_shallow() || self.supports("shallow"),
+            "Server does not support shallow clients"
+        );
+
+        // Version 2 servers that do shallow fetches do all of these
+        for (capability, option, used) in [
+            (
+                "deepen-since",
+                "--shallow-since",
+                options.deepen_since.is_some(),
+            ),
+            (
+                "deepen-not",
+                "--shallow-exclude",
+                !options.deepen_not.is_empty(),
+            ),
+            ("deepen-relative", "--deepen", options.deepen_relative),
+        ] {
+            ensure!(
+                !used || self.version == 2 || self.supports(capability),
+                "Server does not support {option}"
+            );
+        }
+
+        if options.filter.is_some() && !self.supports("filter") {
+            eprintln!("warning: filtering not recognized by server, ignoring");
+
+            options.filter = None;
+        }
+
+        Ok(options)
+    }
+
+    /// Refs on the remote under any of `prefixes`, with `HEAD` when it is asked for.
+    pub fn list_refs(&mut self, prefixes: &[&str]) -> anyhow::Result<Vec<RemoteRef>> {
+        let wanted = |name: &str| prefixes.iter().any(|prefix| name.starts_with(prefix));
+
+        if self.version == 0 {
+            return Ok(self
+                .advertised
+                .iter()
+                .filter(|remote_ref| wanted(&remote_ref.name))
+                .cloned()
+                .collect());
+        }
+
+        let mut request = PacketWriter::new();
+
+        request
+            .line("command=ls-refs")
+            .line(AGENT)
+            .delim()
+            .line("peel")
+            .line("symrefs");
+
+        for prefix in prefixes {
+            request.line(&format!("ref-prefix {prefix}"));
+        }
+
+        request.flush();
+
+        let response = self.transport.request(UPLOAD_PACK, &request.into_bytes())?;
+
+        let (lines, _) = PacketReader::new(&response).read_lines()?;
+
+        let mut refs = Vec::new();
+
+        for line in lines {
+            let mut fields = line.split(' ');
+
+            let (Some(hash), Some(name)) = (fields.next(), fields.next()) else {
+                bail!("invalid ls-refs response: {line}");
+            };
+
+            let mut remote_ref = RemoteRef {
+                name: name.to_string(),
+                hash: hash.to_string(),
+                peeled: None,
+                symref_target: None,
+            };
+
+            for attribute in fields {
+                if let Some(target) = attribute.strip_prefix("symref-target:") {
+                    remote_ref.symref_target = Some(target.to_string());
+                } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                    remote_ref.peeled = Some(peeled.to_string());
+                }
+            }
+
+            if wanted(&remote_ref.name) {
+                refs.push(remote_ref);
+            }
+        }
+
+        Ok(refs)
+    }
+
+    /// Negotiates with the remote and returns a pack holding `wants` and everything they
+    /// reach that is not reachable from `haves` (given newest first), as far as `options`
+    /// let history and objects go.
+    pub fn fetch_pack(
+        &mut self,
+        wants: &[String],
+        haves: &[String],
+        options: &FetchOptions,
+    ) -> anyhow::Result<FetchedPack> {
+        ensure!(!wants.is_empty(), "nothing to fetch");
+
+        let options = self.check_options(options)?;
+
+        if self.version == 0 {
+            return self.fetch_pack_v0(wants, haves, &options);
+        }
+
+        let mut fetched = FetchedPack::default();
+
+        let mut common: Vec<String> = Vec::new();
+
+        let mut remaining = haves.iter();
+
+        loop {
+            let round: Vec<&String> = remaining.by_ref().take(HAVES_PER_ROUND).collect();
+
+            // Out of things to offer: ask for the pack with what is known to be common
+            let done = round.is_empty();
+
+            let mut request = PacketWriter::new();
+
+            request
+                .line("command=fetch")
+                .line(AGENT)
+                .delim()
+                .line("ofs-delta")
+                .line("include-tag");
+
+            for want in wants {
+                request.line(&format!("want {want}"));
+            }
+
+            for have in common.iter().chain(round.iter().copied()) {
+                request.line(&format!("have {have}"));
+            }
+
+            for line in options.shallow_lines() {
+                request.line(&line);
+            }
+
+            // Version 0 asks for this as a capability instead
+            if options.deepen_relative {
+                request.line("deepen-relative");
+            }
+
+            if let Some(filter) = &options.filter {
+                request.line(&format!("filter {filter}"));
+            }
+
+            if done {
+                request.line("done");
+            }
+
+            request.flush();
+
+            let response = self.transport.request(UPLOAD_PACK, &request.into_bytes())?;
+
+            let mut reader = PacketReader::new(&response);
+
+            while !reader.is_empty() {
+                let section = reader.next_packet()?.text().unwrap_or_default();
+
+                if let Some(message) = section.strip_prefix("ERR ") {
+                    bail!("remote error: {message}");
+                }
+
+                if section == "packfile" {
+                    fetched.pack = reader.read_sideband(std::io::stderr().is_terminal())?;
+
+                    return Ok(fetched);
+                }
+
+                ensure!(
+                    matches!(
+                        section.as_str(),
+                        "acknowledgments" | "shallow-info" | "wanted-refs" | "packfile-uris"
+                    ),
+                    "expected section header, got '{section}'"
+                );
+
+                let (lines, end) = reader.read_lines()?;
+
+                if section == "acknowledgments" {
+                    common.extend(
+                        lines
+                            .iter()
+                            .filter_map(|line| line.strip_prefix("ACK "))
+                            .map(str::to_string),
+                    );
+                }
+
+                if section == "shallow-info" {
+                    for line in &lines {
+                        ensure!(
+                            fetched.parse_shallow(line),
+                            "expected shallow/unshallow, got '{line}'"
+                        );
+                    }
+                }
+
+                if end == Packet::Flush {
+                    break;
+                }
+            }
+
+            ensure!(!done, "expected packfile in fetch response");
+        }
+    }
+
+    /// Version 0 has the server wait for `done` in stateless mode, so every `have` goes in
+    /// a single request.
+    fn fetch_pack_v0(
+        &mut self,
+        wants: &[String],
+        haves: &[String],
+        options: &FetchOptions,
+    ) -> anyhow::Result<FetchedPack> {
+        let mut capabilities = vec![AGENT];
+
+        for capability in ["side-band-64k", "ofs-delta", "include-tag"] {
+            if self.has_capability(capability) {
+                capabilities.push(capability);
+            }
+        }
+
+        if options.is_shallow() {
+            capabilities.push("shallow");
+        }
+
+        for (capability, used) in [
+            ("deepen-since", options.deepen_since.is_some()),
+            ("deepen-not", !options.deepen_not.is_empty()),
+            ("deepen-relative", options.deepen_relative),
+            ("filter", options.filter.is_some()),
+        ] {
+            if used {
+                capabilities.push(capability);
+            }
+        }
+
+        let mut request = PacketWriter::new();
+
+        for (index, want) in wants.iter().enumerate() {
+            match index {
+                0 => request.line(&format!("want {want} {}", capabilities.join(" "))),
+
+                _ => request.line(&format!("want {want}")),
+            };
+        }
+
+        for line in options.shallow_lines() {
+            request.line(&line);
+        }
+
+        if let Some(filter) = &options.filter {
+            request.line(&format!("filter {filter}"));
+        }
+
+        request.flush();
+
+        for have in haves {
+            request.line(&format!("have {have}"));
+        }
+
+        request.line("done");
+
+        let response = self.transport.request(UPLOAD_PACK, &request.into_bytes())?;
+
+        let mut reader = PacketReader::new(&response);
+
+        let mut fetched = FetchedPack::default();
+
+        // The history cut comes first, ended by a flush
+        if options.deepens() {
+            loop {
+                match reader.next_packet()? {
+                    Packet::Flush => break,
+
+                    packet => {
+                        let line = packet.text().unwrap_or_default();
+
+                        if let Some(message) = line.strip_prefix("ERR ") {
+                            bail!("remote error: {message}");
+                        }
+
+                        ensure!(
+                            fetched.parse_shallow(&line),
+                            "expected shallow/unshallow, got '{line}'"
+                        );
+                    }
+                }
+            }
+        }
+
+        loop {
+            let line = reader.next_packet()?.text().unwrap_or_default();
+
+            if line == "NAK" || (line.starts_with("ACK ") && line.split(' ').count() == 2) {
+                break;
+            }
+
+            if let Some(message) = line.strip_prefix("ERR ") {
+                bail!("remote error: {message}");
+            }
+        }
+
+        fetched.pack = if self.has_capability("side-band-64k") {
+            reader.read_sideband(std::io::stderr().is_terminal())?
+        } else {
+            reader.remaining().to_vec()
+        };
+
+        Ok(fetched)
+    }
+}
+
+/// One ref change sent to receive-pack, where a zero hash on the old side creates the
+/// ref and on the new side deletes it.
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    pub name: String,
+
+    pub old: String,
+
+    pub new: String,
+}
+
+/// A conversation with a remote receive-pack, which only speaks protocol version 0.
+pub struct PushConnection {
+    transport: Box<dyn Transport>,
+
+    capabilities: Vec<String>,
+
+    /// Refs the remote advertised.
+    pub refs: Vec<RemoteRef>,
+}
+
+impl PushConnection {
+    pub fn open(mut transport: Box<dyn Transport>) -> anyhow::Result<Self> {
+        let advertisement = transport.advertisement(RECEIVE_PACK)?;
+
+        let (lines, _) = PacketReader::new(&advertisement).read_lines()?;
+
+        let (refs, capabilities) = parse_v0_advertisement(&lines)?;
+
+        Ok(PushConnection {
+            transport,
+            capabilities,
+            refs,
+        })
+    }
+
+    /// Sends the updates, followed by `pack` unless every update is a deletion, and
+    /// returns the status the remote reports for each ref: `None` when it was updated,
+    /// otherwise the reason it was refused.
+    pub fn send_pack(
+        &mut self,
+        updates: &[RefUpdate],
+        pack: Option<&[u8]>,
+        atomic: bool,
+        push_options: &[String],
+    ) -> anyhow::Result<Vec<(String, Option<String>)>> {
+        let mut capabilities = vec!["report-status"];
+
+        let sideband = has_capability(&self.capabilities, "side-band-64k");
+
+        if sideband {
+            capabilities.push("side-band-64k");
+        }
+
+        if atomic {
+            ensure!(
+                has_capability(&self.capabilities, "atomic"),
+                "the receiving end does not support --atomic push"
+            );
+
+            capabilities.push("atomic");
+        }
+
+        if !push_options.is_empty() {
+            ensure!(
+                has_capability(&self.capabilities, "push-options"),
+                "the receiving end does not support push options"
+            );
+
+            capabilities.push("push-options");
+        }
+
+        capabilities.push(AGENT);
+
+        let mut request = PacketWriter::new();
+
+        for (index, update) in updates.iter().enumerate() {
+            let mut command = format!("{} {} {}", update.old, update.new, update.name);
+
+            if index == 0 {
+                command.push('\0');
+                command.push_str(&capabilities.join(" "));
+            }
+
+            request.data(command.as_bytes());
+        }
+
+        request.flush();
+
+        if !push_options.is_empty() {
+            for option in push_options {
+                request.data(option.as_bytes());
+            }
+
+            request.flush();
+        }
+
+        let mut body = request.into_bytes();
+
+        if let Some(pack) = pack {
+            body.extend_from_slice(pack);
+        }
+
+        let response = self.transport.request(RECEIVE_PACK, &body)?;
+
+        // Band 2 carries what the remote's hooks print, which is always shown
+        let report = if sideband {
+            PacketReader::new(&response).read_sideband(true)?
+        } else {
+            response
+        };
+
+        let (lines, _) = PacketReader::new(&report).read_lines()?;
+
+        let mut lines = lines.into_iter();
+
+        let unpack = lines.next().unwrap_or_default();
+
+        match unpack.strip_prefix("unpack ") {
+            Some("ok") => {}
+
+            Some(error) => bail!("remote unpack failed: {error}"),
+
+            None => bail!("protocol error: expected unpack status, got '{unpack}'"),
+        }
+
+        let mut statuses = Vec::new();
+
+        for line in lines {
+            if let Some(name) = line.strip_prefix("ok ") {
+                statuses.push((name.to_string(), None));
+            } else if let Some((name, reason)) = line
+                .strip_prefix("ng ")
+                .and_then(|rest| rest.split_once(' '))
+            {
+                statuses.push((name.to_string(), Some(reason.to_string())));
+            } else {
+                bail!("protocol error: invalid ref status from remote: {line}");
+            }
+        }
+
+        Ok(statuses)
+    }
+}
+
+/// Whether a capability list holds `name`, bare or with a value.
+fn has_capability(capabilities: &[String], name: &str) -> bool {
+    capabilities
+        .iter()
+        .any(|capability| capability == name || capability.starts_with(&format!("{name}=")))
+}
+
+/// Refs and capabilities of a version 0 advertisement, where the capabilities follow a
+/// NUL after the first ref and `HEAD`'s target comes as a `symref=` capability.
+fn parse_v0_advertisement(lines: &[String]) -> anyhow::Result<(Vec<RemoteRef>, Vec<String>)> {
+    let mut refs: Vec<RemoteRef> = Vec::new();
+
+    let mut capabilities = Vec::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        let line = match line.split_once('\0') {
+            Some((line, caps)) if index == 0 => {
+                capabilities = caps.split(' ').map(str::to_string).collect();
+
+                line
+            }
+
+            _ => line.as_str(),
+        };
+
+        let Some((hash, name)) = line.split_once(' ') else {
+            bail!("protocol error: unexpected '{line}'");
+        };
+
+        if hash == ZERO_HASH && name == "capabilities^{}" {
+            continue;
+        }
+
+        if let Some(tag) = name.strip_suffix("^{}") {
+            if let Some(remote_ref) = refs.iter_mut().find(|remote_ref| remote_ref.name == tag) {
+                remote_ref.peeled = Some(hash.to_string());
+            }
+
+            continue;
+        }
+
+        refs.push(RemoteRef {
+            name: name.to_string(),
+            hash: hash.to_string(),
+            peeled: None,
+            symref_target: None,
+        });
+    }
+
+    for capability in &capabilities {
+        if let Some((name, target)) = capability
+            .strip_prefix("symref=")
+            .and_then(|symref| symref.split_once(':'))
+        {
+            if let Some(remote_ref) = refs.iter_mut().find(|remote_ref| remote_ref.name == name) {
+                remote_ref.symref_target = Some(target.to_string());
+            }
+        }
+    }
+
+    Ok((refs, capabilities))
+}
@@ -0,0 +1,927 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, Write};
+
+use anyhow::{anyhow, bail, ensure};
+
+use crate::bitmap;
+use crate::git_objects::GitObject;
+use crate::pack::{write_pack, write_pack_counting};
+use crate::pkt_line::{read_packet, Packet, PacketReader, PacketWriter};
+use crate::refs::{
+    check_ref_format, expand_ref, head_target, list_refs, read_ref, resolve_ref, HEAD,
+};
+use crate::revision::CommitWalker;
+use crate::transport::{AGENT, ZERO_HASH};
+use crate::utils::{is_object_id, object_exists, parse_magnitude};
+
+/// Capabilities of the version 0 advertisement, before `symref` and `agent`.
+const V0_CAPABILITIES: &str = "multi_ack multi_ack_detailed no-done thin-pack side-band-64k \
+    ofs-delta shallow deepen-since deepen-not deepen-relative no-progress include-tag \
+    allow-tip-sha1-in-want allow-reachable-sha1-in-want filter object-format=sha1";
+
+/// Protocol version 2 capability advertisement of the current repository.
+pub fn advertisement() -> Vec<u8> {
+    let mut response = PacketWriter::new();
+
+    response
+        .line("version 2")
+        .line(AGENT)
+        .line("ls-refs")
+        .line("fetch=shallow wait-for-done filter")
+        .line("object-format=sha1")
+        .flush();
+
+    response.into_bytes()
+}
+
+/// Version 0 ref advertisement of the current repository: `HEAD` and every ref, the
+/// capabilities after the first one, and what annotated tags point at as `<tag>^{}`.
+pub fn advertisement_v0() -> anyhow::Result<Vec<u8>> {
+    let mut refs = Vec::new();
+
+    if let Some(hash) = resolve_ref(HEAD)? {
+        refs.push((HEAD.to_string(), hash));
+    }
+
+    refs.extend(list_refs("refs/")?);
+
+    let mut capabilities = V0_CAPABILITIES.to_string();
+
+    if let (Some(_), Some(target)) = (refs.first(), head_target()?) {
+        capabilities.push_str(&format!(" symref=HEAD:{target}"));
+    }
+
+    capabilities.push_str(&format!(" {AGENT}"));
+
+    let mut response = PacketWriter::new();
+
+    if refs.is_empty() {
+        response.line(&format!("{ZERO_HASH} capabilities^{{}}\0{capabilities}"));
+    }
+
+    for (index, (name, hash)) in refs.iter().enumerate() {
+        match index {
+            0 => response.line(&format!("{hash} {name}\0{capabilities}")),
+
+            _ => response.line(&format!("{hash} {name}")),
+        };
+
+        let peeled = GitObject::peel(hash)?;
+
+        if peeled != *hash {
+            response.line(&format!("{peeled} {name}^{{}}"));
+        }
+    }
+
+    response.flush();
+
+    Ok(response.into_bytes())
+}
+
+/// Protocol version a client asks for in `GIT_PROTOCOL` or the `Git-Protocol` header,
+/// colon-separated fields such as `version=2`.
+pub fn protocol_version(git_protocol: &str) -> u8 {
+    git_protocol
+        .split(':')
+        .find_map(|field| field.strip_prefix("version="))
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Runs upload-pack for the current repository over a connection such as a pipe, in
+/// protocol `version`. Stateless mode answers a single request, as behind an HTTP
+/// server.
+pub fn serve(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    version: u8,
+    stateless: bool,
+    advertise_only: bool,
+) -> anyhow::Result<()> {
+    if advertise_only || !stateless {
+        match version {
+            2 => output.write_all(&advertisement())?,
+
+            1 => {
+                let mut response = PacketWriter::new();
+
+                response.line("version 1");
+
+                output.write_all(&response.into_bytes())?;
+                output.write_all(&advertisement_v0()?)?;
+            }
+
+            _ => output.write_all(&advertisement_v0()?)?,
+        }
+
+        output.flush()?;
+    }
+
+    if advertise_only {
+        return Ok(());
+    }
+
+    let served = match version {
+        2 => serve_v2(input, output, stateless),
+
+        _ => serve_v0(input, output, stateless),
+    };
+
+    // Tell the client what went wrong rather than just hanging up on it
+    if let Err(error) = &served {
+        let mut response = PacketWriter::new();
+
+        response.line(&format!("ERR {error}"));
+
+        let _ = output
+            .write_all(&response.into_bytes())
+            .and_then(|_| output.flush());
+    }
+
+    served
+}
+
+/// Version 2: command requests until the client hangs up, or just one when stateless.
+fn serve_v2(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    stateless: bool,
+) -> anyhow::Result<()> {
+    while let Some(request) = read_request(input)? {
+        output.write_all(&handle_request(&request)?)?;
+        output.flush()?;
+
+        if stateless {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// The packets of one version 2 request, or `None` once the client ends the session by
+/// hanging up or sending a lone flush.
+fn read_request(input: &mut impl BufRead) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut request = PacketWriter::new();
+
+    let mut empty = true;
+
+    while let Some(packet) = read_packet(input)? {
+        request.packet(&packet);
+
+        if packet == Packet::Flush {
+            return Ok((!empty).then(|| request.into_bytes()));
+        }
+
+        empty = false;
+    }
+
+    Ok(None)
+}
+
+/// Answers one protocol version 2 command request against the current repository.
+pub fn handle_request(request: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut reader = PacketReader::new(request);
+
+    let (capabilities, end) = reader.read_lines()?;
+
+    let Some(command) = capabilities
+        .first()
+        .and_then(|line| line.strip_prefix("command="))
+    else {
+        bail!("protocol error: no command requested");
+    };
+
+    let arguments = match end {
+        Packet::Delim => reader.read_lines()?.0,
+
+        _ => Vec::new(),
+    };
+
+    match command {
+        "ls-refs" => ls_refs(&arguments),
+
+        "fetch" => fetch(&arguments),
+
+        other => bail!("invalid command '{other}'"),
+    }
+}
+
+fn ls_refs(arguments: &[String]) -> anyhow::Result<Vec<u8>> {
+    let peel = arguments.iter().any(|argument| argument == "peel");
+
+    let symrefs = arguments.iter().any(|argument| argument == "symrefs");
+
+    let prefixes: Vec<&str> = arguments
+        .iter()
+        .filter_map(|argument| argument.strip_prefix("ref-prefix "))
+        .collect();
+
+    let wanted = |name: &str| prefixes.is_empty() || prefixes.iter().any(|p| name.starts_with(p));
+
+    let mut refs = Vec::new();
+
+    if let Some(hash) = resolve_ref(HEAD)? {
+        refs.push((HEAD.to_string(), hash));
+    }
+
+    refs.extend(list_refs("refs/")?);
+
+    let mut response = PacketWriter::new();
+
+    for (name, hash) in refs {
+        if !wanted(&name) {
+            continue;
+        }
+
+        let mut line = format!("{hash} {name}");
+
+        if symrefs {
+            if let Some(target) =
+                read_ref(&name)?.and_then(|v| v.strip_prefix("ref: ").map(str::to_string))
+            {
+                line.push_str(&format!(" symref-target:{target}"));
+            }
+        }
+
+        if peel {
+            let peeled = GitObject::peel(&hash)?;
+
+            if peeled != hash {
+                line.push_str(&format!(" peeled:{peeled}"));
+            }
+        }
+
+        response.line(&line);
+    }
+
+    response.flush();
+
+    Ok(response.into_bytes())
+}
+
+fn fetch(arguments: &[String]) -> anyhow::Result<Vec<u8>> {
+    let mut request = FetchRequest::default();
+
+    let mut common = Vec::new();
+
+    let mut done = false;
+
+    for argument in arguments {
+        match argument.split_once(' ').unwrap_or((argument, "")) {
+            ("have", hash) => {
+                ensure!(
+                    is_object_id(hash),
+                    "git upload-pack: expected SHA1 object, got '{hash}'"
+                );
+
+                // Only haves we have ourselves tell us anything
+                if object_exists(hash) {
+                    common.push(hash.to_string());
+                }
+            }
+
+            ("done", _) => done = true,
+
+            _ => ensure!(request.parse(argument)?, "unexpected line: '{argument}'"),
+        }
+    }
+
+    request.check_wants()?;
+
+    let mut response = PacketWriter::new();
+
+    if !done {
+        response.line("acknowledgments");
+
+        if common.is_empty() {
+            response.line("NAK");
+        }
+
+        for hash in &common {
+            response.line(&format!("ACK {hash}"));
+        }
+
+        // Keep negotiating until something in common turns up, or the client says so
+        if common.is_empty() || request.has("wait-for-done") {
+            response.flush();
+
+            return Ok(response.into_bytes());
+        }
+
+        response.line("ready").delim();
+    }
+
+    let shallow = request.shallow_info()?;
+
+    if request.deepens() {
+        response.line("shallow-info");
+
+        for commit in &shallow.shallow {
+            response.line(&format!("shallow {commit}"));
+        }
+
+        for commit in &shallow.unshallow {
+            response.line(&format!("unshallow {commit}"));
+        }
+
+        response.delim();
+    }
+
+    let objects = request.objects(&common, &shallow)?;
+
+    response.line("packfile");
+
+    write_pack_sideband(&mut response, &objects, !request.has("no-progress"))?;
+
+    Ok(response.into_bytes())
+}
+
+/// Version 0 fetch: the wants and shallow requests, then `have` lines answered after
+/// each flush until the client is done (or, with `no-done`, until the server is
+/// ready), then the pack.
+fn serve_v0(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    stateless: bool,
+) -> anyhow::Result<()> {
+    let mut request = FetchRequest::default();
+
+    while let Some(Packet::Data(line)) = read_packet(input)? {
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+
+        ensure!(request.parse(&line)?, "protocol error: unexpected '{line}'");
+    }
+
+    // A client that was only after the refs hangs up without wanting anything
+    if request.wants.is_empty() {
+        return Ok(());
+    }
+
+    request.check_wants()?;
+
+    let shallow = request.shallow_info()?;
+
+    if request.deepens() {
+        let mut response = PacketWriter::new();
+
+        for commit in &shallow.shallow {
+            response.line(&format!("shallow {commit}"));
+        }
+
+        for commit in &shallow.unshallow {
+            response.line(&format!("unshallow {commit}"));
+        }
+
+        response.flush();
+
+        output.write_all(&response.into_bytes())?;
+        output.flush()?;
+    }
+
+    let detailed = request.has("multi_ack_detailed");
+
+    let multi_ack = detailed || request.has("multi_ack");
+
+    let mut common: Vec<String> = Vec::new();
+
+    let mut sent_ready = false;
+
+    loop {
+        let mut response = PacketWriter::new();
+
+        match read_packet(input)? {
+            None => return Ok(()),
+
+            Some(Packet::Data(line)) => {
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+
+                if line == "done" {
+                    match common.last() {
+                        Some(last) if multi_ack => response.line(&format!("ACK {last}")),
+
+                        Some(_) => &mut response,
+
+                        None => response.line("NAK"),
+                    };
+
+                    output.write_all(&response.into_bytes())?;
+
+                    break;
+                }
+
+                let Some(hash) = line.strip_prefix("have ") else {
+                    bail!("protocol error: unexpected '{line}'");
+                };
+
+                ensure!(
+                    is_object_id(hash),
+                    "git upload-pack: expected SHA1 list, got '{line}'"
+                );
+
+                if !object_exists(hash) {
+                    continue;
+                }
+
+                common.push(hash.to_string());
+
+                if detailed {
+                    response.line(&format!("ACK {hash} common"));
+                } else if multi_ack {
+                    response.line(&format!("ACK {hash} continue"));
+                } else if common.len() == 1 {
+                    response.line(&format!("ACK {hash}"));
+                }
+            }
+
+            Some(_) => {
+                if let (Some(last), false, true) = (common.last(), sent_ready, detailed) {
+                    response.line(&format!("ACK {last} ready"));
+
+                    sent_ready = true;
+                }
+
+                if let (Some(last), true, true) =
+                    (common.last(), sent_ready, request.has("no-done"))
+                {
+                    response.line(&format!("ACK {last}"));
+
+                    output.write_all(&response.into_bytes())?;
+
+                    break;
+                }
+
+                if common.is_empty() || multi_ack {
+                    response.line("NAK");
+                }
+
+                if stateless {
+                    output.write_all(&response.into_bytes())?;
+
+                    return Ok(output.flush()?);
+                }
+            }
+        }
+
+        output.write_all(&response.into_bytes())?;
+        output.flush()?;
+    }
+
+    let objects = request.objects(&common, &shallow)?;
+
+    if request.has("side-band-64k") {
+        let mut response = PacketWriter::new();
+
+        write_pack_sideband(&mut response, &objects, !request.has("no-progress"))?;
+
+        output.write_all(&response.into_bytes())?;
+    } else {
+        output.write_all(&write_pack(&objects)?)?;
+    }
+
+    Ok(output.flush()?)
+}
+
+/// Adds the pack for `objects` as sideband data, with progress messages unless the
+/// client asked for none, and the flush ending it.
+fn write_pack_sideband(
+    response: &mut PacketWriter,
+    objects: &[String],
+    progress: bool,
+) -> anyhow::Result<()> {
+    if progress {
+        response.sideband(
+            2,
+            format!("Enumerating objects: {}, done.\n", objects.len()).as_bytes(),
+        );
+    }
+
+    let (pack, reused, deltas) = write_pack_counting(objects)?;
+
+    response.sideband(1, &pack);
+
+    if progress {
+        response.sideband(
+            2,
+            format!(
+                "Total {} (delta {deltas}), reused {reused} (delta {deltas}), pack-reused 0\n",
+                objects.len()
+            )
+            .as_bytes(),
+        );
+    }
+
+    response.flush();
+
+    Ok(())
+}
+
+/// What a client asks upload-pack for, in either protocol version.
+#[derive(Debug, Default)]
+struct FetchRequest {
+    wants: Vec<String>,
+
+    /// Capabilities (version 0) or flag arguments (version 2) such as `include-tag`.
+    capabilities: Vec<String>,
+
+    /// Commits the client has without their parents.
+    shallow: Vec<String>,
+
+    depth: Option<usize>,
+
+    since: Option<i64>,
+
+    /// Commits whose history the client does not want deepened into.
+    not: Vec<String>,
+
+    filter: Option<Filter>,
+}
+
+/// Where a shallow fetch cuts history.
+#[derive(Debug, Default)]
+struct ShallowInfo {
+    /// Commits the client should record as shallow.
+    shallow: Vec<String>,
+
+    /// Shallow commits of the client whose parents it now gets.
+    unshallow: Vec<String>,
+
+    /// Commits whose parents are not walked while collecting objects.
+    grafts: HashSet<String>,
+}
+
+impl FetchRequest {
+    /// Takes a line both protocol versions share, returning whether it was one.
+    fn parse(&mut self, line: &str) -> anyhow::Result<bool> {
+        let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+
+        match name {
+            "want" => {
+                // Version 0 clients put their capabilities after the first want
+                let mut fields = value.split(' ');
+
+                let hash = fields.next().unwrap_or_default();
+
+                self.capabilities.extend(fields.map(str::to_string));
+
+                ensure!(
+                    is_object_id(hash),
+                    "git upload-pack: protocol error, expected to get object ID, not '{line}'"
+                );
+
+                ensure!(object_exists(hash), "upload-pack: not our ref {hash}");
+
+                self.wants.push(hash.to_string());
+            }
+
+            "shallow" => {
+                ensure!(is_object_id(value), "invalid shallow line: {line}");
+
+                self.shallow.push(value.to_string());
+            }
+
+            "deepen" => {
+                let depth = value
+                    .parse()
+                    .ok()
+                    .filter(|depth| *depth > 0)
+                    .ok_or_else(|| anyhow!("invalid depth: {value}"))?;
+
+                self.depth = Some(depth);
+            }
+
+            "deepen-since" => {
+                let since = value
+                    .parse()
+                    .map_err(|_| anyhow!("invalid deepen-since: {value}"))?;
+
+                self.since = Some(since);
+            }
+
+            "deepen-not" => {
+                let name = check_ref_format(value, true)
+                    .then(|| expand_ref(value))
+                    .transpose()?
+                    .flatten();
+
+                let Some(hash) = name
+                    .map(|name| resolve_ref(&name))
+                    .transpose()?
+                    .flatten()
+                    .filter(|hash| is_object_id(hash))
+                else {
+                    bail!("git upload-pack: ambiguous deepen-not: {value}");
+                };
+
+                // A tag excludes the history of what it tags
+                self.not.push(GitObject::peel(&hash)?);
+            }
+
+            "filter" => self.filter = Some(Filter::parse(value)?),
+
+            "include-tag" | "no-progress" | "ofs-delta" | "thin-pack" | "wait-for-done"
+            | "deepen-relative" => self.capabilities.push(name.to_string()),
+
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Refuses wants that are neither a ref tip nor reachable from one, which is all
+    /// `allow-tip-sha1-in-want` and `allow-reachable-sha1-in-want` let a client ask for.
+    fn check_wants(&self) -> anyhow::Result<()> {
+        let mut tips = Vec::new();
+
+        if let Some(hash) = resolve_ref(HEAD)? {
+            tips.push(hash);
+        }
+
+        for (_, hash) in list_refs("refs/")? {
+            let peeled = GitObject::peel(&hash)?;
+
+            tips.push(hash);
+            tips.push(peeled);
+        }
+
+        let mut pending: HashSet<&String> = self
+            .wants
+            .iter()
+            .filter(|want| !tips.contains(want))
+            .collect();
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut walker = CommitWalker::new();
+
+        // Commits are found by walking history alone, and that usually settles it
+        let mut seen = HashSet::new();
+
+        let mut stack: Vec<String> = tips
+            .iter()
+            .filter(|tip| walker.commit(tip).is_ok())
+            .cloned()
+            .collect();
+
+        while let Some(commit) = stack.pop() {
+            if pending.is_empty() {
+                break;
+            }
+
+            if seen.insert(commit.clone()) {
+                pending.remove(&commit);
+
+                stack.extend(walker.parents(&commit)?);
+            }
+        }
+
+        if !pending.is_empty() {
+            let reachable: HashSet<String> = match bitmap::objects_between(&[], &tips)? {
+                Some(objects) => objects,
+
+                None => walker.objects_between(&[], &tips)?,
+            }
+            .into_iter()
+            .collect();
+
+            pending.retain(|want| !reachable.contains(*want));
+        }
+
+        match pending.into_iter().next() {
+            Some(want) => bail!("upload-pack: not our ref {want}"),
+
+            None => Ok(()),
+        }
+    }
+
+    fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    fn deepens(&self) -> bool {
+        self.depth.is_some() || self.since.is_some() || !self.not.is_empty()
+    }
+
+    /// The shallow commits to announce and the history cut to apply. A client that is
+    /// shallow already never has the parents of its shallow commits.
+    fn shallow_info(&self) -> anyhow::Result<ShallowInfo> {
+        let client: HashSet<String> = self.shallow.iter().cloned().collect();
+
+        let mut info = ShallowInfo {
+            grafts: client.clone(),
+            ..ShallowInfo::default()
+        };
+
+        if !self.deepens() {
+            return Ok(info);
+        }
+
+        ensure!(
+            self.depth.is_none() || self.since.is_none() && self.not.is_empty(),
+            "git upload-pack: deepen and deepen-since (or deepen-not) cannot be used together"
+        );
+
+        let mut walker = CommitWalker::new();
+
+        // A relative depth counts on from where the client's history ends now
+        let (tips, depth) = if self.has("deepen-relative") {
+            (self.shallow.clone(), self.depth.map(|depth| depth + 1))
+        } else {
+            let mut tips = Vec::new();
+
+            for want in &self.wants {
+                let peeled = GitObject::peel(want)?;
+
+                if walker.commit(&peeled).is_ok() {
+                    tips.push(peeled);
+                }
+            }
+
+            (tips, self.depth)
+        };
+
+        let (covered, boundary) = walker.shallow_cut(&tips, depth, self.since, &self.not)?;
+
+        info.shallow = boundary
+            .iter()
+            .filter(|commit| !client.contains(*commit))
+            .cloned()
+            .collect();
+
+        info.unshallow = self
+            .shallow
+            .iter()
+            .filter(|commit| covered.contains(*commit) && !boundary.contains(*commit))
+            .cloned()
+            .collect();
+
+        info.shallow.sort();
+
+        info.grafts.extend(boundary);
+
+        Ok(info)
+    }
+
+    /// Objects for the pack, given the commits the client has in common with us.
+    fn objects(&self, common: &[String], shallow: &ShallowInfo) -> anyhow::Result<Vec<String>> {
+        let mut include = self.wants.clone();
+
+        // The client has these commits but not the history behind them
+        for commit in &shallow.unshallow {
+            include.extend(CommitWalker::new().parents(commit)?);
+        }
+
+        // Bitmaps describe full history, so they only answer for complete clones and fetches
+        let counted = match shallow.grafts.is_empty() {
+            true => bitmap::objects_between(common, &include)?,
+
+            false => None,
+        };
+
+        let mut objects = match counted {
+            Some(objects) => objects,
+
+            None => CommitWalker::with_shallow(shallow.grafts.clone())
+                .objects_between(common, &include)?,
+        };
+
+        if self.has("include-tag") {
+            objects.splice(0..0, tags_pointing_into(&objects)?);
+        }
+
+        match self.filter {
+            Some(filter) => filter.apply(objects, &self.wants),
+
+            None => Ok(objects),
+        }
+    }
+}
+
+/// Objects a partial clone leaves out.
+#[derive(Debug, Clone, Copy)]
+enum Filter {
+    /// Blobs of at least this size, so every blob for `blob:none`.
+    BlobLimit(u64),
+
+    /// Every blob, and trees and blobs at least this deep below a commit's root tree, for
+    /// `tree:<depth>`.
+    TreeDepth(usize),
+}
+
+impl Filter {
+    fn parse(spec: &str) -> anyhow::Result<Self> {
+        if spec == "blob:none" {
+            return Ok(Filter::BlobLimit(0));
+        }
+
+        if let Some(depth) = spec.strip_prefix("tree:") {
+            let Ok(depth) = depth.parse() else {
+                bail!("invalid filter-spec '{spec}'");
+            };
+
+            return Ok(Filter::TreeDepth(depth));
+        }
+
+        let Some(limit) = spec.strip_prefix("blob:limit=") else {
+            bail!("invalid filter-spec '{spec}'");
+        };
+
+        let Some(limit) = parse_magnitude(limit) else {
+            bail!("invalid filter-spec '{spec}'");
+        };
+
+        Ok(Filter::BlobLimit(limit))
+    }
+
+    /// Drops the objects the filter leaves out, keeping any the client asked for by name.
+    fn apply(self, objects: Vec<String>, wants: &[String]) -> anyhow::Result<Vec<String>> {
+        let depths = match self {
+            Filter::TreeDepth(_) => tree_depths(&objects)?,
+
+            Filter::BlobLimit(_) => HashMap::new(),
+        };
+
+        let mut kept = Vec::new();
+
+        for hash in objects {
+            let (kind, content) = GitObject::read_raw(&hash)?;
+
+            let keep = match (self, kind.as_str()) {
+                _ if wants.contains(&hash) => true,
+
+                (_, "commit" | "tag") => true,
+
+                (Filter::TreeDepth(limit), _) => depths.get(&hash).is_some_and(|d| *d < limit),
+
+                (Filter::BlobLimit(limit), "blob") => (content.len() as u64) < limit,
+
+                (Filter::BlobLimit(_), _) => true,
+            };
+
+            if keep {
+                kept.push(hash);
+            }
+        }
+
+        Ok(kept)
+    }
+}
+
+/// How far below the root tree of a commit among `objects` each of their trees and blobs
+/// lies, at the shallowest place it appears.
+fn tree_depths(objects: &[String]) -> anyhow::Result<HashMap<String, usize>> {
+    let included: HashSet<&String> = objects.iter().collect();
+
+    let mut depths = HashMap::new();
+
+    let mut queue = VecDeque::new();
+
+    for hash in objects {
+        if let GitObject::Commit { tree, .. } = GitObject::from_hash(hash)? {
+            queue.push_back((tree, 0));
+        }
+    }
+
+    // Breadth first, so each object is reached at its shallowest first
+    while let Some((hash, depth)) = queue.pop_front() {
+        if !included.contains(&hash) || depths.contains_key(&hash) {
+            continue;
+        }
+
+        depths.insert(hash.clone(), depth);
+
+        let (kind, content) = GitObject::read_raw(&hash)?;
+
+        if kind == "tree" {
+            for (_, _, entry) in GitObject::parse_tree_entries(&content)? {
+                queue.push_back((entry, depth + 1));
+            }
+        }
+    }
+
+    Ok(depths)
+}
+
+/// Annotated tags that are not in `objects` but point at something that is, so a client
+/// asking for `include-tag` can follow them.
+fn tags_pointing_into(objects: &[String]) -> anyhow::Result<Vec<String>> {
+    let included: HashSet<&String> = objects.iter().collect();
+
+    let mut tags = Vec::new();
+
+    for (_, hash) in list_refs("refs/tags/")? {
+        if included.contains(&hash) || tags.contains(&hash) {
+            continue;
+        }
+
+        let (kind, _) = GitObject::read_raw(&hash)?;
+
+        if kind == "tag" && included.contains(&GitObject::peel(&hash)?) {
+            tags.push(hash);
+        }
+    }
+
+    Ok(tags)
+}
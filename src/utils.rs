@@ -6,6 +6,15 @@ use std::fs;
 use std::io::Write;
 use std::num::ParseIntError;
 
+use std::sync::Mutex;
+
+use crate::pack;
+use crate::promisor;
+use crate::refs::git_path;
+
+/// The object directories of the repository they were listed for, `None` until then.
+static OBJECT_DIRECTORIES: Mutex<Option<(String, Vec<String>)>> = Mutex::new(None);
+
 pub fn to_hex_string(content: &[u8]) -> String {
     content
         .iter()
@@ -58,7 +67,7 @@ pub fn decompress(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
 pub fn create_object_directory(hash: &str) -> anyhow::Result<String> {
     let (dir_name, file_name) = hash.split_at(2);
 
-    let dir_path = format!(".git/objects/{dir_name}");
+    let dir_path = git_path(&format!("objects/{dir_name}"));
 
     let file_path = format!("{dir_path}/{file_name}");
 
@@ -93,12 +102,636 @@ pub fn write_to_file(file_name: &str, content: &[u8]) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn read_object(hash: &str) -> anyhow::Result<Vec<u8>> {
+/// The repository's object directory (or `$GIT_OBJECT_DIRECTORY`) followed by those
+/// listed in `objects/info/alternates` and `$GIT_ALTERNATE_OBJECT_DIRECTORIES`, whose
+/// objects are borrowed from elsewhere.
+pub fn object_directories() -> Vec<String> {
+    let objects = match std::env::var("GIT_OBJECT_DIRECTORY") {
+        Ok(directory) if !directory.is_empty() => directory,
+
+        _ => git_path("objects"),
+    };
+
+    let mut cache = OBJECT_DIRECTORIES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if let Some((cached, directories)) = cache.as_ref() {
+        if *cached == objects {
+            return directories.clone();
+        }
+    }
+
+    let mut directories = vec![objects.clone()];
+
+    if let Ok(content) = fs::read_to_string(format!("{objects}/info/alternates")) {
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // Relative entries are relative to the object directory
+            if line.starts_with('/') {
+                directories.push(line.to_string());
+            } else {
+                directories.push(format!("{objects}/{line}"));
+            }
+        }
+    }
+
+    if let Ok(alternates) = std::env::var("GIT_ALTERNATE_OBJECT_DIRECTORIES") {
+        directories.extend(
+            alternates
+                .split(':')
+                .filter(|directory| !directory.is_empty())
+                .map(str::to_string),
+        );
+    }
+
+    *cache = Some((objects, directories.clone()));
+
+    directories
+}
+
+/// Forgets the object directories, for when the repository or its alternates change.
+pub fn reload_object_directories() {
+    if let Ok(mut cache) = OBJECT_DIRECTORIES.lock() {
+        *cache = None;
+    }
+}
+
+/// Whether `hash` is a full object id: 40 hexadecimal digits.
+pub fn is_object_id(hash: &str) -> bool {
+    hash.len() == 40 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Path of a loose object in whichever object directory has it.
+fn loose_object_path(hash: &str) -> Option<String> {
+    // Anything else could name a path outside the object directories
+    if hash.len() < 3 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
     let (folder_name, file_name) = hash.split_at(2);
 
-    let file_path = format!(".git/objects/{}/{}", folder_name, file_name);
+    object_directories()
+        .into_iter()
+        .map(|directory| format!("{directory}/{folder_name}/{file_name}"))
+        .find(|path| std::path::Path::new(path).is_file())
+}
+
+/// Type and content of an object, loose or packed. Packed objects come as their pack
+/// inflates them; a partial clone first fetches what it left out.
+pub fn read_object(hash: &str) -> anyhow::Result<(String, Vec<u8>)> {
+    if let Some(path) = loose_object_path(hash) {
+        let content = decompress(&read_file(&path)?)
+            .with_context(|| format!("Could not read object at path: {path:?}"))?;
+
+        let Some(type_end) = content.iter().position(|&b| b == b' ') else {
+            bail!("Object header is missing its type");
+        };
+
+        let content_start = content
+            .iter()
+            .position(|&b| b == 0)
+            .map_or(content.len(), |i| i + 1);
+
+        return Ok((
+            String::from_utf8(content[..type_end].to_vec())?,
+            content[content_start..].to_vec(),
+        ));
+    }
+
+    if let Some(object) = pack::read_packed_object(hash)? {
+        return Ok(object);
+    }
+
+    // A partial clone gets what it left out from its promisor remote
+    if promisor::fetch_missing(&[hash.to_string()])? {
+        return read_object(hash);
+    }
+
+    bail!("Could not read object {hash}")
+}
+
+pub fn object_exists(hash: &str) -> bool {
+    loose_object_path(hash).is_some() || pack::packed_object_exists(hash)
+}
+
+pub fn current_timestamp() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// A size as git shows it to humans: `1.50 MiB`, `12.00 KiB`, `512 bytes`.
+pub fn human_size(bytes: u64) -> String {
+    if bytes > 1 << 30 {
+        format!(
+            "{}.{:02} GiB",
+            bytes >> 30,
+            (bytes & ((1 << 30) - 1)) / 10737419
+        )
+    } else if bytes > 1 << 20 {
+        let rounded = bytes + 5243;
+
+        format!(
+            "{}.{:02} MiB",
+            rounded >> 20,
+            ((rounded & ((1 << 20) - 1)) * 100) >> 20
+        )
+    } else if bytes > 1 << 10 {
+        let rounded = bytes + 5;
+
+        format!(
+            "{}.{:02} KiB",
+            rounded >> 10,
+            ((rounded & ((1 << 10) - 1)) * 100) >> 10
+        )
+    } else if bytes == 1 {
+        "1 byte".to_string()
+    } else {
+        format!("{bytes} bytes")
+    }
+}
+
+/// A size with an optional `k`, `m` or `g` suffix, as git reads them: `512`, `10k`, `2G`.
+pub fn parse_magnitude(value: &str) -> Option<u64> {
+    let (digits, scale) = match value.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => {
+            let scale = match unit.to_ascii_lowercase() {
+                'k' => 1 << 10,
+
+                'm' => 1 << 20,
+
+                'g' => 1 << 30,
+
+                _ => return None,
+            };
+
+            (&value[..index], scale)
+        }
+
+        _ => (value, 1),
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// Shell-style glob match supporting `*`, `?` and `[...]` classes (with `!`/`^`
+/// negation and ranges). `*` also matches `/`, as in git's ref patterns.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[u8], text: &[u8]) -> bool {
+        match pattern.first() {
+            None => text.is_empty(),
+
+            Some(b'*') => (0..=text.len()).any(|skip| matches(&pattern[1..], &text[skip..])),
+
+            Some(b'?') => !text.is_empty() && matches(&pattern[1..], &text[1..]),
+
+            Some(b'[') => {
+                let Some(&ch) = text.first() else {
+                    return false;
+                };
+
+                let mut index = 1;
+
+                let negated = matches!(pattern.get(index), Some(b'!' | b'^'));
+
+                if negated {
+                    index += 1;
+                }
+
+                let mut found = false;
+
+                let mut first = true;
+
+                while index < pattern.len() && (first || pattern[index] != b']') {
+                    first = false;
+
+                    let start = pattern[index];
+
+                    if pattern.get(index + 1) == Some(&b'-')
+                        && pattern.get(index + 2).is_some_and(|&end| end != b']')
+                    {
+                        found |= (start..=pattern[index + 2]).contains(&ch);
+
+                        index += 3;
+                    } else {
+                        found |= start == ch;
+
+                        index += 1;
+                    }
+                }
+
+                // An unterminated class is matched literally
+                if index >= pattern.len() {
+                    return ch == b'[' && matches(&pattern[1..], &text[1..]);
+                }
+
+                found != negated && matches(&pattern[index + 1..], &text[1..])
+            }
+
+            Some(&literal) => text.first() == Some(&literal) && matches(&pattern[1..], &text[1..]),
+        }
+    }
+
+    matches(pattern.as_bytes(), text.as_bytes())
+}
+
+/// Orders names the way `version:refname` sorting does, comparing runs of digits by
+/// their numeric value so that `v1.9` comes before `v1.10`.
+pub fn version_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+
+    loop {
+        match (a.first(), b.first()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.iter().take_while(|c| c.is_ascii_digit()).count();
+                let b_len = b.iter().take_while(|c| c.is_ascii_digit()).count();
+
+                let a_digits = trim_leading_zeros(&a[..a_len]);
+                let b_digits = trim_leading_zeros(&b[..b_len]);
+
+                let ordering = a_digits
+                    .len()
+                    .cmp(&b_digits.len())
+                    .then_with(|| a_digits.cmp(b_digits));
+
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering;
+                }
+
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(y);
+                }
+
+                a = &a[1..];
+                b = &b[1..];
+            }
+        }
+    }
+}
+
+fn trim_leading_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&c| c == b'0').count();
+
+    &digits[zeros.min(digits.len().saturating_sub(1))..]
+}
+
+/// Parses the date forms git accepts in `@{<date>}` and expiry options: `now`,
+/// `yesterday`, `<n>.<unit>.ago` (or with spaces), `@<timestamp>`, a bare timestamp and
+/// `YYYY-MM-DD[ HH:MM[:SS]]` (taken as UTC, at the time of day of `now` when no time is
+/// given, as git does). Returns seconds since the epoch.
+pub fn parse_date(value: &str, now: i64) -> Option<i64> {
+    let value = value.trim().to_lowercase();
+
+    match value.as_str() {
+        "now" => return Some(now),
+
+        "yesterday" => return Some(now - 86_400),
+
+        _ => {}
+    }
+
+    if let Some(timestamp) = value.strip_prefix('@') {
+        return timestamp.parse().ok();
+    }
+
+    if value.len() >= 9 && value.chars().all(|c| c.is_ascii_digit()) {
+        return value.parse().ok();
+    }
+
+    let words: Vec<&str> = value.split(['.', ' ']).filter(|w| !w.is_empty()).collect();
+
+    if let [count, unit, "ago"] = words.as_slice() {
+        let count: i64 = count.parse().ok()?;
+
+        let seconds = match unit.trim_end_matches('s') {
+            "second" | "sec" => 1,
+            "minute" | "min" => 60,
+            "hour" => 3_600,
+            "day" => 86_400,
+            "week" => 604_800,
+            "month" => 2_592_000,
+            "year" => 31_536_000,
+            _ => return None,
+        };
+
+        return Some(now - count * seconds);
+    }
+
+    let (date, time) = value.split_once([' ', 't']).unzip();
+
+    let mut date_parts = date
+        .unwrap_or(&value)
+        .split('-')
+        .map(|p| p.parse::<i64>().ok());
+
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) =
+        (date_parts.next(), date_parts.next(), date_parts.next())
+    else {
+        return None;
+    };
+
+    let Some(time) = time else {
+        return Some(days_from_civil(year, month, day) * 86_400 + now.rem_euclid(86_400));
+    };
+
+    let mut time_parts = time.split(':').map(|p| p.parse::<i64>().ok());
+
+    let hour = time_parts.next().flatten().unwrap_or(0);
+    let minute = time_parts.next().flatten().unwrap_or(0);
+    let second = time_parts.next().flatten().unwrap_or(0);
+
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+
+    let era = year.div_euclid(400);
+
+    let year_of_era = year - era * 400;
+
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Offset from UTC in seconds of local time at `timestamp`, from the zone `TZ` names (a
+/// zoneinfo file or a POSIX rule) or else `/etc/localtime`. Zero when neither tells.
+pub fn local_utc_offset(timestamp: i64) -> i64 {
+    let zone = std::env::var("TZ").ok();
+
+    let path = match zone.as_deref().map(|zone| zone.trim_start_matches(':')) {
+        None => "/etc/localtime".to_string(),
+
+        Some("") => return 0,
+
+        Some(name) if name.starts_with('/') => name.to_string(),
+
+        Some(name) => format!("/usr/share/zoneinfo/{name}"),
+    };
+
+    fs::read(path)
+        .ok()
+        .and_then(|data| zoneinfo_offset(&data, timestamp))
+        .or_else(|| zone.and_then(|zone| posix_zone_offset(&zone, timestamp)))
+        .unwrap_or(0)
+}
+
+/// An offset from UTC in seconds as commits record it: `+0200`, `-0530`.
+pub fn format_utc_offset(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+
+    let minutes = offset.abs() / 60;
+
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// Parses an offset from UTC as commits record it, the inverse of [`format_utc_offset`].
+pub fn parse_utc_offset(value: &str) -> Option<i64> {
+    let (sign, digits) = match value.strip_prefix('+') {
+        Some(digits) => (1, digits),
+
+        None => (-1, value.strip_prefix('-')?),
+    };
+
+    if digits.len() != 4 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+
+    Some(sign * (hours * 3_600 + minutes * 60))
+}
+
+/// Offset at `timestamp` in a compiled zoneinfo (TZif) file. Version 2 files repeat
+/// the data with 64-bit times and end with a POSIX rule for times past the last change.
+fn zoneinfo_offset(data: &[u8], timestamp: i64) -> Option<i64> {
+    let counts = |header: &[u8]| -> Option<[usize; 6]> {
+        if header.get(..4)? != b"TZif" {
+            return None;
+        }
+
+        let mut counts = [0; 6];
+
+        for (index, count) in counts.iter_mut().enumerate() {
+            *count = be_u32(header.get(20 + 4 * index..24 + 4 * index)?) as usize;
+        }
+
+        Some(counts)
+    };
+
+    // Times, then their types, type details, names, leap seconds and two flag arrays
+    let size = |[utc, standard, leaps, times, types, names]: [usize; 6], time_size: usize| {
+        44 + times * (time_size + 1) + types * 6 + names + leaps * (time_size + 4) + standard + utc
+    };
+
+    let (block, time_size) = match data.get(4)? {
+        0 => (data, 4),
+
+        _ => (data.get(size(counts(data)?, 4)..)?, 8),
+    };
+
+    let counts = counts(block)?;
+
+    let [_, _, _, times, types, _] = counts;
+
+    let transitions = block.get(44..44 + times * time_size)?;
+
+    let indices = block.get(44 + times * time_size..44 + times * (time_size + 1))?;
+
+    let details =
+        block.get(44 + times * (time_size + 1)..44 + times * (time_size + 1) + types * 6)?;
+
+    let transition = |index: usize| -> i64 {
+        let bytes = &transitions[index * time_size..(index + 1) * time_size];
+
+        match time_size {
+            4 => be_u32(bytes) as i32 as i64,
+
+            _ => i64::from_be_bytes(bytes.try_into().unwrap_or_default()),
+        }
+    };
+
+    let passed = (0..times)
+        .take_while(|&index| transition(index) <= timestamp)
+        .count();
+
+    if passed == times && time_size == 8 {
+        let footer = block.get(size(counts, 8)..)?;
+
+        let rule = String::from_utf8_lossy(footer);
+
+        if let Some(offset) = rule
+            .strip_prefix('\n')
+            .and_then(|rule| rule.split('\n').next())
+            .and_then(|rule| posix_zone_offset(rule, timestamp))
+        {
+            return Some(offset);
+        }
+    }
+
+    let kind = match passed {
+        0 => 0,
+
+        passed => *indices.get(passed - 1)? as usize,
+    };
+
+    Some(be_u32(details.get(kind * 6..kind * 6 + 4)?) as i32 as i64)
+}
+
+/// Offset at `timestamp` under a POSIX zone rule such as `CET-1CEST,M3.5.0,M10.5.0/3`:
+/// the standard time, then optionally daylight saving time and when it starts and ends.
+fn posix_zone_offset(rule: &str, timestamp: i64) -> Option<i64> {
+    fn name<'a>(rule: &mut &'a str) -> Option<&'a str> {
+        let end = match rule.strip_prefix('<') {
+            Some(quoted) => quoted.find('>')? + 2,
+
+            None => rule
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rule.len()),
+        };
+
+        let (name, rest) = rule.split_at(end);
+
+        *rule = rest;
+
+        (name.len() >= 3).then_some(name)
+    }
+
+    // `[+-]hh[:mm[:ss]]`, in seconds
+    fn time(value: &str) -> Option<i64> {
+        let (sign, value) = match value.strip_prefix('-') {
+            Some(value) => (-1, value),
+
+            None => (1, value.trim_start_matches('+')),
+        };
+
+        let mut seconds = 0;
+
+        for (part, scale) in value.split(':').zip([3_600, 60, 1]) {
+            seconds += part.parse::<i64>().ok()? * scale;
+        }
+
+        Some(sign * seconds)
+    }
+
+    fn offset(rule: &mut &str) -> Option<i64> {
+        let end = rule
+            .find(|c: char| !matches!(c, '0'..='9' | ':' | '+' | '-'))
+            .unwrap_or(rule.len());
+
+        let (value, rest) = rule.split_at(end);
+
+        *rule = rest;
+
+        // POSIX counts hours west of Greenwich
+        Some(-time(value)?)
+    }
+
+    // `Mm.w.d[/time]`: day `d` of week `w` (5 for the last) of month `m`, in local time
+    let change = |rule: &str, year: i64| -> Option<i64> {
+        let (date, at) = match rule.split_once('/') {
+            Some((date, at)) => (date, time(at)?),
+
+            None => (rule, 7_200),
+        };
+
+        let fields: Vec<i64> = date
+            .strip_prefix('M')?
+            .split('.')
+            .map(|field| field.parse().ok())
+            .collect::<Option<_>>()?;
+
+        let [month, week, weekday] = fields[..] else {
+            return None;
+        };
+
+        let first = days_from_civil(year, month, 1);
+
+        let days_in_month = days_from_civil(year + month / 12, month % 12 + 1, 1) - first;
+
+        // 1970-01-01 was a Thursday
+        let mut day = (weekday - (first + 4)).rem_euclid(7) + (week - 1) * 7;
+
+        while day >= days_in_month {
+            day -= 7;
+        }
+
+        Some((first + day) * 86_400 + at)
+    };
+
+    let mut rest = rule;
+
+    name(&mut rest)?;
+
+    let standard = offset(&mut rest)?;
+
+    if rest.is_empty() {
+        return Some(standard);
+    }
+
+    name(&mut rest)?;
+
+    let daylight = match rest.starts_with(',') || rest.is_empty() {
+        true => standard + 3_600,
+
+        false => offset(&mut rest)?,
+    };
+
+    let (start, end) = rest
+        .strip_prefix(',')
+        .unwrap_or("M3.2.0,M11.1.0")
+        .split_once(',')?;
+
+    let local = timestamp + standard;
+
+    let mut year = 1970 + local.div_euclid(86_400 * 365);
+
+    while days_from_civil(year, 1, 1) * 86_400 > local {
+        year -= 1;
+    }
+
+    let start = change(start, year)? - standard;
+
+    let end = change(end, year)? - daylight;
+
+    let daylight_saving = match start < end {
+        true => (start..end).contains(&timestamp),
+
+        false => !(end..start).contains(&timestamp),
+    };
+
+    Some(if daylight_saving { daylight } else { standard })
+}
+
+/// The big-endian number the first four bytes hold.
+pub fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
 
-    read_file(&file_path).with_context(|| format!("Could not read object at path: {file_path:?}"))
+/// The big-endian number the first eight bytes hold.
+pub fn be_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[..8].try_into().unwrap_or_default())
 }
 
 pub fn read_file(file_name: &str) -> anyhow::Result<Vec<u8>> {
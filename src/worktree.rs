@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+use crate::diff::{flatten_tree, FileEntry};
+use crate::git_objects::{GitObject, TreeFileModes};
+use crate::index::{Index, IndexEntry};
+use crate::merge::MergeResult;
+use crate::utils::{generate_object_id, glob_match};
+
+/// Blob hash and mode of a file in the working tree, or `None` if it doesn't exist.
+pub fn hash_worktree_file(path: &str) -> anyhow::Result<Option<FileEntry>> {
+    let Some((mode, content)) = read_worktree_file(path)? else {
+        return Ok(None);
+    };
+
+    let header = format!("blob {}\0", content.len());
+
+    let hash = generate_object_id(&[header.as_bytes(), content.as_slice()].concat())?;
+
+    Ok(Some(FileEntry { mode, hash }))
+}
+
+/// Like `hash_worktree_file`, but also writes the blob to the object store.
+pub fn store_worktree_file(path: &str) -> anyhow::Result<Option<FileEntry>> {
+    let Some((mode, content)) = read_worktree_file(path)? else {
+        return Ok(None);
+    };
+
+    let hash = GitObject::write_raw("blob", &content)?;
+
+    Ok(Some(FileEntry { mode, hash }))
+}
+
+/// Mode and blob content of a file (a symbolic link's content is its target).
+fn read_worktree_file(path: &str) -> anyhow::Result<Option<(TreeFileModes, Vec<u8>)>> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+
+    let (mode, content) = if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path)?;
+
+        (
+            TreeFileModes::SymbolicLink,
+            target.to_string_lossy().as_bytes().to_vec(),
+        )
+    } else if metadata.is_dir() {
+        return Ok(None);
+    } else {
+        let mode = if metadata.permissions().mode() & 0o111 != 0 {
+            TreeFileModes::Executable
+        } else {
+            TreeFileModes::Regular
+        };
+
+        (mode, fs::read(path)?)
+    };
+
+    Ok(Some((mode, content)))
+}
+
+/// Whether the working tree file differs from what the index recorded for it.
+pub fn is_worktree_modified(entry: &IndexEntry) -> anyhow::Result<bool> {
+    let Ok(metadata) = fs::symlink_metadata(&entry.path) else {
+        return Ok(true);
+    };
+
+    // A submodule not checked out is only its directory
+    if entry.mode == TreeFileModes::Gitlink {
+        return Ok(!metadata.is_dir());
+    }
+
+    if entry.is_stat_clean(&metadata) {
+        return Ok(false);
+    }
+
+    Ok(match hash_worktree_file(&entry.path)? {
+        Some(file) => file.hash != entry.hash || file.mode != entry.mode,
+
+        None => true,
+    })
+}
+
+/// Writes a blob to the working tree with the mode it has in the tree. A submodule
+/// gets an empty directory, as git leaves one it has not cloned.
+pub fn write_worktree_file(path: &str, entry: &FileEntry) -> anyhow::Result<()> {
+    if entry.mode == TreeFileModes::Gitlink {
+        if fs::symlink_metadata(path).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(path)?;
+        }
+
+        fs::create_dir_all(path).with_context(|| format!("Could not create {path}"))?;
+
+        return Ok(());
+    }
+
+    let (obj_type, content) = GitObject::read_raw(&entry.hash)?;
+
+    if obj_type != "blob" {
+        bail!("{} is not a blob", entry.hash);
+    }
+
+    if let Some(parent) = Path::new(path).parent() {
+        // A file standing where a directory is needed goes away first
+        if parent.is_file() {
+            fs::remove_file(parent)?;
+        }
+
+        fs::create_dir_all(parent).with_context(|| format!("Could not create {parent:?}"))?;
+    }
+
+    if Path::new(path).is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+
+    match entry.mode {
+        TreeFileModes::SymbolicLink => {
+            symlink(String::from_utf8_lossy(&content).as_ref(), path)?;
+        }
+
+        mode => {
+            fs::write(path, &content).with_context(|| format!("Could not write {path}"))?;
+
+            let permissions = if mode == TreeFileModes::Executable {
+                0o755
+            } else {
+                0o644
+            };
+
+            fs::set_permissions(path, fs::Permissions::from_mode(permissions))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes a file and any directories left empty by its removal.
+pub fn remove_worktree_file(path: &str) -> anyhow::Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path).with_context(|| format!("Could not remove {path}"))?;
+    }
+
+    let mut parent = Path::new(path).parent();
+
+    while let Some(directory) = parent {
+        if directory.as_os_str().is_empty() || fs::remove_dir(directory).is_err() {
+            break;
+        }
+
+        parent = directory.parent();
+    }
+
+    Ok(())
+}
+
+/// The index, or the files of `tree` when the repository has no index yet.
+pub fn read_index_or_tree(tree: Option<&str>) -> anyhow::Result<Index> {
+    if Index::exists() {
+        Index::read()
+    } else {
+        Index::from_tree(tree)
+    }
+}
+
+fn same(a: Option<&FileEntry>, b: Option<&FileEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.hash == b.hash && a.mode == b.mode,
+
+        (None, None) => true,
+
+        _ => false,
+    }
+}
+
+/// Moves the index and working tree from `old_tree` to `new_tree`. Local changes to
+/// paths that are the same in both trees are carried over; local changes to paths the
+/// switch has to touch abort it before anything is written, unless `force` is set.
+pub fn checkout_tree(
+    old_tree: Option<&str>,
+    new_tree: Option<&str>,
+    force: bool,
+    operation: &str,
+) -> anyhow::Result<()> {
+    let old_files = match old_tree {
+        Some(tree) => flatten_tree(tree)?,
+
+        None => BTreeMap::new(),
+    };
+
+    let new_files = match new_tree {
+        Some(tree) => flatten_tree(tree)?,
+
+        None => BTreeMap::new(),
+    };
+
+    let mut index = read_index_or_tree(old_tree)?;
+
+    if index.has_conflicts() && !force {
+        bail!("you need to resolve your current index first");
+    }
+
+    let paths: BTreeSet<&String> = old_files.keys().chain(new_files.keys()).collect();
+
+    let mut changes: Vec<(&String, Option<&FileEntry>)> = Vec::new();
+
+    let mut local_changes = Vec::new();
+
+    let mut untracked = Vec::new();
+
+    for path in paths {
+        let old = old_files.get(path);
+        let new = new_files.get(path);
+
+        if same(old, new) && !force {
+            continue;
+        }
+
+        let staged = index.get(path).map(IndexEntry::file_entry);
+
+        let worktree_modified = match index.get(path) {
+            Some(entry) => is_worktree_modified(entry)?,
+
+            None => fs::symlink_metadata(path).is_ok(),
+        };
+
+        // Already in the state the new tree wants
+        if !force && same(staged.as_ref(), new) && !worktree_modified {
+            continue;
+        }
+
+        if !force {
+            if old.is_none() && staged.is_none() && worktree_modified {
+                if !same(hash_worktree_file(path)?.as_ref(), new) {
+                    untracked.push(path.clone());
+                }
+            } else if !same(staged.as_ref(), old) || worktree_modified {
+                local_changes.push(path.clone());
+            }
+        }
+
+        changes.push((path, new));
+    }
+
+    let action = match operation {
+        "checkout" => "switch branches",
+
+        other => other,
+    };
+
+    if !local_changes.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by {operation}:\n\t{}\nPlease commit your changes or stash them before you {action}.\nAborting",
+            local_changes.join("\n\t")
+        );
+    }
+
+    if !untracked.is_empty() {
+        bail!(
+            "The following untracked working tree files would be overwritten by {operation}:\n\t{}\nPlease move or remove them before you {action}.\nAborting",
+            untracked.join("\n\t")
+        );
+    }
+
+    // Deletions first, so files can make room for directories and the other way round
+    for (path, new) in &changes {
+        if new.is_none() {
+            remove_worktree_file(path)?;
+
+            index.remove(path);
+        }
+    }
+
+    for (path, new) in &changes {
+        if let Some(entry) = new {
+            write_worktree_file(path, entry)?;
+
+            index.add(IndexEntry::from_file(path, entry)?);
+        }
+    }
+
+    // Forcing drops tracked files the new tree doesn't have, even newly staged ones
+    if force {
+        let stale: Vec<String> = index
+            .entries
+            .iter()
+            .filter(|entry| !new_files.contains_key(&entry.path))
+            .map(|entry| entry.path.clone())
+            .collect();
+
+        for path in stale {
+            remove_worktree_file(&path)?;
+
+            index.remove(&path);
+        }
+    }
+
+    index.write()
+}
+
+/// Checks out the tree of a merge, then records its conflicts in the index so that
+/// each conflicted path has its base, ours and theirs stages.
+pub fn checkout_merge_result(
+    old_tree: Option<&str>,
+    result: &MergeResult,
+    operation: &str,
+) -> anyhow::Result<()> {
+    checkout_tree(old_tree, Some(&result.tree), false, operation)?;
+
+    if result.conflicts.is_empty() {
+        return Ok(());
+    }
+
+    let mut index = Index::read()?;
+
+    for conflict in &result.conflicts {
+        index.add_conflict(&conflict.path, &conflict.stages);
+    }
+
+    index.write()
+}
+
+/// Files in the working tree that the index doesn't track, skipping `.git` and
+/// anything matched by the top-level `.gitignore`.
+pub fn list_untracked_files(index: &Index) -> anyhow::Result<Vec<String>> {
+    let tracked: BTreeSet<&str> = index.entries.iter().map(|e| e.path.as_str()).collect();
+
+    let ignored: Vec<String> = match fs::read_to_string(".gitignore") {
+        Ok(content) => content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.trim_matches('/').to_string())
+            .collect(),
+
+        Err(_) => Vec::new(),
+    };
+
+    let is_ignored = |path: &str| {
+        let name = path.rsplit('/').next().unwrap_or(path);
+
+        ignored
+            .iter()
+            .any(|pattern| glob_match(pattern, name) || glob_match(pattern, path))
+    };
+
+    let mut untracked = Vec::new();
+
+    let mut directories = vec![String::new()];
+
+    while let Some(directory) = directories.pop() {
+        let entries = fs::read_dir(if directory.is_empty() {
+            "."
+        } else {
+            &directory
+        })?;
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            let path = if directory.is_empty() {
+                name.clone()
+            } else {
+                format!("{directory}/{name}")
+            };
+
+            if name == ".git" || is_ignored(&path) {
+                continue;
+            }
+
+            if entry.file_type()?.is_dir() {
+                directories.push(path);
+            } else if !tracked.contains(path.as_str()) {
+                untracked.push(path);
+            }
+        }
+    }
+
+    untracked.sort();
+
+    Ok(untracked)
+}
+
+/// Whether a path matches one of the given paths, directly or as a directory prefix.
+pub fn matches_pathspec(path: &str, pathspecs: &[String]) -> bool {
+    pathspecs.iter().any(|spec| {
+        let spec = spec.trim_end_matches('/');
+
+        spec == "." || path == spec || path.starts_with(&format!("{spec}/"))
+    })
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use common::{binary, git, run, run_ok, write, Scratch};
+
+/// A repository with a few commits, repacked with a bitmap by the binary under test.
+fn bitmapped_repository(scratch: &Scratch) -> (PathBuf, PathBuf) {
+    let repository = scratch.repository("repository", false);
+
+    for number in 0..5 {
+        write(
+            &repository,
+            &format!("file{number}"),
+            &format!("{number}\n"),
+        );
+        git(&repository, &["add", "."]);
+        git(&repository, &["commit", "-qm", &format!("commit {number}")]);
+    }
+
+    run_ok(&repository, &["repack", "-a", "-d", "-b"]);
+
+    let bitmap = std::fs::read_dir(repository.join(".git/objects/pack"))
+        .expect("read pack directory")
+        .map(|entry| entry.expect("pack directory entry").path())
+        .find(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "bitmap")
+        })
+        .expect("a bitmap");
+
+    (repository, bitmap)
+}
+
+/// Object ids `rev-list --objects` lists, sorted, without the paths.
+fn object_ids(listing: &str) -> Vec<String> {
+    let mut ids: Vec<String> = listing
+        .lines()
+        .map(|line| line.split(' ').next().unwrap_or_default().to_string())
+        .collect();
+
+    ids.sort();
+
+    ids
+}
+
+#[test]
+fn written_bitmap_passes_git_test() {
+    let scratch = Scratch::new("bitmap-written");
+
+    let (repository, _) = bitmapped_repository(&scratch);
+
+    let tested = Command::new("git")
+        .args(["rev-list", "--test-bitmap", "HEAD"])
+        .current_dir(&repository)
+        .output()
+        .expect("run git");
+
+    // The verdict goes to stderr
+    let verdict = String::from_utf8_lossy(&tested.stderr);
+
+    assert!(
+        tested.status.success() && verdict.contains("OK!"),
+        "{verdict}"
+    );
+
+    assert_eq!(
+        object_ids(&run_ok(
+            &repository,
+            &[
+                "rev-list",
+                "--use-bitmap-index",
+                "--objects",
+                "HEAD~2..HEAD"
+            ]
+        )),
+        object_ids(&git(
+            &repository,
+            &["rev-list", "--objects", "HEAD~2..HEAD"]
+        ))
+    );
+}
+
+#[test]
+fn corrupt_bitmap_falls_back_to_walking() {
+    let scratch = Scratch::new("bitmap-corrupt");
+
+    let (repository, bitmap) = bitmapped_repository(&scratch);
+
+    let mut content = std::fs::read(&bitmap).expect("read bitmap");
+
+    // The first EWAH bitmap claims far more words than the file holds
+    content[32..36].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff]);
+    content[36..40].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff]);
+
+    std::fs::write(&bitmap, content).expect("corrupt bitmap");
+
+    let listed = run(
+        &repository,
+        &["rev-list", "--use-bitmap-index", "--objects", "--all"],
+    );
+
+    assert!(listed.status.success());
+    assert!(String::from_utf8_lossy(&listed.stderr).contains("warning: ignoring"));
+    assert_eq!(
+        object_ids(&String::from_utf8_lossy(&listed.stdout)),
+        object_ids(&git(&repository, &["rev-list", "--objects", "--all"]))
+    );
+
+    // Serving a clone falls back the same way
+    let upload_pack = format!("--upload-pack={} upload-pack", binary());
+
+    git(
+        &scratch.path,
+        &[
+            "clone",
+            "-q",
+            "--no-local",
+            &upload_pack,
+            repository.to_str().expect("utf-8 path"),
+            "clone",
+        ],
+    );
+
+    git(&scratch.path.join("clone"), &["fsck", "--no-progress"]);
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::{git, run, run_ok, Scratch};
+
+/// A repository where `merged` is behind `main` and `topic` one commit ahead of it.
+fn branched_repository(scratch: &Scratch) -> PathBuf {
+    let repository = scratch.repository("repository", false);
+
+    let commit = |message: &str| {
+        git(
+            &repository,
+            &["commit", "-q", "--allow-empty", "-m", message],
+        );
+    };
+
+    commit("one");
+    git(&repository, &["branch", "merged"]);
+    commit("two");
+    git(&repository, &["checkout", "-qb", "topic"]);
+    commit("three");
+    git(&repository, &["checkout", "-q", "main"]);
+
+    repository
+}
+
+#[test]
+fn listing_matches_git() {
+    let scratch = Scratch::new("branch-list");
+
+    let repository = branched_repository(&scratch);
+
+    git(
+        &repository,
+        &["branch", "--set-upstream-to", "main", "topic"],
+    );
+
+    for args in [
+        &[][..],
+        &["-l", "m*"],
+        &["-v"],
+        &["-vv"],
+        &["--merged"],
+        &["--merged", "topic"],
+        &["--no-merged"],
+        &["--contains", "HEAD~1"],
+        &["--contains", "topic"],
+    ] {
+        let args = [&["branch"][..], args].concat();
+
+        assert_eq!(
+            run_ok(&repository, &args),
+            git(&repository, &args),
+            "{args:?}"
+        );
+    }
+}
+
+#[test]
+fn delete_checks_merged_status() {
+    let scratch = Scratch::new("branch-delete");
+
+    let repository = branched_repository(&scratch);
+
+    let refused = run(&repository, &["branch", "-d", "topic"]);
+
+    assert!(!refused.status.success());
+    assert!(
+        String::from_utf8_lossy(&refused.stderr).contains("not fully merged"),
+        "{}",
+        String::from_utf8_lossy(&refused.stderr)
+    );
+
+    run_ok(&repository, &["branch", "-d", "merged"]);
+    run_ok(&repository, &["branch", "-D", "topic"]);
+
+    assert_eq!(git(&repository, &["branch"]), "* main\n");
+    assert!(!repository.join(".git/logs/refs/heads/topic").exists());
+}
+
+#[test]
+fn rename_and_copy_carry_the_reflog() {
+    let scratch = Scratch::new("branch-rename");
+
+    let repository = branched_repository(&scratch);
+
+    let reflog = git(&repository, &["reflog", "show", "--format=%H %gs", "topic"]);
+
+    run_ok(&repository, &["branch", "-m", "topic", "renamed"]);
+    run_ok(&repository, &["branch", "-c", "renamed", "copied"]);
+
+    assert!(!repository.join(".git/refs/heads/topic").exists());
+
+    for branch in ["renamed", "copied"] {
+        let moved = git(&repository, &["reflog", "show", "--format=%H %gs", branch]);
+
+        assert!(moved.ends_with(&reflog), "{branch}: {moved}");
+    }
+
+    // The checked out branch takes HEAD along
+    run_ok(&repository, &["branch", "-m", "trunk"]);
+
+    assert_eq!(
+        git(&repository, &["symbolic-ref", "HEAD"]),
+        "refs/heads/trunk\n"
+    );
+}
+
+#[test]
+fn bad_names_are_refused() {
+    let scratch = Scratch::new("branch-names");
+
+    let repository = branched_repository(&scratch);
+
+    for name in ["bad..name", "bad.lock", "-dash", "a b", "HEAD", "tail/"] {
+        assert!(
+            !run(&repository, &["branch", "--", name]).status.success(),
+            "{name} was accepted"
+        );
+    }
+
+    run_ok(&repository, &["branch", "feature/x"]);
+
+    let refused = run(&repository, &["branch", "feature"]);
+
+    assert!(
+        String::from_utf8_lossy(&refused.stderr)
+            .contains("'refs/heads/feature/x' exists; cannot create 'refs/heads/feature'"),
+        "{}",
+        String::from_utf8_lossy(&refused.stderr)
+    );
+
+    // Free again once the branch below is gone, even from packed-refs
+    git(&repository, &["pack-refs", "--all"]);
+
+    run_ok(&repository, &["branch", "-D", "feature/x"]);
+    run_ok(&repository, &["branch", "feature"]);
+
+    assert!(!run(&repository, &["branch", "feature/y"]).status.success());
+
+    run_ok(&repository, &["branch", "-D", "feature"]);
+    run_ok(&repository, &["branch", "feature/y"]);
+
+    git(&repository, &["fsck", "--no-progress"]);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{git, malformed_packs, run, run_ok, write, Scratch};
+
+/// The ref a hand-made bundle claims to carry.
+const TIP: &str = "1111111111111111111111111111111111111111";
+
+#[test]
+fn incremental_unbundle_is_stored_complete() {
+    let scratch = Scratch::new("bundle-thin");
+
+    let source = scratch.repository("source", false);
+
+    let lines: String = (0..2000).map(|line| format!("line {line}\n")).collect();
+
+    write(&source, "big", &lines);
+    git(&source, &["add", "big"]);
+    git(&source, &["commit", "-qm", "first"]);
+
+    let target = scratch.path.join("target");
+
+    git(
+        &scratch.path,
+        &["clone", "-q", "--bare", "--no-local", "source", "target"],
+    );
+
+    // A one-line edit is bundled as a delta against the prerequisite's blob
+    write(
+        &source,
+        "big",
+        &lines.replace("line 1000\n", "line one thousand\n"),
+    );
+    git(&source, &["commit", "-qam", "second"]);
+    git(
+        &source,
+        &["bundle", "create", "-q", "../thin.bundle", "main~1..main"],
+    );
+
+    let listing = run_ok(&target, &["bundle", "unbundle", "../thin.bundle"]);
+
+    let head = git(&source, &["rev-parse", "main"]);
+
+    assert!(listing.starts_with(head.trim()), "{listing}");
+
+    git(&target, &["update-ref", "refs/heads/main", head.trim()]);
+    git(&target, &["fsck", "--strict", "--no-progress"]);
+
+    for entry in std::fs::read_dir(target.join("objects/pack")).expect("read pack directory") {
+        let path = entry.expect("pack directory entry").path();
+
+        if path.extension().is_some_and(|extension| extension == "idx") {
+            git(
+                &target,
+                &["verify-pack", path.to_str().expect("utf-8 path")],
+            );
+        }
+    }
+}
+
+#[test]
+fn malformed_packs_are_refused() {
+    let scratch = Scratch::new("bundle-malformed");
+
+    let repository = scratch.repository("repository", true);
+
+    for (name, pack) in malformed_packs() {
+        let mut bundle = format!("# v2 git bundle\n{TIP} refs/heads/main\n\n").into_bytes();
+
+        bundle.extend(pack);
+
+        std::fs::write(scratch.path.join("malformed.bundle"), bundle).expect("write bundle");
+
+        let output = run(&repository, &["bundle", "unbundle", "../malformed.bundle"]);
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(output.status.code(), Some(1), "{name}: {stderr}");
+        assert!(!stderr.contains("panicked"), "{name}: {stderr}");
+    }
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+use common::{binary, git, write, Scratch};
+
+/// Runs `program` with fixed dates, so that the commits it makes can be compared.
+fn dated(program: &str, directory: &Path, args: &[&str]) -> Output {
+    Command::new(program)
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_AUTHOR_DATE", "1600000000 +0200")
+        .env("GIT_COMMITTER_DATE", "1700000000 +0000")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .expect("run command")
+}
+
+/// The status, the last three commits and `f`.
+fn state(repository: &Path) -> String {
+    format!(
+        "{}{}{}",
+        git(repository, &["status", "--porcelain"]),
+        git(repository, &["log", "--format=%H %s", "-3"]),
+        std::fs::read_to_string(repository.join("f")).expect("read f")
+    )
+}
+
+#[test]
+fn sequences_match_git() {
+    let scratch = Scratch::new("cherry-pick");
+
+    let repository = scratch.repository("repository", false);
+
+    let lines = "1\n2\n3\n4\n5\n";
+
+    let commit = |message: &str| {
+        dated("git", &repository, &["commit", "-qam", message]);
+    };
+
+    write(&repository, "f", lines);
+    git(&repository, &["add", "f"]);
+    commit("base");
+
+    git(&repository, &["checkout", "-qb", "side"]);
+    write(&repository, "f", &lines.replace('2', "two"));
+    commit("two");
+    write(
+        &repository,
+        "f",
+        &lines.replace('2', "two").replace('4', "four"),
+    );
+    commit("four");
+    write(&repository, "n", "n\n");
+    git(&repository, &["add", "n"]);
+    commit("new file");
+
+    // A merge of the first side commit, for `-m`
+    git(&repository, &["checkout", "-qb", "merged", "main"]);
+    dated(
+        "git",
+        &repository,
+        &["merge", "-q", "--no-ff", "-m", "merge", "side~2"],
+    );
+
+    git(&repository, &["checkout", "-q", "main"]);
+    write(&repository, "f", &lines.replace('4', "FOUR"));
+    commit("main");
+
+    let expected = scratch.path.join("expected");
+
+    assert!(Command::new("cp")
+        .arg("-r")
+        .arg(&repository)
+        .arg(&expected)
+        .status()
+        .expect("run cp")
+        .success());
+
+    let resolved = lines.replace('4', "both");
+
+    let steps: [(&[&str], bool); 11] = [
+        (&["revert", "HEAD"], false),
+        (&["reset", "--hard", "HEAD~1"], false),
+        // Conflicts on `four`, which is skipped
+        (&["cherry-pick", "side~1"], false),
+        (&["cherry-pick", "--skip"], false),
+        (&["cherry-pick", "-x", "side~2", "side~1", "side"], false),
+        (&["cherry-pick", "--abort"], false),
+        (&["cherry-pick", "side~1", "side"], true),
+        (&["cherry-pick", "--continue"], false),
+        (&["cherry-pick", "-m", "1", "merged"], false),
+        (&["revert", "-m", "1", "merged"], false),
+        (&["cherry-pick", "-n", "side~2"], false),
+    ];
+
+    for (args, resolve) in steps {
+        let ours = dated(binary(), &repository, args);
+        let theirs = dated("git", &expected, args);
+
+        assert_eq!(
+            ours.status.success(),
+            theirs.status.success(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&ours.stderr)
+        );
+        assert_eq!(state(&repository), state(&expected), "{args:?}");
+
+        if resolve {
+            for directory in [&repository, &expected] {
+                write(directory, "f", &resolved);
+                git(directory, &["add", "f"]);
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use common::{binary, git, write, Scratch};
+
+/// A repository with six commits a day apart on `main`, the fourth tagged `v3` and the
+/// fifth the tip of `side`, served with filters allowed.
+fn upstream(scratch: &Scratch) -> PathBuf {
+    let upstream = scratch.repository("upstream", false);
+
+    for day in 1..=6 {
+        let date = format!("{} +0000", 1_600_000_000 + day * 86_400);
+
+        write(&upstream, &format!("f{day}"), &format!("{day}\n"));
+        git(&upstream, &["add", "."]);
+
+        assert!(Command::new("git")
+            .args(["commit", "-qm", &format!("c{day}")])
+            .current_dir(&upstream)
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .status()
+            .expect("run git")
+            .success());
+    }
+
+    git(&upstream, &["tag", "-a", "-m", "tag", "v3", "HEAD~3"]);
+    git(&upstream, &["branch", "side", "HEAD~2"]);
+    git(&upstream, &["config", "uploadpack.allowFilter", "true"]);
+
+    upstream
+}
+
+/// Runs the binary under test, or git, in `directory`.
+fn run_with(program: &str, directory: &Path, args: &[&str]) -> Output {
+    Command::new(program)
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .expect("run command")
+}
+
+/// The shallow commits, the history and refs, the remote's config, the objects a
+/// partial clone is missing and whether the repository is sound.
+fn state(repository: &Path) -> String {
+    let mut shallow: Vec<String> = std::fs::read_to_string(repository.join(".git/shallow"))
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect();
+
+    shallow.sort();
+
+    let missing: String = git(
+        repository,
+        &["rev-list", "--all", "--objects", "--missing=print"],
+    )
+    .lines()
+    .filter(|line| line.starts_with('?'))
+    .map(|line| format!("{line}\n"))
+    .collect();
+
+    format!(
+        "{}\n{}{}{}{}{}",
+        shallow.join("\n"),
+        git(repository, &["log", "--format=%s", "--all"]),
+        git(repository, &["for-each-ref", "--format=%(refname)"]),
+        git(repository, &["config", "--get-regexp", "^remote"]),
+        missing,
+        run_with("git", repository, &["fsck", "--no-progress"])
+            .status
+            .success()
+    )
+}
+
+/// Clones `upstream` with `options` twice, with the binary under test and with git, then
+/// runs each step in both clones, comparing the outcome.
+fn compare(scratch: &Scratch, upstream: &Path, options: &[&str], steps: &[&[&str]]) {
+    let url = format!("file://{}", upstream.display());
+
+    let clones = [
+        (binary(), scratch.path.join("ours")),
+        ("git", scratch.path.join("theirs")),
+    ];
+
+    for (program, clone) in &clones {
+        let _ = std::fs::remove_dir_all(clone);
+
+        let clone = clone.to_str().expect("utf-8 path");
+
+        let output = run_with(
+            program,
+            &scratch.path,
+            &[&["clone"], options, &[&url, clone]].concat(),
+        );
+
+        assert!(
+            output.status.success(),
+            "{program} clone {options:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let [(_, ours), (_, theirs)] = &clones;
+
+    assert_eq!(state(ours), state(theirs), "clone {options:?}");
+
+    // Compared without trailing newlines, which `cat-file -s` and `-t` do not print
+    for args in steps {
+        let output = run_with(binary(), ours, args);
+        let expected = run_with("git", theirs, args);
+
+        assert_eq!(
+            (
+                output.status.success(),
+                String::from_utf8_lossy(&output.stdout)
+                    .trim_end()
+                    .to_string()
+            ),
+            (
+                expected.status.success(),
+                String::from_utf8_lossy(&expected.stdout)
+                    .trim_end()
+                    .to_string()
+            ),
+            "{options:?} {args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(state(ours), state(theirs), "{options:?} {args:?}");
+    }
+}
+
+#[test]
+fn shallow_clones_match_git() {
+    let scratch = Scratch::new("clone-shallow");
+
+    let upstream = upstream(&scratch);
+
+    compare(
+        &scratch,
+        &upstream,
+        &["--depth", "1"],
+        &[
+            &["fetch", "--deepen", "1"],
+            &["fetch", "--depth", "4"],
+            &["fetch", "--shallow-since", "2020-09-16 13:00"],
+            &["fetch", "--shallow-exclude", "v3"],
+            &["fetch", "--depth", "1", "origin", "side"],
+            &["fetch", "--unshallow"],
+        ],
+    );
+
+    compare(
+        &scratch,
+        &upstream,
+        &["--shallow-since", "2020-09-17 13:00"],
+        &[],
+    );
+
+    // A tag excludes what it tags
+    compare(
+        &scratch,
+        &upstream,
+        &["--shallow-exclude", "v3", "--shallow-exclude", "side"],
+        &[],
+    );
+
+    compare(
+        &scratch,
+        &upstream,
+        &["--depth", "2", "--no-single-branch"],
+        &[],
+    );
+
+    // A depth and a date or an excluded ref do not mix
+    for program in [binary(), "git"] {
+        let output = run_with(
+            program,
+            &scratch.path,
+            &[
+                "clone",
+                "--depth",
+                "2",
+                "--shallow-exclude",
+                "side",
+                &format!("file://{}", upstream.display()),
+                "mixed",
+            ],
+        );
+
+        assert!(!output.status.success(), "{program}");
+        assert!(!scratch.path.join("mixed").exists(), "{program}");
+    }
+}
+
+#[test]
+fn partial_clones_fetch_what_they_miss() {
+    let scratch = Scratch::new("clone-partial");
+
+    let upstream = upstream(&scratch);
+
+    let object = |revision: &str| git(&upstream, &["rev-parse", revision]).trim().to_string();
+
+    let blob = object("HEAD~4:f1");
+    let tree = object("HEAD~4^{tree}");
+    let older = object("HEAD~5^{tree}");
+
+    for filter in ["blob:none", "blob:limit=2", "tree:0", "tree:1"] {
+        compare(
+            &scratch,
+            &upstream,
+            &["--filter", filter],
+            &[
+                &["cat-file", "-p", &blob],
+                &["cat-file", "-s", &tree],
+                &["cat-file", "-t", &older],
+                &["fetch", "origin"],
+            ],
+        );
+    }
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{git, run_ok, write, Scratch};
+
+/// A repository with a merge in its history, its objects packed.
+fn merged_repository(scratch: &Scratch) -> PathBuf {
+    let repository = scratch.repository("repository", false);
+
+    for number in 0..3 {
+        write(
+            &repository,
+            &format!("file{number}"),
+            &format!("{number}\n"),
+        );
+        git(&repository, &["add", "."]);
+        git(&repository, &["commit", "-qm", &format!("commit {number}")]);
+    }
+
+    git(&repository, &["checkout", "-qb", "side", "HEAD~1"]);
+    write(&repository, "side", "side\n");
+    git(&repository, &["add", "."]);
+    git(&repository, &["commit", "-qm", "side"]);
+
+    git(&repository, &["checkout", "-q", "main"]);
+    git(&repository, &["merge", "-q", "--no-edit", "side"]);
+    git(&repository, &["repack", "-q", "-d"]);
+
+    repository
+}
+
+/// Adds a commit on `main`, so that a new layer has something to hold.
+fn add_commit(repository: &Path, name: &str) {
+    write(repository, name, &format!("{name}\n"));
+    git(repository, &["add", "."]);
+    git(repository, &["commit", "-qm", name]);
+}
+
+#[test]
+fn written_graph_passes_git_verify() {
+    let scratch = Scratch::new("commit-graph-written");
+
+    let repository = merged_repository(&scratch);
+
+    for options in [&["--reachable"][..], &["--reachable", "--changed-paths"]] {
+        run_ok(&repository, &[&["commit-graph", "write"], options].concat());
+
+        git(&repository, &["commit-graph", "verify"]);
+    }
+
+    add_commit(&repository, "layer");
+
+    run_ok(
+        &repository,
+        &["commit-graph", "write", "--reachable", "--split=no-merge"],
+    );
+
+    let chain = std::fs::read_to_string(
+        repository.join(".git/objects/info/commit-graphs/commit-graph-chain"),
+    )
+    .expect("read commit-graph chain");
+
+    assert_eq!(chain.lines().count(), 2, "{chain}");
+
+    git(&repository, &["commit-graph", "verify"]);
+}
+
+#[test]
+fn git_graph_passes_verify() {
+    let scratch = Scratch::new("commit-graph-git");
+
+    let repository = merged_repository(&scratch);
+
+    git(
+        &repository,
+        &["commit-graph", "write", "--reachable", "--changed-paths"],
+    );
+
+    run_ok(&repository, &["commit-graph", "verify"]);
+
+    add_commit(&repository, "layer");
+
+    git(
+        &repository,
+        &["commit-graph", "write", "--reachable", "--split=no-merge"],
+    );
+
+    run_ok(&repository, &["commit-graph", "verify"]);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use std::process::Command;
+
+use common::{binary, git, write, Scratch};
+
+#[test]
+fn environment_dates_match_git() {
+    let scratch = Scratch::new("commit-tree-dates");
+
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "a", "a\n");
+    git(&repository, &["add", "a"]);
+
+    let tree = git(&repository, &["write-tree"]);
+    let tree = tree.trim();
+
+    let commit_tree = |program: &str, date: &str| {
+        let output = Command::new(program)
+            .args(["commit-tree", tree, "-m", "dated"])
+            .current_dir(&repository)
+            .env("TZ", "UTC")
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .env_remove("RUST_BACKTRACE")
+            .output()
+            .expect("run commit-tree");
+
+        (
+            output.status.success(),
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        )
+    };
+
+    for date in [
+        "1112911993 +0200",
+        "@1112911993",
+        "2005-04-07T22:13:13",
+        "2005-04-07 22:13:13 -0530",
+    ] {
+        let expected = commit_tree("git", date);
+
+        assert!(expected.0, "git refused {date}");
+        assert_eq!(commit_tree(binary(), date), expected, "{date}");
+    }
+
+    assert!(!commit_tree(binary(), "not a date").0);
+}
@@ -0,0 +1,151 @@
+//! Scratch repositories set up with the system git, for checking what the binary
+//! writes against what git makes of it.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A directory under the system temporary directory, removed when dropped.
+pub struct Scratch {
+    pub path: PathBuf,
+}
+
+impl Scratch {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("codecrafters-git-{name}-{}", std::process::id()));
+
+        let _ = fs::remove_dir_all(&path);
+
+        fs::create_dir_all(&path).expect("create scratch directory");
+
+        Scratch { path }
+    }
+
+    /// A new repository with an identity configured, on branch `main`.
+    pub fn repository(&self, name: &str, bare: bool) -> PathBuf {
+        let path = self.path.join(name);
+
+        let mut args = vec!["init", "-q", "-b", "main"];
+
+        if bare {
+            args.push("--bare");
+        }
+
+        args.push(path.to_str().expect("utf-8 path"));
+
+        git(&self.path, &args);
+
+        git(&path, &["config", "user.name", "Test"]);
+        git(&path, &["config", "user.email", "test@example.com"]);
+
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Runs git in `directory`, failing the test unless it succeeds. Returns its output.
+pub fn git(directory: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .output()
+        .expect("run git");
+
+    assert!(
+        output.status.success(),
+        "git {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+/// Runs the binary under test in `directory`.
+pub fn run(directory: &Path, args: &[&str]) -> Output {
+    Command::new(binary())
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .expect("run binary")
+}
+
+/// Runs the binary under test in `directory`, failing the test unless it succeeds.
+pub fn run_ok(directory: &Path, args: &[&str]) -> String {
+    let output = run(directory, args);
+
+    assert!(
+        output.status.success(),
+        "{args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+pub fn binary() -> &'static str {
+    env!("CARGO_BIN_EXE_codecrafters-git")
+}
+
+pub fn write(directory: &Path, name: &str, content: &str) {
+    fs::write(directory.join(name), content).expect("write file");
+}
+
+/// Small packs that each break one rule of the format, named for what they get wrong.
+pub fn malformed_packs() -> Vec<(&'static str, Vec<u8>)> {
+    use std::io::Write;
+
+    use sha1::{Digest, Sha1};
+
+    let compressed = |content: &[u8]| {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+
+        encoder.write_all(content).expect("compress");
+
+        encoder.finish().expect("compress")
+    };
+
+    let pack = |entry: Vec<u8>| {
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+
+        pack.extend(entry);
+
+        let checksum = Sha1::digest(&pack);
+
+        pack.extend_from_slice(&checksum);
+
+        pack
+    };
+
+    vec![
+        (
+            "offset delta reaching before the pack",
+            pack([&[0x60, 0x64][..], &compressed(b"")].concat()),
+        ),
+        (
+            "truncated ref delta base id",
+            pack(vec![0x70, 1, 2, 3, 4, 5]),
+        ),
+        (
+            "size of about 2^60",
+            pack(
+                [
+                    &[0xbf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f][..],
+                    &compressed(b"x"),
+                ]
+                .concat(),
+            ),
+        ),
+    ]
+}
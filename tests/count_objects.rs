@@ -0,0 +1,248 @@
+mod common;
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use common::{git, run, run_ok, write, Scratch};
+
+/// Compares `count-objects` with git's, output and warnings, in every mode.
+fn assert_counts_match(repository: &Path, situation: &str) {
+    for args in [
+        &["count-objects"][..],
+        &["count-objects", "-v"],
+        &["count-objects", "-H"],
+        &["count-objects", "-v", "-H"],
+    ] {
+        let ours = run(repository, args);
+
+        let theirs = std::process::Command::new("git")
+            .args(args)
+            .current_dir(repository)
+            .output()
+            .expect("run git");
+
+        assert_eq!(
+            (
+                String::from_utf8_lossy(&ours.stdout),
+                String::from_utf8_lossy(&ours.stderr)
+            ),
+            (
+                String::from_utf8_lossy(&theirs.stdout),
+                String::from_utf8_lossy(&theirs.stderr)
+            ),
+            "{situation}: {args:?}"
+        );
+    }
+}
+
+#[test]
+fn count_objects_matches_git() {
+    let scratch = Scratch::new("count-objects");
+
+    let repository = scratch.repository("repository", false);
+
+    assert_counts_match(&repository, "empty");
+
+    let lines: String = (0..20_000).map(|line| format!("{line}\n")).collect();
+
+    write(&repository, "big", &lines);
+    write(&repository, "small", "small\n");
+    git(&repository, &["add", "."]);
+    git(&repository, &["commit", "-qm", "first"]);
+
+    assert_counts_match(&repository, "loose");
+
+    // Without `-d` the loose copies stay, and could be pruned
+    git(&repository, &["repack", "-q", "-a"]);
+
+    assert_counts_match(&repository, "prune-packable");
+
+    write(&repository, "small", "changed\n");
+    git(&repository, &["commit", "-qam", "second"]);
+
+    assert_counts_match(&repository, "loose and packed");
+
+    // Stray files among the loose objects and the packs, and an index without its pack
+    std::fs::create_dir_all(repository.join(".git/objects/00")).expect("create directory");
+
+    write(&repository, ".git/objects/00/garbage", "garbage\n");
+    write(&repository, ".git/objects/pack/garbage", "garbage\n");
+    write(&repository, ".git/objects/pack/pack-stray.idx", "");
+
+    assert_counts_match(&repository, "garbage");
+
+    git(&repository, &["cat-file", "-e", "HEAD"]);
+    run_ok(&repository, &["cat-file", "-p", "HEAD"]);
+}
+
+/// The entries under a heading of the `stats` report, split into words.
+fn section(report: &str, heading: &str) -> Vec<Vec<String>> {
+    report
+        .lines()
+        .skip_while(|line| *line != heading)
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.split_whitespace().map(str::to_string).collect())
+        .collect()
+}
+
+/// The object id among the words of an entry.
+fn hash(words: &[String]) -> &str {
+    words
+        .iter()
+        .find(|word| word.len() == 40 && word.chars().all(|c| c.is_ascii_hexdigit()))
+        .expect("an object id")
+}
+
+#[test]
+fn stats_agree_with_git() {
+    let scratch = Scratch::new("stats");
+
+    let repository = scratch.repository("repository", false);
+
+    std::fs::create_dir_all(repository.join("a/b/c")).expect("create directory");
+
+    let lines: Vec<String> = (0..3000).map(|line| format!("{line}\n")).collect();
+
+    for version in 0..5 {
+        write(&repository, "a/b/c/deep", &lines[version..].concat());
+        write(&repository, "small", &format!("{version}\n"));
+        git(&repository, &["add", "."]);
+        git(
+            &repository,
+            &["commit", "-qm", &format!("version {version}")],
+        );
+    }
+
+    git(&repository, &["repack", "-q", "-a", "-d", "--depth=3"]);
+
+    write(&repository, "loose", "loose\n");
+    git(&repository, &["add", "loose"]);
+    git(&repository, &["commit", "-qm", "loose"]);
+
+    let report = run_ok(&repository, &["stats", "-n", "3"]);
+
+    // Counts per type, and where the objects are
+    let mut kinds: HashMap<String, usize> = HashMap::new();
+
+    let objects = git(
+        &repository,
+        &[
+            "cat-file",
+            "--batch-all-objects",
+            "--batch-check=%(objecttype) %(objectsize) %(objectname)",
+        ],
+    );
+
+    let mut blobs = Vec::new();
+
+    for line in objects.lines() {
+        let [kind, size, name] = line.split(' ').collect::<Vec<_>>()[..] else {
+            panic!("unexpected line {line}");
+        };
+
+        let size: u64 = size.parse().expect("a size");
+
+        *kinds.entry(kind.to_string()).or_default() += 1;
+
+        if kind == "blob" {
+            blobs.push((size, name.to_string()));
+        }
+    }
+
+    for words in section(&report, "Objects") {
+        if let [kind, count, ..] = &words[..] {
+            if let Some(expected) = kinds.get(kind.trim_end_matches('s')) {
+                assert_eq!(count.parse::<usize>().ok(), Some(*expected), "{kind}");
+            }
+        }
+    }
+
+    let counts = git(&repository, &["count-objects", "-v"]);
+
+    let count_of = |name: &str| {
+        counts
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+            .expect("a count")
+            .to_string()
+    };
+
+    assert!(
+        report.contains(&format!(
+            "{} loose, {} packed in 1 pack",
+            count_of("count"),
+            count_of("in-pack")
+        )),
+        "{report}"
+    );
+
+    // The largest blob comes first
+    blobs.sort();
+
+    let largest = section(&report, "Largest blobs");
+
+    assert_eq!(largest.len(), 3, "{report}");
+    assert_eq!(hash(&largest[0]), blobs.last().expect("a blob").1);
+
+    // The deepest directory, with its depth
+    let deepest = section(&report, "Deepest trees");
+
+    assert_eq!(deepest[0][0], "3");
+    assert_eq!(deepest[0].last().map(String::as_str), Some("a/b/c"));
+    assert_eq!(
+        hash(&deepest[0]),
+        git(&repository, &["rev-parse", "HEAD:a/b/c"]).trim()
+    );
+
+    // Delta chains as long as verify-pack finds them, the longest first
+    let pack = std::fs::read_dir(repository.join(".git/objects/pack"))
+        .expect("read pack directory")
+        .map(|entry| entry.expect("pack directory entry").path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "idx"))
+        .expect("a pack");
+
+    let verified = git(
+        &repository,
+        &["verify-pack", "-v", pack.to_str().expect("utf-8 path")],
+    );
+
+    let depths: HashMap<&str, usize> = verified
+        .lines()
+        .filter_map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            (words.len() == 7).then(|| (words[0], words[5].parse().expect("a depth")))
+        })
+        .collect();
+
+    let chains = section(&report, "Longest delta chains");
+
+    assert_eq!(
+        chains[0][0].parse::<usize>().ok(),
+        depths.values().max().copied()
+    );
+
+    for words in &chains {
+        assert_eq!(
+            Some(&words[0].parse::<usize>().expect("a length")),
+            depths.get(hash(words)),
+            "{words:?}"
+        );
+    }
+
+    // Versions of a path, counted over the history
+    let versions: HashSet<String> = git(&repository, &["rev-list", "--all"])
+        .lines()
+        .map(|commit| git(&repository, &["rev-parse", &format!("{commit}:a/b/c/deep")]))
+        .collect();
+
+    let growth = section(&report, "Path growth");
+
+    let deep = growth
+        .iter()
+        .find(|words| words.last().map(String::as_str) == Some("a/b/c/deep"))
+        .expect("a/b/c/deep in the path growth");
+
+    assert_eq!(deep[2], versions.len().to_string(), "{report}");
+}
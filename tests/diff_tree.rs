@@ -0,0 +1,114 @@
+mod common;
+
+use std::path::Path;
+
+use common::{git, run, run_ok, write, Scratch};
+
+/// Lines `0..count` of a file, each naming the file so that no two files share many.
+fn numbered(name: &str, count: usize) -> String {
+    (0..count).map(|line| format!("{name} {line}\n")).collect()
+}
+
+/// Commits everything in the work tree and returns the commit id.
+fn commit(repository: &Path, message: &str) -> String {
+    git(repository, &["add", "-A"]);
+    git(repository, &["commit", "-qm", message]);
+
+    git(repository, &["rev-parse", "HEAD"]).trim().to_string()
+}
+
+#[test]
+fn rename_scores_match_git() {
+    let scratch = Scratch::new("diff-tree-scores");
+
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "a", &numbered("a", 20));
+    write(&repository, "b", &numbered("b", 20));
+
+    let before = commit(&repository, "before");
+
+    // `a` keeps 15 of its 20 lines and `b` only 10, both under a new name
+    std::fs::remove_file(repository.join("a")).expect("remove a");
+    std::fs::remove_file(repository.join("b")).expect("remove b");
+
+    write(&repository, "c", &(numbered("a", 15) + &numbered("c", 5)));
+    write(&repository, "d", &(numbered("b", 10) + &numbered("d", 10)));
+
+    let after = commit(&repository, "after");
+
+    for score in [
+        "", "=0", "=0%", "=1", "=5", "=50%", "=6", "=60%", "=7", "=75%", "=.75", "=0.7", "=80%",
+        "=9", "=100%", "=10", "=1000", "=12.5%",
+    ] {
+        let option = format!("--find-renames{score}");
+
+        let args = ["diff-tree", "-r", &option, &before, &after];
+
+        assert_eq!(
+            run_ok(&repository, &args),
+            git(&repository, &args),
+            "{option}"
+        );
+    }
+
+    for score in ["=x", "=5%5", "=-1"] {
+        let option = format!("--find-renames{score}");
+
+        let output = run(&repository, &["diff-tree", "-r", &option, &before, &after]);
+
+        assert!(!output.status.success(), "{option} was accepted");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("Invalid similarity score"),
+            "{option}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn rename_ties_are_broken_like_git() {
+    let scratch = Scratch::new("diff-tree-ties");
+
+    let repository = scratch.repository("repository", false);
+
+    let content = numbered("same", 20);
+
+    // Identical content under several names, with and without a matching base name
+    for path in ["one", "two", "dir/one", "three"] {
+        std::fs::create_dir_all(repository.join(path).parent().expect("parent"))
+            .expect("create directory");
+
+        write(&repository, path, &content);
+    }
+
+    write(&repository, "x", &numbered("x", 20));
+    write(&repository, "y", &numbered("x", 20));
+
+    let before = commit(&repository, "before");
+
+    for path in ["one", "two", "dir/one", "three", "x", "y"] {
+        std::fs::remove_file(repository.join(path)).expect("remove file");
+    }
+
+    std::fs::create_dir_all(repository.join("new")).expect("create directory");
+
+    write(&repository, "new/one", &content);
+    write(&repository, "four", &content);
+
+    // Equally similar to both `x` and `y`
+    write(&repository, "z", &(numbered("x", 18) + &numbered("z", 2)));
+    write(&repository, "w", &(numbered("x", 18) + &numbered("w", 2)));
+
+    let after = commit(&repository, "after");
+
+    for option in ["-M", "-C", "--find-copies-harder"] {
+        let args = ["diff-tree", "-r", option, &before, &after];
+
+        assert_eq!(
+            run_ok(&repository, &args),
+            git(&repository, &args),
+            "{option}"
+        );
+    }
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{git, run, write, Scratch};
+
+#[test]
+fn truncated_pack_index_is_reported() {
+    let scratch = Scratch::new("fsck-truncated-index");
+
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "a", "a\n");
+    git(&repository, &["add", "a"]);
+    git(&repository, &["commit", "-qm", "first"]);
+    git(&repository, &["gc", "-q"]);
+
+    let index = std::fs::read_dir(repository.join(".git/objects/pack"))
+        .expect("read pack directory")
+        .map(|entry| entry.expect("pack directory entry").path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "idx"))
+        .expect("a pack index");
+
+    // The fanout table still claims every object
+    let content = std::fs::read(&index).expect("read index");
+
+    std::fs::write(&index, &content[..1100]).expect("truncate index");
+
+    let fsck = run(&repository, &["fsck"]);
+
+    let stderr = String::from_utf8_lossy(&fsck.stderr);
+
+    assert!(stderr.contains("is truncated"), "{stderr}");
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    assert_eq!(fsck.status.code().map(|code| code & 0o4), Some(0o4));
+
+    for args in [["cat-file", "-p", "HEAD"], ["count-objects", "-v", "-H"]] {
+        let output = run(&repository, &args);
+
+        assert!(!output.status.success(), "{args:?}");
+        assert!(
+            !String::from_utf8_lossy(&output.stderr).contains("panicked"),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
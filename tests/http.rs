@@ -0,0 +1,286 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use common::{binary, git, malformed_packs, write, Scratch};
+
+/// A running `serve`, stopped when dropped.
+struct Server {
+    child: Child,
+
+    address: String,
+}
+
+impl Server {
+    fn start(root: &std::path::Path, options: &[&str]) -> Self {
+        let mut child = Command::new(binary())
+            .args(["serve", "--port", "0"])
+            .args(options)
+            .arg(root)
+            .env_remove("RUST_BACKTRACE")
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("run serve");
+
+        let mut stderr = BufReader::new(child.stderr.take().expect("stderr"));
+
+        let mut line = String::new();
+
+        stderr.read_line(&mut line).expect("read listening address");
+
+        // The server logs every request, so its stderr must not fill up or close
+        std::thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+
+        let address = line
+            .trim()
+            .rsplit_once("http://")
+            .map(|(_, address)| address.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| panic!("unexpected first line: {line}"));
+
+        Server { child, address }
+    }
+
+    /// Sends a request with `headers` and `body`, returning the whole response.
+    fn request(&self, request_line: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut stream = TcpStream::connect(&self.address).expect("connect");
+
+        write!(
+            stream,
+            "{request_line} HTTP/1.1\r\n{headers}Content-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .expect("send request");
+
+        stream.write_all(body).expect("send body");
+
+        let mut response = Vec::new();
+
+        let _ = stream.read_to_end(&mut response);
+
+        response
+    }
+
+    /// Whether the server still answers, rather than having died on an earlier request.
+    fn is_serving(&mut self) -> bool {
+        let response = self.request("GET /repository/info/refs?service=git-upload-pack", "", b"");
+
+        self.child.try_wait().expect("check server").is_none()
+            && response.starts_with(b"HTTP/1.1 200")
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn stalled_connection_does_not_block_others() {
+    let scratch = Scratch::new("http-stalled");
+
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "a", "a\n");
+    git(&repository, &["add", "a"]);
+    git(&repository, &["commit", "-qm", "first"]);
+
+    let server = Server::start(&scratch.path, &[]);
+
+    // Connects and then says nothing
+    let _idle = TcpStream::connect(&server.address).expect("connect");
+
+    git(
+        &scratch.path,
+        &[
+            "clone",
+            "-q",
+            &format!("http://{}/repository/.git", server.address),
+            "clone",
+        ],
+    );
+
+    assert_eq!(
+        git(&scratch.path.join("clone"), &["rev-parse", "HEAD"]),
+        git(&repository, &["rev-parse", "HEAD"])
+    );
+}
+
+#[test]
+fn oversized_bodies_are_refused() {
+    let scratch = Scratch::new("http-oversized");
+
+    scratch.repository("repository", true);
+
+    let server = Server::start(&scratch.path, &[]);
+
+    for framing in [
+        "Transfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n",
+        "Content-Length: 99999999999999\r\n\r\nabc",
+    ] {
+        let mut stream = TcpStream::connect(&server.address).expect("connect");
+
+        write!(
+            stream,
+            "POST /repository/git-upload-pack HTTP/1.1\r\n\
+             Content-Type: application/x-git-upload-pack-request\r\n{framing}"
+        )
+        .expect("send request");
+
+        stream
+            .shutdown(std::net::Shutdown::Write)
+            .expect("end request");
+
+        // Dropped without an answer, and without a body that size being allocated
+        let mut response = Vec::new();
+
+        let _ = stream.read_to_end(&mut response);
+
+        assert!(
+            response.is_empty(),
+            "{}",
+            String::from_utf8_lossy(&response)
+        );
+    }
+
+    let advertisement = TcpStream::connect(&server.address)
+        .and_then(|mut stream| {
+            write!(
+                stream,
+                "GET /repository/info/refs?service=git-upload-pack HTTP/1.1\r\n\r\n"
+            )?;
+
+            let mut response = String::new();
+
+            stream.read_to_string(&mut response)?;
+
+            Ok(response)
+        })
+        .expect("request advertisement");
+
+    assert!(advertisement.starts_with("HTTP/1.1 200"), "{advertisement}");
+}
+
+#[test]
+fn cgi_serves_only_exported_repositories() {
+    let scratch = Scratch::new("http-cgi");
+
+    let repository = scratch.repository("repository", true);
+
+    let backend = |variables: &[(&str, &str)]| {
+        let mut command = Command::new(binary());
+
+        command
+            .arg("http-backend")
+            .env_remove("RUST_BACKTRACE")
+            .env_remove("GIT_PROJECT_ROOT")
+            .env_remove("GIT_HTTP_EXPORT_ALL")
+            .env("REQUEST_METHOD", "GET")
+            .env("PATH_INFO", "/repository/info/refs")
+            .env("QUERY_STRING", "service=git-upload-pack")
+            .envs(variables.iter().copied());
+
+        command.output().expect("run http-backend")
+    };
+
+    let status = |output: &std::process::Output| {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        stdout.lines().next().unwrap_or_default().to_string()
+    };
+
+    let root = scratch.path.to_str().expect("utf-8 path");
+
+    let unrooted = backend(&[]);
+
+    assert!(!unrooted.status.success());
+    assert!(String::from_utf8_lossy(&unrooted.stderr).contains("GIT_PROJECT_ROOT is not set"));
+
+    assert_eq!(
+        status(&backend(&[("GIT_PROJECT_ROOT", root)])),
+        "Status: 404 Not Found"
+    );
+
+    assert_eq!(
+        status(&backend(&[
+            ("GIT_PROJECT_ROOT", root),
+            ("GIT_HTTP_EXPORT_ALL", "")
+        ])),
+        "Status: 200 OK"
+    );
+
+    write(&repository, "git-daemon-export-ok", "");
+
+    assert_eq!(
+        status(&backend(&[("GIT_PROJECT_ROOT", root)])),
+        "Status: 200 OK"
+    );
+}
+
+#[test]
+fn malformed_pushes_do_not_stop_the_server() {
+    let scratch = Scratch::new("http-malformed");
+
+    let repository = scratch.repository("repository", true);
+
+    let mut server = Server::start(&scratch.path, &["--enable-receive-pack"]);
+
+    let command = format!(
+        "{} {} refs/heads/main\0report-status\n",
+        "0".repeat(40),
+        "1".repeat(40)
+    );
+
+    for (name, pack) in malformed_packs() {
+        let mut body = format!("{:04x}{command}0000", command.len() + 4).into_bytes();
+
+        body.extend(pack);
+
+        server.request(
+            "POST /repository/git-receive-pack",
+            "Content-Type: application/x-git-receive-pack-request\r\n",
+            &body,
+        );
+
+        assert!(server.is_serving(), "{name}: the server stopped");
+    }
+
+    assert!(!repository.join("refs/heads/main").exists());
+}
+
+#[test]
+fn oversized_compressed_bodies_are_refused() {
+    let scratch = Scratch::new("http-compressed");
+
+    scratch.repository("repository", true);
+
+    let mut server = Server::start(&scratch.path, &[]);
+
+    // A small body that inflates to more than a request may hold
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+
+    for _ in 0..65 {
+        encoder.write_all(&[0; 1 << 20]).expect("compress");
+    }
+
+    let body = encoder.finish().expect("compress");
+
+    let response = server.request(
+        "POST /repository/git-upload-pack",
+        "Content-Type: application/x-git-upload-pack-request\r\nContent-Encoding: gzip\r\n",
+        &body,
+    );
+
+    assert!(
+        response.starts_with(b"HTTP/1.1 413"),
+        "{}",
+        String::from_utf8_lossy(&response)
+    );
+    assert!(server.is_serving());
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use common::{git, run, run_ok, write, Scratch};
+
+const BASE_SUBMODULE: &str = "1111111111111111111111111111111111111111";
+const OUR_SUBMODULE: &str = "2222222222222222222222222222222222222222";
+
+#[test]
+fn merge_keeps_submodule_entries() {
+    let scratch = Scratch::new("merge-submodule");
+
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "a", "a\n");
+    git(&repository, &["add", "a"]);
+    git(
+        &repository,
+        &[
+            "update-index",
+            "--add",
+            "--cacheinfo",
+            &format!("160000,{BASE_SUBMODULE},sub"),
+        ],
+    );
+    git(&repository, &["commit", "-qm", "base"]);
+
+    git(&repository, &["checkout", "-qb", "side"]);
+    write(&repository, "b", "b\n");
+    git(&repository, &["add", "b"]);
+    git(&repository, &["commit", "-qm", "side"]);
+
+    git(&repository, &["checkout", "-q", "main"]);
+    git(
+        &repository,
+        &[
+            "update-index",
+            "--cacheinfo",
+            &format!("160000,{OUR_SUBMODULE},sub"),
+        ],
+    );
+    git(&repository, &["commit", "-qm", "ours"]);
+
+    let expected = git(&repository, &["merge-tree", "--write-tree", "main", "side"]);
+
+    let merged = run_ok(&repository, &["merge-tree", "--write-tree", "main", "side"]);
+
+    assert_eq!(merged, expected);
+
+    let listing = git(&repository, &["ls-tree", merged.trim()]);
+
+    assert!(
+        listing.contains(&format!("160000 commit {OUR_SUBMODULE}\tsub")),
+        "{listing}"
+    );
+
+    git(&repository, &["fsck", "--no-progress"]);
+}
+
+/// A repository whose `main` and `side` both change the middle line of `f`, and `side`
+/// also adds `n`.
+fn conflicting_branches(scratch: &Scratch) -> std::path::PathBuf {
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "f", "a\nb\nc\n");
+    git(&repository, &["add", "f"]);
+    git(&repository, &["commit", "-qm", "base"]);
+
+    git(&repository, &["checkout", "-qb", "side"]);
+    write(&repository, "f", "a\nside\nc\n");
+    write(&repository, "n", "n\n");
+    git(&repository, &["add", "f", "n"]);
+    git(&repository, &["commit", "-qm", "side"]);
+
+    git(&repository, &["checkout", "-q", "main"]);
+    write(&repository, "f", "a\nmain\nc\n");
+    git(&repository, &["commit", "-qam", "main"]);
+
+    repository
+}
+
+#[test]
+fn conflicted_merge_is_continued() {
+    let scratch = Scratch::new("merge-continue");
+
+    let repository = conflicting_branches(&scratch);
+
+    let merge = run(&repository, &["merge", "side"]);
+
+    assert_eq!(merge.status.code(), Some(1));
+    assert_eq!(
+        std::fs::read_to_string(repository.join("f")).expect("read f"),
+        "a\n<<<<<<< HEAD\nmain\n=======\nside\n>>>>>>> side\nc\n"
+    );
+    assert_eq!(git(&repository, &["status", "--short"]), "UU f\nA  n\n");
+    assert_eq!(
+        git(&repository, &["rev-parse", "MERGE_HEAD"]),
+        git(&repository, &["rev-parse", "side"])
+    );
+
+    // Refused until the conflict is resolved
+    assert!(!run(&repository, &["merge", "--continue"]).status.success());
+
+    write(&repository, "f", "a\nboth\nc\n");
+    git(&repository, &["add", "f"]);
+
+    run_ok(&repository, &["merge", "--continue"]);
+
+    assert_eq!(
+        git(&repository, &["log", "-1", "--format=%P%n%s"]),
+        format!(
+            "{} {}\nMerge branch 'side'\n",
+            git(&repository, &["rev-parse", "main~1"]).trim(),
+            git(&repository, &["rev-parse", "side"]).trim()
+        )
+    );
+    assert!(!repository.join(".git/MERGE_HEAD").exists());
+
+    git(&repository, &["fsck", "--strict", "--no-progress"]);
+}
+
+#[test]
+fn conflicted_merge_is_aborted() {
+    let scratch = Scratch::new("merge-abort");
+
+    let repository = conflicting_branches(&scratch);
+
+    let head = git(&repository, &["rev-parse", "HEAD"]);
+
+    assert_eq!(run(&repository, &["merge", "side"]).status.code(), Some(1));
+
+    run_ok(&repository, &["merge", "--abort"]);
+
+    assert_eq!(git(&repository, &["rev-parse", "HEAD"]), head);
+    assert_eq!(git(&repository, &["status", "--short"]), "");
+    assert!(!repository.join("n").exists());
+    assert!(!repository.join(".git/MERGE_HEAD").exists());
+}
+
+/// Lines 1 to 12, with some replaced by the given text, or dropped when it is empty.
+fn edited(changes: &[(usize, &str)]) -> String {
+    (1..=12)
+        .map(
+            |line| match changes.iter().find(|(number, _)| *number == line) {
+                Some((_, text)) => text.to_string(),
+                None => format!("{line}\n"),
+            },
+        )
+        .collect()
+}
+
+#[test]
+fn conflict_markers_match_git() {
+    let scratch = Scratch::new("merge-markers");
+
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "f", &edited(&[]));
+    git(&repository, &["add", "f"]);
+    git(&repository, &["commit", "-qm", "base"]);
+    git(&repository, &["tag", "base"]);
+
+    let deleted: Vec<(usize, &str)> = (2..=11).map(|line| (line, "")).collect();
+
+    let cases = [
+        (
+            "first line",
+            edited(&[(1, "one\n")]),
+            edited(&[(1, "uno\n")]),
+        ),
+        (
+            "last line",
+            edited(&[(12, "twelve\n")]),
+            edited(&[(12, "doce\n")]),
+        ),
+        (
+            "adjacent lines",
+            edited(&[(5, "five\n")]),
+            edited(&[(6, "six\n")]),
+        ),
+        (
+            "interleaved lines",
+            edited(&[(5, "five\n"), (7, "seven\n")]),
+            edited(&[(6, "six\n")]),
+        ),
+        (
+            "deleted around a change",
+            edited(&[(4, ""), (5, ""), (6, ""), (7, ""), (8, "")]),
+            edited(&[(6, "six\n")]),
+        ),
+        (
+            "insertion",
+            edited(&[(3, "3\nextra\n")]),
+            edited(&[(3, "three\n")]),
+        ),
+        (
+            "both appending",
+            edited(&[(12, "12\nend\n")]),
+            edited(&[(12, "12\nfin\n")]),
+        ),
+        (
+            "same change and another",
+            edited(&[(6, "same\n")]),
+            edited(&[(6, "same\n"), (9, "nine\n")]),
+        ),
+        (
+            "most lines deleted",
+            edited(&deleted),
+            edited(&[(6, "x\n")]),
+        ),
+        (
+            "no final newline",
+            edited(&[(12, "ours")]),
+            edited(&[(12, "theirs")]),
+        ),
+    ];
+
+    for (name, ours, theirs) in cases {
+        for (branch, content) in [("ours", &ours), ("theirs", &theirs)] {
+            git(&repository, &["checkout", "-q", "-B", branch, "base"]);
+            write(&repository, "f", content);
+            git(&repository, &["commit", "-qam", branch]);
+        }
+
+        let args = ["merge-tree", "--write-tree", "ours", "theirs"];
+
+        let merged = run(&repository, &args);
+
+        let expected = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&repository)
+            .output()
+            .expect("run git");
+
+        assert_eq!(merged.status.code(), expected.status.code(), "{name}");
+        assert_eq!(
+            String::from_utf8_lossy(&merged.stdout),
+            String::from_utf8_lossy(&expected.stdout),
+            "{name}"
+        );
+    }
+}
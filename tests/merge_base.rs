@@ -0,0 +1,123 @@
+mod common;
+
+use std::path::Path;
+
+use common::{git, run_ok, Scratch};
+
+/// Builds a history with criss-cross merges and an octopus, and returns every commit.
+fn tangled_history(repository: &Path) -> Vec<String> {
+    let commit = |message: &str| {
+        git(
+            repository,
+            &["commit", "-q", "--allow-empty", "-m", message],
+        );
+    };
+
+    commit("root");
+    git(repository, &["branch", "a"]);
+    git(repository, &["branch", "b"]);
+    git(repository, &["branch", "c"]);
+
+    for round in 0..3 {
+        for branch in ["a", "b", "c"] {
+            git(repository, &["checkout", "-q", branch]);
+            commit(&format!("{branch} {round}"));
+        }
+
+        // Each of a and b merges the other's previous tip: a criss-cross
+        let a = git(repository, &["rev-parse", "a"]);
+        let b = git(repository, &["rev-parse", "b"]);
+
+        git(repository, &["checkout", "-q", "a"]);
+        git(repository, &["merge", "-q", "--no-edit", b.trim()]);
+        git(repository, &["checkout", "-q", "b"]);
+        git(repository, &["merge", "-q", "--no-edit", a.trim()]);
+    }
+
+    git(repository, &["checkout", "-q", "main"]);
+    git(repository, &["merge", "-q", "--no-edit", "a", "c"]);
+    commit("tip");
+
+    git(repository, &["rev-list", "--all"])
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+fn sorted_lines(output: &str) -> Vec<String> {
+    let mut lines: Vec<String> = output.lines().map(str::to_string).collect();
+
+    lines.sort();
+
+    lines
+}
+
+#[test]
+fn merge_bases_match_git() {
+    let scratch = Scratch::new("merge-base-all");
+
+    let repository = scratch.repository("repository", false);
+
+    let commits = tangled_history(&repository);
+
+    for graph in [false, true] {
+        if graph {
+            git(&repository, &["commit-graph", "write", "--reachable"]);
+        }
+
+        for (index, one) in commits.iter().enumerate() {
+            for two in &commits[index..] {
+                let args = ["merge-base", "--all", one, two];
+
+                assert_eq!(
+                    sorted_lines(&run_ok(&repository, &args)),
+                    sorted_lines(&git(&repository, &args)),
+                    "{one} {two}, commit-graph: {graph}"
+                );
+            }
+        }
+
+        let args = ["merge-base", "--octopus", "a", "b", "c"];
+
+        assert_eq!(
+            sorted_lines(&run_ok(&repository, &args)),
+            sorted_lines(&git(&repository, &args))
+        );
+    }
+}
+
+#[test]
+fn fork_point_follows_the_reflog() {
+    let scratch = Scratch::new("merge-base-fork-point");
+
+    let repository = scratch.repository("repository", false);
+
+    for message in ["one", "two", "three"] {
+        git(
+            &repository,
+            &["commit", "-q", "--allow-empty", "-m", message],
+        );
+    }
+
+    git(&repository, &["checkout", "-qb", "topic"]);
+    git(
+        &repository,
+        &["commit", "-q", "--allow-empty", "-m", "topic"],
+    );
+
+    // Upstream rewinds and rewrites its history: the fork point is still where topic
+    // left it, which plain merge-base no longer finds
+    git(&repository, &["checkout", "-q", "main"]);
+    git(&repository, &["reset", "-q", "--hard", "HEAD~2"]);
+    git(
+        &repository,
+        &["commit", "-q", "--allow-empty", "-m", "rewritten"],
+    );
+
+    let args = ["merge-base", "--fork-point", "main", "topic"];
+
+    let expected = git(&repository, &args);
+
+    assert_eq!(expected, git(&repository, &["rev-parse", "topic~1"]));
+    assert_eq!(run_ok(&repository, &args), expected);
+}
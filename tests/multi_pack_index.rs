@@ -0,0 +1,74 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{git, run_ok, write, Scratch};
+
+/// A repository whose objects are spread over three packs.
+fn repository_with_packs(scratch: &Scratch) -> PathBuf {
+    let repository = scratch.repository("repository", false);
+
+    for number in 0..3 {
+        write(
+            &repository,
+            &format!("file{number}"),
+            &format!("{number}\n"),
+        );
+        git(&repository, &["add", "."]);
+        git(&repository, &["commit", "-qm", &format!("commit {number}")]);
+        git(&repository, &["repack", "-q", "-d"]);
+    }
+
+    repository
+}
+
+/// File names of the packs in a repository, sorted.
+fn packs(repository: &Path) -> Vec<String> {
+    let mut packs: Vec<String> = std::fs::read_dir(repository.join(".git/objects/pack"))
+        .expect("read pack directory")
+        .map(|entry| entry.expect("pack directory entry").file_name())
+        .filter_map(|name| name.into_string().ok())
+        .filter(|name| name.ends_with(".pack"))
+        .collect();
+
+    packs.sort();
+
+    packs
+}
+
+#[test]
+fn written_index_passes_git_verify() {
+    let scratch = Scratch::new("midx-written");
+
+    let repository = repository_with_packs(&scratch);
+
+    run_ok(&repository, &["multi-pack-index", "write"]);
+
+    git(&repository, &["multi-pack-index", "verify"]);
+
+    let preferred = format!("--preferred-pack={}", packs(&repository)[1]);
+
+    run_ok(&repository, &["multi-pack-index", "write", &preferred]);
+
+    git(&repository, &["multi-pack-index", "verify"]);
+
+    // Gathered into one pack, after which the others are no longer referenced
+    run_ok(&repository, &["multi-pack-index", "repack"]);
+    run_ok(&repository, &["multi-pack-index", "expire"]);
+
+    assert_eq!(packs(&repository).len(), 1);
+
+    git(&repository, &["multi-pack-index", "verify"]);
+    git(&repository, &["fsck", "--strict", "--no-progress"]);
+}
+
+#[test]
+fn git_index_passes_verify() {
+    let scratch = Scratch::new("midx-git");
+
+    let repository = repository_with_packs(&scratch);
+
+    git(&repository, &["multi-pack-index", "write"]);
+
+    run_ok(&repository, &["multi-pack-index", "verify"]);
+}
@@ -0,0 +1,216 @@
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use common::{binary, git, malformed_packs, run, run_ok, write, Scratch};
+
+const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// The commit a hand-made push claims to bring.
+const TIP: &str = "1111111111111111111111111111111111111111";
+
+#[test]
+fn thin_push_is_stored_complete() {
+    let scratch = Scratch::new("push-thin");
+
+    let remote = scratch.repository("remote", true);
+    let local = scratch.repository("local", false);
+
+    let receive_pack = format!("--receive-pack={} receive-pack", binary());
+    let remote_path = remote.to_str().expect("utf-8 path");
+
+    let lines: String = (0..2000).map(|line| format!("line {line}\n")).collect();
+
+    write(&local, "big", &lines);
+    git(&local, &["add", "big"]);
+    git(&local, &["commit", "-qm", "first"]);
+    git(&local, &["push", "-q", &receive_pack, remote_path, "main"]);
+
+    // A one-line edit goes over as a delta against the blob the remote already has
+    write(
+        &local,
+        "big",
+        &lines.replace("line 1000\n", "line one thousand\n"),
+    );
+    git(&local, &["commit", "-qam", "second"]);
+    git(&local, &["push", "-q", &receive_pack, remote_path, "main"]);
+
+    git(&remote, &["fsck", "--strict", "--no-progress"]);
+
+    for entry in std::fs::read_dir(remote.join("objects/pack")).expect("read pack directory") {
+        let path = entry.expect("pack directory entry").path();
+
+        if path.extension().is_some_and(|extension| extension == "idx") {
+            git(
+                &remote,
+                &["verify-pack", path.to_str().expect("utf-8 path")],
+            );
+        }
+    }
+
+    assert_eq!(
+        git(&remote, &["rev-parse", "main"]),
+        git(&local, &["rev-parse", "main"])
+    );
+}
+
+#[test]
+fn push_missing_objects_is_refused() {
+    let scratch = Scratch::new("push-missing");
+
+    let remote = scratch.repository("remote", true);
+    let local = scratch.repository("local", false);
+
+    write(&local, "a", "a\n");
+    git(&local, &["add", "a"]);
+    git(&local, &["commit", "-qm", "first"]);
+
+    let commit = git(&local, &["rev-parse", "main"]);
+    let commit = commit.trim();
+
+    // A pack with the commit alone, leaving out its tree and blob
+    let pack = Command::new("git")
+        .args(["pack-objects", "--stdout", "-q"])
+        .current_dir(&local)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            writeln!(child.stdin.take().expect("stdin"), "{commit}")?;
+
+            child.wait_with_output()
+        })
+        .expect("run pack-objects")
+        .stdout;
+
+    let command = format!("{ZERO_HASH} {commit} refs/heads/main\0report-status\n");
+
+    let mut request = format!("{:04x}{command}0000", command.len() + 4).into_bytes();
+
+    request.extend(pack);
+
+    let mut child = Command::new(binary())
+        .args(["receive-pack", "--stateless-rpc"])
+        .arg(&remote)
+        .env_remove("RUST_BACKTRACE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("run receive-pack");
+
+    child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(&request)
+        .expect("write request");
+
+    let output = child.wait_with_output().expect("wait for receive-pack");
+
+    let report = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        report.contains("ng refs/heads/main missing necessary objects"),
+        "{report}"
+    );
+
+    assert!(!remote.join("refs/heads/main").exists());
+    assert!(
+        std::fs::read_dir(remote.join("objects/pack"))
+            .expect("read pack directory")
+            .next()
+            .is_none(),
+        "the pack left quarantine"
+    );
+}
+
+#[test]
+fn malformed_packs_are_refused() {
+    let scratch = Scratch::new("push-malformed");
+
+    let remote = scratch.repository("remote", true);
+
+    for (name, pack) in malformed_packs() {
+        let command = format!("{ZERO_HASH} {TIP} refs/heads/main\0report-status\n");
+
+        let mut request = format!("{:04x}{command}0000", command.len() + 4).into_bytes();
+
+        request.extend(pack);
+
+        let mut child = Command::new(binary())
+            .args(["receive-pack", "--stateless-rpc"])
+            .arg(&remote)
+            .env_remove("RUST_BACKTRACE")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("run receive-pack");
+
+        child
+            .stdin
+            .take()
+            .expect("stdin")
+            .write_all(&request)
+            .expect("write request");
+
+        let output = child.wait_with_output().expect("wait for receive-pack");
+
+        let report = String::from_utf8_lossy(&output.stdout);
+
+        assert!(output.status.code().is_some(), "{name}: killed by a signal");
+        assert!(
+            !String::from_utf8_lossy(&output.stderr).contains("panicked"),
+            "{name}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        // Refused in the status report, or when the pack is cut short, before it
+        assert!(
+            report.contains("ng refs/heads/main") || output.status.code() == Some(1),
+            "{name}: {report}"
+        );
+    }
+
+    assert!(!remote.join("refs/heads/main").exists());
+}
+
+#[test]
+fn lease_without_value_expects_no_ref() {
+    let scratch = Scratch::new("push-lease-absent");
+
+    let remote = scratch.repository("remote", true);
+    let local = scratch.repository("local", false);
+
+    let remote_path = remote.to_str().expect("utf-8 path");
+
+    write(&local, "a", "a\n");
+    git(&local, &["add", "a"]);
+    git(&local, &["commit", "-qm", "first"]);
+
+    for lease in [
+        "--force-with-lease=main:",
+        &format!("--force-with-lease=main:{ZERO_HASH}"),
+    ] {
+        let _ = std::fs::remove_file(remote.join("refs/heads/main"));
+
+        // Creating the ref is what the lease allows
+        run_ok(&local, &["push", lease, remote_path, "main"]);
+
+        assert_eq!(
+            git(&remote, &["rev-parse", "main"]),
+            git(&local, &["rev-parse", "main"])
+        );
+
+        git(&local, &["commit", "-q", "--amend", "-m", lease]);
+
+        // Once it exists, even a forced update is refused
+        let refused = run(&local, &["push", lease, remote_path, "+main"]);
+
+        assert!(!refused.status.success(), "{lease}");
+        assert_ne!(
+            git(&remote, &["rev-parse", "main"]),
+            git(&local, &["rev-parse", "main"])
+        );
+    }
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use common::{binary, git, write, Scratch};
+
+/// A command's arguments, its extra environment, and a file to write and stage first.
+type Step<'a> = (
+    &'a [&'a str],
+    &'a [(&'a str, &'a str)],
+    Option<(&'a str, &'a str)>,
+);
+
+/// Runs `program` with fixed dates and the given extra environment, so that the commits
+/// it makes can be compared.
+fn dated(program: &str, directory: &Path, args: &[&str], environment: &[(&str, &str)]) -> Output {
+    Command::new(program)
+        .args(args)
+        .current_dir(directory)
+        .env("GIT_AUTHOR_DATE", "1600000000 +0200")
+        .env("GIT_COMMITTER_DATE", "1700000000 +0000")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env_remove("RUST_BACKTRACE")
+        .envs(environment.iter().copied())
+        .output()
+        .expect("run command")
+}
+
+/// The status, the last eight commits, the current branch, `f` and whether a rebase
+/// is in progress.
+fn state(repository: &Path) -> String {
+    format!(
+        "{}{}{}{}{}",
+        git(repository, &["status", "--porcelain"]),
+        git(repository, &["log", "--format=%H %s", "-8"]),
+        git(repository, &["rev-parse", "--abbrev-ref", "HEAD"]),
+        std::fs::read_to_string(repository.join("f")).unwrap_or_default(),
+        repository.join(".git/rebase-merge").exists()
+    )
+}
+
+/// Two repositories with the same history, the second for git: `topic` has `two`,
+/// `four`, `new file` and fixups for the first two on top of `base`, and `main` changes
+/// the line `four` changes, then adds `y`.
+fn repositories(scratch: &Scratch) -> (PathBuf, PathBuf) {
+    let repository = scratch.repository("repository", false);
+
+    let lines = "1\n2\n3\n4\n5\n";
+
+    let commit = |message: &str| {
+        git(&repository, &["add", "."]);
+        dated("git", &repository, &["commit", "-qm", message], &[]);
+    };
+
+    write(&repository, "f", lines);
+    commit("base");
+
+    git(&repository, &["checkout", "-qb", "topic"]);
+    write(&repository, "f", &lines.replace('2', "two"));
+    commit("two");
+    write(
+        &repository,
+        "f",
+        &lines.replace('2', "two").replace('4', "four"),
+    );
+    commit("four");
+    write(&repository, "n", "n\n");
+    commit("new file");
+    write(&repository, "g", "g\n");
+    commit("fixup! two");
+    write(&repository, "n", "n2\n");
+    commit("squash! new file");
+
+    git(&repository, &["checkout", "-q", "main"]);
+    write(&repository, "f", &lines.replace('4', "FOUR"));
+    commit("main");
+    git(&repository, &["branch", "old"]);
+    write(&repository, "y", "y\n");
+    commit("y");
+    git(&repository, &["checkout", "-q", "topic"]);
+
+    let expected = scratch.path.join("expected");
+
+    assert!(Command::new("cp")
+        .arg("-r")
+        .arg(&repository)
+        .arg(&expected)
+        .status()
+        .expect("run cp")
+        .success());
+
+    (repository, expected)
+}
+
+/// Runs each step in both repositories, one with the binary under test and the other
+/// with git, comparing the outcome. A step may first write and stage a file.
+fn compare(steps: &[Step]) {
+    let scratch = Scratch::new(&format!("rebase-{}", steps[0].0.join("-").replace('~', "")));
+
+    let (repository, expected) = repositories(&scratch);
+
+    for (args, environment, change) in steps {
+        if let Some((path, content)) = change {
+            for directory in [&repository, &expected] {
+                write(directory, path, content);
+                git(directory, &["add", path]);
+            }
+        }
+
+        let ours = dated(binary(), &repository, args, environment);
+        let theirs = dated("git", &expected, args, environment);
+
+        assert_eq!(
+            ours.status.success(),
+            theirs.status.success(),
+            "{args:?}: {}",
+            String::from_utf8_lossy(&ours.stderr)
+        );
+        assert_eq!(state(&repository), state(&expected), "{args:?}");
+    }
+}
+
+#[test]
+fn rebases_match_git() {
+    compare(&[
+        (&["rebase", "--onto", "old", "main", "topic"], &[], None),
+        (&["reset", "--hard", "ORIG_HEAD"], &[], None),
+        // Stops at `four`
+        (&["rebase", "main"], &[], None),
+        (&["rebase", "--abort"], &[], None),
+        (&["rebase", "main"], &[], None),
+        (&["rebase", "--skip"], &[], None),
+        (&["reset", "--hard", "ORIG_HEAD"], &[], None),
+        (&["rebase", "main"], &[], None),
+        (
+            &["rebase", "--continue"],
+            &[("GIT_EDITOR", "sed -i 1s/^/resolved:/")],
+            Some(("f", "1\ntwo\n3\nboth\n5\n")),
+        ),
+    ]);
+}
+
+#[test]
+fn interactive_rebases_match_git() {
+    compare(&[
+        (
+            &["rebase", "-i", "--autosquash", "old"],
+            &[("GIT_SEQUENCE_EDITOR", "true"), ("GIT_EDITOR", "true")],
+            None,
+        ),
+        (&["reset", "--hard", "ORIG_HEAD"], &[], None),
+        (
+            &["rebase", "-i", "HEAD~4"],
+            &[
+                (
+                    "GIT_SEQUENCE_EDITOR",
+                    "sed -i -e 1s/^pick/reword/ -e 2s/^pick/fixup/ -e 3d",
+                ),
+                ("GIT_EDITOR", "sed -i 1s/^/edited:/"),
+            ],
+            None,
+        ),
+        (&["reset", "--hard", "ORIG_HEAD"], &[], None),
+        // Stops to amend `two`, and runs a command after `four`
+        (
+            &["rebase", "-i", "HEAD~5"],
+            &[(
+                "GIT_SEQUENCE_EDITOR",
+                "sed -i -e 1s/^pick/edit/ -e '2a exec echo hi > hi' -e 3d -e 4s/^pick/drop/",
+            )],
+            None,
+        ),
+        (
+            &["rebase", "--continue"],
+            &[("GIT_EDITOR", "sed -i 1s/^/amended:/")],
+            Some(("e", "e\n")),
+        ),
+        (&["reset", "--hard", "ORIG_HEAD"], &[], None),
+        (
+            &["rebase", "-i", "HEAD~5"],
+            &[("GIT_SEQUENCE_EDITOR", "sed -i 1s/^pick/edit/")],
+            None,
+        ),
+        // A failing editor leaves the rebase stopped
+        (
+            &["rebase", "--continue"],
+            &[("GIT_EDITOR", "false")],
+            Some(("e", "e\n")),
+        ),
+    ]);
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use common::{git, run_ok, Scratch};
+
+/// A repository whose reflogs record commits, a branch made and switched to by the
+/// binary under test, a hard reset and a detached commit since left behind.
+fn moved_repository(scratch: &Scratch) -> PathBuf {
+    let repository = scratch.repository("repository", false);
+
+    let commit = |message: &str| {
+        git(
+            &repository,
+            &["commit", "-q", "--allow-empty", "-m", message],
+        );
+    };
+
+    commit("one");
+    commit("two");
+    run_ok(&repository, &["branch", "side"]);
+    run_ok(&repository, &["switch", "side"]);
+    commit("three");
+    run_ok(&repository, &["switch", "main"]);
+    run_ok(&repository, &["reset", "--hard", "HEAD~1"]);
+    git(&repository, &["checkout", "-q", "--detach"]);
+    commit("detached");
+    git(&repository, &["checkout", "-q", "main"]);
+    git(&repository, &["branch", "-f", "side", "main"]);
+
+    repository
+}
+
+/// A copy of `repository` beside it, for git to work on.
+fn copy(repository: &Path, name: &str) -> PathBuf {
+    let destination = repository.with_file_name(name);
+
+    let status = Command::new("cp")
+        .arg("-r")
+        .arg(repository)
+        .arg(&destination)
+        .status()
+        .expect("run cp");
+
+    assert!(status.success());
+
+    destination
+}
+
+fn reflog(repository: &Path, name: &str) -> String {
+    std::fs::read_to_string(repository.join(".git/logs").join(name)).expect("read reflog")
+}
+
+#[test]
+fn reflogs_match_git() {
+    let scratch = Scratch::new("reflog-show");
+
+    let repository = moved_repository(&scratch);
+
+    for args in [&["show"][..], &["show", "main"], &["show", "side"]] {
+        let args = [&["reflog"][..], args].concat();
+
+        assert_eq!(
+            run_ok(&repository, &args),
+            git(&repository, &args),
+            "{args:?}"
+        );
+    }
+
+    for revision in ["main@{1}", "HEAD@{2}", "@{1}", "side@{0}", "main@{now}"] {
+        assert_eq!(
+            run_ok(&repository, &["cat-file", "-p", revision]),
+            git(&repository, &["cat-file", "-p", revision]),
+            "{revision}"
+        );
+    }
+}
+
+#[test]
+fn delete_and_expire_match_git() {
+    let scratch = Scratch::new("reflog-expire");
+
+    let repository = moved_repository(&scratch);
+
+    let expected = copy(&repository, "expected");
+
+    for args in [
+        &["delete", "main@{1}"][..],
+        &["expire", "--expire-unreachable=now", "--all"],
+        &["expire", "--expire=now", "HEAD"],
+    ] {
+        let args = [&["reflog"][..], args].concat();
+
+        run_ok(&repository, &args);
+        git(&expected, &args);
+
+        for name in ["HEAD", "refs/heads/main", "refs/heads/side"] {
+            assert_eq!(
+                reflog(&repository, name),
+                reflog(&expected, name),
+                "{args:?}: {name}"
+            );
+        }
+    }
+}
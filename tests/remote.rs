@@ -0,0 +1,103 @@
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+use common::{git, run, Scratch};
+
+/// The output and success of a command, then the config and the refs it leaves.
+fn outcome(output: std::process::Output, repository: &Path) -> String {
+    format!(
+        "{}{}\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        output.status.success(),
+        std::fs::read_to_string(repository.join(".git/config")).expect("read config"),
+        git(
+            repository,
+            &["for-each-ref", "--format=%(refname) %(objectname)"]
+        )
+    )
+}
+
+#[test]
+fn remotes_match_git() {
+    let scratch = Scratch::new("remote");
+
+    let upstream = scratch.repository("upstream", false);
+
+    git(&upstream, &["commit", "-q", "--allow-empty", "-m", "first"]);
+
+    for branch in ["a", "b"] {
+        git(&upstream, &["branch", branch]);
+    }
+
+    git(&scratch.path, &["clone", "-q", "upstream", "repository"]);
+
+    let repository = scratch.path.join("repository");
+    let expected = scratch.path.join("expected");
+
+    assert!(Command::new("cp")
+        .arg("-r")
+        .arg(&repository)
+        .arg(&expected)
+        .status()
+        .expect("run cp")
+        .success());
+
+    let compare = |step: &str| {
+        let args: Vec<&str> = step.split(' ').collect();
+
+        assert_eq!(
+            outcome(run(&repository, &args), &repository),
+            outcome(
+                Command::new("git")
+                    .args(&args)
+                    .current_dir(&expected)
+                    .output()
+                    .expect("run git"),
+                &expected
+            ),
+            "{step}"
+        );
+    };
+
+    for step in [
+        // Fetches only the tracked branches
+        "remote add -f -t main -t a -m main up ../upstream",
+        "remote add --tags second ../upstream",
+        "remote add --no-tags third ../upstream",
+        "remote add third ../elsewhere",
+        "remote -v",
+        "remote rename second fourth",
+        // Moves the remote-tracking branches and the upstream of `main`
+        "remote rename origin upstream",
+        "remote rename missing other",
+        "remote set-url fourth /elsewhere",
+        "remote set-url --push fourth /push",
+        "remote set-url --add fourth /added",
+        "remote set-url --add fourth /third",
+        "remote set-url fourth /new added",
+        "remote set-url --delete fourth elsewhere",
+        "remote set-url --delete fourth missing",
+        "remote set-url --delete fourth /",
+        "remote -v",
+        "remote show",
+        "remote show upstream",
+        "remote show -n up",
+    ] {
+        compare(step);
+    }
+
+    // Remote-tracking branches of a branch deleted upstream go
+    git(&upstream, &["branch", "-q", "-D", "b"]);
+
+    for step in [
+        "remote prune -n upstream",
+        "remote prune upstream",
+        "remote remove up",
+        "remote remove up",
+        "remote",
+    ] {
+        compare(step);
+    }
+}
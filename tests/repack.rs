@@ -0,0 +1,202 @@
+mod common;
+
+use std::path::Path;
+
+use common::{git, run_ok, write, Scratch};
+
+/// Total size of the packs in a repository's pack directory.
+fn pack_size(git_dir: &Path) -> u64 {
+    std::fs::read_dir(git_dir.join("objects/pack"))
+        .expect("read pack directory")
+        .map(|entry| entry.expect("pack directory entry").path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "pack")
+        })
+        .map(|path| std::fs::metadata(path).expect("pack metadata").len())
+        .sum()
+}
+
+#[test]
+fn repack_keeps_deltas() {
+    let scratch = Scratch::new("repack-deltas");
+
+    let repository = scratch.repository("repository", false);
+
+    let lines: Vec<String> = (0..3000).map(|line| format!("line {line}\n")).collect();
+
+    // Forty versions of a large file, each a one-line edit away from the last
+    for version in 0..40 {
+        let mut content = lines.clone();
+
+        content[version * 50] = format!("version {version}\n");
+
+        write(&repository, "big", &content.concat());
+        git(&repository, &["add", "big"]);
+        git(
+            &repository,
+            &["commit", "-qm", &format!("version {version}")],
+        );
+    }
+
+    git(&repository, &["repack", "-q", "-a", "-d", "-f"]);
+
+    let before = pack_size(&repository.join(".git"));
+
+    // Rewritten through a second pack, so that every object is copied from another one
+    git(
+        &repository,
+        &["commit", "-q", "--allow-empty", "-m", "loose"],
+    );
+    run_ok(&repository, &["repack", "-d"]);
+    run_ok(&repository, &["repack", "-a", "-d"]);
+
+    let after = pack_size(&repository.join(".git"));
+
+    assert!(after < before * 2, "{before} bytes grew to {after}");
+
+    git(&repository, &["fsck", "--strict", "--no-progress"]);
+
+    let pack = std::fs::read_dir(repository.join(".git/objects/pack"))
+        .expect("read pack directory")
+        .map(|entry| entry.expect("pack directory entry").path())
+        .find(|path| path.extension().is_some_and(|extension| extension == "idx"))
+        .expect("a pack");
+
+    let verified = git(
+        &repository,
+        &["verify-pack", "-v", pack.to_str().expect("utf-8 path")],
+    );
+
+    assert!(verified.contains("chain length = 1"), "{verified}");
+}
+
+/// Whether the repository has the object, packed or loose.
+fn has_object(repository: &Path, object: &str) -> bool {
+    run_git(repository, &["cat-file", "-e", object])
+        .status
+        .success()
+}
+
+/// Runs git in `repository`, whether or not it succeeds.
+fn run_git(repository: &Path, args: &[&str]) -> std::process::Output {
+    std::process::Command::new("git")
+        .args(args)
+        .current_dir(repository)
+        .output()
+        .expect("run git")
+}
+
+/// Objects `(dropped, rewound, staged, loose)` of a repository: a blob of a deleted
+/// branch, left in a pack; one only the reflog of `main` reaches; one only the index
+/// reaches; and an unreachable loose one.
+fn objects_of_every_kind(repository: &Path) -> [String; 4] {
+    let blob = |path: &str| {
+        git(repository, &["rev-parse", &format!("HEAD:{path}")])
+            .trim()
+            .to_string()
+    };
+
+    write(repository, "f", "1\n");
+    git(repository, &["add", "f"]);
+    git(repository, &["commit", "-qm", "first"]);
+    git(repository, &["tag", "-a", "-m", "tag", "v1"]);
+
+    git(repository, &["checkout", "-qb", "dropped"]);
+    write(repository, "f", "dropped\n");
+    git(repository, &["commit", "-qam", "dropped"]);
+
+    let dropped = blob("f");
+
+    git(repository, &["checkout", "-q", "main"]);
+    git(repository, &["repack", "-q", "-a", "-d"]);
+    git(repository, &["branch", "-q", "-D", "dropped"]);
+    git(repository, &["reflog", "expire", "--expire=now", "--all"]);
+
+    write(repository, "f", "rewound\n");
+    git(repository, &["commit", "-qam", "rewound"]);
+
+    let rewound = blob("f");
+
+    git(repository, &["reset", "-q", "--hard", "HEAD~1"]);
+
+    write(repository, "g", "staged\n");
+    git(repository, &["add", "g"]);
+
+    let staged = git(repository, &["rev-parse", ":g"]).trim().to_string();
+
+    write(repository, "loose", "loose\n");
+
+    let loose = git(repository, &["hash-object", "-w", "loose"])
+        .trim()
+        .to_string();
+
+    std::fs::remove_file(repository.join("loose")).expect("remove loose");
+
+    [dropped, rewound, staged, loose]
+}
+
+#[test]
+fn repack_and_prune_keep_what_is_reachable() {
+    let scratch = Scratch::new("repack-reachable");
+
+    let repository = scratch.repository("repository", false);
+
+    let [dropped, rewound, staged, loose] = objects_of_every_kind(&repository);
+
+    assert!(has_object(&repository, &dropped));
+
+    // Reachable objects are packed; unreachable packed ones go, loose ones stay
+    run_ok(&repository, &["repack", "-a", "-d"]);
+
+    for object in [&rewound, &staged, &loose, "v1"] {
+        assert!(has_object(&repository, object), "{object} was lost");
+    }
+
+    assert!(!has_object(&repository, &dropped), "{dropped} was kept");
+
+    git(&repository, &["fsck", "--strict", "--no-progress"]);
+
+    // Too recent for the expiry date
+    run_ok(&repository, &["prune", "--expire=2.weeks.ago"]);
+
+    assert!(has_object(&repository, &loose));
+
+    run_ok(&repository, &["prune", "--expire=now"]);
+
+    assert!(!has_object(&repository, &loose));
+
+    for object in [&rewound, &staged, "v1"] {
+        assert!(has_object(&repository, object), "{object} was pruned");
+    }
+
+    git(&repository, &["fsck", "--strict", "--no-progress"]);
+}
+
+#[test]
+fn repack_keeping_unreachable_loosens_them() {
+    let scratch = Scratch::new("repack-unreachable");
+
+    let repository = scratch.repository("repository", false);
+
+    let [dropped, ..] = objects_of_every_kind(&repository);
+
+    run_ok(&repository, &["repack", "-A", "-d"]);
+
+    assert!(repository
+        .join(".git/objects")
+        .join(&dropped[..2])
+        .join(&dropped[2..])
+        .exists());
+
+    // Until a gc prunes them
+    run_ok(&repository, &["gc", "-q"]);
+
+    assert!(has_object(&repository, &dropped));
+
+    run_ok(&repository, &["gc", "-q", "--prune=now"]);
+
+    assert!(!has_object(&repository, &dropped));
+
+    git(&repository, &["fsck", "--strict", "--no-progress"]);
+}
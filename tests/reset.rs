@@ -0,0 +1,103 @@
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+use common::{git, run, run_ok, write, Scratch};
+
+/// A file to write and stage, with its content.
+type Change = Option<(&'static str, &'static str)>;
+
+/// What a reset changes: the status, `HEAD`, `ORIG_HEAD`, the last reflog message of
+/// `HEAD` and the files in the work tree with their content.
+fn state(repository: &Path) -> String {
+    let reflog = std::fs::read_to_string(repository.join(".git/logs/HEAD")).expect("read reflog");
+
+    let mut files: Vec<String> = std::fs::read_dir(repository)
+        .expect("read work tree")
+        .map(|entry| entry.expect("work tree entry").file_name())
+        .filter(|name| name != ".git")
+        .map(|name| {
+            let content = std::fs::read_to_string(repository.join(&name)).expect("read file");
+
+            format!("{}: {content}", name.to_string_lossy())
+        })
+        .collect();
+
+    files.sort();
+
+    format!(
+        "{}{}{}\n{}",
+        git(repository, &["status", "--porcelain"]),
+        git(repository, &["rev-parse", "HEAD", "ORIG_HEAD"]),
+        reflog
+            .lines()
+            .last()
+            .and_then(|line| line.split('\t').nth(1))
+            .unwrap_or_default(),
+        files.concat()
+    )
+}
+
+#[test]
+fn resets_match_git() {
+    let scratch = Scratch::new("reset");
+
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "a", "1\n");
+    write(&repository, "b", "1\n");
+    git(&repository, &["add", "."]);
+    git(&repository, &["commit", "-qm", "one"]);
+
+    write(&repository, "a", "2\n");
+    write(&repository, "c", "c\n");
+    std::fs::remove_file(repository.join("b")).expect("remove b");
+    git(&repository, &["add", "-A"]);
+    git(&repository, &["commit", "-qm", "two"]);
+
+    let expected = scratch.path.join("expected");
+
+    assert!(Command::new("cp")
+        .arg("-r")
+        .arg(&repository)
+        .arg(&expected)
+        .status()
+        .expect("run cp")
+        .success());
+
+    let steps: [(&[&str], Change); 10] = [
+        (&["--soft", "HEAD~1"], None),
+        (&["HEAD@{1}"], Some(("a", "x\n"))),
+        (&["--mixed", "HEAD~1"], None),
+        (&["HEAD@{1}"], None),
+        (&["--hard", "HEAD~1"], None),
+        (&["--hard", "HEAD@{1}"], Some(("a", "y\n"))),
+        (&["HEAD", "--", "a"], Some(("a", "z\n"))),
+        (&["HEAD~1", "--", "a", "c"], None),
+        (&["--hard"], None),
+        (&["HEAD~1", "b"], None),
+    ];
+
+    for (args, change) in steps {
+        // The change comes first, staged
+        if let Some((path, content)) = change {
+            for directory in [&repository, &expected] {
+                write(directory, path, content);
+                git(directory, &["add", path]);
+            }
+        }
+
+        let args = [&["reset"][..], args].concat();
+
+        run_ok(&repository, &args);
+
+        git(&expected, &args);
+
+        assert_eq!(state(&repository), state(&expected), "{args:?}");
+    }
+
+    assert!(!run(&repository, &["reset", "--hard", "HEAD", "--", "a"])
+        .status
+        .success());
+}
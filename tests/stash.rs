@@ -0,0 +1,136 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{git, run, run_ok, write, Scratch};
+
+/// A repository with `a` changed both in the index (`2`) and the work tree (`3`), `b`
+/// changed in the work tree only and `u` untracked.
+fn changed_repository(scratch: &Scratch) -> PathBuf {
+    let repository = scratch.repository("repository", false);
+
+    write(&repository, "a", "1\n");
+    write(&repository, "b", "1\n");
+    git(&repository, &["add", "."]);
+    git(&repository, &["commit", "-qm", "one"]);
+
+    write(&repository, "a", "2\n");
+    git(&repository, &["add", "a"]);
+    write(&repository, "a", "3\n");
+    write(&repository, "b", "2\n");
+    write(&repository, "u", "u\n");
+
+    repository
+}
+
+fn show(repository: &Path, object: &str) -> String {
+    git(repository, &["show", object])
+}
+
+#[test]
+fn stash_commits_have_git_shape() {
+    let scratch = Scratch::new("stash-shape");
+
+    let repository = changed_repository(&scratch);
+
+    run_ok(&repository, &["stash", "push", "-m", "first"]);
+
+    assert_eq!(git(&repository, &["status", "--porcelain"]), "?? u\n");
+    assert_eq!(
+        git(&repository, &["stash", "list"]),
+        "stash@{0}: On main: first\n"
+    );
+
+    // The work tree on top of HEAD and the index
+    assert_eq!(
+        git(&repository, &["rev-parse", "stash^1"]),
+        git(&repository, &["rev-parse", "HEAD"])
+    );
+    assert_eq!(show(&repository, "stash^2:a"), "2\n");
+    assert_eq!(show(&repository, "stash:a"), "3\n");
+    assert_eq!(show(&repository, "stash:b"), "2\n");
+
+    run_ok(&repository, &["stash", "push", "-u"]);
+
+    assert_eq!(git(&repository, &["status", "--porcelain"]), "");
+    assert_eq!(show(&repository, "stash^3:u"), "u\n");
+    assert_eq!(
+        run_ok(&repository, &["stash", "list"]),
+        git(&repository, &["stash", "list"])
+    );
+
+    git(&repository, &["fsck", "--strict", "--no-progress"]);
+
+    // Git applies them back
+    git(&repository, &["stash", "pop"]);
+
+    assert_eq!(
+        std::fs::read_to_string(repository.join("u")).expect("read u"),
+        "u\n"
+    );
+
+    git(&repository, &["stash", "pop", "--index"]);
+
+    assert_eq!(
+        git(&repository, &["status", "--porcelain"]),
+        "MM a\n M b\n?? u\n"
+    );
+}
+
+#[test]
+fn pop_restores_the_index() {
+    let scratch = Scratch::new("stash-pop");
+
+    let repository = changed_repository(&scratch);
+
+    run_ok(&repository, &["stash"]);
+    run_ok(&repository, &["stash", "pop", "--index"]);
+
+    assert_eq!(
+        git(&repository, &["status", "--porcelain"]),
+        "MM a\n M b\n?? u\n"
+    );
+    assert_eq!(git(&repository, &["stash", "list"]), "");
+
+    run_ok(&repository, &["stash"]);
+    run_ok(&repository, &["stash", "apply"]);
+
+    assert_eq!(
+        git(&repository, &["status", "--porcelain"]),
+        " M a\n M b\n?? u\n"
+    );
+
+    run_ok(&repository, &["stash", "drop"]);
+
+    assert_eq!(git(&repository, &["stash", "list"]), "");
+}
+
+#[test]
+fn conflicting_pop_keeps_the_stash() {
+    let scratch = Scratch::new("stash-conflict");
+
+    let repository = changed_repository(&scratch);
+
+    run_ok(&repository, &["stash"]);
+
+    write(&repository, "a", "conflict\n");
+    git(&repository, &["commit", "-qam", "conflict"]);
+
+    let popped = run(&repository, &["stash", "pop"]);
+
+    assert_eq!(popped.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&popped.stdout).contains("CONFLICT (content): Merge conflict in a"),
+        "{}",
+        String::from_utf8_lossy(&popped.stdout)
+    );
+    assert_eq!(
+        std::fs::read_to_string(repository.join("a")).expect("read a"),
+        "<<<<<<< Updated upstream\nconflict\n=======\n3\n>>>>>>> Stashed changes\n"
+    );
+    assert_eq!(
+        git(&repository, &["status", "--porcelain"]),
+        "UU a\nM  b\n?? u\n"
+    );
+    assert_eq!(git(&repository, &["stash", "list"]).lines().count(), 1);
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use common::{git, run, run_ok, write, Scratch};
+
+/// A repository on `main` with `a`, `d/b` and an executable `x.sh`, and a branch `side`
+/// that changes `a`, deletes `d/b` and adds `n` and a symbolic link `link`.
+fn branched_repository(scratch: &Scratch) -> PathBuf {
+    let repository = scratch.repository("repository", false);
+
+    std::fs::create_dir(repository.join("d")).expect("create d");
+
+    write(&repository, "a", "a\n");
+    write(&repository, "d/b", "b\n");
+    write(&repository, "x.sh", "x\n");
+
+    std::fs::set_permissions(
+        repository.join("x.sh"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .expect("make x.sh executable");
+
+    git(&repository, &["add", "."]);
+    git(&repository, &["commit", "-qm", "first"]);
+
+    git(&repository, &["checkout", "-qb", "side"]);
+    git(&repository, &["rm", "-q", "d/b"]);
+    write(&repository, "a", "side\n");
+    write(&repository, "n", "n\n");
+
+    std::os::unix::fs::symlink("a", repository.join("link")).expect("create link");
+
+    git(&repository, &["add", "."]);
+    git(&repository, &["commit", "-qm", "side"]);
+    git(&repository, &["checkout", "-q", "main"]);
+
+    repository
+}
+
+fn read(repository: &Path, path: &str) -> String {
+    std::fs::read_to_string(repository.join(path)).expect("read file")
+}
+
+fn current_branch(repository: &Path) -> String {
+    git(repository, &["rev-parse", "--abbrev-ref", "HEAD"])
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn switch_writes_the_tree() {
+    let scratch = Scratch::new("switch-tree");
+
+    let repository = branched_repository(&scratch);
+
+    run_ok(&repository, &["switch", "side"]);
+
+    assert_eq!(current_branch(&repository), "side");
+    assert_eq!(read(&repository, "a"), "side\n");
+    assert_eq!(read(&repository, "n"), "n\n");
+    assert!(!repository.join("d").exists());
+    assert_eq!(
+        std::fs::read_link(repository.join("link")).expect("read link"),
+        Path::new("a")
+    );
+    assert_eq!(
+        std::fs::metadata(repository.join("x.sh"))
+            .expect("x.sh metadata")
+            .permissions()
+            .mode()
+            & 0o111,
+        0o111
+    );
+    assert_eq!(git(&repository, &["status", "--porcelain"]), "");
+
+    run_ok(&repository, &["switch", "-c", "new", "main"]);
+
+    assert_eq!(current_branch(&repository), "new");
+    assert_eq!(read(&repository, "d/b"), "b\n");
+    assert!(!repository.join("n").exists());
+    assert!(!repository.join("link").exists());
+    assert_eq!(git(&repository, &["status", "--porcelain"]), "");
+
+    run_ok(&repository, &["checkout", "side~0"]);
+
+    assert_eq!(current_branch(&repository), "HEAD");
+    assert_eq!(
+        git(&repository, &["rev-parse", "HEAD"]),
+        git(&repository, &["rev-parse", "side"])
+    );
+    assert_eq!(git(&repository, &["status", "--porcelain"]), "");
+}
+
+#[test]
+fn switch_keeps_local_changes() {
+    let scratch = Scratch::new("switch-local");
+
+    let repository = branched_repository(&scratch);
+
+    write(&repository, "a", "local\n");
+
+    let refused = run(&repository, &["switch", "side"]);
+
+    assert!(!refused.status.success());
+    assert!(
+        String::from_utf8_lossy(&refused.stderr).contains("would be overwritten"),
+        "{}",
+        String::from_utf8_lossy(&refused.stderr)
+    );
+    assert_eq!(current_branch(&repository), "main");
+    assert_eq!(read(&repository, "a"), "local\n");
+
+    // Changes to files the switch leaves alone come along
+    write(&repository, "a", "a\n");
+    write(&repository, "x.sh", "local\n");
+
+    run_ok(&repository, &["switch", "side"]);
+
+    assert_eq!(read(&repository, "x.sh"), "local\n");
+    assert_eq!(git(&repository, &["status", "--porcelain"]), " M x.sh\n");
+
+    write(&repository, "a", "local\n");
+
+    run_ok(&repository, &["switch", "-f", "main"]);
+
+    assert_eq!(read(&repository, "a"), "a\n");
+    assert_eq!(git(&repository, &["status", "--porcelain"]), "");
+}
+
+#[test]
+fn restore_takes_paths_from_the_index_or_a_commit() {
+    let scratch = Scratch::new("switch-restore");
+
+    let repository = branched_repository(&scratch);
+
+    write(&repository, "a", "changed\n");
+    write(&repository, "d/b", "changed\n");
+    git(&repository, &["add", "d/b"]);
+
+    run_ok(&repository, &["restore", "a"]);
+
+    assert_eq!(read(&repository, "a"), "a\n");
+
+    run_ok(&repository, &["restore", "--staged", "d/b"]);
+
+    assert_eq!(git(&repository, &["status", "--porcelain"]), " M d/b\n");
+
+    run_ok(&repository, &["restore", "--source", "side", "a"]);
+
+    assert_eq!(read(&repository, "a"), "side\n");
+
+    run_ok(&repository, &["checkout", "--", "d/b"]);
+
+    assert_eq!(git(&repository, &["status", "--porcelain"]), " M a\n");
+}
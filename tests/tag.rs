@@ -0,0 +1,124 @@
+mod common;
+
+use std::path::PathBuf;
+
+use common::{git, run, run_ok, Scratch};
+
+/// A repository with two commits and tags made by the binary under test: lightweight
+/// `v1.2`, `v2.0-rc1` and `light`, annotated `v1.10` and `v1.9`, and `nested`, a tag
+/// of `v1.9` made by git.
+fn tagged_repository(scratch: &Scratch) -> PathBuf {
+    let repository = scratch.repository("repository", false);
+
+    git(&repository, &["commit", "-q", "--allow-empty", "-m", "one"]);
+
+    run_ok(&repository, &["tag", "v1.2"]);
+    run_ok(
+        &repository,
+        &["tag", "-a", "v1.10", "-m", "ten\nsecond line\nthird line"],
+    );
+
+    git(&repository, &["commit", "-q", "--allow-empty", "-m", "two"]);
+
+    run_ok(&repository, &["tag", "-a", "v1.9", "-m", "nine"]);
+    run_ok(&repository, &["tag", "v2.0-rc1"]);
+    run_ok(&repository, &["tag", "light", "HEAD~1"]);
+
+    git(
+        &repository,
+        &[
+            "-c",
+            "advice.nestedTag=false",
+            "tag",
+            "-a",
+            "nested",
+            "-m",
+            "nested",
+            "v1.9",
+        ],
+    );
+
+    repository
+}
+
+#[test]
+fn tags_are_readable_by_git() {
+    let scratch = Scratch::new("tag-objects");
+
+    let repository = tagged_repository(&scratch);
+
+    assert_eq!(git(&repository, &["cat-file", "-t", "v1.10"]), "tag\n");
+    assert_eq!(git(&repository, &["cat-file", "-t", "v1.2"]), "commit\n");
+
+    let tag = git(&repository, &["cat-file", "-p", "v1.10"]);
+
+    assert!(
+        tag.contains("\ntag v1.10\ntagger Test <test@example.com> "),
+        "{tag}"
+    );
+    assert!(tag.ends_with("\n\nten\nsecond line\nthird line\n"), "{tag}");
+
+    git(&repository, &["fsck", "--strict", "--no-progress"]);
+
+    // Peeled down to the commit through both tags
+    for revision in [
+        "nested^{}",
+        "nested^{tag}",
+        "v1.10^{commit}",
+        "v1.9~0",
+        "v1.9^0",
+    ] {
+        assert_eq!(
+            run_ok(&repository, &["cat-file", "-p", revision]),
+            git(&repository, &["cat-file", "-p", revision]),
+            "{revision}"
+        );
+    }
+}
+
+#[test]
+fn listing_matches_git() {
+    let scratch = Scratch::new("tag-list");
+
+    let repository = tagged_repository(&scratch);
+
+    for args in [
+        &[][..],
+        &["-l", "v1*"],
+        &["--sort=version:refname"],
+        &["--sort=-version:refname"],
+        &["-n"],
+        &["-n2"],
+        &["--points-at", "HEAD"],
+        &["--points-at", "HEAD~1"],
+        &["--points-at", "v1.9"],
+    ] {
+        let args = [&["tag"][..], args].concat();
+
+        assert_eq!(
+            run_ok(&repository, &args),
+            git(&repository, &args),
+            "{args:?}"
+        );
+    }
+}
+
+#[test]
+fn existing_tags_need_force() {
+    let scratch = Scratch::new("tag-force");
+
+    let repository = tagged_repository(&scratch);
+
+    assert!(!run(&repository, &["tag", "light"]).status.success());
+
+    run_ok(&repository, &["tag", "-f", "light"]);
+
+    assert_eq!(
+        git(&repository, &["rev-parse", "light"]),
+        git(&repository, &["rev-parse", "HEAD"])
+    );
+
+    run_ok(&repository, &["tag", "-d", "light", "v1.10"]);
+
+    assert_eq!(git(&repository, &["tag"]), "nested\nv1.2\nv1.9\nv2.0-rc1\n");
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use common::{binary, git, write, Scratch};
+
+#[test]
+fn malformed_want_is_refused() {
+    let scratch = Scratch::new("upload-pack-want");
+
+    let repository = scratch.repository("repository", true);
+
+    let mut child = Command::new(binary())
+        .args(["upload-pack", "--stateless-rpc"])
+        .arg(&repository)
+        .env_remove("RUST_BACKTRACE")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run upload-pack");
+
+    child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(b"000ewant a\xc3\xa9b\n0000")
+        .expect("write request");
+
+    let output = child.wait_with_output().expect("wait for upload-pack");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(!output.status.success());
+    assert!(
+        stdout.contains("ERR git upload-pack: protocol error"),
+        "{stdout}"
+    );
+    assert!(
+        !String::from_utf8_lossy(&output.stderr).contains("panicked"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn unreachable_want_is_refused() {
+    let scratch = Scratch::new("upload-pack-unreachable");
+
+    let server = scratch.repository("server", false);
+    let client = scratch.repository("client", false);
+
+    write(&server, "a", "a\n");
+    git(&server, &["add", "a"]);
+    git(&server, &["commit", "-qm", "first"]);
+    git(&server, &["commit", "-q", "--allow-empty", "-m", "second"]);
+
+    let reachable = git(&server, &["rev-parse", "main~1"]);
+    let tree = git(&server, &["rev-parse", "main^{tree}"]);
+    let dangling = git(&server, &["commit-tree", "-m", "dangling", tree.trim()]);
+
+    let upload_pack = format!("--upload-pack={} upload-pack", binary());
+    let server_path = server.to_str().expect("utf-8 path");
+
+    for version in ["protocol.version=0", "protocol.version=2"] {
+        let fetch = |hash: &str| {
+            Command::new("git")
+                .args(["-c", version, "fetch", "-q", "--no-tags", &upload_pack])
+                .args([server_path, hash])
+                .current_dir(&client)
+                .output()
+                .expect("run git fetch")
+        };
+
+        let refused = fetch(dangling.trim());
+
+        assert!(!refused.status.success());
+        assert!(
+            String::from_utf8_lossy(&refused.stderr).contains("not our ref"),
+            "{}",
+            String::from_utf8_lossy(&refused.stderr)
+        );
+
+        assert!(fetch(reachable.trim()).status.success(), "{version}");
+    }
+}